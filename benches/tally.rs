@@ -0,0 +1,55 @@
+//! Cost per tick of a run in which almost no agent changes state, against the cost of
+//! counting all agents as the tally did before it was kept up to date incrementally.
+//!
+//! Run with `cargo bench --bench tally`. The remaining cost of a tick still grows with the
+//! population, as every agent is moved and checked for a transition.
+use std::time::{Duration, Instant};
+
+use bkamins_sir_abm::julia_reimpl::Environment;
+use bkamins_sir_abm::params::SimulationParams;
+use bkamins_sir_abm::presets;
+
+const TICKS: usize = 50;
+
+/// Mean duration of `f` over `TICKS` calls
+fn per_tick(mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..TICKS {
+        f();
+    }
+    start.elapsed() / TICKS as u32
+}
+
+fn main() {
+    println!("| agents | tick | full count | share |");
+    println!("|---:|---:|---:|---:|");
+    for &n in &[10_000, 100_000, 1_000_000] {
+        // the density of the blogpost, with a single case that infects no one in the run
+        let side = ((n as f64 / presets::blog_default().density()).sqrt()) as usize;
+        let params = SimulationParams {
+            n,
+            infected: 1,
+            duration: usize::MAX,
+            p_infect: 0.0,
+            xdim: side,
+            ydim: side,
+            seed: Some(1),
+            ..presets::blog_default()
+        };
+        let mut environment = Environment::from_params(&params);
+        environment.set_invariant_checks(false);
+        let tick = per_tick(|| {
+            environment.step();
+        });
+        let count = per_tick(|| {
+            std::hint::black_box(environment.get_statistics());
+        });
+        println!(
+            "| {} | {:?} | {:?} | {:.1}% |",
+            n,
+            tick,
+            count,
+            100.0 * count.as_secs_f64() / (tick + count).as_secs_f64()
+        );
+    }
+}
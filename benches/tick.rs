@@ -0,0 +1,55 @@
+//! Cost of a single tick of the default scenario of the blogpost, at the peak of its epidemic
+//! and once it has died out, and of a late tick of the high mortality scenario depending on
+//! whether the dead are removed from the grid.
+//!
+//! Run with `cargo bench --bench tick`.
+use std::time::{Duration, Instant};
+
+use bkamins_sir_abm::julia_reimpl::Environment;
+use bkamins_sir_abm::params::{CorpseHandling, SimulationParams};
+use bkamins_sir_abm::presets;
+
+const RUNS: usize = 200;
+
+/// Mean duration of the tick after `ticks` ticks of the seeded `params`
+fn tick_after(params: &SimulationParams, ticks: usize) -> Duration {
+    let params = SimulationParams {
+        seed: Some(42),
+        ..params.clone()
+    };
+    let mut total = Duration::default();
+    for _ in 0..RUNS {
+        let mut environment = Environment::from_params(&params);
+        environment.set_invariant_checks(false);
+        for _ in 0..ticks {
+            environment.step();
+        }
+        let start = Instant::now();
+        environment.step();
+        total += start.elapsed();
+    }
+    total / RUNS as u32
+}
+
+fn main() {
+    println!("| after ticks | tick |");
+    println!("|---:|---:|");
+    for &ticks in &[0, 20, 100] {
+        println!(
+            "| {} | {:?} |",
+            ticks,
+            tick_after(&presets::blog_default(), ticks)
+        );
+    }
+
+    println!();
+    println!("| corpses | tick after 100 of high mortality |");
+    println!("|---|---:|");
+    for &handling in &[CorpseHandling::Keep, CorpseHandling::Remove] {
+        let params = SimulationParams {
+            corpse_handling: handling,
+            ..presets::high_mortality()
+        };
+        println!("| {:?} | {:?} |", handling, tick_after(&params, 100));
+    }
+}
@@ -0,0 +1,188 @@
+//! Profile of the heap allocations of a run, see [`AllocationReport`].
+//!
+//! With the `alloc-profile` feature, the binary installs [`CountingAllocator`] as its global
+//! allocator, which counts the allocations of each thread, and [`result::run`] attaches a
+//! report to the metadata of each run. Other binaries may install it the same way:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOCATOR: CountingAllocator = CountingAllocator;
+//! ```
+//!
+//! Runs have no report without the feature, or when the allocator is not installed.
+//!
+//! [`result::run`]: crate::result::run
+use serde::{Deserialize, Serialize};
+
+use crate::julia_reimpl::Environment;
+
+/// Number of ticks at the start of a run that are excluded from
+/// [`AllocationReport::allocations_per_tick`], during which the buffers of the environment
+/// grow to their working size
+pub const WARM_UP_TICKS: usize = 10;
+
+/// Heap allocations of a run
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AllocationReport {
+    /// Number of allocations and reallocations, including setting up the environment
+    pub allocations: u64,
+    /// Number of bytes allocated
+    pub bytes: u64,
+    /// Largest [`Environment::heap_bytes`] at the end of a tick
+    pub peak_environment_bytes: usize,
+    /// Mean number of allocations per tick after the first [`WARM_UP_TICKS`] ticks, zero for
+    /// runs that end before
+    pub allocations_per_tick: f64,
+}
+
+#[cfg(feature = "alloc-profile")]
+pub use counting::CountingAllocator;
+
+/// Number of bytes allocated by the current thread so far, which stays zero unless
+/// [`CountingAllocator`] is installed
+#[cfg(feature = "alloc-profile")]
+#[must_use]
+pub fn allocated_bytes() -> u64 {
+    counting::counts().1
+}
+
+#[cfg(feature = "alloc-profile")]
+mod counting {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    thread_local! {
+        /// Allocations and bytes allocated by this thread
+        static COUNTS: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+    }
+
+    fn count(bytes: usize) {
+        // the counts are gone while the thread shuts down
+        let _ = COUNTS.try_with(|counts| {
+            let (allocations, total) = counts.get();
+            counts.set((allocations + 1, total + bytes as u64));
+        });
+    }
+
+    /// Allocations and bytes allocated by the current thread so far
+    pub(super) fn counts() -> (u64, u64) {
+        COUNTS.try_with(Cell::get).unwrap_or_default()
+    }
+
+    /// The system allocator, counting allocations
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct CountingAllocator;
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count(layout.size());
+            System.alloc(layout)
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            count(layout.size());
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count(new_size);
+            System.realloc(ptr, layout, new_size)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+}
+
+/// Collects the [`AllocationReport`] of a run on the current thread
+#[derive(Debug, Clone)]
+pub(crate) struct Profiler {
+    #[cfg(feature = "alloc-profile")]
+    start: (u64, u64),
+    /// Allocations by the end of the warm-up, and the tick at which it ended
+    #[cfg(feature = "alloc-profile")]
+    warm: Option<(u64, usize)>,
+    #[cfg(feature = "alloc-profile")]
+    peak: usize,
+}
+
+#[cfg(feature = "alloc-profile")]
+impl Profiler {
+    /// Start counting, including the allocations made since `start` was taken
+    pub(crate) fn start(start: (u64, u64)) -> Self {
+        Self {
+            start,
+            warm: None,
+            peak: 0,
+        }
+    }
+
+    /// Counts of the current thread, to pass to [`Profiler::start`]
+    pub(crate) fn counts() -> (u64, u64) {
+        counting::counts()
+    }
+
+    /// Account for the end of `tick`
+    pub(crate) fn tick<X>(&mut self, environment: &Environment<X>, tick: usize) {
+        self.peak = self.peak.max(environment.heap_bytes());
+        if tick == WARM_UP_TICKS {
+            self.warm = Some((counting::counts().0, tick));
+        }
+    }
+
+    /// Report of the run of `environment`, unless [`CountingAllocator`] is not installed such
+    /// that nothing was counted
+    pub(crate) fn report<X>(&self, environment: &Environment<X>) -> Option<AllocationReport> {
+        let (allocations, bytes) = counting::counts();
+        if allocations == self.start.0 {
+            return None;
+        }
+        let allocations_per_tick = match self.warm {
+            Some((warm, tick)) if environment.tick() > tick => {
+                (allocations - warm) as f64 / (environment.tick() - tick) as f64
+            }
+            _ => 0.0,
+        };
+        Some(AllocationReport {
+            allocations: allocations - self.start.0,
+            bytes: bytes - self.start.1,
+            peak_environment_bytes: self.peak.max(environment.heap_bytes()),
+            allocations_per_tick,
+        })
+    }
+}
+
+#[cfg(not(feature = "alloc-profile"))]
+impl Profiler {
+    pub(crate) fn start(_: (u64, u64)) -> Self {
+        Self {}
+    }
+
+    pub(crate) fn counts() -> (u64, u64) {
+        (0, 0)
+    }
+
+    pub(crate) fn tick<X>(&mut self, _: &Environment<X>, _: usize) {}
+
+    pub(crate) fn report<X>(&self, _: &Environment<X>) -> Option<AllocationReport> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::presets;
+    use crate::result;
+
+    #[test]
+    fn test_no_report_without_allocator() {
+        // the allocator is only installed by the binary
+        assert_eq!(
+            result::run(&presets::dense_small_grid())
+                .metadata
+                .allocations,
+            None
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Branching runs, which continue several futures from the same state of a scenario.
+//!
+//! A base scenario runs up to the branch point, after which each branch continues a copy of
+//! the environment under its own interventions, see [`Environment::branch`]. The records of
+//! all branches are thus identical up to and including the branch point, and a branch that
+//! keeps the seed and interventions of the base continues exactly like the base itself.
+use serde::{Deserialize, Serialize};
+
+use crate::julia_reimpl::{Environment, TallyStates, TallyStatesSlice, TallyStatesVec};
+use crate::params::{ConfigError, SimulationParams};
+
+/// Record of one branch, including the history it shares with the other branches
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BranchRun {
+    /// Tick from which the branch continues on its own, being the branch point of
+    /// [`run_branches`], or the end of the base run if that ended earlier
+    pub branch_point: usize,
+    /// Tally of each tick from zero
+    pub record: TallyStatesVec,
+}
+
+impl BranchRun {
+    /// Tallies of the ticks after the branch point
+    #[must_use]
+    pub fn continuation(&self) -> TallyStatesSlice<'_> {
+        self.record.slice(self.branch_point + 1..self.record.len())
+    }
+}
+
+/// Run the `base` scenario up to `branch_point`, and continue a branch from there for each
+/// entry of `branches`, in that order. All branches draw the random numbers of the base
+/// scenario, such that they differ only by their interventions.
+///
+/// Fails without running any branch if one of them cannot continue the base scenario, see
+/// [`Environment::branch`].
+pub fn run_branches(
+    base: &SimulationParams,
+    branch_point: usize,
+    branches: &[SimulationParams],
+) -> Result<Vec<BranchRun>, ConfigError> {
+    let mut environment = Environment::from_params(base);
+    let mut history = vec![environment.get_statistics()];
+    while environment.tick() < branch_point && environment.is_active() {
+        environment.advance(&mut |_, _| {});
+        history.push(environment.get_statistics());
+    }
+    let branch_point = environment.tick();
+
+    let branches = branches
+        .iter()
+        .map(|overrides| environment.branch(overrides, None))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(branches
+        .into_iter()
+        .map(|mut branch| {
+            let mut record: Vec<TallyStates> = history.clone();
+            while branch.is_active() && !branch.reached_max_ticks() {
+                branch.advance(&mut |_, _| {});
+                record.push(branch.get_statistics());
+            }
+            BranchRun {
+                branch_point,
+                record: record.into_iter().collect(),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    #[test]
+    fn test_branches_share_history() {
+        let base = presets::dense_small_grid();
+        let lethal = SimulationParams {
+            p_death: 1.0,
+            ..base.clone()
+        };
+        let harmless = SimulationParams {
+            p_death: 0.0,
+            ..base.clone()
+        };
+        let runs = run_branches(&base, 8, &[lethal, harmless, base.clone()]).unwrap();
+        assert_eq!(runs.len(), 3);
+        for run in &runs {
+            assert_eq!(run.branch_point, 8);
+            assert_eq!(run.record.len(), 9 + run.continuation().len());
+            assert_eq!(run.record.slice(0..9), runs[0].record.slice(0..9));
+        }
+        // after the branch point, agents only die in the lethal branch and only recover in
+        // the harmless one
+        let (lethal, harmless) = (&runs[0], &runs[1]);
+        assert!(lethal
+            .continuation()
+            .recovered
+            .iter()
+            .all(|&recovered| recovered == lethal.record.recovered[8]));
+        assert!(harmless
+            .continuation()
+            .dead
+            .iter()
+            .all(|&dead| dead == harmless.record.dead[8]));
+        assert_ne!(lethal.record.dead.last(), harmless.record.dead.last());
+    }
+
+    #[test]
+    fn test_branch_keeps_state() {
+        let base = presets::dense_small_grid();
+        let mut environment = Environment::from_params(&base);
+        for _ in 0..3 {
+            environment.advance(&mut |_, _| {});
+        }
+        let branch = environment
+            .branch(
+                &SimulationParams {
+                    p_death: 0.5,
+                    ..base.clone()
+                },
+                None,
+            )
+            .unwrap();
+        assert_eq!(branch.tick(), 3);
+        assert!(branch.iter_agents().eq(environment.iter_agents()));
+        assert_eq!(branch.get_statistics(), environment.get_statistics());
+
+        // a branch point past the end of the epidemic is the end of the base run
+        let runs = run_branches(
+            &presets::dense_small_grid(),
+            10_000,
+            &[presets::dense_small_grid()],
+        )
+        .unwrap();
+        assert_eq!(runs[0].branch_point + 1, runs[0].record.len());
+        assert!(runs[0].continuation().is_empty());
+    }
+
+    #[test]
+    fn test_no_op_branch_reproduces_continuation() {
+        let base = SimulationParams {
+            seed: Some(11),
+            ..presets::dense_small_grid()
+        };
+        let mut environment = Environment::from_params(&base);
+        for _ in 0..4 {
+            environment.advance(&mut |_, _| {});
+        }
+        let mut kept = environment.branch(&base, None).unwrap();
+        let mut reseeded = environment.branch(&base, Some(11)).unwrap();
+        let mut forked = environment.branch(&base, Some(12)).unwrap();
+        let continuation = environment.run();
+        assert_eq!(kept.run(), continuation);
+        assert_eq!(reseeded.run(), continuation);
+        assert_eq!(forked.seed(), 12);
+        // another seed only changes the future
+        let forked_continuation = forked.run();
+        assert_eq!(forked_continuation[0], continuation[0]);
+        assert_ne!(forked_continuation, continuation);
+
+        let runs = run_branches(&base, 4, std::slice::from_ref(&base)).unwrap();
+        let mut unbranched = Environment::from_params(&base);
+        let record: TallyStatesVec = unbranched.run().into_iter().collect();
+        assert_eq!(runs[0].record, record);
+    }
+
+    #[test]
+    fn test_setup_cannot_branch() {
+        let base = presets::dense_small_grid();
+        let environment = Environment::from_params(&base);
+        let branch = |overrides: SimulationParams| environment.branch(&overrides, None).err();
+        assert_eq!(
+            branch(SimulationParams {
+                n: base.n + 1,
+                ..base.clone()
+            }),
+            Some(ConfigError::SetupOnly { name: "n" })
+        );
+        assert_eq!(
+            branch(SimulationParams {
+                ydim: 5,
+                ..base.clone()
+            }),
+            Some(ConfigError::SetupOnly { name: "ydim" })
+        );
+        assert_eq!(
+            branch(SimulationParams {
+                reservoirs: vec![(0, 0)],
+                ..base.clone()
+            }),
+            Some(ConfigError::SetupOnly { name: "reservoirs" })
+        );
+        assert_eq!(
+            branch(SimulationParams {
+                seed: Some(1),
+                ..base.clone()
+            }),
+            Some(ConfigError::SetupOnly { name: "seed" })
+        );
+        assert!(matches!(
+            branch(SimulationParams {
+                p_death: 2.0,
+                ..base.clone()
+            }),
+            Some(ConfigError::InvalidProbability { .. })
+        ));
+        assert!(matches!(
+            run_branches(
+                &base,
+                3,
+                &[
+                    base.clone(),
+                    SimulationParams {
+                        xdim: 4,
+                        ..base.clone()
+                    }
+                ]
+            ),
+            Err(ConfigError::SetupOnly { name: "xdim" })
+        ));
+    }
+}
@@ -0,0 +1,319 @@
+//! Infections and deaths averted by an intervention, relative to a baseline scenario.
+//!
+//! Replicates of the two scenarios are compared one to one. Only when each pair of
+//! replicates was run from the same seed, i.e. under common random numbers, does the
+//! comparison count as paired, which narrows its interval. Otherwise the replicates are
+//! treated as independent samples of the two scenarios.
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::AtomicBool;
+
+use crate::ensemble;
+use crate::params::SimulationParams;
+use crate::result::RunResult;
+
+/// How the replicates of the two scenarios relate to each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Pairing {
+    /// Each pair of replicates was run from the same seed
+    CommonRandomNumbers,
+    /// The replicates were run independently
+    Independent,
+}
+
+/// Mean of a per-replicate difference, with a normal 95% interval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AvertedEstimate {
+    pub mean: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+/// Comparison of an intervention scenario against a baseline, see [`compare`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AvertedCases {
+    pub pairing: Pairing,
+    /// Infections averted in each pair of replicates, being the difference in the number of
+    /// agents that were ever infected
+    pub infections: Vec<f64>,
+    /// Deaths averted in each pair of replicates
+    pub deaths: Vec<f64>,
+    pub mean_infections: AvertedEstimate,
+    pub mean_deaths: AvertedEstimate,
+    /// Cumulative infections averted by each tick, for each pair of replicates, where runs
+    /// that ended early are padded with their final tally
+    pub cumulative: Vec<Vec<f64>>,
+    /// Mean of `cumulative` across the pairs of replicates
+    pub mean_cumulative: Vec<f64>,
+}
+
+/// Number of agents that were ever infected by each tick of `run`, taken from its
+/// [summary](RunResult::summary)
+fn cumulative_infections(run: &RunResult) -> Vec<f64> {
+    run.summary()
+        .cumulative_infections
+        .iter()
+        .map(|&infections| infections as f64)
+        .collect()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Sample variance, zero for fewer than two values
+fn variance(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let mean = mean(values);
+    values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / (values.len() - 1) as f64
+}
+
+/// Estimate of the mean difference of `baseline` and `intervention`
+fn estimate(pairing: Pairing, baseline: &[f64], intervention: &[f64]) -> AvertedEstimate {
+    let replicates = baseline.len() as f64;
+    let differences: Vec<f64> = baseline
+        .iter()
+        .zip(intervention)
+        .map(|(b, i)| b - i)
+        .collect();
+    let variance = match pairing {
+        Pairing::CommonRandomNumbers => variance(&differences),
+        Pairing::Independent => variance(baseline) + variance(intervention),
+    };
+    let mean = mean(&differences);
+    let half_width = 1.96 * (variance / replicates).sqrt();
+    AvertedEstimate {
+        mean,
+        lower: mean - half_width,
+        upper: mean + half_width,
+    }
+}
+
+/// Compare the replicates of an `intervention` to those of a `baseline`, pairing them in
+/// order.
+///
+/// # Panics
+///
+/// If there are no replicates, or not as many of the intervention as of the baseline.
+#[must_use]
+pub fn compare(baseline: &[RunResult], intervention: &[RunResult]) -> AvertedCases {
+    assert!(!baseline.is_empty(), "no replicates to compare");
+    assert_eq!(
+        baseline.len(),
+        intervention.len(),
+        "replicates must be paired"
+    );
+    let seeded = baseline
+        .iter()
+        .zip(intervention)
+        .all(|(b, i)| b.metadata.seed.is_some() && b.metadata.seed == i.metadata.seed);
+    let pairing = if seeded {
+        Pairing::CommonRandomNumbers
+    } else {
+        Pairing::Independent
+    };
+
+    let last = |series: &[f64]| *series.last().expect("a run has at least one tick");
+    let infections = |runs: &[RunResult]| -> Vec<f64> {
+        runs.iter()
+            .map(|run| last(&cumulative_infections(run)))
+            .collect()
+    };
+    let deaths = |runs: &[RunResult]| -> Vec<f64> {
+        runs.iter().map(|run| run.summary().deaths as f64).collect()
+    };
+    let (baseline_infections, intervention_infections) =
+        (infections(baseline), infections(intervention));
+    let (baseline_deaths, intervention_deaths) = (deaths(baseline), deaths(intervention));
+
+    let cumulative: Vec<Vec<f64>> = baseline
+        .iter()
+        .zip(intervention)
+        .map(|(b, i)| {
+            let (b, i) = (cumulative_infections(b), cumulative_infections(i));
+            (0..b.len().max(i.len()))
+                .map(|tick| b[tick.min(b.len() - 1)] - i[tick.min(i.len() - 1)])
+                .collect()
+        })
+        .collect();
+    let ticks = cumulative.iter().map(Vec::len).max().unwrap_or(0);
+    let mean_cumulative = (0..ticks)
+        .map(|tick| {
+            let at: Vec<f64> = cumulative
+                .iter()
+                .map(|series| series[tick.min(series.len() - 1)])
+                .collect();
+            mean(&at)
+        })
+        .collect();
+
+    AvertedCases {
+        pairing,
+        infections: baseline_infections
+            .iter()
+            .zip(&intervention_infections)
+            .map(|(b, i)| b - i)
+            .collect(),
+        deaths: baseline_deaths
+            .iter()
+            .zip(&intervention_deaths)
+            .map(|(b, i)| b - i)
+            .collect(),
+        mean_infections: estimate(pairing, &baseline_infections, &intervention_infections),
+        mean_deaths: estimate(pairing, &baseline_deaths, &intervention_deaths),
+        cumulative,
+        mean_cumulative,
+    }
+}
+
+/// Run `replicates` replicates of both scenarios, and [`compare`] them.
+///
+/// When both scenarios have the same [seed](SimulationParams::seed), each pair of replicates
+/// runs from the same [replicate seed](ensemble::replicate_seed), and the comparison is
+/// [`Pairing::CommonRandomNumbers`]. Otherwise it is [`Pairing::Independent`].
+#[must_use]
+pub fn run_comparison(
+    baseline: &SimulationParams,
+    intervention: &SimulationParams,
+    replicates: usize,
+) -> AvertedCases {
+    let cancel = AtomicBool::new(false);
+    let run = |params| -> Vec<RunResult> {
+        ensemble::run_replicates(params, replicates, &cancel)
+            .into_iter()
+            .flatten()
+            .collect()
+    };
+    compare(&run(baseline), &run(intervention))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::EnvironmentConfig;
+    use crate::presets;
+
+    #[test]
+    fn test_scenario_against_itself() {
+        let runs: Vec<RunResult> =
+            ensemble::run_replicates(&presets::dense_small_grid(), 3, &AtomicBool::new(false))
+                .into_iter()
+                .flatten()
+                .collect();
+        let averted = compare(&runs, &runs);
+        // every run records its seed, such that a run is paired with itself
+        assert_eq!(averted.pairing, Pairing::CommonRandomNumbers);
+        assert_eq!(averted.infections, vec![0.0; 3]);
+        assert_eq!(averted.deaths, vec![0.0; 3]);
+        assert_eq!(averted.mean_infections.mean, 0.0);
+        assert!(averted
+            .cumulative
+            .iter()
+            .all(|series| series.iter().all(|&value| value == 0.0)));
+    }
+
+    #[test]
+    fn test_isolation_averts_infections() {
+        let baseline = presets::dense_small_grid();
+        let (intervention, _) = EnvironmentConfig::from(baseline.clone())
+            .detection(1.0)
+            .isolation()
+            .build_params()
+            .unwrap();
+        let averted = run_comparison(&baseline, &intervention, 4);
+        assert_eq!(averted.infections.len(), 4);
+        assert!(averted.mean_infections.mean > 0.0);
+        assert!(averted.mean_infections.lower <= averted.mean_infections.mean);
+        // the cumulative series end at the total differences
+        for (series, &total) in averted.cumulative.iter().zip(&averted.infections) {
+            assert_eq!(*series.last().unwrap(), total);
+        }
+        assert_eq!(
+            averted.mean_cumulative.len(),
+            averted.cumulative.iter().map(Vec::len).max().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_vaccination_averts_infections() {
+        let baseline = SimulationParams {
+            seed: Some(5),
+            ..presets::dense_small_grid()
+        };
+        let (intervention, _) = EnvironmentConfig::from(baseline.clone())
+            .vaccinate_at(0, 0.5)
+            .build_params()
+            .unwrap();
+        let averted = run_comparison(&baseline, &intervention, 4);
+        assert_eq!(averted.pairing, Pairing::CommonRandomNumbers);
+        assert!(averted.mean_infections.mean > 0.0);
+        for (series, &total) in averted.cumulative.iter().zip(&averted.infections) {
+            assert_eq!(*series.last().unwrap(), total);
+        }
+    }
+
+    #[test]
+    fn test_common_random_numbers() {
+        let baseline = SimulationParams {
+            seed: Some(3),
+            xdim: 40,
+            ydim: 40,
+            ..presets::dense_small_grid()
+        };
+        let (intervention, _) = EnvironmentConfig::from(baseline.clone())
+            .detection(0.1)
+            .isolation()
+            .build_params()
+            .unwrap();
+        let paired = run_comparison(&baseline, &intervention, 6);
+        assert_eq!(paired.pairing, Pairing::CommonRandomNumbers);
+        assert_eq!(paired, run_comparison(&baseline, &intervention, 6));
+
+        // the agents of a pair of replicates move alike, such that the same replicates compared
+        // as independent runs have a wider interval
+        let cancel = AtomicBool::new(false);
+        let runs = |params| -> Vec<RunResult> {
+            ensemble::run_replicates(params, 6, &cancel)
+                .into_iter()
+                .flatten()
+                .map(|mut run| {
+                    run.metadata.seed = None;
+                    run
+                })
+                .collect()
+        };
+        let independent = compare(&runs(&baseline), &runs(&intervention));
+        assert_eq!(independent.pairing, Pairing::Independent);
+        assert_eq!(independent.infections, paired.infections);
+        let width = |estimate: &AvertedEstimate| estimate.upper - estimate.lower;
+        assert!(width(&paired.mean_infections) < width(&independent.mean_infections));
+
+        let unseeded = |params: &SimulationParams| SimulationParams {
+            seed: None,
+            ..params.clone()
+        };
+        let independent = run_comparison(&unseeded(&baseline), &unseeded(&intervention), 2);
+        assert_eq!(independent.pairing, Pairing::Independent);
+    }
+
+    #[test]
+    fn test_pairing_requires_common_seeds() {
+        let mut runs: Vec<RunResult> =
+            ensemble::run_replicates(&presets::dense_small_grid(), 2, &AtomicBool::new(false))
+                .into_iter()
+                .flatten()
+                .collect();
+        for (seed, run) in runs.iter_mut().enumerate() {
+            run.metadata.seed = Some(seed as u64);
+        }
+        assert_eq!(compare(&runs, &runs).pairing, Pairing::CommonRandomNumbers);
+        let mut shifted = runs.clone();
+        shifted[1].metadata.seed = Some(7);
+        assert_eq!(compare(&runs, &shifted).pairing, Pairing::Independent);
+    }
+}
@@ -0,0 +1,217 @@
+//! Conversions of run results into [polars](https://pola.rs) data frames.
+//!
+//! All counts and indices are stored as `u64` columns, and states as string columns named like
+//! the fields of [`TallyStates`](crate::julia_reimpl::TallyStates).
+//!
+//! Peak number of infected agents per replicate:
+//!
+//! ```
+//! use bkamins_sir_abm::{dataframe, presets, result};
+//! use polars::prelude::*;
+//!
+//! let runs: Vec<_> = (0..3)
+//!     .map(|_| result::run(&presets::dense_small_grid()))
+//!     .collect();
+//! let peaks = dataframe::runs_to_dataframe(&runs)?
+//!     .lazy()
+//!     .filter(col("state").eq(lit("infected")))
+//!     .group_by([col("run_id")])
+//!     .agg([col("count").max().alias("peak_infected")])
+//!     .collect()?;
+//! assert_eq!(peaks.height(), 3);
+//! # Ok::<(), PolarsError>(())
+//! ```
+use polars::prelude::*;
+
+use crate::julia_reimpl::{SeedingRecord, TallyStatesVec};
+use crate::result::RunResult;
+
+/// Names of the states, in the order of the tally columns
+pub const STATE_NAMES: [&str; 7] = [
+    "susceptible",
+    "exposed",
+    "infected",
+    "quarantined",
+    "recovered",
+    "vaccinated",
+    "dead",
+];
+
+fn to_u64(values: &[usize]) -> Vec<u64> {
+    values.iter().map(|&value| value as u64).collect()
+}
+
+impl TallyStatesVec {
+    /// Tally record in wide format, with columns `tick`, `susceptible`, `exposed`, `infected`,
+    /// `quarantined`, `recovered`, `vaccinated` and `dead`
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        let ticks: Vec<u64> = (0..self.len() as u64).collect();
+        DataFrame::new(vec![
+            Column::new("tick".into(), ticks),
+            Column::new("susceptible".into(), to_u64(&self.susceptible)),
+            Column::new("exposed".into(), to_u64(&self.exposed)),
+            Column::new("infected".into(), to_u64(&self.infected)),
+            Column::new("quarantined".into(), to_u64(&self.quarantined)),
+            Column::new("recovered".into(), to_u64(&self.recovered)),
+            Column::new("vaccinated".into(), to_u64(&self.vaccinated)),
+            Column::new("dead".into(), to_u64(&self.dead)),
+        ])
+    }
+}
+
+/// Tally records of several runs in long format, with one row per run, tick and state in
+/// the columns `run_id`, `tick`, `state` and `count`
+pub fn runs_to_dataframe(runs: &[RunResult]) -> PolarsResult<DataFrame> {
+    let rows: usize = runs
+        .iter()
+        .map(|run| run.record.len() * STATE_NAMES.len())
+        .sum();
+    let mut run_ids = Vec::with_capacity(rows);
+    let mut ticks = Vec::with_capacity(rows);
+    let mut states = Vec::with_capacity(rows);
+    let mut counts = Vec::with_capacity(rows);
+    for (run_id, run) in runs.iter().enumerate() {
+        let record = &run.record;
+        let columns = [
+            &record.susceptible,
+            &record.exposed,
+            &record.infected,
+            &record.quarantined,
+            &record.recovered,
+            &record.vaccinated,
+            &record.dead,
+        ];
+        for tick in 0..record.len() {
+            for (state, column) in STATE_NAMES.iter().zip(columns.iter()) {
+                run_ids.push(run_id as u64);
+                ticks.push(tick as u64);
+                states.push(*state);
+                counts.push(column[tick] as u64);
+            }
+        }
+    }
+    DataFrame::new(vec![
+        Column::new("run_id".into(), run_ids),
+        Column::new("tick".into(), ticks),
+        Column::new("state".into(), states),
+        Column::new("count".into(), counts),
+    ])
+}
+
+/// Line list of seeded infections, with columns `entry`, `tick`, `agent`, `x` and `y`
+pub fn seeding_log_to_dataframe(log: &[SeedingRecord]) -> PolarsResult<DataFrame> {
+    let column = |name: &str, value: fn(&SeedingRecord) -> usize| {
+        let values: Vec<u64> = log.iter().map(|record| value(record) as u64).collect();
+        Column::new(name.into(), values)
+    };
+    DataFrame::new(vec![
+        column("entry", |record| record.entry),
+        column("tick", |record| record.tick),
+        column("agent", |record| record.agent),
+        column("x", |record| record.x),
+        column("y", |record| record.y),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::{EnvironmentConfig, SeedLocation};
+    use crate::presets;
+    use crate::result;
+
+    #[test]
+    fn test_tally_schema() {
+        let run = result::run(&presets::dense_small_grid());
+        let df = run.record.to_dataframe().unwrap();
+        assert_eq!(
+            df.get_column_names(),
+            &[
+                "tick",
+                "susceptible",
+                "exposed",
+                "infected",
+                "quarantined",
+                "recovered",
+                "vaccinated",
+                "dead"
+            ]
+        );
+        assert!(df.dtypes().iter().all(|dtype| *dtype == DataType::UInt64));
+        assert_eq!(df.height(), run.record.len());
+
+        let infected: Vec<u64> = df
+            .column("infected")
+            .unwrap()
+            .u64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(infected, to_u64(&run.record.infected));
+    }
+
+    #[test]
+    fn test_peak_infected_per_run() {
+        let runs: Vec<_> = (0..3)
+            .map(|_| result::run(&presets::dense_small_grid()))
+            .collect();
+        let df = runs_to_dataframe(&runs).unwrap();
+        assert_eq!(df.get_column_names(), &["run_id", "tick", "state", "count"]);
+        assert_eq!(
+            df.dtypes(),
+            &[
+                DataType::UInt64,
+                DataType::UInt64,
+                DataType::String,
+                DataType::UInt64
+            ]
+        );
+        let rows: usize = runs
+            .iter()
+            .map(|run| run.record.len() * STATE_NAMES.len())
+            .sum();
+        assert_eq!(df.height(), rows);
+
+        let peaks = df
+            .lazy()
+            .filter(col("state").eq(lit("infected")))
+            .group_by([col("run_id")])
+            .agg([col("count").max()])
+            .sort(["run_id"], Default::default())
+            .collect()
+            .unwrap();
+        let peaks: Vec<u64> = peaks
+            .column("count")
+            .unwrap()
+            .u64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        let expected: Vec<u64> = runs
+            .iter()
+            .map(|run| *run.record.infected.iter().max().unwrap() as u64)
+            .collect();
+        assert_eq!(peaks, expected);
+    }
+
+    #[test]
+    fn test_seeding_line_list() {
+        let (params, _) = EnvironmentConfig::from(presets::dense_small_grid())
+            .seed_at(2, 5, SeedLocation::Anywhere)
+            .build_params()
+            .unwrap();
+        let run = result::run(&params);
+        let df = seeding_log_to_dataframe(&run.seeding_log).unwrap();
+        assert_eq!(df.get_column_names(), &["entry", "tick", "agent", "x", "y"]);
+        assert_eq!(df.height(), run.seeding_log.len());
+        let agents: Vec<u64> = df
+            .column("agent")
+            .unwrap()
+            .u64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        let expected: Vec<u64> = run.seeding_log.iter().map(|r| r.agent as u64).collect();
+        assert_eq!(agents, expected);
+    }
+}
@@ -0,0 +1,670 @@
+//! Ensembles of independent replicates of the same scenario, run in parallel.
+//!
+//! Curves of an ensemble are aligned by tick, where runs that ended early are padded with
+//! their final tally. The fraction infected across a range of durations, as in the blogpost,
+//! is a [sweep](crate::sweep::sweep) of such ensembles.
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::julia_reimpl::{AgentType, TallyStates};
+use crate::params::{ConfigError, EnvironmentConfig, SimulationParams};
+use crate::result::{self, RunMetadata, RunResult, TerminationReason};
+
+/// Quantiles of the number of agents in each state reported by [`EnsembleSummary`]
+pub const QUANTILES: [f64; 5] = [0.05, 0.25, 0.5, 0.75, 0.95];
+
+/// Run `replicates` replicates of `params` on all available cores.
+///
+/// With `params.seed`, the replicates are seeded by [`replicate_seed`], such that the ensemble
+/// is reproducible regardless of the number of cores. Once `cancel` is set, no further
+/// replicates are started and those not run are `None`, while replicates already running are
+/// completed.
+#[must_use]
+pub fn run_replicates(
+    params: &SimulationParams,
+    replicates: usize,
+    cancel: &AtomicBool,
+) -> Vec<Option<RunResult>> {
+    run_replicates_with_budget(params, replicates, cancel, None)
+}
+
+/// Like [`run_replicates`], within an overall `budget` of wall-clock time, if any.
+///
+/// Once the budget is exhausted, no further replicates are started, and replicates that are
+/// still running stop, such that only the completed replicates are `Some`.
+#[must_use]
+pub fn run_replicates_with_budget(
+    params: &SimulationParams,
+    replicates: usize,
+    cancel: &AtomicBool,
+    budget: Option<Duration>,
+) -> Vec<Option<RunResult>> {
+    run_replicates_with(params, replicates, cancel, budget, |_, _| {})
+}
+
+/// Like [`run_replicates_with_budget`], calling `on_finish` with the index and result of each
+/// replicate as soon as it completes, e.g. to write it out as `SqliteSink::write_replicates`
+/// does under the `sqlite` feature.
+///
+/// `on_finish` is called on the calling thread, in the order in which the replicates finish.
+pub fn run_replicates_with(
+    params: &SimulationParams,
+    replicates: usize,
+    cancel: &AtomicBool,
+    budget: Option<Duration>,
+    on_finish: impl FnMut(usize, &RunResult),
+) -> Vec<Option<RunResult>> {
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(replicates);
+    let deadline = budget.map(|budget| Instant::now() + budget);
+    dispatch(
+        replicates,
+        threads,
+        cancel,
+        deadline,
+        |replicate, deadline| {
+            result::run_until(&replicate_params(params, replicate), deadline, |_, _| {})
+        },
+        on_finish,
+    )
+}
+
+/// Tally records of `replicates` replicates of the scenario of `config`, run in parallel as by
+/// [`run_replicates`] and summarised by [`moments`].
+///
+/// Fails without running anything if `config` is invalid.
+pub fn run_ensemble(
+    config: &EnvironmentConfig,
+    replicates: usize,
+) -> Result<Vec<Vec<TallyStates>>, ConfigError> {
+    let (params, _) = config.clone().build_params()?;
+    Ok(run_replicates(&params, replicates, &AtomicBool::new(false))
+        .into_iter()
+        .map(|run| run.expect("replicates are not cancelled").records())
+        .collect())
+}
+
+/// Seed of replicate `replicate` of an ensemble with `seed`.
+///
+/// The seed and the index are mixed by SplitMix64, such that ensembles of different seeds,
+/// e.g. of neighbouring seeds, share no replicate seeds in practice.
+#[must_use]
+pub fn replicate_seed(seed: u64, replicate: usize) -> u64 {
+    splitmix64(splitmix64(seed) ^ replicate as u64)
+}
+
+/// Finalizer of the SplitMix64 generator, a bijection of `u64` that scrambles all bits
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Parameters of replicate `replicate` of an ensemble of `params`
+pub(crate) fn replicate_params(params: &SimulationParams, replicate: usize) -> SimulationParams {
+    SimulationParams {
+        seed: params.seed.map(|seed| replicate_seed(seed, replicate)),
+        ..params.clone()
+    }
+}
+
+/// Run `replicates` runs of `run` on `threads` threads, where the replicates of each thread
+/// are run in increasing order, passing each completed run to `on_finish` on the calling
+/// thread, see [`run_replicates_with`]
+pub(crate) fn dispatch(
+    replicates: usize,
+    threads: usize,
+    cancel: &AtomicBool,
+    deadline: Option<Instant>,
+    run: impl Fn(usize, Option<Instant>) -> RunResult + Sync,
+    mut on_finish: impl FnMut(usize, &RunResult),
+) -> Vec<Option<RunResult>> {
+    let mut runs: Vec<Option<RunResult>> = (0..replicates).map(|_| None).collect();
+    let run = &run;
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|worker| {
+                let sender = sender.clone();
+                scope.spawn(move || {
+                    for replicate in (worker..replicates).step_by(threads) {
+                        let expired = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+                        if cancel.load(Ordering::Relaxed) || expired {
+                            break;
+                        }
+                        let result = run(replicate, deadline);
+                        if result.metadata.termination == TerminationReason::TimedOut {
+                            break;
+                        }
+                        if sender.send((replicate, result)).is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+        // the receiver ends once every worker has finished and dropped its sender
+        drop(sender);
+        for (replicate, run) in receiver {
+            on_finish(replicate, &run);
+            runs[replicate] = Some(run);
+        }
+        for worker in workers {
+            worker.join().expect("replicate panicked");
+        }
+    });
+    runs
+}
+
+/// Per-tick values of a curve for each state
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateCurves {
+    pub susceptible: Vec<f64>,
+    #[serde(default)]
+    pub exposed: Vec<f64>,
+    pub infected: Vec<f64>,
+    #[serde(default)]
+    pub quarantined: Vec<f64>,
+    pub recovered: Vec<f64>,
+    #[serde(default)]
+    pub vaccinated: Vec<f64>,
+    pub dead: Vec<f64>,
+}
+
+impl StateCurves {
+    /// Curve of the agents in `state`
+    #[must_use]
+    pub fn state(&self, state: &AgentType) -> &[f64] {
+        match state {
+            AgentType::AgentS => &self.susceptible,
+            AgentType::AgentE => &self.exposed,
+            AgentType::AgentI => &self.infected,
+            AgentType::AgentQ => &self.quarantined,
+            AgentType::AgentR => &self.recovered,
+            AgentType::AgentV => &self.vaccinated,
+            AgentType::AgentD => &self.dead,
+        }
+    }
+
+    fn state_mut(&mut self, state: &AgentType) -> &mut Vec<f64> {
+        match state {
+            AgentType::AgentS => &mut self.susceptible,
+            AgentType::AgentE => &mut self.exposed,
+            AgentType::AgentI => &mut self.infected,
+            AgentType::AgentQ => &mut self.quarantined,
+            AgentType::AgentR => &mut self.recovered,
+            AgentType::AgentV => &mut self.vaccinated,
+            AgentType::AgentD => &mut self.dead,
+        }
+    }
+}
+
+/// Mean and sample standard deviation of the number of agents in each state per tick across
+/// `records`, where records that ended early are padded with their final tally
+#[must_use]
+pub fn moments(records: &[Vec<TallyStates>]) -> (StateCurves, StateCurves) {
+    let ticks = records.iter().map(Vec::len).max().unwrap_or(0);
+    let mut mean = StateCurves::default();
+    let mut sd = StateCurves::default();
+    for (state, count) in [
+        (
+            AgentType::AgentS,
+            (|tally| tally.susceptible) as fn(&TallyStates) -> usize,
+        ),
+        (AgentType::AgentE, |tally| tally.exposed),
+        (AgentType::AgentI, |tally| tally.infected),
+        (AgentType::AgentQ, |tally| tally.quarantined),
+        (AgentType::AgentR, |tally| tally.recovered),
+        (AgentType::AgentV, |tally| tally.vaccinated),
+        (AgentType::AgentD, |tally| tally.dead),
+    ]
+    .iter()
+    {
+        for tick in 0..ticks {
+            let counts: Vec<f64> = records
+                .iter()
+                .map(|record| count(&record[tick.min(record.len() - 1)]) as f64)
+                .collect();
+            let m = counts.iter().sum::<f64>() / counts.len() as f64;
+            let squares: f64 = counts.iter().map(|count| (count - m).powi(2)).sum();
+            mean.state_mut(state).push(m);
+            sd.state_mut(state).push(if counts.len() > 1 {
+                (squares / (counts.len() - 1) as f64).sqrt()
+            } else {
+                0.0
+            });
+        }
+    }
+    (mean, sd)
+}
+
+/// Per-tick quantile of the number of agents in each state across the replicates
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuantileCurve {
+    pub quantile: f64,
+    #[serde(flatten)]
+    pub curves: StateCurves,
+}
+
+/// Aggregate statistics of the completed replicates of an ensemble
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnsembleSummary {
+    /// Number of replicates requested
+    pub replicates: usize,
+    /// Indices of the replicates that were completed
+    pub completed: Vec<usize>,
+    /// Metadata of each completed replicate, in the order of `completed`
+    pub metadata: Vec<RunMetadata>,
+    /// Mean number of agents in each state per tick
+    pub mean: StateCurves,
+    /// Sample standard deviation of the number of agents in each state per tick
+    pub sd: StateCurves,
+    /// Quantiles at [`QUANTILES`] of the number of agents in each state per tick
+    pub quantiles: Vec<QuantileCurve>,
+    /// Number of agents that were infected by the end of each completed replicate, being
+    /// neither susceptible nor vaccinated, as in its [summary](RunResult::summary)
+    pub final_size: Vec<usize>,
+}
+
+/// Value at `quantile` of the sorted `values`, interpolating linearly between ranks
+fn quantile(sorted: &[f64], quantile: f64) -> f64 {
+    let rank = quantile * (sorted.len() - 1) as f64;
+    let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[below] + (sorted[above] - sorted[below]) * (rank - below as f64)
+}
+
+impl EnsembleSummary {
+    /// Summarise the completed replicates of `runs`, as returned by [`run_replicates`]
+    #[must_use]
+    pub fn new(runs: &[Option<RunResult>]) -> Self {
+        let completed: Vec<usize> = (0..runs.len()).filter(|&i| runs[i].is_some()).collect();
+        let done: Vec<&RunResult> = runs.iter().flatten().collect();
+        let ticks = done.iter().map(|run| run.record.len()).max().unwrap_or(0);
+
+        // value of `column` at `tick`, padded with the final value
+        let at = |column: &Vec<usize>, tick: usize| column[tick.min(column.len() - 1)] as f64;
+        let (mean, sd) = moments(&done.iter().map(|run| run.records()).collect::<Vec<_>>());
+        let mut quantiles: Vec<QuantileCurve> = QUANTILES
+            .iter()
+            .map(|&q| QuantileCurve {
+                quantile: q,
+                curves: StateCurves::default(),
+            })
+            .collect();
+        for (state, column) in [
+            (
+                AgentType::AgentS,
+                (|run| &run.record.susceptible) as fn(&RunResult) -> &Vec<usize>,
+            ),
+            (AgentType::AgentE, |run| &run.record.exposed),
+            (AgentType::AgentI, |run| &run.record.infected),
+            (AgentType::AgentQ, |run| &run.record.quarantined),
+            (AgentType::AgentR, |run| &run.record.recovered),
+            (AgentType::AgentV, |run| &run.record.vaccinated),
+            (AgentType::AgentD, |run| &run.record.dead),
+        ]
+        .iter()
+        {
+            for tick in 0..ticks {
+                let mut counts: Vec<f64> = done.iter().map(|run| at(column(run), tick)).collect();
+                counts.sort_by(|a, b| a.partial_cmp(b).expect("counts are finite"));
+                for curve in &mut quantiles {
+                    let value = quantile(&counts, curve.quantile);
+                    curve.curves.state_mut(state).push(value);
+                }
+            }
+        }
+
+        Self {
+            replicates: runs.len(),
+            completed,
+            metadata: done.iter().map(|run| run.metadata.clone()).collect(),
+            mean,
+            sd,
+            quantiles,
+            final_size: done
+                .iter()
+                .map(|run| {
+                    let summary = run.summary();
+                    summary.cumulative_infections.last().copied().unwrap_or(0)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Write the tally records of `runs` in long format, with the columns `replicate`, `tick`,
+/// `state` and `count`.
+///
+/// The header is preceded by comments with the metadata of the first run, see
+/// [`RunMetadata::write_comments`](crate::result::RunMetadata::write_comments), and the seed
+/// of each further seeded replicate.
+pub fn write_long_csv<'a, W: io::Write>(
+    mut writer: W,
+    runs: impl IntoIterator<Item = (usize, &'a RunResult)>,
+) -> io::Result<()> {
+    let runs: Vec<_> = runs.into_iter().collect();
+    if let Some((_, first)) = runs.first() {
+        first.metadata.write_comments(&mut writer)?;
+        for (replicate, run) in &runs[1..] {
+            if let Some(seed) = run.metadata.seed {
+                writeln!(writer, "# seed of replicate {}: {}", replicate, seed)?;
+            }
+        }
+    }
+    writeln!(writer, "replicate,tick,state,count")?;
+    for (replicate, run) in runs {
+        write_rows(&mut writer, run, |tick, state, count| {
+            format!("{},{},{},{}", replicate, tick, state, count)
+        })?;
+    }
+    Ok(())
+}
+
+/// Write the tally record of `run` in long format, with the columns `tick`, `state` and
+/// `count`, after comments with its metadata
+pub fn write_replicate_csv<W: io::Write>(mut writer: W, run: &RunResult) -> io::Result<()> {
+    run.metadata.write_comments(&mut writer)?;
+    writeln!(writer, "tick,state,count")?;
+    write_rows(&mut writer, run, |tick, state, count| {
+        format!("{},{},{}", tick, state, count)
+    })
+}
+
+fn write_rows<W: io::Write>(
+    writer: &mut W,
+    run: &RunResult,
+    row: impl Fn(usize, &str, usize) -> String,
+) -> io::Result<()> {
+    let record = &run.record;
+    for tick in 0..record.len() {
+        for (state, column) in [
+            ("susceptible", &record.susceptible),
+            ("exposed", &record.exposed),
+            ("infected", &record.infected),
+            ("quarantined", &record.quarantined),
+            ("recovered", &record.recovered),
+            ("vaccinated", &record.vaccinated),
+            ("dead", &record.dead),
+        ]
+        .iter()
+        {
+            writeln!(writer, "{}", row(tick, state, column[tick]))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_summary() {
+        let runs = run_replicates(&presets::dense_small_grid(), 4, &AtomicBool::new(false));
+        assert!(runs.iter().all(Option::is_some));
+        let summary = EnsembleSummary::new(&runs);
+        assert_eq!(summary.completed, vec![0, 1, 2, 3]);
+        assert_eq!(summary.metadata.len(), 4);
+        assert_eq!(summary.final_size.len(), 4);
+
+        let longest = runs.iter().flatten().map(|r| r.record.len()).max().unwrap();
+        assert_eq!(summary.mean.infected.len(), longest);
+        for tick in 0..longest {
+            let total = summary.mean.susceptible[tick]
+                + summary.mean.infected[tick]
+                + summary.mean.recovered[tick]
+                + summary.mean.dead[tick];
+            assert!((total - 500.0).abs() < 1e-9);
+            for state in &[AgentType::AgentS, AgentType::AgentI, AgentType::AgentD] {
+                let values: Vec<f64> = summary
+                    .quantiles
+                    .iter()
+                    .map(|curve| curve.curves.state(state)[tick])
+                    .collect();
+                assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+            }
+        }
+        // padded with the final tally, where no one is infected
+        assert_eq!(*summary.mean.infected.last().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_moments() {
+        let tally = |susceptible, infected, recovered| TallyStates {
+            susceptible,
+            exposed: 0,
+            infected,
+            quarantined: 0,
+            recovered,
+            vaccinated: 0,
+            dead: 0,
+        };
+        let records = vec![
+            vec![tally(3, 1, 0), tally(2, 1, 1), tally(2, 0, 2)],
+            vec![tally(3, 1, 0), tally(3, 0, 1)],
+        ];
+        let (mean, sd) = moments(&records);
+        assert_eq!(mean.susceptible, vec![3.0, 2.5, 2.5]);
+        assert_eq!(mean.infected, vec![1.0, 0.5, 0.0]);
+        assert_eq!(mean.recovered, vec![0.0, 1.0, 1.5]);
+        assert_eq!(mean.dead, vec![0.0; 3]);
+        let half = 0.5f64.sqrt();
+        assert_eq!(sd.susceptible, vec![0.0, half, half]);
+        assert_eq!(sd.infected, vec![0.0, half, 0.0]);
+        assert_eq!(sd.recovered, vec![0.0, 0.0, half]);
+
+        let (mean, sd) = moments(&records[..1]);
+        assert_eq!(mean.susceptible, vec![3.0, 2.0, 2.0]);
+        assert_eq!(sd.susceptible, vec![0.0; 3]);
+        assert_eq!(moments(&[]).0, StateCurves::default());
+    }
+
+    #[test]
+    fn test_run_ensemble() {
+        let config = EnvironmentConfig::from(SimulationParams {
+            seed: Some(7),
+            ..presets::dense_small_grid()
+        });
+        let records = run_ensemble(&config, 4).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records, run_ensemble(&config, 4).unwrap());
+        // each replicate is seeded from the master seed
+        let second = result::run(&SimulationParams {
+            seed: Some(replicate_seed(7, 1)),
+            ..presets::dense_small_grid()
+        });
+        assert_eq!(records[1], second.records());
+
+        let (mean, sd) = moments(&records);
+        let summary = EnsembleSummary::new(&run_replicates(
+            &config.clone().build_params().unwrap().0,
+            4,
+            &AtomicBool::new(false),
+        ));
+        assert_eq!((mean, sd), (summary.mean, summary.sd));
+
+        assert!(matches!(
+            run_ensemble(&config.grid(0, 0), 4),
+            Err(ConfigError::EmptyGrid { .. })
+        ));
+    }
+
+    #[test]
+    fn test_seeded_replicates() {
+        let params = SimulationParams {
+            seed: Some(100),
+            ..presets::dense_small_grid()
+        };
+        let records = |runs: Vec<Option<RunResult>>| {
+            runs.into_iter()
+                .map(|run| run.unwrap().records())
+                .collect::<Vec<_>>()
+        };
+        let parallel = records(run_replicates(&params, 4, &AtomicBool::new(false)));
+        let serial = records(dispatch(
+            4,
+            1,
+            &AtomicBool::new(false),
+            None,
+            |i, _| result::run(&replicate_params(&params, i)),
+            |_, _| {},
+        ));
+        assert_eq!(parallel, serial);
+        assert_ne!(parallel[0], parallel[1]);
+        // each replicate is reproduced by a single run with its seed
+        let third = result::run(&SimulationParams {
+            seed: Some(replicate_seed(100, 2)),
+            ..params.clone()
+        });
+        assert_eq!(third.records(), parallel[2]);
+    }
+
+    #[test]
+    fn test_replicates_passed_on_as_they_finish() {
+        let params = SimulationParams {
+            seed: Some(5),
+            ..presets::dense_small_grid()
+        };
+        let mut finished = Vec::new();
+        let runs = run_replicates_with(&params, 5, &AtomicBool::new(false), None, |i, run| {
+            finished.push((i, run.records()))
+        });
+        finished.sort_by_key(|&(i, _)| i);
+        let expected: Vec<_> = runs
+            .into_iter()
+            .enumerate()
+            .map(|(i, run)| (i, run.unwrap().records()))
+            .collect();
+        assert_eq!(finished, expected);
+    }
+
+    #[test]
+    fn test_final_size_of_capped_runs() {
+        // the runs stop at their cap with agents still exposed or infected
+        let params = SimulationParams {
+            seed: Some(3),
+            max_ticks: Some(20),
+            incubation: 2,
+            ..presets::blog_default()
+        };
+        let runs = run_replicates(&params, 2, &AtomicBool::new(false));
+        let summary = EnsembleSummary::new(&runs);
+        for (run, &size) in runs.iter().flatten().zip(&summary.final_size) {
+            let last = run.records().pop().unwrap();
+            assert!(last.exposed + last.infected > 0, "{:?}", last);
+            assert_eq!(size, params.n - last.susceptible - last.vaccinated);
+            assert!(size > last.recovered + last.dead);
+        }
+    }
+
+    #[test]
+    fn test_neighbouring_seeds_share_no_replicates() {
+        let seeds = |seed| (0..1000).map(move |replicate| replicate_seed(seed, replicate));
+        for seed in (0..20).chain(u64::MAX - 20..u64::MAX) {
+            let next: HashSet<u64> = seeds(seed.wrapping_add(1)).collect();
+            assert!(
+                seeds(seed).all(|replicate| !next.contains(&replicate)),
+                "{}",
+                seed
+            );
+            assert_eq!(seeds(seed).collect::<HashSet<_>>().len(), 1000);
+        }
+    }
+
+    #[test]
+    fn test_cancelled_before_start() {
+        let runs = run_replicates(&presets::dense_small_grid(), 3, &AtomicBool::new(true));
+        assert!(runs.iter().all(Option::is_none));
+        let summary = EnsembleSummary::new(&runs);
+        assert!(summary.completed.is_empty());
+        assert!(summary.mean.infected.is_empty());
+        assert!(summary.quantiles[0].curves.infected.is_empty());
+    }
+
+    #[test]
+    fn test_budget_stops_dispatch() {
+        let params = presets::dense_small_grid();
+        let deadline = Instant::now() + Duration::from_millis(1000);
+        let runs = dispatch(
+            10,
+            1,
+            &AtomicBool::new(false),
+            deadline.into(),
+            |_, deadline| {
+                // a slow observer at the start of each run
+                result::run_until(&params, deadline, |_, tick| {
+                    if tick == 1 {
+                        std::thread::sleep(Duration::from_millis(300))
+                    }
+                })
+            },
+            |_, _| {},
+        );
+        // run serially, the completed replicates are those up to the deadline
+        let completed = EnsembleSummary::new(&runs).completed;
+        assert!(
+            !completed.is_empty() && completed.len() < 10,
+            "{:?}",
+            completed
+        );
+        assert_eq!(completed, (0..completed.len()).collect::<Vec<_>>());
+        assert!(runs
+            .iter()
+            .flatten()
+            .all(|run| run.metadata.termination == TerminationReason::Extinct));
+
+        let runs = run_replicates_with_budget(
+            &params,
+            4,
+            &AtomicBool::new(false),
+            Some(Duration::from_secs(0)),
+        );
+        assert!(runs.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn test_long_csv() {
+        let runs = run_replicates(&presets::dense_small_grid(), 2, &AtomicBool::new(false));
+        let mut csv = Vec::new();
+        write_long_csv(
+            &mut csv,
+            runs.iter()
+                .enumerate()
+                .filter_map(|(i, run)| run.as_ref().map(|run| (i, run))),
+        )
+        .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: usize = runs.iter().flatten().map(|r| r.record.len() * 7).sum();
+        let (comments, table): (Vec<_>, Vec<_>) =
+            csv.lines().partition(|line| line.starts_with('#'));
+        assert_eq!(table.len(), rows + 1);
+        assert_eq!(table[0], "replicate,tick,state,count");
+        assert!(table[1].starts_with("0,0,susceptible,"));
+
+        // the comments describe the run
+        let params = comments
+            .iter()
+            .find_map(|line| line.strip_prefix("# params: "))
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<crate::params::SimulationParams>(params).unwrap(),
+            presets::dense_small_grid()
+        );
+        assert!(comments.contains(&concat!("# crate_version: ", env!("CARGO_PKG_VERSION"))));
+
+        let mut csv = Vec::new();
+        write_replicate_csv(&mut csv, runs[0].as_ref().unwrap()).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("# crate_version: "));
+        assert!(csv.contains("\n# params: {"));
+        assert!(csv.contains("\ntick,state,count\n"));
+    }
+}
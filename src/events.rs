@@ -0,0 +1,581 @@
+//! Long-format log of the events of the agents, being one row per change of state of an agent
+//! and optionally one row per agent and tick of its location, see
+//! [`Environment::enable_event_recording`](crate::julia_reimpl::Environment::enable_event_recording).
+//!
+//! The tables have the following columns in this order, as CSV with a header row or, with the
+//! `arrow` feature, as Parquet files of non-nullable columns with the given Arrow types:
+//!
+//! | transitions  | type     |                                                        |
+//! |--------------|----------|--------------------------------------------------------|
+//! | `agent_id`   | `UInt64` | index of the agent in the environment                  |
+//! | `tick`       | `UInt64` | tick of the transition                                 |
+//! | `from_state` | `Utf8`   | state before the transition                            |
+//! | `to_state`   | `Utf8`   | state after the transition                             |
+//! | `x`          | `UInt64` | location of the agent at the transition in x-dimension |
+//! | `y`          | `UInt64` | location of the agent at the transition in y-dimension |
+//!
+//! | positions  | type     |                                                     |
+//! |------------|----------|-----------------------------------------------------|
+//! | `agent_id` | `UInt64` | index of the agent in the environment               |
+//! | `tick`     | `UInt64` | tick at whose end the agent was at the location     |
+//! | `x`        | `UInt64` | location of the agent in x-dimension                |
+//! | `y`        | `UInt64` | location of the agent in y-dimension                |
+//!
+//! where the states are `S`, `E`, `I`, `Q`, `R`, `V` and `D` as in a
+//! [layout](crate::layout). The transitions are in the order they happened and the positions
+//! ordered by tick, then agent.
+//!
+//! The rows are kept in memory until they are written, being about 40 bytes per transition and
+//! 32 bytes per position, such that the positions of the 2000 agents of
+//! [`presets::blog_default`](crate::presets::blog_default) take about 64 kB per tick. The
+//! Parquet writers convert them into Arrow arrays one row group of at most
+//! [`ROW_GROUP_ROWS`] rows at a time.
+use std::io::{self, Write};
+#[cfg(feature = "arrow")]
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::julia_reimpl::AgentType;
+use crate::layout::state_code;
+
+/// Maximum number of rows in a row group of a Parquet file
+pub const ROW_GROUP_ROWS: usize = 65_536;
+
+/// Change of state of an agent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transition {
+    pub agent_id: usize,
+    pub tick: usize,
+    pub from_state: AgentType,
+    pub to_state: AgentType,
+    /// Location of the agent at the transition
+    pub x: usize,
+    pub y: usize,
+}
+
+/// Location of an agent at the end of a tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    pub agent_id: usize,
+    pub tick: usize,
+    pub x: usize,
+    pub y: usize,
+}
+
+/// Transitions, and optionally positions, of the agents of an environment since recording was
+/// enabled, see the [module documentation](self) for the schema of the tables
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventRecorder {
+    transitions: Vec<Transition>,
+    /// Positions, if they are recorded
+    positions: Option<Vec<Position>>,
+}
+
+impl EventRecorder {
+    /// Recorder of the transitions, and of the positions if `positions` is set
+    #[must_use]
+    pub fn new(positions: bool) -> Self {
+        Self {
+            transitions: Vec::new(),
+            positions: if positions { Some(Vec::new()) } else { None },
+        }
+    }
+
+    /// Transitions in the order they happened
+    #[must_use]
+    pub fn transitions(&self) -> &[Transition] {
+        &self.transitions
+    }
+
+    /// Positions ordered by tick and agent, or an empty slice if they are not recorded
+    #[must_use]
+    pub fn positions(&self) -> &[Position] {
+        self.positions.as_deref().unwrap_or(&[])
+    }
+
+    /// Whether positions are recorded
+    #[must_use]
+    pub fn records_positions(&self) -> bool {
+        self.positions.is_some()
+    }
+
+    pub(crate) fn push_transition(&mut self, transition: Transition) {
+        self.transitions.push(transition);
+    }
+
+    /// Record the locations of `agents`, given by their index and location, at the end of
+    /// `tick`, if positions are recorded
+    pub(crate) fn push_positions(
+        &mut self,
+        tick: usize,
+        agents: impl Iterator<Item = (usize, (usize, usize))>,
+    ) {
+        if let Some(positions) = &mut self.positions {
+            positions.extend(agents.map(|(agent_id, (x, y))| Position {
+                agent_id,
+                tick,
+                x,
+                y,
+            }));
+        }
+    }
+
+    /// Estimate of the heap memory held by the rows in bytes
+    pub(crate) fn heap_bytes(&self) -> usize {
+        self.transitions.capacity() * std::mem::size_of::<Transition>()
+            + self.positions.as_ref().map_or(0, |positions| {
+                positions.capacity() * std::mem::size_of::<Position>()
+            })
+    }
+
+    /// Write the transitions as CSV
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "agent_id,tick,from_state,to_state,x,y")?;
+        for transition in &self.transitions {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                transition.agent_id,
+                transition.tick,
+                state_code(&transition.from_state),
+                state_code(&transition.to_state),
+                transition.x,
+                transition.y
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the positions as CSV, being only the header if they are not recorded
+    pub fn write_positions_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "agent_id,tick,x,y")?;
+        for position in self.positions() {
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                position.agent_id, position.tick, position.x, position.y
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "arrow")]
+mod parquet {
+    use std::fs::File;
+    use std::io;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use polars_arrow::array::{Array, PrimitiveArray, Utf8ViewArray};
+    use polars_arrow::datatypes::{ArrowDataType, ArrowSchema, Field};
+    use polars_arrow::record_batch::RecordBatchT;
+    use polars_parquet::write::{
+        CompressionOptions, Encoding, FileWriter, RowGroupIterator, StatisticsOptions, Version,
+        WriteOptions,
+    };
+
+    use super::{state_code, Position, Transition};
+
+    fn to_io_error(error: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, error)
+    }
+
+    /// Column of `rows` as `u64`
+    fn u64_column<T>(rows: &[T], value: impl Fn(&T) -> usize) -> Box<dyn Array> {
+        PrimitiveArray::from_vec(rows.iter().map(|row| value(row) as u64).collect()).boxed()
+    }
+
+    /// Table of rows of type `T` in a Parquet file
+    pub(super) trait Table: Sized {
+        /// Name and type of each column
+        const COLUMNS: &'static [(&'static str, ArrowDataType)];
+
+        /// Column of each of `COLUMNS` for `rows`
+        fn columns(rows: &[Self]) -> Vec<Box<dyn Array>>;
+    }
+
+    impl Table for Transition {
+        const COLUMNS: &'static [(&'static str, ArrowDataType)] = &[
+            ("agent_id", ArrowDataType::UInt64),
+            ("tick", ArrowDataType::UInt64),
+            ("from_state", ArrowDataType::Utf8View),
+            ("to_state", ArrowDataType::Utf8View),
+            ("x", ArrowDataType::UInt64),
+            ("y", ArrowDataType::UInt64),
+        ];
+
+        fn columns(rows: &[Self]) -> Vec<Box<dyn Array>> {
+            let states = |state: fn(&Transition) -> &_| {
+                Utf8ViewArray::from_slice_values(
+                    rows.iter()
+                        .map(|row| state_code(state(row)))
+                        .collect::<Vec<_>>(),
+                )
+                .boxed()
+            };
+            vec![
+                u64_column(rows, |row| row.agent_id),
+                u64_column(rows, |row| row.tick),
+                states(|row| &row.from_state),
+                states(|row| &row.to_state),
+                u64_column(rows, |row| row.x),
+                u64_column(rows, |row| row.y),
+            ]
+        }
+    }
+
+    impl Table for Position {
+        const COLUMNS: &'static [(&'static str, ArrowDataType)] = &[
+            ("agent_id", ArrowDataType::UInt64),
+            ("tick", ArrowDataType::UInt64),
+            ("x", ArrowDataType::UInt64),
+            ("y", ArrowDataType::UInt64),
+        ];
+
+        fn columns(rows: &[Self]) -> Vec<Box<dyn Array>> {
+            vec![
+                u64_column(rows, |row| row.agent_id),
+                u64_column(rows, |row| row.tick),
+                u64_column(rows, |row| row.x),
+                u64_column(rows, |row| row.y),
+            ]
+        }
+    }
+
+    /// Write `rows` to a Parquet file at `path` in row groups of at most `group_rows` rows,
+    /// building the arrays of one row group at a time
+    pub(super) fn write<T: Table>(rows: &[T], path: &Path, group_rows: usize) -> io::Result<()> {
+        let schema: ArrowSchema = T::COLUMNS
+            .iter()
+            .map(|(name, dtype)| Field::new((*name).into(), dtype.clone(), false))
+            .collect();
+        let schema = Arc::new(schema);
+        let options = WriteOptions {
+            statistics: StatisticsOptions::default(),
+            version: Version::V2,
+            compression: CompressionOptions::Uncompressed,
+            data_page_size: None,
+        };
+        let groups = rows
+            .chunks(group_rows)
+            .map(|rows| RecordBatchT::try_new(rows.len(), schema.clone(), T::columns(rows)));
+        let encodings = T::COLUMNS.iter().map(|_| vec![Encoding::Plain]).collect();
+        let groups =
+            RowGroupIterator::try_new(groups, &schema, options, encodings).map_err(to_io_error)?;
+        let file = io::BufWriter::new(File::create(path)?);
+        let mut writer =
+            FileWriter::try_new(file, (*schema).clone(), options).map_err(to_io_error)?;
+        for group in groups {
+            writer
+                .write(group.map_err(to_io_error)?)
+                .map_err(to_io_error)?;
+        }
+        writer.end(None).map_err(to_io_error)?;
+        // flushes the buffer
+        writer.into_inner().into_inner()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl EventRecorder {
+    /// Write the transitions to a Parquet file at `path`
+    pub fn write_parquet<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        parquet::write(&self.transitions, path.as_ref(), ROW_GROUP_ROWS)
+    }
+
+    /// Write the positions to a Parquet file at `path`, without any rows if they are not
+    /// recorded
+    pub fn write_positions_parquet<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        parquet::write(self.positions(), path.as_ref(), ROW_GROUP_ROWS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::julia_reimpl::{AgentSpec, Environment, TallyStates};
+    use crate::params::{EnvironmentConfig, SimulationParams};
+    use crate::presets;
+    use AgentType::*;
+
+    /// Crowded grid with incubation, quarantine, waning immunity and vaccination, whose
+    /// transitions and positions are recorded from the start, and the initial states
+    fn recording(seed: u64) -> (Environment, Vec<AgentType>) {
+        let (params, _) = EnvironmentConfig::from(presets::dense_small_grid())
+            .incubation(2)
+            .quarantine(1, 0.5)
+            .immunity_duration(8)
+            .max_ticks(40)
+            .build_params()
+            .unwrap();
+        let mut e = Environment::from_params(&SimulationParams {
+            seed: Some(seed),
+            ..params
+        });
+        e.schedule_vaccination(5, 0.2);
+        e.enable_event_recording(true);
+        let initial = e.iter_agents().map(|agent| agent.state).collect();
+        (e, initial)
+    }
+
+    fn recorded_run(seed: u64) -> (Environment, Vec<AgentType>, Vec<TallyStates>) {
+        let (mut e, initial) = recording(seed);
+        let record = e.run();
+        (e, initial, record)
+    }
+
+    fn tally(states: &[AgentType]) -> TallyStates {
+        let mut tally = TallyStates::default();
+        for state in states {
+            *match state {
+                AgentS => &mut tally.susceptible,
+                AgentE => &mut tally.exposed,
+                AgentI => &mut tally.infected,
+                AgentQ => &mut tally.quarantined,
+                AgentR => &mut tally.recovered,
+                AgentV => &mut tally.vaccinated,
+                AgentD => &mut tally.dead,
+            } += 1;
+        }
+        tally
+    }
+
+    #[test]
+    fn test_transitions_replay_tallies() {
+        let (e, mut states, record) = recorded_run(3);
+        let recorder = e.event_recorder().unwrap();
+        let transitions = recorder.transitions();
+        assert!(transitions
+            .windows(2)
+            .all(|pair| pair[0].tick <= pair[1].tick));
+        for state in &[AgentE, AgentQ, AgentR, AgentV] {
+            assert!(
+                transitions.iter().any(|t| t.to_state == *state),
+                "{:?}",
+                state
+            );
+        }
+        // waning immunity
+        assert!(transitions
+            .iter()
+            .any(|t| (&t.from_state, &t.to_state) == (&AgentR, &AgentS)));
+
+        let mut next = transitions.iter().peekable();
+        for (tick, expected) in record.iter().enumerate() {
+            while let Some(transition) = next.next_if(|t| t.tick == tick) {
+                let state = &mut states[transition.agent_id];
+                assert_eq!(*state, transition.from_state, "{:?}", transition);
+                *state = transition.to_state.clone();
+            }
+            assert_eq!(&tally(&states), expected, "tick {}", tick);
+        }
+        assert!(next.next().is_none());
+        let current: Vec<_> = e.iter_agents().map(|agent| agent.state).collect();
+        assert_eq!(states, current);
+
+        // every agent at the end of each tick, starting with tick zero
+        let n = states.len();
+        let positions = recorder.positions();
+        assert_eq!(positions.len(), n * record.len());
+        for (tick, positions) in positions.chunks(n).enumerate() {
+            assert!(positions
+                .iter()
+                .enumerate()
+                .all(|(i, position)| (position.agent_id, position.tick) == (i, tick)));
+        }
+        let last = e
+            .iter_agents()
+            .map(|agent| (agent.x, agent.y))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions[n * (record.len() - 1)..]
+                .iter()
+                .map(|position| (position.x, position.y))
+                .collect::<Vec<_>>(),
+            last
+        );
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let spec = |state| AgentSpec { x: 2, y: 1, state };
+        let mut e =
+            Environment::from_agents(vec![spec(AgentI), spec(AgentS)], 2, 1.0, 4, 4, Some(1))
+                .unwrap();
+        e.enable_event_recording(false);
+        e.step();
+        e.import_case(0);
+        let mut csv = Vec::new();
+        e.event_recorder().unwrap().write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "agent_id,tick,from_state,to_state,x,y\n1,1,S,I,2,1\n"
+        );
+
+        let mut e = Environment::from_agents(vec![spec(AgentD)], 2, 1.0, 4, 4, Some(1)).unwrap();
+        e.enable_event_recording(true);
+        e.step();
+        let mut csv = Vec::new();
+        e.event_recorder()
+            .unwrap()
+            .write_positions_csv(&mut csv)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "agent_id,tick,x,y\n0,0,2,1\n0,1,2,1\n"
+        );
+        assert!(Environment::from_agents(Vec::new(), 2, 1.0, 4, 4, Some(1))
+            .unwrap()
+            .event_recorder()
+            .is_none());
+    }
+
+    #[test]
+    fn test_take_events() {
+        let (whole, _, record) = recorded_run(5);
+        let whole = whole.event_recorder().unwrap();
+
+        // the same run, taken in parts while it goes on
+        let (mut e, _) = recording(5);
+        let mut parts = Vec::new();
+        while !e.is_finished() {
+            for _ in 0..7 {
+                if e.is_finished() {
+                    break;
+                }
+                e.step();
+            }
+            parts.push(e.take_events().unwrap());
+        }
+        assert_eq!(e.tick() + 1, record.len());
+        assert!(parts.len() > 2);
+        assert!(e.event_recorder().unwrap().transitions().is_empty());
+        assert!(e.event_recorder().unwrap().records_positions());
+        let transitions: Vec<_> = parts.iter().flat_map(|part| part.transitions()).collect();
+        assert_eq!(transitions, whole.transitions().iter().collect::<Vec<_>>());
+        let positions: Vec<_> = parts.iter().flat_map(|part| part.positions()).collect();
+        assert_eq!(positions, whole.positions().iter().collect::<Vec<_>>());
+    }
+
+    /// Values of the column `name` of the Parquet file at `path`, in each row group
+    #[cfg(feature = "arrow")]
+    fn read_column(path: &std::path::Path, name: &str) -> Vec<Box<dyn polars_arrow::array::Array>> {
+        use polars_parquet::read::*;
+
+        let bytes = std::fs::read(path).unwrap();
+        let metadata = read_metadata(&mut io::Cursor::new(&bytes)).unwrap();
+        let field = infer_schema(&metadata).unwrap().get(name).unwrap().clone();
+        metadata
+            .row_groups
+            .iter()
+            .map(|group| {
+                let columns: Vec<_> = group.columns_under_root_iter(name).unwrap().collect();
+                let pages = columns
+                    .iter()
+                    .map(|column| {
+                        let reader = polars_utils::mmap::MemReader::from_vec(bytes.clone());
+                        let pages = get_page_iterator(column, reader, vec![], usize::MAX).unwrap();
+                        BasicDecompressor::new(pages, vec![])
+                    })
+                    .collect();
+                let types = columns
+                    .iter()
+                    .map(|column| &column.descriptor().descriptor.primitive_type)
+                    .collect();
+                column_iter_to_arrays(pages, types, field.clone(), None).unwrap()
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_write_parquet() {
+        use super::parquet::Table;
+        use polars_arrow::array::{PrimitiveArray, Utf8ViewArray};
+        use polars_parquet::read::{infer_schema, read_metadata};
+
+        let (e, _, _) = recorded_run(3);
+        let recorder = e.event_recorder().unwrap();
+        let transitions = recorder.transitions();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transitions.parquet");
+        // in row groups of 100 rows, such that the transitions span several of them
+        parquet::write(transitions, &path, 100).unwrap();
+
+        let metadata = read_metadata(&mut std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(metadata.num_rows, transitions.len());
+        assert_eq!(metadata.row_groups.len(), transitions.len().div_ceil(100));
+        assert!(metadata.row_groups.len() > 2);
+        let schema = infer_schema(&metadata).unwrap();
+        let columns: Vec<_> = schema
+            .iter_values()
+            .map(|field| (field.name.as_str(), field.dtype.clone(), field.is_nullable))
+            .collect();
+        let expected: Vec<_> = Transition::COLUMNS
+            .iter()
+            .map(|(name, dtype)| (*name, dtype.clone(), false))
+            .collect();
+        assert_eq!(columns, expected);
+
+        let agents: Vec<usize> = read_column(&path, "agent_id")
+            .iter()
+            .flat_map(|array| {
+                let array = array
+                    .as_any()
+                    .downcast_ref::<PrimitiveArray<u64>>()
+                    .unwrap();
+                array
+                    .values()
+                    .iter()
+                    .map(|&id| id as usize)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(
+            agents,
+            transitions.iter().map(|t| t.agent_id).collect::<Vec<_>>()
+        );
+        let states: Vec<String> = read_column(&path, "to_state")
+            .iter()
+            .flat_map(|array| {
+                let array = array.as_any().downcast_ref::<Utf8ViewArray>().unwrap();
+                array.values_iter().map(String::from).collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(
+            states,
+            transitions
+                .iter()
+                .map(|t| state_code(&t.to_state))
+                .collect::<Vec<_>>()
+        );
+
+        let path = dir.path().join("positions.parquet");
+        recorder.write_positions_parquet(&path).unwrap();
+        let metadata = read_metadata(&mut std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(metadata.num_rows, recorder.positions().len());
+        let ticks: Vec<u64> = read_column(&path, "tick")
+            .iter()
+            .flat_map(|array| {
+                let array = array
+                    .as_any()
+                    .downcast_ref::<PrimitiveArray<u64>>()
+                    .unwrap();
+                array.values().to_vec()
+            })
+            .collect();
+        assert!(ticks
+            .iter()
+            .zip(recorder.positions())
+            .all(|(&tick, position)| tick as usize == position.tick));
+
+        // without any rows
+        let empty = EventRecorder::new(false);
+        empty.write_positions_parquet(&path).unwrap();
+        let metadata = read_metadata(&mut std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(metadata.num_rows, 0);
+    }
+}
@@ -0,0 +1,513 @@
+//! Frames of the grid, as an animated GIF or as raw PNG images for assembling videos with
+//! external tools such as `ffmpeg`.
+//!
+//! Each frame shows every cell in a fixed colour according to the states of the agents in it:
+//! the infected colour if any agent is infected, otherwise the quarantined colour if any is
+//! quarantined, then the exposed, susceptible, recovered and vaccinated colours, the dead
+//! colour if only dead agents remain, and white if the cell is empty. Dead agents that were
+//! [removed](crate::params::CorpseHandling::Remove) from the grid are not drawn. As the
+//! colours do not depend on the counts, they mean the same in every frame.
+use plotters::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::julia_reimpl::Environment;
+
+/// Name of the manifest written alongside the frames
+pub const MANIFEST_NAME: &str = "frames.json";
+
+/// Largest width or height of a frame in pixels
+pub const MAX_FRAME_SIDE: u32 = 8192;
+
+/// Colours of the states of the agents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorScheme {
+    /// Blue susceptible, orange exposed, red infected, dark red quarantined, green recovered,
+    /// purple vaccinated and black dead agents
+    Classic,
+    /// Colours from the Okabe-Ito palette, distinguishable with colour vision deficiencies
+    Colorblind,
+}
+
+impl ColorScheme {
+    /// Names accepted by [`ColorScheme::from_name`]
+    pub const NAMES: [&'static str; 2] = ["classic", "colorblind"];
+
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "classic" => Some(ColorScheme::Classic),
+            "colorblind" => Some(ColorScheme::Colorblind),
+            _ => None,
+        }
+    }
+
+    /// Colours of the susceptible, exposed, infected, quarantined, recovered, vaccinated and
+    /// dead states
+    fn colours(self) -> [RGBColor; 7] {
+        match self {
+            ColorScheme::Classic => [
+                RGBColor(40, 90, 220),
+                RGBColor(240, 150, 30),
+                RGBColor(220, 30, 30),
+                RGBColor(120, 20, 40),
+                RGBColor(40, 170, 60),
+                RGBColor(140, 70, 190),
+                BLACK,
+            ],
+            ColorScheme::Colorblind => [
+                RGBColor(0, 114, 178),
+                RGBColor(230, 159, 0),
+                RGBColor(213, 94, 0),
+                RGBColor(240, 228, 66),
+                RGBColor(0, 158, 115),
+                RGBColor(204, 121, 167),
+                BLACK,
+            ],
+        }
+    }
+}
+
+/// How the frames are rendered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameOptions {
+    /// Render every `every`-th tick, starting with tick zero
+    pub every: usize,
+    /// Side length in pixels of a block of cells
+    pub cell_pixels: u32,
+    /// Side length in cells of the blocks that are drawn as one, where `1` draws every cell
+    pub downsample: usize,
+    pub scheme: ColorScheme,
+    /// Stop rendering after this many frames, while the simulation runs to the end
+    pub max_frames: Option<usize>,
+}
+
+impl Default for FrameOptions {
+    fn default() -> Self {
+        Self {
+            every: 1,
+            cell_pixels: 4,
+            downsample: 1,
+            scheme: ColorScheme::Classic,
+            max_frames: None,
+        }
+    }
+}
+
+/// A frame written to disk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameRecord {
+    pub tick: usize,
+    /// File name of the frame, relative to the directory of the manifest
+    pub file: String,
+}
+
+/// Contents of `frames.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameManifest {
+    /// Width and height of every frame in pixels
+    pub width: u32,
+    pub height: u32,
+    pub every: usize,
+    pub downsample: usize,
+    pub frames: Vec<FrameRecord>,
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// Rendering or encoding a frame failed
+    Render(String),
+    /// The options cannot produce frames
+    InvalidOptions(&'static str),
+    /// A frame would be wider or higher than [`MAX_FRAME_SIDE`]
+    TooLarge {
+        width: u64,
+        height: u64,
+    },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(error) => write!(f, "failed to write frames: {}", error),
+            FrameError::Json(error) => write!(f, "failed to write manifest: {}", error),
+            FrameError::Render(error) => write!(f, "failed to render frame: {}", error),
+            FrameError::InvalidOptions(reason) => write!(f, "invalid frame options: {}", reason),
+            FrameError::TooLarge { width, height } => write!(
+                f,
+                "frames of {}x{} pixels exceed the limit of {} pixels per side",
+                width, height, MAX_FRAME_SIDE
+            ),
+        }
+    }
+}
+
+impl Error for FrameError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FrameError::Io(error) => Some(error),
+            FrameError::Json(error) => Some(error),
+            FrameError::Render(_) | FrameError::InvalidOptions(_) | FrameError::TooLarge { .. } => {
+                None
+            }
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(error: io::Error) -> Self {
+        FrameError::Io(error)
+    }
+}
+
+/// Size in pixels of the frames of a grid of size `grid_size`, or `None` if a side would
+/// exceed `u32::MAX` pixels
+#[must_use]
+pub fn frame_size(grid_size: (usize, usize), options: &FrameOptions) -> Option<(u32, u32)> {
+    let side = |dim: usize| {
+        u32::try_from(dim.div_ceil(options.downsample))
+            .ok()?
+            .checked_mul(options.cell_pixels)
+    };
+    Some((side(grid_size.0)?, side(grid_size.1)?))
+}
+
+/// Colour of a block of cells, given whether any agent of each state is in it, in the order
+/// susceptible, exposed, infected, quarantined, recovered, vaccinated and dead
+fn block_colour(present: [bool; 7], scheme: ColorScheme) -> RGBColor {
+    let [susceptible, exposed, infected, quarantined, recovered, vaccinated, dead] =
+        scheme.colours();
+    match present {
+        [_, _, true, _, _, _, _] => infected,
+        [_, _, _, true, _, _, _] => quarantined,
+        [_, true, _, _, _, _, _] => exposed,
+        [true, _, _, _, _, _, _] => susceptible,
+        [_, _, _, _, true, _, _] => recovered,
+        [_, _, _, _, _, true, _] => vaccinated,
+        [_, _, _, _, _, _, true] => dead,
+        _ => WHITE,
+    }
+}
+
+/// Draw the current grid of `env` onto `area`
+pub fn draw_grid<X, DB: DrawingBackend>(
+    env: &Environment<X>,
+    area: &DrawingArea<DB, plotters::coord::Shift>,
+    options: &FrameOptions,
+) -> Result<(), FrameError> {
+    let render = |error: DrawingAreaErrorKind<DB::ErrorType>| FrameError::Render(error.to_string());
+    let (xdim, ydim) = env.grid_size();
+    let ydim_blocks = ydim.div_ceil(options.downsample);
+    let xdim_blocks = xdim.div_ceil(options.downsample);
+    let mut present = vec![[false; 7]; xdim_blocks * ydim_blocks];
+    // the snapshot leaves out the removed dead
+    for (y, row) in env.grid_snapshot().iter().enumerate() {
+        for (x, cell) in row.iter().enumerate() {
+            let block = (x / options.downsample) * ydim_blocks + y / options.downsample;
+            let counts = [
+                cell.susceptible,
+                cell.exposed,
+                cell.infected,
+                cell.quarantined,
+                cell.recovered,
+                cell.vaccinated,
+                cell.dead,
+            ];
+            for (present, &count) in present[block].iter_mut().zip(&counts) {
+                *present |= count > 0;
+            }
+        }
+    }
+
+    area.fill(&WHITE).map_err(render)?;
+    let side = options.cell_pixels as i32;
+    for (block, &states) in present.iter().enumerate() {
+        let colour = block_colour(states, options.scheme);
+        if colour == WHITE {
+            continue;
+        }
+        let x = (block / ydim_blocks) as i32 * side;
+        let y = (block % ydim_blocks) as i32 * side;
+        area.draw(&Rectangle::new(
+            [(x, y), (x + side - 1, y + side - 1)],
+            colour.filled(),
+        ))
+        .map_err(render)?;
+    }
+    Ok(())
+}
+
+/// File name of the frame of `tick`
+#[must_use]
+pub fn frame_name(tick: usize) -> String {
+    format!("frame_{:06}.png", tick)
+}
+
+/// Check `options` and return the size of the frames of a grid of size `grid_size`
+fn checked_frame_size(
+    grid_size: (usize, usize),
+    options: &FrameOptions,
+) -> Result<(u32, u32), FrameError> {
+    if options.every == 0 || options.cell_pixels == 0 || options.downsample == 0 {
+        return Err(FrameError::InvalidOptions(
+            "every, cell_pixels and downsample must be positive",
+        ));
+    }
+    frame_size(grid_size, options)
+        .filter(|&(width, height)| width <= MAX_FRAME_SIDE && height <= MAX_FRAME_SIDE)
+        .ok_or_else(|| {
+            let side = |dim: usize| {
+                (dim.div_ceil(options.downsample) as u64)
+                    .saturating_mul(u64::from(options.cell_pixels))
+            };
+            FrameError::TooLarge {
+                width: side(grid_size.0),
+                height: side(grid_size.1),
+            }
+        })
+}
+
+/// Run `env` to the end, calling `draw` with every tick that is due for a frame, up to
+/// `options.max_frames` times. Returns the ticks that were drawn.
+fn render_run<X>(
+    env: &mut Environment<X>,
+    options: &FrameOptions,
+    mut draw: impl FnMut(&Environment<X>) -> Result<(), FrameError>,
+) -> Result<Vec<usize>, FrameError> {
+    let max_frames = options.max_frames.unwrap_or(usize::MAX);
+    let mut ticks = Vec::new();
+    let mut render = |env: &Environment<X>| -> Result<(), FrameError> {
+        let tick = env.tick();
+        if tick % options.every == 0 && ticks.len() < max_frames {
+            draw(env)?;
+            ticks.push(tick);
+        }
+        Ok(())
+    };
+
+    render(env)?;
+    let mut failure = None;
+    env.run_with_hook(|env, _| {
+        if failure.is_none() {
+            failure = render(env).err();
+        }
+    });
+    match failure {
+        Some(error) => Err(error),
+        None => Ok(ticks),
+    }
+}
+
+/// Run `env` to the end, writing a PNG of its grid into `dir` every `options.every` ticks,
+/// followed by the manifest [`MANIFEST_NAME`].
+///
+/// Frames are rendered and written one at a time while the simulation runs. The directory is
+/// created if it does not exist.
+pub fn export_frames<X, P: AsRef<Path>>(
+    env: &mut Environment<X>,
+    dir: P,
+    options: &FrameOptions,
+) -> Result<FrameManifest, FrameError> {
+    let (width, height) = checked_frame_size(env.grid_size(), options)?;
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+
+    let ticks = render_run(env, options, |env| {
+        let path = dir.join(frame_name(env.tick()));
+        let root = BitMapBackend::new(&path, (width, height)).into_drawing_area();
+        draw_grid(env, &root, options)?;
+        root.present()
+            .map_err(|error| FrameError::Render(error.to_string()))
+    })?;
+    let manifest = FrameManifest {
+        width,
+        height,
+        every: options.every,
+        downsample: options.downsample,
+        frames: ticks
+            .into_iter()
+            .map(|tick| FrameRecord {
+                tick,
+                file: frame_name(tick),
+            })
+            .collect(),
+    };
+
+    let path: PathBuf = dir.join(MANIFEST_NAME);
+    serde_json::to_writer_pretty(File::create(path)?, &manifest).map_err(FrameError::Json)?;
+    Ok(manifest)
+}
+
+/// Run `env` to the end, writing an animated GIF of its grid to `path` with a frame every
+/// `options.every` ticks, each shown for `frame_delay_ms` milliseconds.
+///
+/// Returns the ticks of the frames.
+pub fn export_gif<X, P: AsRef<Path>>(
+    env: &mut Environment<X>,
+    path: P,
+    options: &FrameOptions,
+    frame_delay_ms: u32,
+) -> Result<Vec<usize>, FrameError> {
+    let size = checked_frame_size(env.grid_size(), options)?;
+    let root = BitMapBackend::gif(path, size, frame_delay_ms)
+        .map_err(|error| FrameError::Render(error.to_string()))?
+        .into_drawing_area();
+    render_run(env, options, |env| {
+        draw_grid(env, &root, options)?;
+        root.present()
+            .map_err(|error| FrameError::Render(error.to_string()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::julia_reimpl::AgentType;
+    use crate::params::{CorpseHandling, EnvironmentConfig, SimulationParams};
+
+    /// Width and height from the header of a PNG file
+    fn png_size(path: &Path) -> (u32, u32) {
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(&bytes[1..4], b"PNG");
+        let read = |at: usize| {
+            u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        (read(16), read(20))
+    }
+
+    fn tiny() -> Environment {
+        let (params, _) = EnvironmentConfig::new()
+            .population(40)
+            .initial_infected(2)
+            .duration(4)
+            .grid(10, 6)
+            .build_params()
+            .unwrap();
+        Environment::from_params(&params)
+    }
+
+    #[test]
+    fn test_export_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = FrameOptions {
+            every: 2,
+            cell_pixels: 3,
+            downsample: 2,
+            ..FrameOptions::default()
+        };
+        let mut e = tiny();
+        let manifest = export_frames(&mut e, dir.path(), &options).unwrap();
+
+        let ticks = e.tick() + 1;
+        assert_eq!(manifest.frames.len(), ticks.div_ceil(2));
+        assert_eq!((manifest.width, manifest.height), (15, 9));
+        for (index, frame) in manifest.frames.iter().enumerate() {
+            assert_eq!(frame.tick, index * 2);
+            assert_eq!(frame.file, format!("frame_{:06}.png", index * 2));
+            assert_eq!(png_size(&dir.path().join(&frame.file)), (15, 9));
+        }
+
+        let files = std::fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(files, manifest.frames.len() + 1);
+        let read: FrameManifest =
+            serde_json::from_reader(File::open(dir.path().join(MANIFEST_NAME)).unwrap()).unwrap();
+        assert_eq!(read, manifest);
+    }
+
+    #[test]
+    fn test_export_gif() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spread.gif");
+        let options = FrameOptions {
+            max_frames: Some(3),
+            scheme: ColorScheme::Colorblind,
+            ..FrameOptions::default()
+        };
+        let ticks = export_gif(&mut tiny(), &path, &options, 50).unwrap();
+        assert_eq!(ticks, vec![0, 1, 2]);
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[..6], b"GIF89a");
+    }
+
+    #[test]
+    fn test_too_large() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = FrameOptions {
+            cell_pixels: MAX_FRAME_SIDE,
+            ..FrameOptions::default()
+        };
+        assert!(matches!(
+            export_frames(&mut tiny(), dir.path(), &options),
+            Err(FrameError::TooLarge { .. })
+        ));
+
+        // sides beyond u32::MAX pixels are too large rather than overflowing
+        let options = FrameOptions {
+            cell_pixels: 4_000_000_000,
+            ..FrameOptions::default()
+        };
+        assert_eq!(frame_size((10, 6), &options), None);
+        match export_gif(&mut tiny(), dir.path().join("huge.gif"), &options, 50) {
+            Err(FrameError::TooLarge { width, height }) => {
+                assert_eq!((width, height), (40_000_000_000, 24_000_000_000))
+            }
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_removed_dead_not_drawn() {
+        for &handling in &[CorpseHandling::Keep, CorpseHandling::Remove] {
+            let (params, _) = EnvironmentConfig::new()
+                .population(40)
+                .initial_infected(10)
+                .duration(2)
+                .p_death(1.0)
+                .corpse_handling(handling)
+                .grid(10, 6)
+                .build_params()
+                .unwrap();
+            let mut e = Environment::from_params(&SimulationParams {
+                seed: Some(3),
+                ..params
+            });
+            e.run();
+            assert!(e
+                .iter_agents()
+                .any(|agent| agent.state == AgentType::AgentD));
+
+            let options = FrameOptions::default();
+            let (width, height) = frame_size(e.grid_size(), &options).unwrap();
+            let mut pixels = vec![0; (width * height * 3) as usize];
+            {
+                let root =
+                    BitMapBackend::with_buffer(&mut pixels, (width, height)).into_drawing_area();
+                draw_grid(&e, &root, &options).unwrap();
+                root.present().unwrap();
+            }
+            // cells with only dead agents are black
+            let black = pixels.chunks(3).any(|pixel| pixel == [0, 0, 0]);
+            assert_eq!(black, handling == CorpseHandling::Keep);
+        }
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = FrameOptions {
+            every: 0,
+            ..FrameOptions::default()
+        };
+        assert!(matches!(
+            export_frames(&mut tiny(), dir.path(), &options),
+            Err(FrameError::InvalidOptions(_))
+        ));
+    }
+}
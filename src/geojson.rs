@@ -0,0 +1,284 @@
+//! Locations of the agents and of their infections as GeoJSON points, for overlaying runs on
+//! mapping tools such as kepler.gl.
+//!
+//! Cells are placed on the map by a [`GeoTransform`]. Each agent is a `Point` feature with its
+//! `id`, `state` and the `tick` of the collection as properties, and each infection is a point
+//! in the cell it happened in with the `id` of the infected agent, the state `infected` and the
+//! `tick` of the infection. [`export_per_tick`] writes a collection of the agents for every
+//! tick of a run, named by [`geojson_name`], for time-slider visualizations.
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::immunity::ImmuneEvent;
+use crate::julia_reimpl::{AgentType, Environment};
+
+/// Affine map from grid coordinates to longitude and latitude in degrees, where cell `(x, y)`
+/// lies at `origin + (x, y) * spacing`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoTransform {
+    /// Longitude and latitude of cell `(0, 0)`
+    pub origin: (f64, f64),
+    /// Degrees of longitude and latitude between neighbouring cells
+    pub spacing: (f64, f64),
+}
+
+impl GeoTransform {
+    /// Longitude and latitude of the cell at `x` and `y`
+    #[must_use]
+    pub fn apply(&self, x: usize, y: usize) -> (f64, f64) {
+        (
+            self.origin.0 + x as f64 * self.spacing.0,
+            self.origin.1 + y as f64 * self.spacing.1,
+        )
+    }
+}
+
+/// Which features are exported, and where
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoJsonOptions {
+    pub transform: GeoTransform,
+    /// Whether recovered and vaccinated agents, being immune, are exported
+    pub recovered: bool,
+    /// Whether dead agents are exported
+    pub dead: bool,
+}
+
+/// Name of `state` in the properties of the features
+fn state_name(state: &AgentType) -> &'static str {
+    match state {
+        AgentType::AgentS => "susceptible",
+        AgentType::AgentE => "exposed",
+        AgentType::AgentI => "infected",
+        AgentType::AgentQ => "quarantined",
+        AgentType::AgentR => "recovered",
+        AgentType::AgentV => "vaccinated",
+        AgentType::AgentD => "dead",
+    }
+}
+
+/// `Point` feature at the cell `(x, y)` with `properties`
+fn point(transform: &GeoTransform, x: usize, y: usize, properties: Value) -> Value {
+    let (lon, lat) = transform.apply(x, y);
+    json!({
+        "type": "Feature",
+        "geometry": {"type": "Point", "coordinates": [lon, lat]},
+        "properties": properties,
+    })
+}
+
+fn feature_collection(features: Vec<Value>) -> Value {
+    json!({"type": "FeatureCollection", "features": features})
+}
+
+/// Current locations of the agents of `env` as a `FeatureCollection`, leaving out the
+/// recovered or dead agents unless `options` include them
+#[must_use]
+pub fn agents<X>(env: &Environment<X>, options: &GeoJsonOptions) -> Value {
+    let features = env
+        .iter_agents()
+        .filter(|agent| match agent.state {
+            AgentType::AgentR | AgentType::AgentV => options.recovered,
+            AgentType::AgentD => options.dead,
+            AgentType::AgentS | AgentType::AgentE | AgentType::AgentI | AgentType::AgentQ => true,
+        })
+        .map(|agent| {
+            let properties = json!({
+                "id": agent.id,
+                "state": state_name(&agent.state),
+                "tick": env.tick(),
+            });
+            point(&options.transform, agent.x, agent.y, properties)
+        })
+        .collect();
+    feature_collection(features)
+}
+
+/// Infections of the agents of `env` so far as a `FeatureCollection`, in order of the index
+/// of the agent and then of the tick of its infection
+#[must_use]
+pub fn infections<X>(env: &Environment<X>, transform: &GeoTransform) -> Value {
+    let features = env
+        .iter_agents()
+        .flat_map(|agent| {
+            let id = agent.id;
+            agent
+                .immune_history
+                .events()
+                .iter()
+                .filter_map(move |event| match *event {
+                    ImmuneEvent::Infection { tick, x, y } => {
+                        let properties = json!({"id": id, "state": "infected", "tick": tick});
+                        Some(point(transform, x, y, properties))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    feature_collection(features)
+}
+
+/// File name of the collection of `tick`
+#[must_use]
+pub fn geojson_name(tick: usize) -> String {
+    format!("agents_{:06}.geojson", tick)
+}
+
+fn write_value(path: &Path, value: &Value) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut writer, value)?;
+    writer.flush()
+}
+
+/// Run `env` to the end, writing the [agents](agents) at every tick from the current one into
+/// `dir`, named by [`geojson_name`], and then all [infections](infections) as
+/// `infections.geojson`. Returns the paths of the files written for the ticks.
+///
+/// The directory is created if it does not exist. Writing stops at the first file that cannot
+/// be written, whose error is returned once the run has ended.
+pub fn export_per_tick<X, P: AsRef<Path>>(
+    env: &mut Environment<X>,
+    dir: P,
+    options: &GeoJsonOptions,
+) -> io::Result<Vec<PathBuf>> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+    let write = |env: &Environment<X>| -> io::Result<PathBuf> {
+        let path = dir.join(geojson_name(env.tick()));
+        write_value(&path, &agents(env, options))?;
+        Ok(path)
+    };
+
+    let mut paths = vec![write(env)?];
+    let mut failure = None;
+    env.run_with_hook(|env, _| {
+        if failure.is_none() {
+            match write(env) {
+                Ok(path) => paths.push(path),
+                Err(error) => failure = Some(error),
+            }
+        }
+    });
+    if let Some(error) = failure {
+        return Err(error);
+    }
+    write_value(
+        &dir.join("infections.geojson"),
+        &infections(env, &options.transform),
+    )?;
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    fn options(recovered: bool, dead: bool) -> GeoJsonOptions {
+        GeoJsonOptions {
+            transform: GeoTransform {
+                origin: (12.5, 55.6),
+                spacing: (0.01, -0.005),
+            },
+            recovered,
+            dead,
+        }
+    }
+
+    /// Check that `value` is a `FeatureCollection` of points within the transformed grid of
+    /// `env`, and return its features
+    fn features<'a, X>(value: &'a Value, env: &Environment<X>) -> &'a [Value] {
+        assert_eq!(value["type"], "FeatureCollection");
+        let features = value["features"].as_array().unwrap();
+        let (xdim, ydim) = env.grid_size();
+        for feature in features {
+            assert_eq!(feature["type"], "Feature");
+            assert_eq!(feature["geometry"]["type"], "Point");
+            let coordinates = feature["geometry"]["coordinates"].as_array().unwrap();
+            let (lon, lat) = (
+                coordinates[0].as_f64().unwrap(),
+                coordinates[1].as_f64().unwrap(),
+            );
+            assert!((12.5..=12.5 + 0.01 * (xdim - 1) as f64 + 1e-9).contains(&lon));
+            assert!((55.6 - 0.005 * (ydim - 1) as f64 - 1e-9..=55.6).contains(&lat));
+            assert!(feature["properties"]["id"].as_u64().unwrap() < 500);
+        }
+        features
+    }
+
+    #[test]
+    fn test_transform() {
+        let transform = options(true, true).transform;
+        assert_eq!(transform.apply(0, 0), (12.5, 55.6));
+        let (lon, lat) = transform.apply(10, 4);
+        assert!((lon - 12.6).abs() < 1e-12 && (lat - 55.58).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_feature_counts_match_tally() {
+        let mut environment = Environment::from_params(&presets::dense_small_grid());
+        for _ in 0..3 {
+            environment.advance(&mut |_, _| {});
+        }
+        let tally = environment.get_statistics();
+        let all = agents(&environment, &options(true, true));
+        let all = features(&all, &environment);
+        assert_eq!(all.len(), 500);
+        let count = |state: &str| {
+            all.iter()
+                .filter(|feature| feature["properties"]["state"] == state)
+                .count()
+        };
+        assert_eq!(count("susceptible"), tally.susceptible);
+        assert_eq!(count("infected"), tally.infected);
+        assert_eq!(count("recovered"), tally.recovered);
+        assert_eq!(count("dead"), tally.dead);
+        assert!(all.iter().all(|feature| feature["properties"]["tick"] == 3));
+
+        // the agent of a feature lies in the transformed cell
+        let agent = environment.agent(7).unwrap();
+        let (lon, lat) = options(true, true).transform.apply(agent.x, agent.y);
+        assert_eq!(all[7]["geometry"]["coordinates"], json!([lon, lat]));
+
+        environment.run();
+        let tally = environment.get_statistics();
+        let living = agents(&environment, &options(true, false));
+        assert_eq!(
+            features(&living, &environment).len(),
+            tally.susceptible + tally.recovered
+        );
+        let unaffected = agents(&environment, &options(false, false));
+        assert_eq!(features(&unaffected, &environment).len(), tally.susceptible);
+
+        let infected = infections(&environment, &options(true, true).transform);
+        assert_eq!(
+            features(&infected, &environment).len(),
+            500 - tally.susceptible
+        );
+    }
+
+    #[test]
+    fn test_export_per_tick() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut environment = Environment::from_params(&presets::dense_small_grid());
+        let paths = export_per_tick(&mut environment, dir.path(), &options(false, true)).unwrap();
+        assert_eq!(paths.len(), environment.tick() + 1);
+        assert_eq!(paths[2], dir.path().join("agents_000002.geojson"));
+        let last: Value =
+            serde_json::from_reader(File::open(paths.last().unwrap()).unwrap()).unwrap();
+        let tally = environment.get_statistics();
+        assert_eq!(
+            features(&last, &environment).len(),
+            tally.susceptible + tally.dead
+        );
+        let infected: Value =
+            serde_json::from_reader(File::open(dir.path().join("infections.geojson")).unwrap())
+                .unwrap();
+        assert_eq!(
+            features(&infected, &environment).len(),
+            500 - tally.susceptible
+        );
+    }
+}
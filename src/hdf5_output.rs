@@ -0,0 +1,314 @@
+//! HDF5 output for a single run or an ensemble of runs.
+//!
+//! The file written by [`write_runs`] is laid out as follows:
+//!
+//! - `/params`: group whose attributes are the parameters of the first run (`n`, `infected`,
+//!   `duration`, `p_death`, `p_infect`, `xdim`, `ydim`, `burn_in_ticks`), the number of
+//!   `replicates`, and the complete parameters as a JSON string in `json`.
+//! - `/tallies`: `u64` dataset of shape (replicate, tick, compartment), with the compartments
+//!   ordered susceptible, exposed, infected, quarantined, recovered, vaccinated, dead as in
+//!   [`TallyStates`](crate::julia_reimpl::TallyStates). Runs that are shorter than the longest
+//!   run are padded with [`TALLY_FILL_VALUE`], which is also the fill value of the dataset.
+//! - `/cells`: group of the `u64` datasets `visits`, `infections` and `deaths`, each of shape
+//!   (y, x) such that the value of cell `(x, y)` is at `[y, x]`, if written by
+//!   [`write_runs_with_cells`]. A map that was not tracked is left out.
+//!
+//! Datasets are chunked, per replicate for the tallies, and compressed with deflate.
+use std::path::Path;
+
+use hdf5::types::VarLenUnicode;
+
+use crate::julia_reimpl::{AgentType, Environment};
+use crate::result::RunResult;
+
+/// Value of the ticks in `/tallies` beyond the end of a run
+pub const TALLY_FILL_VALUE: u64 = u64::MAX;
+
+/// Number of compartments in `/tallies`
+const COMPARTMENTS: usize = 7;
+
+/// Maps of a run over the cells of its grid in row-major order, such that the value of cell
+/// `(x, y)` is at index `y * xdim + x`, see [`write_runs_with_cells`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellMaps {
+    pub xdim: usize,
+    pub ydim: usize,
+    /// Visits of living agents, or empty if they were not tracked, see
+    /// [`Environment::cell_visit_counts`]
+    pub visits: Vec<usize>,
+    /// Infections by contact, or empty if they were not tracked, see
+    /// [`Environment::cell_infection_counts`]
+    pub infections: Vec<usize>,
+    /// Agents that died in each cell
+    pub deaths: Vec<usize>,
+}
+
+impl CellMaps {
+    /// Maps of the run of `environment` so far, whose visits and infections are only counted
+    /// if [`Environment::enable_cell_tracking`] was called before the run
+    #[must_use]
+    pub fn from_environment<X>(environment: &Environment<X>) -> Self {
+        let (xdim, ydim) = environment.grid_size();
+        let mut deaths = vec![0; xdim * ydim];
+        // the dead stay in the cell where they died, even when removed from the grid
+        for agent in environment.iter_agents() {
+            if agent.state == AgentType::AgentD {
+                deaths[agent.y * xdim + agent.x] += 1;
+            }
+        }
+        Self {
+            xdim,
+            ydim,
+            visits: environment.cell_visit_counts().to_vec(),
+            infections: environment.cell_infection_counts().to_vec(),
+            deaths,
+        }
+    }
+}
+
+/// Write `runs` to a new HDF5 file at `path`, overwriting any existing file.
+pub fn write_runs<P: AsRef<Path>>(path: P, runs: &[RunResult]) -> hdf5::Result<()> {
+    write(path.as_ref(), runs, None)
+}
+
+/// Write `runs` to a new HDF5 file at `path` as [`write_runs`] does, along with the maps of
+/// `cells` in `/cells`, e.g. those of a single run
+pub fn write_runs_with_cells<P: AsRef<Path>>(
+    path: P,
+    runs: &[RunResult],
+    cells: &CellMaps,
+) -> hdf5::Result<()> {
+    write(path.as_ref(), runs, Some(cells))
+}
+
+fn write(path: &Path, runs: &[RunResult], cells: Option<&CellMaps>) -> hdf5::Result<()> {
+    let first = runs.first().ok_or("there are no runs to write")?;
+
+    let file = hdf5::File::create(path)?;
+    let group = file.create_group("params")?;
+    let params = &first.metadata.params;
+    for &(name, value) in [
+        ("n", params.n),
+        ("infected", params.infected),
+        ("duration", params.duration),
+        ("xdim", params.xdim),
+        ("ydim", params.ydim),
+        ("burn_in_ticks", params.burn_in_ticks),
+        ("replicates", runs.len()),
+    ]
+    .iter()
+    {
+        group
+            .new_attr::<u64>()
+            .create(name)?
+            .write_scalar(&(value as u64))?;
+    }
+    for &(name, value) in [("p_death", params.p_death), ("p_infect", params.p_infect)].iter() {
+        group.new_attr::<f64>().create(name)?.write_scalar(&value)?;
+    }
+    let json = serde_json::to_string(params).map_err(|err| err.to_string())?;
+    let json: VarLenUnicode = json
+        .parse()
+        .map_err(|_| "parameters are not valid unicode")?;
+    group
+        .new_attr::<VarLenUnicode>()
+        .create("json")?
+        .write_scalar(&json)?;
+
+    let ticks = runs.iter().map(|run| run.record.len()).max().unwrap_or(0);
+    let mut tallies = vec![TALLY_FILL_VALUE; runs.len() * ticks * COMPARTMENTS];
+    for (replicate, run) in runs.iter().enumerate() {
+        let record = &run.record;
+        for tick in 0..record.len() {
+            let offset = (replicate * ticks + tick) * COMPARTMENTS;
+            tallies[offset..offset + COMPARTMENTS].copy_from_slice(&[
+                record.susceptible[tick] as u64,
+                record.exposed[tick] as u64,
+                record.infected[tick] as u64,
+                record.quarantined[tick] as u64,
+                record.recovered[tick] as u64,
+                record.vaccinated[tick] as u64,
+                record.dead[tick] as u64,
+            ]);
+        }
+    }
+    file.new_dataset::<u64>()
+        .chunk((1, ticks.max(1), COMPARTMENTS))
+        .deflate(4)
+        .fill_value(TALLY_FILL_VALUE)
+        .shape((runs.len(), ticks, COMPARTMENTS))
+        .create("tallies")?
+        .write_raw(tallies.as_slice())?;
+
+    if let Some(cells) = cells {
+        let group = file.create_group("cells")?;
+        let shape = (cells.ydim, cells.xdim);
+        for &(name, map) in [
+            ("visits", &cells.visits),
+            ("infections", &cells.infections),
+            ("deaths", &cells.deaths),
+        ]
+        .iter()
+        {
+            if map.is_empty() {
+                continue;
+            }
+            if map.len() != cells.xdim * cells.ydim {
+                return Err(format!(
+                    "map `{}` has {} values for {}x{} cells",
+                    name,
+                    map.len(),
+                    cells.xdim,
+                    cells.ydim
+                )
+                .into());
+            }
+            let values: Vec<u64> = map.iter().map(|&value| value as u64).collect();
+            group
+                .new_dataset::<u64>()
+                .chunk(shape)
+                .deflate(4)
+                .shape(shape)
+                .create(name)?
+                .write_raw(values.as_slice())?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::SimulationParams;
+    use crate::presets;
+    use crate::result::run;
+
+    #[test]
+    fn test_write_ensemble() {
+        let params = presets::dense_small_grid();
+        let mut runs: Vec<_> = (0..3)
+            .map(|seed| {
+                run(&SimulationParams {
+                    seed: Some(seed),
+                    ..params.clone()
+                })
+            })
+            .collect();
+        runs[1].record.truncate(3);
+        let ticks = runs.iter().map(|run| run.record.len()).max().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ensemble.h5");
+        write_runs(&path, &runs).unwrap();
+
+        let file = hdf5::File::open(&path).unwrap();
+        let group = file.group("params").unwrap();
+        let attr = |name: &str| group.attr(name).unwrap().read_scalar::<u64>().unwrap();
+        assert_eq!(attr("n"), params.n as u64);
+        assert_eq!(attr("duration"), params.duration as u64);
+        assert_eq!(attr("replicates"), 3);
+        let p_death: f64 = group.attr("p_death").unwrap().read_scalar().unwrap();
+        assert_eq!(p_death, params.p_death);
+        let json: VarLenUnicode = group.attr("json").unwrap().read_scalar().unwrap();
+        assert_eq!(
+            serde_json::from_str::<SimulationParams>(json.as_str()).unwrap(),
+            runs[0].metadata.params
+        );
+
+        let tallies = file.dataset("tallies").unwrap();
+        assert_eq!(tallies.shape(), vec![3, ticks, COMPARTMENTS]);
+        assert!(tallies.is_chunked());
+        let values = tallies.read_raw::<u64>().unwrap();
+        for (replicate, run) in runs.iter().enumerate() {
+            for tick in 0..ticks {
+                let offset = (replicate * ticks + tick) * COMPARTMENTS;
+                let row = &values[offset..offset + COMPARTMENTS];
+                if tick < run.record.len() {
+                    let tally = &run.records()[tick];
+                    let expected = [
+                        tally.susceptible,
+                        tally.exposed,
+                        tally.infected,
+                        tally.quarantined,
+                        tally.recovered,
+                        tally.vaccinated,
+                        tally.dead,
+                    ];
+                    let expected: Vec<u64> = expected.iter().map(|&count| count as u64).collect();
+                    assert_eq!(row, expected.as_slice());
+                } else {
+                    assert!(row.iter().all(|&value| value == TALLY_FILL_VALUE));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_cells() {
+        let params = SimulationParams {
+            n: 60,
+            infected: 3,
+            xdim: 7,
+            ydim: 3,
+            p_death: 0.5,
+            seed: Some(2),
+            ..SimulationParams::default()
+        };
+        let mut environment = Environment::from_params(&params);
+        environment.enable_cell_tracking();
+        let record = environment.run();
+        let cells = CellMaps::from_environment(&environment);
+        assert_eq!(
+            cells.deaths.iter().sum::<usize>(),
+            record.last().unwrap().dead
+        );
+        // tracking draws no random numbers, so the same run
+        let runs = [run(&params)];
+        assert_eq!(runs[0].records(), record);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cells.h5");
+        write_runs_with_cells(&path, &runs, &cells).unwrap();
+        let file = hdf5::File::open(&path).unwrap();
+        for &(name, map) in [
+            ("visits", &cells.visits),
+            ("infections", &cells.infections),
+            ("deaths", &cells.deaths),
+        ]
+        .iter()
+        {
+            let dataset = file.dataset(&format!("cells/{}", name)).unwrap();
+            assert_eq!(dataset.shape(), vec![3, 7]);
+            let values = dataset.read_2d::<u64>().unwrap();
+            for (x, y) in (0..7).flat_map(|x| (0..3).map(move |y| (x, y))) {
+                assert_eq!(
+                    values[[y, x]],
+                    map[y * 7 + x] as u64,
+                    "{} {:?}",
+                    name,
+                    (x, y)
+                );
+            }
+        }
+
+        // maps that were not tracked are left out
+        let untracked = CellMaps {
+            visits: Vec::new(),
+            infections: Vec::new(),
+            ..cells.clone()
+        };
+        write_runs_with_cells(&path, &runs, &untracked).unwrap();
+        let file = hdf5::File::open(&path).unwrap();
+        assert!(file.dataset("cells/visits").is_err());
+        assert!(file.dataset("cells/deaths").is_ok());
+        assert!(write_runs(&path, &runs).is_ok());
+        assert!(hdf5::File::open(&path).unwrap().group("cells").is_err());
+    }
+
+    #[test]
+    fn test_no_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(write_runs(dir.path().join("empty.h5"), &[]).is_err());
+    }
+}
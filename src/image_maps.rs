@@ -0,0 +1,227 @@
+//! Spatial input maps loaded from grayscale PNG images.
+//!
+//! The image must be `xdim` pixels wide and `ydim` pixels high, where the pixel in column `x`
+//! and row `y` describes the cell `(x, y)`. Colour images are converted to grayscale first.
+//! Intensities are mapped linearly, without any gamma correction, from `0..=255` to `0.0..=1.0`.
+use image::{GrayImage, ImageError};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// How the pixel intensities of an image are interpreted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapKind {
+    /// Multiplier of the transmission risk in each cell, from 0 (black) to 1 (white)
+    TransmissionRisk,
+    /// Relative weight of each cell when placing agents initially, from 0 (black) to 1 (white)
+    PlacementWeight,
+    /// Cells that agents cannot enter, being those with an intensity below `threshold`,
+    /// such that dark pixels are obstacles
+    Obstacles { threshold: u8 },
+}
+
+/// Values of a map, with one value per cell
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridMap<T> {
+    grid_size: (usize, usize),
+    /// Values indexed by `x * ydim + y`
+    values: Vec<T>,
+}
+
+impl<T> GridMap<T> {
+    /// Size of the grid in x- and y-dimension
+    #[must_use]
+    pub fn grid_size(&self) -> (usize, usize) {
+        self.grid_size
+    }
+
+    /// Value of the cell `(x, y)`, if it is on the grid
+    #[must_use]
+    pub fn get(&self, x: usize, y: usize) -> Option<&T> {
+        let (xdim, ydim) = self.grid_size;
+        if x < xdim && y < ydim {
+            self.values.get(x * ydim + y)
+        } else {
+            None
+        }
+    }
+}
+
+impl GridMap<bool> {
+    /// Cells that are `true`, e.g. as the
+    /// [reservoir cells](crate::params::EnvironmentConfig::reservoirs) of a scenario
+    #[must_use]
+    pub fn cells(&self) -> Vec<(usize, usize)> {
+        let ydim = self.grid_size.1;
+        (0..self.values.len())
+            .filter(|&i| self.values[i])
+            .map(|i| (i / ydim, i % ydim))
+            .collect()
+    }
+}
+
+/// A map loaded from an image, according to its [`MapKind`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpatialMap {
+    TransmissionRisk(GridMap<f64>),
+    PlacementWeight(GridMap<f64>),
+    /// `true` for cells that are obstacles
+    Obstacles(GridMap<bool>),
+}
+
+#[derive(Debug)]
+pub enum ImageMapError {
+    Image(ImageError),
+    /// The image does not have the dimensions of the grid
+    DimensionMismatch {
+        image: (usize, usize),
+        grid: (usize, usize),
+    },
+}
+
+impl fmt::Display for ImageMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageMapError::Image(error) => write!(f, "failed to load image: {}", error),
+            ImageMapError::DimensionMismatch { image, grid } => write!(
+                f,
+                "image of {}x{} pixels does not match grid of {}x{} cells",
+                image.0, image.1, grid.0, grid.1
+            ),
+        }
+    }
+}
+
+impl Error for ImageMapError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImageMapError::Image(error) => Some(error),
+            ImageMapError::DimensionMismatch { .. } => None,
+        }
+    }
+}
+
+impl From<ImageError> for ImageMapError {
+    fn from(error: ImageError) -> Self {
+        ImageMapError::Image(error)
+    }
+}
+
+/// Interpret `image` as a map of kind `kind` on a grid of size `grid_size`
+pub fn from_image(
+    image: &GrayImage,
+    grid_size: (usize, usize),
+    kind: MapKind,
+) -> Result<SpatialMap, ImageMapError> {
+    let dimensions = (image.width() as usize, image.height() as usize);
+    if dimensions != grid_size {
+        return Err(ImageMapError::DimensionMismatch {
+            image: dimensions,
+            grid: grid_size,
+        });
+    }
+    let intensity = |pixel: u8| f64::from(pixel) / 255.0;
+    Ok(match kind {
+        MapKind::TransmissionRisk => SpatialMap::TransmissionRisk(cell_values(image, intensity)),
+        MapKind::PlacementWeight => SpatialMap::PlacementWeight(cell_values(image, intensity)),
+        MapKind::Obstacles { threshold } => {
+            SpatialMap::Obstacles(cell_values(image, |pixel| pixel < threshold))
+        }
+    })
+}
+
+/// Map the intensity of each pixel of `image` to the value of its cell
+fn cell_values<T>(image: &GrayImage, value: impl Fn(u8) -> T) -> GridMap<T> {
+    let (xdim, ydim) = (image.width(), image.height());
+    let values = (0..xdim)
+        .flat_map(|x| (0..ydim).map(move |y| (x, y)))
+        .map(|(x, y)| value(image.get_pixel(x, y).0[0]))
+        .collect();
+    GridMap {
+        grid_size: (xdim as usize, ydim as usize),
+        values,
+    }
+}
+
+/// Load the image at `path` as a map of kind `kind` on a grid of size `grid_size`
+pub fn load<P: AsRef<Path>>(
+    path: P,
+    grid_size: (usize, usize),
+    kind: MapKind,
+) -> Result<SpatialMap, ImageMapError> {
+    let image = image::open(path)?.into_luma8();
+    from_image(&image, grid_size, kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// Image of 3x2 pixels, with intensities increasing along the columns
+    fn gradient() -> GrayImage {
+        GrayImage::from_fn(3, 2, |x, y| Luma([(x * 100 + y * 10) as u8]))
+    }
+
+    #[test]
+    fn test_intensity_maps() {
+        for &kind in &[MapKind::TransmissionRisk, MapKind::PlacementWeight] {
+            let map = match from_image(&gradient(), (3, 2), kind).unwrap() {
+                SpatialMap::TransmissionRisk(map) => {
+                    assert_eq!(kind, MapKind::TransmissionRisk);
+                    map
+                }
+                SpatialMap::PlacementWeight(map) => {
+                    assert_eq!(kind, MapKind::PlacementWeight);
+                    map
+                }
+                SpatialMap::Obstacles(_) => unreachable!(),
+            };
+            assert_eq!(map.grid_size(), (3, 2));
+            for x in 0..3 {
+                for y in 0..2 {
+                    let expected = (x * 100 + y * 10) as f64 / 255.0;
+                    assert_eq!(map.get(x, y), Some(&expected));
+                }
+            }
+            assert_eq!(map.get(3, 0), None);
+        }
+    }
+
+    #[test]
+    fn test_obstacles() {
+        let map = match from_image(&gradient(), (3, 2), MapKind::Obstacles { threshold: 105 }) {
+            Ok(SpatialMap::Obstacles(map)) => map,
+            other => panic!("unexpected {:?}", other),
+        };
+        let obstacles: Vec<_> = (0..3)
+            .flat_map(|x| (0..2).map(move |y| (x, y)))
+            .map(|(x, y)| *map.get(x, y).unwrap())
+            .collect();
+        // intensities 0, 10, 100, 110, 200, 210
+        assert_eq!(obstacles, vec![true, true, true, false, false, false]);
+        assert_eq!(map.cells(), vec![(0, 0), (0, 1), (1, 0)]);
+    }
+
+    #[test]
+    fn test_load_png() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("risk.png");
+        gradient().save(&path).unwrap();
+        assert_eq!(
+            load(&path, (3, 2), MapKind::TransmissionRisk).unwrap(),
+            from_image(&gradient(), (3, 2), MapKind::TransmissionRisk).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_dimension_mismatch() {
+        assert!(matches!(
+            from_image(&gradient(), (2, 3), MapKind::PlacementWeight),
+            Err(ImageMapError::DimensionMismatch {
+                image: (3, 2),
+                grid: (2, 3)
+            })
+        ));
+    }
+}
@@ -0,0 +1,325 @@
+//! Immune history of each agent, see [`ImmuneHistory`].
+//!
+//! Every agent records the events that changed its immunity, in the order they happened:
+//! each of its infections with the cell it happened in, their recoveries, the waning of its
+//! immunity, and the doses of vaccine it was given through [`Environment::immunize`]. The
+//! model has a single strain, such that infections carry no strain label.
+//! [`write_immune_history_csv`] dumps the histories of all agents, typically at the end of a
+//! run.
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+use std::io;
+
+use crate::julia_reimpl::Environment;
+
+/// Event in the immune history of an agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImmuneEvent {
+    /// The agent was infected at `tick` in the cell at `x` and `y`
+    Infection { tick: usize, x: usize, y: usize },
+    /// The agent recovered from its latest infection at `tick`
+    Recovery { tick: usize },
+    /// The agent was given a dose of vaccine at `tick`
+    Vaccination { tick: usize },
+    /// The immunity of the agent from its latest recovery waned at `tick`, leaving it
+    /// susceptible, see [`SimulationParams::immunity_duration`]
+    ///
+    /// [`SimulationParams::immunity_duration`]: crate::params::SimulationParams::immunity_duration
+    Waning { tick: usize },
+}
+
+impl ImmuneEvent {
+    /// Tick at which the event happened
+    #[must_use]
+    pub fn tick(self) -> usize {
+        match self {
+            ImmuneEvent::Infection { tick, .. }
+            | ImmuneEvent::Recovery { tick }
+            | ImmuneEvent::Vaccination { tick }
+            | ImmuneEvent::Waning { tick } => tick,
+        }
+    }
+
+    /// Whether the event left the agent immune
+    #[must_use]
+    pub fn is_immunizing(self) -> bool {
+        matches!(
+            self,
+            ImmuneEvent::Recovery { .. } | ImmuneEvent::Vaccination { .. }
+        )
+    }
+}
+
+/// Events that changed the immunity of an agent, in the order they happened.
+///
+/// Most agents see few events, which are stored inline without allocating.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImmuneHistory {
+    events: SmallVec<[ImmuneEvent; 2]>,
+}
+
+impl ImmuneHistory {
+    pub(crate) fn push(&mut self, event: ImmuneEvent) {
+        self.events.push(event);
+    }
+
+    /// All events, in the order they happened
+    #[must_use]
+    pub fn events(&self) -> &[ImmuneEvent] {
+        &self.events
+    }
+
+    /// Ticks of the infections of the agent
+    pub fn infections(&self) -> impl Iterator<Item = usize> + '_ {
+        self.events.iter().filter_map(|event| match *event {
+            ImmuneEvent::Infection { tick, .. } => Some(tick),
+            _ => None,
+        })
+    }
+
+    /// Ticks of the doses of vaccine given to the agent
+    pub fn doses(&self) -> impl Iterator<Item = usize> + '_ {
+        self.events.iter().filter_map(|event| match *event {
+            ImmuneEvent::Vaccination { tick } => Some(tick),
+            _ => None,
+        })
+    }
+
+    /// Tick of the latest recovery or dose of vaccine, if any
+    #[must_use]
+    pub fn last_immunized(&self) -> Option<usize> {
+        self.events
+            .iter()
+            .rev()
+            .find(|event| event.is_immunizing())
+            .map(|event| event.tick())
+    }
+
+    /// Heap memory held by the history in bytes, zero while the events are stored inline
+    pub(crate) fn heap_bytes(&self) -> usize {
+        if self.events.spilled() {
+            self.events.capacity() * std::mem::size_of::<ImmuneEvent>()
+        } else {
+            0
+        }
+    }
+}
+
+/// Write the immune history of each agent of `env` as CSV, with the ticks of its infections
+/// and of its doses of vaccine separated by `;`, and the tick of its latest immunizing event
+/// or an empty field
+pub fn write_immune_history_csv<X, W: io::Write>(
+    env: &Environment<X>,
+    mut writer: W,
+) -> io::Result<()> {
+    fn ticks(ticks: impl Iterator<Item = usize>) -> String {
+        ticks
+            .map(|tick| tick.to_string())
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+    writeln!(
+        writer,
+        "agent,infections,infection_ticks,doses,dose_ticks,last_immunized"
+    )?;
+    for agent in env.iter_agents() {
+        let history = &agent.immune_history;
+        writeln!(
+            writer,
+            "{},{},{},{},{},{}",
+            agent.id,
+            history.infections().count(),
+            ticks(history.infections()),
+            history.doses().count(),
+            ticks(history.doses()),
+            history
+                .last_immunized()
+                .map_or_else(String::new, |tick| tick.to_string())
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::julia_reimpl::AgentType;
+    use crate::params::{EnvironmentConfig, VectorParams};
+    use crate::presets;
+
+    #[test]
+    fn test_one_infection_per_agent() {
+        let mut environment = Environment::from_params(&presets::dense_small_grid());
+        environment.run();
+        for agent in environment.iter_agents() {
+            let history = &agent.immune_history;
+            let infections = history.infections().count();
+            match agent.state {
+                AgentType::AgentS => assert!(history.events().is_empty()),
+                AgentType::AgentR => {
+                    assert_eq!(infections, 1);
+                    assert_eq!(history.last_immunized(), Some(agent.since_tick));
+                }
+                AgentType::AgentD => {
+                    assert_eq!(history.events().len(), 1);
+                    assert_eq!(history.last_immunized(), None);
+                }
+                AgentType::AgentE | AgentType::AgentI | AgentType::AgentQ => {
+                    unreachable!("the run has ended")
+                }
+                AgentType::AgentV => unreachable!("no one is vaccinated"),
+            }
+            assert_eq!(history.doses().count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_vaccination_schedule() {
+        // two agents are vaccinated at each of ticks 1, 3 and 5
+        let schedule = [1, 3, 5];
+        let mut environment = Environment::from_params(&presets::dense_small_grid());
+        let mut vaccinated = Vec::new();
+        environment.run_with_hook(|environment, tick| {
+            if schedule.contains(&tick) {
+                let candidates: Vec<usize> = environment
+                    .iter_agents()
+                    .filter(|agent| agent.state == AgentType::AgentS)
+                    .map(|agent| agent.id)
+                    .take(2)
+                    .collect();
+                for id in candidates {
+                    assert!(environment.immunize(id));
+                    vaccinated.push((id, tick));
+                }
+            }
+        });
+        assert!(!vaccinated.is_empty());
+        for agent in environment.iter_agents() {
+            let doses: Vec<usize> = agent.immune_history.doses().collect();
+            let expected: Vec<usize> = vaccinated
+                .iter()
+                .filter(|(id, _)| *id == agent.id)
+                .map(|&(_, tick)| tick)
+                .collect();
+            assert_eq!(doses, expected);
+            if let Some(&tick) = expected.last() {
+                assert_eq!(agent.immune_history.infections().count(), 0);
+                assert_eq!(agent.immune_history.last_immunized(), Some(tick));
+            }
+        }
+    }
+
+    #[test]
+    fn test_rollout_schedule() {
+        let (params, _) = EnvironmentConfig::from(presets::dense_small_grid())
+            .vaccinate_at(1, 0.1)
+            .vaccinate_at(3, 0.2)
+            .vaccinate_at(5, 0.1)
+            .build_params()
+            .unwrap();
+        let mut environment = Environment::from_params(&params);
+        let record = environment.run();
+        let mut doses_per_tick = vec![0; record.len()];
+        for agent in environment.iter_agents() {
+            let doses: Vec<usize> = agent.immune_history.doses().collect();
+            assert!(doses.len() <= 1);
+            for tick in doses {
+                assert!([1, 3, 5].contains(&tick));
+                doses_per_tick[tick] += 1;
+            }
+        }
+        for tick in 1..record.len() {
+            assert_eq!(
+                doses_per_tick[tick],
+                record[tick].vaccinated - record[tick - 1].vaccinated
+            );
+        }
+        assert!([1, 3, 5].iter().all(|&tick| doses_per_tick[tick] > 0));
+    }
+
+    #[test]
+    fn test_reinfection_count() {
+        let waning = || {
+            EnvironmentConfig::from(presets::dense_small_grid())
+                .p_death(0.0)
+                .immunity_duration(3)
+                .max_ticks(100)
+        };
+        let vectors = VectorParams {
+            count: 200,
+            p_move: 1.0,
+            p_bite: 0.5,
+            p_transmit: 0.5,
+            infectious_duration: None,
+            turnover: 0.01,
+        };
+        // by contact, spillover and import, and by vectors
+        for config in [
+            waning().reservoirs(vec![(3, 3)], 0.2),
+            waning().vectors(vectors),
+        ] {
+            let (params, _) = config.build_params().unwrap();
+            let mut environment = Environment::from_params(&params);
+            environment.run_with_hook(|environment, tick| {
+                if tick % 10 == 0 {
+                    let susceptible = environment
+                        .iter_agents()
+                        .find(|agent| agent.state == AgentType::AgentS);
+                    if let Some(agent) = susceptible {
+                        environment.import_case(agent.id);
+                    }
+                }
+            });
+            let reinfections: usize = environment
+                .iter_agents()
+                .map(|agent| agent.immune_history.infections().count().saturating_sub(1))
+                .sum();
+            assert!(reinfections > 0);
+            assert_eq!(environment.reinfections(), reinfections);
+        }
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut history = ImmuneHistory::default();
+        history.push(ImmuneEvent::Infection {
+            tick: 2,
+            x: 0,
+            y: 0,
+        });
+        history.push(ImmuneEvent::Recovery { tick: 9 });
+        history.push(ImmuneEvent::Vaccination { tick: 12 });
+        history.push(ImmuneEvent::Infection {
+            tick: 30,
+            x: 1,
+            y: 0,
+        });
+        assert!(history.heap_bytes() > 0);
+        assert_eq!(history.infections().collect::<Vec<_>>(), vec![2, 30]);
+        assert_eq!(history.last_immunized(), Some(12));
+
+        let mut environment = Environment::from_params(&presets::dense_small_grid());
+        environment.run();
+        let mut csv = Vec::new();
+        write_immune_history_csv(&environment, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("agent,infections,infection_ticks,doses,dose_ticks,last_immunized")
+        );
+        let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), environment.iter_agents().count());
+        for (row, agent) in rows.iter().zip(environment.iter_agents()) {
+            assert_eq!(row.len(), 6);
+            assert_eq!(row[0], agent.id.to_string());
+            let infected = agent.state != AgentType::AgentS;
+            assert_eq!(row[1], if infected { "1" } else { "0" });
+            if agent.state == AgentType::AgentR {
+                assert_eq!(row[5], agent.since_tick.to_string());
+            } else {
+                assert_eq!(row[5], "");
+            }
+        }
+    }
+}
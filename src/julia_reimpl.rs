@@ -2,26 +2,95 @@
 //! Things to try out or expand on
 //!
 //! - Plot the states on the grid itself as to see how the spread is happening
-//! - Count how many times each cell has been occupied
 //! - Find out by how many agents has any given cell been occupied with at any given time?
 //!
 //!
 //!
 //! This is a strict Rust implementation of the presented Julia code in [bkamins' SIR blogpost](https://bkamins.github.io/julialang/2020/08/22/sir.html).
-use std::collections::HashMap;
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::time::{Duration, Instant};
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum AgentType {
     /// Susceptible
     AgentS,
+    /// Exposed, being infected but not yet infectious, see [`SimulationParams::incubation`]
+    AgentE,
     /// Infected
     AgentI,
+    /// Infected and quarantined, such that it neither moves nor infects others, see
+    /// [`QuarantineParams`]. Its infection runs its course from the tick it became infectious.
+    AgentQ,
     /// Recovered
     AgentR,
+    /// Vaccinated, see [`Environment::vaccinate`]
+    AgentV,
     /// Dead
     AgentD,
 }
 
-#[derive(Debug, Clone)]
+/// Read-only description of an agent, decoupled from its internal representation.
+///
+/// New fields may be added as the model grows, thus it cannot be constructed outside the crate.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct AgentView {
+    /// Index of the agent within the environment
+    pub id: usize,
+    /// Location of the agent in x-dimension
+    pub x: usize,
+    /// Location of the agent in y-dimension
+    pub y: usize,
+    /// State of the agent
+    pub state: AgentType,
+    /// Moment in time when agent entered `state`
+    pub since_tick: usize,
+    /// Whether the infection of the agent has been detected, see [`DetectionParams`]
+    pub detected: bool,
+    /// Last tick at which the agent is protected by prophylaxis, see [`PpeParams`]
+    pub protected_until: Option<usize>,
+    /// Probability of the agent taking part in a behavioural intervention, see [`Compliance`]
+    pub compliance: f64,
+    /// Whether the agent isolates, see [`SimulationParams::isolation`]
+    pub isolated: bool,
+    /// Number of infectious cellmates at the latest infection of the agent, unless it was not
+    /// infected by contact, see [`DoseSeverity`]
+    pub dose: Option<usize>,
+    /// Whether the agent is an essential worker, exempt from the lockdown
+    pub essential: bool,
+    /// Number of ticks at which the agent was susceptible and shared its cell with at least
+    /// one agent that could infect it, whether or not it was infected. This includes the tick
+    /// of its infection by contact, and excludes exposure to vectors.
+    pub exposure: usize,
+    /// Infections, recoveries, waning immunity and doses of vaccine of the agent so far
+    pub immune_history: ImmuneHistory,
+    /// Factor of the probability of the agent being infected, see [`AgentParams`]
+    pub susceptibility: Option<f64>,
+    /// Probability of death of the agent, unless it is that of the environment
+    pub p_death: Option<f64>,
+    /// Duration of the infections of the agent, unless it is that of the environment
+    pub duration: Option<usize>,
+    /// Duration of the latest infection of the agent, if it was drawn from the
+    /// [`DurationModel`] or given by its own duration
+    pub infection_duration: Option<usize>,
+}
+
+/// Location and initial state of an agent, see [`Environment::from_agents`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentSpec {
+    /// Location of the agent in x-dimension
+    pub x: usize,
+    /// Location of the agent in y-dimension
+    pub y: usize,
+    /// State of the agent, entered at tick zero
+    pub state: AgentType,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Agent {
     /// Location of an gent in x-dimension
     x: usize,
@@ -31,64 +100,749 @@ struct Agent {
     agent_type: AgentType,
     /// Moment in time when agent entered `type`
     tick: usize,
+    /// Whether the infection of the agent has been detected
+    detected: bool,
+    /// Last tick of post-exposure prophylaxis, see [`PpeParams`]
+    protected_until: Option<usize>,
+    /// Probability of taking part in a behavioural intervention, see [`Compliance`]
+    compliance: f64,
+    /// Whether the agent isolates, such that it neither moves nor infects its cellmates
+    isolated: bool,
+    /// Number of infectious cellmates at the latest infection by contact, see [`DoseSeverity`]
+    dose: Option<usize>,
+    /// Whether the agent is an essential worker, exempt from the lockdown
+    essential: bool,
+    /// Number of ticks the agent was exposed while susceptible, see [`AgentView::exposure`]
+    exposure: usize,
+    /// Last tick counted in `exposure`
+    exposed_at: Option<usize>,
+    /// Events that changed the immunity of the agent
+    history: ImmuneHistory,
+    /// Factor of the probability of being infected, see [`AgentParams`]
+    #[serde(default)]
+    susceptibility: Option<f64>,
+    /// Probability of death in place of that of the environment
+    #[serde(default)]
+    p_death: Option<f64>,
+    /// Duration of infection in place of that of the environment
+    #[serde(default)]
+    duration: Option<usize>,
+    /// Duration of the latest infection in place of that of the environment, see
+    /// [`DurationModel`]
+    #[serde(default)]
+    infection_duration: Option<usize>,
 }
 
 impl Agent {
+    /// Agent that entered `agent_type` at tick zero, being infected at that tick if it is
+    /// infected
+    fn new(x: usize, y: usize, agent_type: AgentType) -> Self {
+        let mut history = ImmuneHistory::default();
+        match agent_type {
+            AgentType::AgentE | AgentType::AgentI | AgentType::AgentQ => {
+                history.push(ImmuneEvent::Infection { tick: 0, x, y })
+            }
+            AgentType::AgentV => history.push(ImmuneEvent::Vaccination { tick: 0 }),
+            AgentType::AgentS | AgentType::AgentR | AgentType::AgentD => {}
+        }
+        Self {
+            x,
+            y,
+            agent_type,
+            tick: 0,
+            detected: false,
+            protected_until: None,
+            compliance: 1.0,
+            isolated: false,
+            dose: None,
+            essential: false,
+            exposure: 0,
+            exposed_at: None,
+            history,
+            susceptibility: None,
+            p_death: None,
+            duration: None,
+            infection_duration: None,
+        }
+    }
+
+    fn view(&self, id: usize) -> AgentView {
+        AgentView {
+            id,
+            x: self.x,
+            y: self.y,
+            state: self.agent_type.clone(),
+            since_tick: self.tick,
+            detected: self.detected,
+            protected_until: self.protected_until,
+            compliance: self.compliance,
+            isolated: self.isolated,
+            dose: self.dose,
+            essential: self.essential,
+            exposure: self.exposure,
+            immune_history: self.history.clone(),
+            susceptibility: self.susceptibility,
+            p_death: self.p_death,
+            duration: self.duration,
+            infection_duration: self.infection_duration,
+        }
+    }
+
+    /// Count `tick` in the exposure of the agent, once per tick
+    fn expose(&mut self, tick: usize) {
+        if self.exposed_at != Some(tick) {
+            self.exposure += 1;
+            self.exposed_at = Some(tick);
+        }
+    }
+
+    /// Factor of the probability of the agent being infected at `tick`, given its own
+    /// susceptibility and the parameters of the prophylaxis
+    fn susceptibility(&self, tick: usize, ppe: Option<&PpeParams>) -> f64 {
+        let protection = match (ppe, self.protected_until) {
+            (Some(ppe), Some(until)) if tick <= until => ppe.efficacy_complement,
+            _ => 1.0,
+        };
+        self.susceptibility
+            .map_or(protection, |factor| factor * protection)
+    }
+
     pub fn die(&mut self, tick: usize) {
         self.agent_type = AgentType::AgentD;
         self.tick = tick;
+        self.isolated = false;
     }
     pub fn recover(&mut self, tick: usize) {
         self.agent_type = AgentType::AgentR;
         self.tick = tick;
+        self.isolated = false;
+        self.history.push(ImmuneEvent::Recovery { tick });
     }
-    pub fn infect(&mut self, tick: usize) {
+    /// Whether the latest infection of the agent is not its first
+    fn is_reinfected(&self) -> bool {
+        self.history.infections().nth(1).is_some()
+    }
+    /// Make the recovered agent susceptible again, such that a later infection may be detected
+    /// anew
+    fn wane(&mut self, tick: usize) {
+        self.agent_type = AgentType::AgentS;
+        self.tick = tick;
+        self.detected = false;
+        self.history.push(ImmuneEvent::Waning { tick });
+    }
+    /// Infect the agent for `duration` ticks, unless it has its own duration, where `None`
+    /// keeps to the duration of the environment
+    pub fn infect(&mut self, tick: usize, duration: Option<usize>) {
         self.agent_type = AgentType::AgentI;
         self.tick = tick;
+        self.dose = None;
+        self.infection_duration = self.duration.or(duration);
+        self.history.push(ImmuneEvent::Infection {
+            tick,
+            x: self.x,
+            y: self.y,
+        });
+    }
+    /// Infect the agent by transmission, after which it is exposed for `incubation` ticks
+    /// before it becomes infectious, or infectious at once without an incubation period
+    fn contract(&mut self, tick: usize, incubation: usize, duration: Option<usize>) {
+        self.infect(tick, duration);
+        if incubation > 0 {
+            self.agent_type = AgentType::AgentE;
+        }
+    }
+    /// Make the agent immune by a dose of vaccine
+    fn vaccinate(&mut self, tick: usize) {
+        self.agent_type = AgentType::AgentV;
+        self.tick = tick;
+        self.history.push(ImmuneEvent::Vaccination { tick });
     }
 
-    pub fn move_agent(&mut self, grid_dimension: (usize, usize)) {
-        if let AgentType::AgentD = self.agent_type {
-        } else {
-            let mut rng = thread_rng();
-            let next_position_sampler = rand_distr::Uniform::new_inclusive(0, 1);
-            let negative_sampler = rand::distributions::Bernoulli::new(0.5).unwrap();
+    /// Number of ticks the current infection of the agent lasts, given the `duration` of the
+    /// environment
+    fn infectious_for(&self, duration: usize) -> usize {
+        self.infection_duration.unwrap_or(duration)
+    }
 
-            self.x = if rng.sample(negative_sampler) {
-                self.x.wrapping_add(rng.sample(next_position_sampler)) % grid_dimension.0
-            } else {
-                self.x.saturating_sub(rng.sample(next_position_sampler)) % grid_dimension.0
-            };
-            self.y = if rng.sample(negative_sampler) {
-                self.y.wrapping_add(rng.sample(next_position_sampler)) % grid_dimension.1
+    pub fn move_agent(
+        &mut self,
+        grid_dimension: (usize, usize),
+        boundary: Boundary,
+        rng: &mut impl Rng,
+    ) {
+        // the dead, quarantined and isolated stay in place, but draw their step all the same, so
+        // that the steps of the other agents come from the same random numbers whoever stays in
+        // place
+        let (x, y) = random_step((self.x, self.y), grid_dimension, boundary, rng);
+        if self.agent_type != AgentType::AgentD
+            && self.agent_type != AgentType::AgentQ
+            && !self.isolated
+        {
+            self.x = x;
+            self.y = y;
+        }
+    }
+}
+
+/// Duration of an infection drawn from `model`, or `None` to keep to the duration of the
+/// environment without a model
+fn draw_duration(model: Option<&DurationModel>, rng: &mut impl Rng) -> Option<usize> {
+    match *model? {
+        DurationModel::Fixed(duration) => Some(duration),
+        DurationModel::Uniform { min, max } => Some(rng.gen_range(min, max + 1)),
+        DurationModel::Geometric { p } => {
+            // number of trials until the first success, by inversion
+            let u = 1.0 - rng.gen::<f64>();
+            Some(1 + (u.ln() / (1.0 - p).ln()).floor() as usize)
+        }
+    }
+}
+
+/// Parameter of an agent drawn from `distribution`, which must be valid
+fn sample_agent_param(distribution: &AgentDistribution, rng: &mut impl Rng) -> f64 {
+    match *distribution {
+        AgentDistribution::Constant(value) => value,
+        AgentDistribution::Uniform { low, high } => low + (high - low) * rng.gen::<f64>(),
+        AgentDistribution::TwoGroup {
+            fraction,
+            high_risk,
+            other,
+        } => {
+            if rng.gen_bool(fraction) {
+                high_risk
             } else {
-                self.y.saturating_sub(rng.sample(next_position_sampler)) % grid_dimension.1
-            };
+                other
+            }
+        }
+    }
+}
+
+/// Compliance of an agent drawn from `compliance`, which must be valid
+fn sample_compliance(compliance: &Compliance, rng: &mut impl Rng) -> f64 {
+    match *compliance {
+        Compliance::Constant(value) => value,
+        Compliance::Uniform { low, high } => low + (high - low) * rng.gen::<f64>(),
+        Compliance::Beta { alpha, beta } => {
+            rng.sample(rand_distr::Beta::new(alpha, beta).expect("shape parameters are positive"))
+        }
+    }
+}
+
+/// Cells within Chebyshev distance `radius` of `(x, y)`, wrapping around the grid if it is a
+/// torus, each once even if the radius spans the whole grid
+fn neighbourhood(
+    (x, y): (usize, usize),
+    radius: usize,
+    (xdim, ydim): (usize, usize),
+    boundary: Boundary,
+) -> impl Iterator<Item = (usize, usize)> {
+    let window = move |center: usize, dim: usize| {
+        let (start, len) = if boundary != Boundary::Torus {
+            let start = center.saturating_sub(radius);
+            (start, (center + radius).min(dim - 1) + 1 - start)
+        } else if 2 * radius + 1 >= dim {
+            (0, dim)
+        } else {
+            (center + dim - radius, 2 * radius + 1)
+        };
+        (0..len).map(move |k| (start + k) % dim)
+    };
+    let xs = window(x, xdim);
+    window(y, ydim).flat_map(move |y| xs.clone().map(move |x| (x, y)))
+}
+
+/// Location after a random step of at most one cell in each dimension from `(x, y)`
+///
+/// As in the reference implementation, `mod1(x + rand(-1:1), xdim)`, the step in each dimension
+/// is drawn uniformly from {-1, 0, +1} independently of the other, so the walker stays in place
+/// with probability 1/9. On a torus a step off one edge enters at the opposite edge, otherwise
+/// it is kept within the grid by the `boundary`.
+fn random_step(
+    (x, y): (usize, usize),
+    grid_dimension: (usize, usize),
+    boundary: Boundary,
+    rng: &mut impl Rng,
+) -> (usize, usize) {
+    let step_sampler = rand_distr::Uniform::new_inclusive(-1, 1);
+    (
+        step_within(x, rng.sample(step_sampler), grid_dimension.0, boundary),
+        step_within(y, rng.sample(step_sampler), grid_dimension.1, boundary),
+    )
+}
+
+/// Coordinate `step` cells away from `coordinate` on a circle of `dim` cells
+fn wrap(coordinate: usize, step: isize, dim: usize) -> usize {
+    num::integer::mod_floor(coordinate as isize + step, dim as isize) as usize
+}
+
+/// Coordinate `step` cells away from `coordinate` on an axis of `dim` cells with `boundary`
+fn step_within(coordinate: usize, step: isize, dim: usize, boundary: Boundary) -> usize {
+    let last = dim as isize - 1;
+    let target = coordinate as isize + step;
+    match boundary {
+        Boundary::Torus => wrap(coordinate, step, dim),
+        Boundary::Clamped => target.clamp(0, last) as usize,
+        Boundary::Reflecting if target < 0 => (-target).min(last) as usize,
+        Boundary::Reflecting if target > last => (2 * last - target).max(0) as usize,
+        Boundary::Reflecting => target as usize,
+    }
+}
+
+/// State of a vector, see [`VectorParams`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VectorState {
+    Susceptible,
+    Infectious,
+    /// No longer infectious after [`VectorParams::infectious_duration`], nor susceptible
+    Spent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Vector {
+    x: usize,
+    y: usize,
+    state: VectorState,
+    /// Moment in time when the vector entered `state`
+    tick: usize,
+}
+
+/// Number of agents in each state in a cell of a [`GridSnapshot`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CellState {
+    pub susceptible: usize,
+    pub exposed: usize,
+    pub infected: usize,
+    pub quarantined: usize,
+    pub recovered: usize,
+    pub vaccinated: usize,
+    pub dead: usize,
+}
+
+impl CellState {
+    /// Whether no agent, dead or alive, occupies the cell
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.susceptible
+            + self.exposed
+            + self.infected
+            + self.quarantined
+            + self.recovered
+            + self.vaccinated
+            + self.dead
+            == 0
+    }
+
+    /// State that stands for the cell: infected if any agent is infected, otherwise
+    /// quarantined, exposed, then susceptible, recovered, vaccinated, and dead if only dead
+    /// agents remain, or `None` if the cell is empty
+    #[must_use]
+    pub fn dominant(&self) -> Option<AgentType> {
+        [
+            (self.infected, AgentType::AgentI),
+            (self.quarantined, AgentType::AgentQ),
+            (self.exposed, AgentType::AgentE),
+            (self.susceptible, AgentType::AgentS),
+            (self.recovered, AgentType::AgentR),
+            (self.vaccinated, AgentType::AgentV),
+            (self.dead, AgentType::AgentD),
+        ]
+        .iter()
+        .find(|(count, _)| *count > 0)
+        .map(|(_, state)| state.clone())
+    }
+
+    fn add(&mut self, state: &AgentType) {
+        match state {
+            AgentType::AgentS => self.susceptible += 1,
+            AgentType::AgentE => self.exposed += 1,
+            AgentType::AgentI => self.infected += 1,
+            AgentType::AgentQ => self.quarantined += 1,
+            AgentType::AgentR => self.recovered += 1,
+            AgentType::AgentV => self.vaccinated += 1,
+            AgentType::AgentD => self.dead += 1,
         }
     }
 }
 
+/// States of the cells of the grid at `tick`, see [`Environment::grid_snapshot`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GridSnapshot {
+    pub tick: usize,
+    /// Rows of cells, where `cells[y][x]` is the cell at `(x, y)`
+    pub cells: Vec<Vec<CellState>>,
+}
+
+/// Tally of the states of the vectors
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorTally {
+    pub susceptible: usize,
+    pub infectious: usize,
+    pub spent: usize,
+}
+
 /// World that the agents reside within
-pub struct Environment {
+///
+/// Each agent can carry user-defined data of type `X` alongside it, see [`Environment::ext`].
+/// The default `X = ()` occupies no memory per agent.
+///
+/// An environment (de)serialises with all of its state between ticks, see
+/// [`Environment::save_checkpoint`].
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Environment<X = ()> {
     /// For each cell of in the grid, a vector of numbers of agents currently occupying a given cell
-    // Note: We first attempt an implementation that relies on *maps
-    grid: HashMap<(usize, usize), Vec<usize>>,
+    grid: Grid,
     grid_size: (usize, usize),
+    /// Edges of the grid, see [`SimulationParams::boundary`]
+    #[serde(default)]
+    boundary: Boundary,
     agents: Vec<Agent>,
+    /// User-defined data of each agent, aligned with `agents`
+    extensions: Vec<X>,
     /// Duration of agents within infected state
     duration: usize,
+    /// Distribution of the duration of each infection, see [`SimulationParams::duration_model`]
+    #[serde(default)]
+    duration_model: Option<DurationModel>,
+    /// Ticks from infection to becoming infectious, see [`SimulationParams::incubation`]
+    #[serde(default)]
+    incubation: usize,
+    /// Ticks of immunity after recovery, see [`SimulationParams::immunity_duration`]
+    #[serde(default)]
+    immunity_duration: Option<usize>,
     /// Probability of death of an agent after duration of infection has elapsed.
     p_death: f64,
+    /// Whether the dead are taken out of the grid, see [`SimulationParams::corpse_handling`]
+    #[serde(default)]
+    corpse_handling: CorpseHandling,
+    /// Order in which the agents are processed, see [`SimulationParams::update_order`]
+    #[serde(default)]
+    update_order: UpdateOrder,
+    /// Probability of infection per infected cellmate and tick
+    p_infect: f64,
+    /// Chebyshev distance within which agents infect each other, see
+    /// [`SimulationParams::infection_radius`]
+    #[serde(default)]
+    infection_radius: usize,
+    /// Probability of an agent moving in a tick, before any lockdown
+    p_move: f64,
+    /// Windows of ticks with their probability of moving in place of `p_move`, see
+    /// [`Environment::set_movement_schedule`]
+    #[serde(default)]
+    movement_schedule: Vec<(Range<usize>, f64)>,
     /// Tally of the current states in the grid
     // stats: BTreeMap<AgentType, usize>,
     stats: TallyStates,
+    /// Infections of agents that had been infected before, see [`Environment::reinfections`]
+    #[serde(default)]
+    reinfections: usize,
     /// Current time tick
     tick: usize,
+    /// Ticks of movement before the initial infections were seeded
+    burn_in_ticks: usize,
+    /// Entries of the seeding schedule with their index in the schedule, ordered by tick
+    seeding: Vec<(usize, SeedingEntry)>,
+    /// Number of entries in `seeding` that have already been applied
+    next_seeding: usize,
+    /// Agents infected by the seeding schedule
+    seeding_log: Vec<SeedingRecord>,
+    /// Vaccination campaigns, ordered by tick
+    #[serde(default)]
+    vaccination: Vec<VaccinationEntry>,
+    /// Number of entries in `vaccination` that have already been applied
+    #[serde(default)]
+    next_vaccination: usize,
+    /// Parameters of the vectors, if the infection is vector-borne
+    vector_params: Option<VectorParams>,
+    vectors: Vec<Vector>,
+    /// Tally of the vectors for each tick of the run
+    vector_record: Vec<VectorTally>,
+    /// Cells of the animal reservoir, see [`SimulationParams::reservoirs`]
+    reservoirs: Vec<(usize, usize)>,
+    p_spillover: f64,
+    /// Agents infected by spillover from the reservoir
+    spillover_log: Vec<SpilloverRecord>,
+    detection: Option<DetectionParams>,
+    ppe: Option<PpeParams>,
+    /// Infected agents whose infection was detected
+    detection_log: Vec<DetectionRecord>,
+    /// Whether detected agents isolate
+    isolation: bool,
+    /// Testing of infected agents, see [`SimulationParams::quarantine`]
+    #[serde(default)]
+    quarantine: Option<QuarantineParams>,
+    dose_severity: DoseSeverity,
+    lockdown: Option<Lockdown>,
+    /// Parameters the environment was set up with, updated by [`Environment::branch`]
+    params: SimulationParams,
+    /// Whether [`Environment::check_invariants`] runs after every tick
+    invariant_checks: bool,
+    /// Cumulative counts per cell, if enabled by [`Environment::enable_cell_tracking`]
+    #[serde(default)]
+    cell_tracking: Option<CellTracking>,
+    /// Infections in the order they happened, if enabled by
+    /// [`Environment::enable_infection_events`]
+    #[serde(default)]
+    infection_events: Option<Vec<InfectionEvent>>,
+    /// Changes of state and locations of the agents, if enabled by
+    /// [`Environment::enable_event_recording`]
+    #[serde(default)]
+    event_recorder: Option<EventRecorder>,
+    /// Seed from which the random numbers of the setup and of each tick are drawn
+    seed: u64,
+    /// Random number generator of the current tick, see [`tick_rng`]
+    ///
+    /// Not serialised, as it is drawn anew from the seed at the start of each tick.
+    #[serde(skip, default = "unseeded_rng")]
+    rng: StdRng,
+    /// Random number generator of the movement of the agents in the current tick, see
+    /// [`MOVEMENT_STREAM`]
+    #[serde(skip, default = "unseeded_rng")]
+    movement_rng: StdRng,
+    /// Buffers of [`Environment::update_type`], kept between ticks to reuse their allocations
+    #[serde(skip)]
+    scratch: Scratch,
+}
+
+/// Transitions decided in a tick before they are applied, see [`Environment::update_type`]
+#[derive(Debug, Clone, Default)]
+struct Scratch {
+    /// Infected agents whose infection ends, and whether they die
+    outcomes: Vec<(usize, bool)>,
+    /// Infections of the tick by the infecting agent, of the infected agent in a cell with
+    /// the dose of the cell
+    infections: Vec<(usize, usize, (usize, usize), usize)>,
+    /// Whether each agent is infected in the tick
+    infecting: Vec<bool>,
+    /// Indices of the agents in the order they are processed in the tick, see [`UpdateOrder`]
+    order: Vec<usize>,
+}
+
+/// Cumulative counts of each cell of the grid in row-major order, see
+/// [`Environment::cell_visit_counts`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CellTracking {
+    visits: Vec<usize>,
+    infections: Vec<usize>,
+}
+
+/// Agents occupying each cell of the grid, stored densely in row-major order, such that the
+/// agents at `(x, y)` are at index `y * xdim + x`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Grid {
+    xdim: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl Grid {
+    /// Grid of the given size without any agents
+    fn new((xdim, ydim): (usize, usize)) -> Self {
+        Self {
+            xdim,
+            cells: vec![Vec::new(); xdim * ydim],
+        }
+    }
+
+    /// Agents in `cell`, which must lie within the grid
+    fn agents(&self, (x, y): (usize, usize)) -> &[usize] {
+        &self.cells[y * self.xdim + x]
+    }
+
+    /// Place `agent` in `cell`, which must lie within the grid
+    fn push(&mut self, (x, y): (usize, usize), agent: usize) {
+        self.cells[y * self.xdim + x].push(agent);
+    }
+
+    /// Remove all agents, keeping the memory of each cell for the next tick
+    fn clear(&mut self) {
+        self.cells.iter_mut().for_each(Vec::clear);
+    }
+
+    /// Location and agents of each cell
+    fn iter(&self) -> impl Iterator<Item = ((usize, usize), &Vec<usize>)> + '_ {
+        let xdim = self.xdim;
+        self.cells
+            .iter()
+            .enumerate()
+            .map(move |(i, agents)| ((i % xdim, i / xdim), agents))
+    }
+}
+
+/// Record the change of state of agent `id` from `from` into its current state at `tick`, if
+/// recording is on
+fn record_transition(
+    recorder: &mut Option<EventRecorder>,
+    tick: usize,
+    id: usize,
+    from: AgentType,
+    agent: &Agent,
+) {
+    if let Some(recorder) = recorder {
+        recorder.push_transition(Transition {
+            agent_id: id,
+            tick,
+            from_state: from,
+            to_state: agent.agent_type.clone(),
+            x: agent.x,
+            y: agent.y,
+        });
+    }
+}
+
+/// Placeholder of a random number generator that is replaced before it is drawn from, see
+/// [`Environment::advance`]
+fn unseeded_rng() -> StdRng {
+    StdRng::from_seed([0; 32])
+}
+
+/// Random number generator of `tick` of an environment with `seed`, where tick zero sets up
+/// the environment.
+///
+/// Each tick draws from its own stream, such that copies of an environment with the same seed
+/// continue identically from any tick, whatever happened before.
+fn tick_rng(seed: u64, tick: usize) -> StdRng {
+    stream_rng(seed, tick, 0)
+}
+
+/// Stream of the random numbers of the movement of the agents in a tick, apart from those of
+/// the course of infection, such that scenarios that differ only by their interventions move
+/// their agents alike under the same seed
+const MOVEMENT_STREAM: u64 = 1;
+
+/// Random number generator of `stream` in `tick` of an environment with `seed`
+fn stream_rng(seed: u64, tick: usize, stream: u64) -> StdRng {
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(&seed.to_le_bytes());
+    key[8..16].copy_from_slice(&(tick as u64).to_le_bytes());
+    key[16..24].copy_from_slice(&stream.to_le_bytes());
+    StdRng::from_seed(key)
+}
+
+/// An agent that was infected by an entry of the seeding schedule
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SeedingRecord {
+    /// Index of the entry in [`SimulationParams::seeding`]
+    pub entry: usize,
+    /// Tick at which the agent was infected
+    pub tick: usize,
+    /// Index of the infected agent
+    pub agent: usize,
+    /// Location of the agent in x-dimension when infected
+    pub x: usize,
+    /// Location of the agent in y-dimension when infected
+    pub y: usize,
+}
+
+/// Infections by contact with a given number of infectious cellmates, see
+/// [`DoseSeverity`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DoseOutcome {
+    /// Number of infectious agents in the cell at the moment of infection
+    pub dose: usize,
+    /// Number of agents infected with this dose
+    pub infected: usize,
+    /// Of which have recovered
+    pub recovered: usize,
+    /// Of which have died
+    pub dead: usize,
+}
+
+impl DoseOutcome {
+    /// Case fatality ratio among the resolved infections, if there are any
+    #[must_use]
+    pub fn cfr(&self) -> Option<f64> {
+        let resolved = self.recovered + self.dead;
+        if resolved > 0 {
+            Some(self.dead as f64 / resolved as f64)
+        } else {
+            None
+        }
+    }
+}
+
+/// Attack rates stratified by whether agents are essential workers, see
+/// [`Environment::attack_rate_by_essential`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EssentialAttackRates {
+    pub essential: Option<f64>,
+    pub other: Option<f64>,
+}
+
+/// Number of agents by their exposure in ticks, where the count at index `k` is the number of
+/// agents exposed for `k` ticks
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExposureDistribution {
+    /// Agents that are still susceptible
+    pub never_infected: Vec<usize>,
+    /// Agents that are no longer susceptible
+    pub infected: Vec<usize>,
+}
+
+/// An infected agent whose infection was detected
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetectionRecord {
+    /// Tick at which the infection was detected
+    pub tick: usize,
+    /// Index of the detected agent
+    pub agent: usize,
+    /// Location of the agent when detected
+    pub x: usize,
+    pub y: usize,
+    /// Susceptible cellmates that were given prophylaxis, see [`PpeParams`]
+    pub protected: Vec<usize>,
+}
+
+/// An agent that was infected by spillover in a reservoir cell
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpilloverRecord {
+    /// Tick at which the agent was infected
+    pub tick: usize,
+    /// Index of the infected agent
+    pub agent: usize,
+    /// Reservoir cell in which the agent was infected
+    pub x: usize,
+    pub y: usize,
+}
+
+/// Infection of an agent, see [`Environment::enable_infection_events`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InfectionEvent {
+    /// Index of the agent that passed on the infection, or `None` if the infection did not
+    /// come from an agent, as for the initial infections and those that were imported,
+    /// spilled over from the reservoir or passed on by a vector
+    pub source: Option<usize>,
+    /// Index of the infected agent
+    pub target: usize,
+    /// Tick at which the agent was infected
+    pub tick: usize,
+    /// Cell in which the agent was infected
+    pub cell: (usize, usize),
 }
 
 use rand::prelude::*;
 
+use crate::events::{EventRecorder, Transition};
+use crate::immunity::{ImmuneEvent, ImmuneHistory};
+use crate::params::{
+    AgentDistribution, AgentParams, Boundary, Compliance, ConfigError, CorpseHandling,
+    DetectionParams, DoseSeverity, DurationModel, Lockdown, PpeParams, QuarantineParams,
+    SeedLocation, SeedingEntry, SeedingStrategy, SimulationParams, UpdateOrder, VaccinationEntry,
+    VectorParams,
+};
+use crate::result::TerminationReason;
+use crate::summary::EpidemicSummary;
+
 impl Environment {
+    /// Set up an environment as described by `params`
+    #[must_use]
+    pub fn from_params(params: &SimulationParams) -> Self {
+        Self::from_params_with(params, |_, _, _| ())
+    }
+
+    /// Set up `n` agents, `infected` of which are infected, on a grid of `xdim` by `ydim` cells.
+    ///
+    /// The arguments are not validated, see
+    /// [`EnvironmentConfig::build`](crate::params::EnvironmentConfig::build) for a configuration
+    /// that rejects invalid combinations instead of panicking.
     #[must_use]
     pub fn init(
         n: usize,
@@ -98,227 +852,4753 @@ impl Environment {
         xdim: usize,
         ydim: usize,
     ) -> Self {
-        let mut grid: HashMap<(usize, usize), Vec<usize>> = HashMap::with_capacity(xdim * ydim);
+        Self::init_with(n, infected, duration, p_death, xdim, ydim, |_, _, _| ())
+    }
+
+    /// Like [`Environment::init`], where all random numbers are drawn from `seed`, such that
+    /// the same arguments give the same run
+    #[must_use]
+    pub fn init_with_seed(
+        n: usize,
+        infected: usize,
+        duration: usize,
+        p_death: f64,
+        xdim: usize,
+        ydim: usize,
+        seed: u64,
+    ) -> Self {
+        Self::from_params(&SimulationParams {
+            n,
+            infected,
+            duration,
+            p_death,
+            xdim,
+            ydim,
+            seed: Some(seed),
+            ..SimulationParams::default()
+        })
+    }
+
+    /// Set up an environment with exactly the agents of `specs` on a grid of `xdim` by `ydim`
+    /// cells, each at its location and in its state, which it entered at tick zero.
+    ///
+    /// Infected agents infect their cellmates with certainty, and all agents move in each
+    /// tick. These and the other parameters of the course of infection can be changed by
+    /// [`Environment::branch`]. All random numbers are drawn from `seed`, or from a random
+    /// seed without one, as in [`Environment::from_params`].
+    ///
+    /// Fails if the grid is empty, `p_death` is not a probability or an agent lies outside
+    /// the grid.
+    ///
+    /// ```
+    /// # use bkamins_sir_abm::julia_reimpl::{AgentSpec, AgentType, Environment};
+    /// let spec = |state| AgentSpec { x: 1, y: 2, state };
+    /// let specs = vec![spec(AgentType::AgentI), spec(AgentType::AgentS)];
+    /// let mut environment = Environment::from_agents(specs, 5, 0.0, 3, 3, Some(1)).unwrap();
+    /// assert_eq!(environment.step().infected, 2);
+    /// ```
+    pub fn from_agents(
+        specs: Vec<AgentSpec>,
+        duration: usize,
+        p_death: f64,
+        xdim: usize,
+        ydim: usize,
+        seed: Option<u64>,
+    ) -> Result<Self, ConfigError> {
+        SimulationParams {
+            n: 0,
+            infected: 0,
+            duration,
+            p_death,
+            xdim,
+            ydim,
+            ..SimulationParams::default()
+        }
+        .validate()?;
+        if let Some(spec) = specs.iter().find(|spec| spec.x >= xdim || spec.y >= ydim) {
+            return Err(ConfigError::OutOfBounds {
+                x: spec.x,
+                y: spec.y,
+            });
+        }
+        let layout: Vec<_> = specs
+            .into_iter()
+            .map(|spec| (spec.x, spec.y, spec.state))
+            .collect();
+        Ok(Self::from_layout(
+            &layout,
+            duration,
+            p_death,
+            (xdim, ydim),
+            seed,
+            |_, _, _| (),
+        ))
+    }
+}
+
+impl<X> Environment<X> {
+    /// Set up an environment as described by `params`, where the data of each agent is given by
+    /// `extension(agent_index, x, y)` evaluated at the agent's location when the epidemic starts.
+    ///
+    /// With `params.burn_in_ticks > 0`, the agents first move around for that many ticks
+    /// before the initial infections are seeded, and the epidemic starts at tick zero.
+    ///
+    /// Without agents or initial infections, the environment is not active and a run ends
+    /// with the tally of tick zero. Parameters that do not [validate](SimulationParams::validate)
+    /// with more initial infections than agents infect all agents.
+    #[must_use]
+    pub fn from_params_with(
+        params: &SimulationParams,
+        mut extension: impl FnMut(usize, usize, usize) -> X,
+    ) -> Self {
+        let &SimulationParams {
+            n,
+            infected,
+            duration,
+            p_death,
+            xdim,
+            ydim,
+            burn_in_ticks,
+            ..
+        } = params;
+        let infected = infected.min(n);
+        let mut grid = Grid::new((xdim, ydim));
 
-        let mut rng = thread_rng();
+        let seed = params.seed.unwrap_or_else(|| thread_rng().gen());
+        let mut rng = tick_rng(seed, 0);
         let rand_loc_x = rand_distr::Uniform::new(0, xdim);
         let rand_loc_y = rand_distr::Uniform::new(0, ydim);
 
-        let agents: Vec<Agent> = (0..n)
-            .map(|i| Agent {
-                x: rng.sample(rand_loc_x),
-                y: rng.sample(rand_loc_y),
-                agent_type: if i <= infected {
-                    AgentType::AgentI
-                } else {
-                    AgentType::AgentS
-                },
-                tick: 0,
+        let mut agents: Vec<Agent> = (0..n)
+            .map(|_| Agent {
+                compliance: sample_compliance(&params.compliance, &mut rng),
+                ..Agent::new(
+                    rng.sample(rand_loc_x),
+                    rng.sample(rand_loc_y),
+                    AgentType::AgentS,
+                )
             })
             .collect();
+        let essential = (params.essential_fraction * n as f64).round() as usize;
+        for i in rand::seq::index::sample(&mut rng, n, essential.min(n)).into_iter() {
+            agents[i].essential = true;
+        }
+        let AgentParams {
+            susceptibility,
+            p_death: agent_p_death,
+            duration: agent_duration,
+        } = &params.heterogeneity;
+        for agent in &mut agents {
+            let mut sample = |distribution: &Option<AgentDistribution>| {
+                distribution
+                    .as_ref()
+                    .map(|distribution| sample_agent_param(distribution, &mut rng))
+            };
+            agent.susceptibility = sample(susceptibility);
+            agent.p_death = sample(agent_p_death);
+            agent.duration = sample(agent_duration).map(|duration| duration.round() as usize);
+        }
 
         for (index, agent) in agents.iter().enumerate() {
-            grid.entry((agent.x, agent.y))
-                .and_modify(|x| x.push(index))
-                .or_insert_with(|| vec![index]);
+            grid.push((agent.x, agent.y), index);
         }
 
         let stats = TallyStates {
             susceptible: n - infected,
+            exposed: 0,
             infected,
+            quarantined: 0,
             recovered: 0,
+            vaccinated: 0,
             dead: 0,
         };
 
-        Self {
+        let mut seeding: Vec<_> = params.seeding.iter().cloned().enumerate().collect();
+        seeding.sort_by_key(|(_, entry)| entry.tick);
+        let mut vaccination = params.vaccination.clone();
+        vaccination.sort_by_key(|entry| entry.tick);
+
+        let vectors = params.vectors.as_ref().map_or(0, |vectors| vectors.count);
+        let vectors: Vec<Vector> = (0..vectors)
+            .map(|_| Vector {
+                x: rng.sample(rand_loc_x),
+                y: rng.sample(rand_loc_y),
+                state: VectorState::Susceptible,
+                tick: 0,
+            })
+            .collect();
+
+        let mut environment = Self {
             grid,
             grid_size: (xdim, ydim),
+            boundary: params.boundary,
             agents,
+            extensions: Vec::with_capacity(n),
             duration,
+            duration_model: params.duration_model.clone(),
+            incubation: params.incubation,
+            immunity_duration: params.immunity_duration,
             p_death,
+            corpse_handling: params.corpse_handling,
+            update_order: params.update_order,
+            p_infect: params.p_infect,
+            infection_radius: params.infection_radius,
+            p_move: params.p_move,
+            movement_schedule: Vec::new(),
             stats,
+            reinfections: 0,
             tick: 0,
-        }
-    }
+            burn_in_ticks,
+            seeding,
+            next_seeding: 0,
+            seeding_log: Vec::new(),
+            vaccination,
+            next_vaccination: 0,
+            vector_params: params.vectors.clone(),
+            vectors,
+            vector_record: Vec::new(),
+            reservoirs: params.reservoirs.clone(),
+            p_spillover: params.p_spillover,
+            spillover_log: Vec::new(),
+            detection: params.detection.clone(),
+            quarantine: params.quarantine.clone(),
+            ppe: params.ppe.clone(),
+            detection_log: Vec::new(),
+            isolation: params.isolation,
+            dose_severity: params.dose_severity.clone(),
+            lockdown: params.lockdown.clone(),
+            params: params.clone(),
+            invariant_checks: cfg!(debug_assertions),
+            cell_tracking: None,
+            infection_events: None,
+            event_recorder: None,
+            seed,
+            rng,
+            movement_rng: stream_rng(seed, 0, MOVEMENT_STREAM),
+            scratch: Scratch::default(),
+        };
 
-    pub fn update_type(&mut self) {
-        let tick = self.tick;
-        let mut rng = thread_rng();
-        // note: cannot change agents while also using their present state
-        // let past_agents = self.agents.clone();
-        for i in 0..self.agents.len() {
-            if let AgentType::AgentI = self.agents[i].agent_type {
-                if tick - self.agents[i].tick > self.duration {
-                    if rng.gen_bool(self.p_death) {
-                        self.agents[i].die(tick)
-                    } else {
-                        self.agents[i].recover(tick)
-                    }
-                } else {
-                    if tick == self.agents[i].tick {
-                        continue;
-                    }
+        // movement only, no one is infected yet
+        for _ in 0..burn_in_ticks {
+            move_all(&mut environment);
+        }
 
-                    for j in self.grid[&(self.agents[i].x, self.agents[i].y)]
-                        .clone()
-                        .into_iter()
-                    {
-                        if let AgentType::AgentS = self.agents[j].agent_type {
-                            self.agents[j].infect(tick);
-                        }
-                    }
-                }
+        environment.place_initial_infections(&params.initial_seeding, infected);
+        for (i, agent) in environment.agents.iter_mut().enumerate() {
+            if i < infected {
+                let duration =
+                    draw_duration(environment.duration_model.as_ref(), &mut environment.rng);
+                agent.infect(0, duration);
             }
         }
-    }
-
-    #[must_use]
-    pub fn get_statistics(&self) -> TallyStates {
-        self.agents
+        environment.extensions = environment
+            .agents
             .iter()
-            .fold(TallyStates::default(), |mut acc, x| {
-                match x.agent_type {
-                    AgentType::AgentS => {
-                        acc.susceptible += 1;
-                    }
-                    AgentType::AgentI => {
-                        acc.infected += 1;
-                    }
-                    AgentType::AgentR => {
-                        acc.recovered += 1;
-                    }
-                    AgentType::AgentD => {
-                        acc.dead += 1;
-                    }
-                };
-                acc
-            })
+            .enumerate()
+            .map(|(index, agent)| extension(index, agent.x, agent.y))
+            .collect();
+        environment.apply_seeding();
+        environment.apply_vaccination();
+
+        environment
     }
 
-    pub fn run(&mut self) -> Vec<TallyStates> {
-        // max ticks for the default scenario is 300 ticks
-        let mut stats_ticks = vec![self.stats.clone()];
-
-        while self.stats.infected > 0 {
-            // run while there are infected individuals
-            self.tick += 1;
-            self.update_type();
-            move_all(self);
-            //FIXME: maybe this needs to be polled somehow?
-            self.stats = self.get_statistics();
-            stats_ticks.push(self.stats.clone());
+    /// Move the first `infected` agents, which are about to be infected, to the cells given by
+    /// `strategy`, which must be valid
+    fn place_initial_infections(&mut self, strategy: &SeedingStrategy, infected: usize) {
+        let cells: Vec<(usize, usize)> = match *strategy {
+            SeedingStrategy::Random => return,
+            SeedingStrategy::Clustered { center, radius } => {
+                let xs = rand_distr::Uniform::new_inclusive(center.0 - radius, center.0 + radius);
+                let ys = rand_distr::Uniform::new_inclusive(center.1 - radius, center.1 + radius);
+                (0..infected)
+                    .map(|_| (self.rng.sample(xs), self.rng.sample(ys)))
+                    .collect()
+            }
+            SeedingStrategy::Explicit(ref cells) => cells.clone(),
+        };
+        for (agent, &(x, y)) in self.agents.iter_mut().zip(&cells) {
+            agent.x = x;
+            agent.y = y;
+        }
+        self.grid.clear();
+        for (index, agent) in self.agents.iter().enumerate() {
+            self.grid.push((agent.x, agent.y), index);
         }
-
-        stats_ticks
     }
-}
 
-use soa_derive::StructOfArray;
+    /// Set up an environment with agents at the given locations and in the given states, all
+    /// of which were entered at tick zero. The locations must lie within the grid. All random
+    /// numbers are drawn from `seed`, or from a random seed without one.
+    pub(crate) fn from_layout(
+        layout: &[(usize, usize, AgentType)],
+        duration: usize,
+        p_death: f64,
+        grid_size: (usize, usize),
+        seed: Option<u64>,
+        mut extension: impl FnMut(usize, usize, usize) -> X,
+    ) -> Self {
+        let seed = seed.unwrap_or_else(|| thread_rng().gen());
+        let mut grid = Grid::new(grid_size);
+        let agents: Vec<Agent> = layout
+            .iter()
+            .map(|(x, y, agent_type)| Agent::new(*x, *y, agent_type.clone()))
+            .collect();
+        for (index, agent) in agents.iter().enumerate() {
+            grid.push((agent.x, agent.y), index);
+        }
+        let extensions = agents
+            .iter()
+            .enumerate()
+            .map(|(index, agent)| extension(index, agent.x, agent.y))
+            .collect();
 
-#[derive(Debug, Default, Clone, StructOfArray)]
-#[soa_derive = "Debug"]
-pub struct TallyStates {
-    susceptible: usize,
-    infected: usize,
-    recovered: usize,
-    dead: usize,
-}
+        let mut environment = Self {
+            grid,
+            grid_size,
+            boundary: Boundary::Torus,
+            agents,
+            extensions,
+            duration,
+            duration_model: None,
+            incubation: 0,
+            immunity_duration: None,
+            p_death,
+            corpse_handling: CorpseHandling::Keep,
+            update_order: UpdateOrder::Indexed,
+            p_infect: 1.0,
+            infection_radius: 0,
+            p_move: 1.0,
+            movement_schedule: Vec::new(),
+            stats: TallyStates::default(),
+            reinfections: 0,
+            tick: 0,
+            burn_in_ticks: 0,
+            seeding: Vec::new(),
+            next_seeding: 0,
+            seeding_log: Vec::new(),
+            vaccination: Vec::new(),
+            next_vaccination: 0,
+            vector_params: None,
+            vectors: Vec::new(),
+            vector_record: Vec::new(),
+            reservoirs: Vec::new(),
+            p_spillover: 0.0,
+            spillover_log: Vec::new(),
+            detection: None,
+            quarantine: None,
+            ppe: None,
+            detection_log: Vec::new(),
+            isolation: false,
+            dose_severity: DoseSeverity::default(),
+            lockdown: None,
+            params: SimulationParams::default(),
+            invariant_checks: cfg!(debug_assertions),
+            cell_tracking: None,
+            infection_events: None,
+            event_recorder: None,
+            seed,
+            rng: tick_rng(seed, 0),
+            movement_rng: stream_rng(seed, 0, MOVEMENT_STREAM),
+            scratch: Scratch::default(),
+        };
+        environment.stats = environment.get_statistics();
+        environment.params = SimulationParams {
+            n: layout.len(),
+            infected: environment.stats.infected,
+            duration,
+            p_death,
+            p_infect: environment.p_infect,
+            p_move: environment.p_move,
+            xdim: grid_size.0,
+            ydim: grid_size.1,
+            ..SimulationParams::default()
+        };
+        environment
+    }
 
-fn move_all(
-    Environment {
-        grid,
+    /// Set up an environment, where the data of each agent is given by
+    /// `extension(agent_index, x, y)` evaluated at the agent's initial location.
+    #[must_use]
+    pub fn init_with(
+        n: usize,
+        infected: usize,
+        duration: usize,
+        p_death: f64,
+        xdim: usize,
+        ydim: usize,
+        extension: impl FnMut(usize, usize, usize) -> X,
+    ) -> Self {
+        let params = SimulationParams {
+            n,
+            infected,
+            duration,
+            p_death,
+            xdim,
+            ydim,
+            ..SimulationParams::default()
+        };
+        Self::from_params_with(&params, extension)
+    }
+
+    /// Copy of the environment in its current state, which continues under the interventions
+    /// and course of infection of `overrides`, i.e. its duration, incubation, immunity,
+    /// probabilities of infection, movement, death and spillover, infection radius, detection,
+    /// quarantine, PPE, isolation, dose severity, lockdown and update order. The random numbers
+    /// of the following ticks are drawn from `new_seed`, or from the seed of the environment if
+    /// `None`, such that a branch without changes continues exactly like the environment itself.
+    ///
+    /// Fails if `overrides` change any of the parameters that only take effect when the
+    /// environment is set up, i.e. the population, the initial infections and their placement,
+    /// the grid and its boundary, the time step, burn-in, seeding and vaccination schedules,
+    /// vectors, reservoirs, compliance, heterogeneity of the agents, essential workers, corpse
+    /// handling and seed. Campaigns can still be added to a branch by
+    /// [`Environment::schedule_vaccination`].
+    pub fn branch(
+        &self,
+        overrides: &SimulationParams,
+        new_seed: Option<u64>,
+    ) -> Result<Self, ConfigError>
+    where
+        X: Clone,
+    {
+        overrides.validate()?;
+        let params = &self.params;
+        let changed = [
+            ("n", params.n != overrides.n),
+            ("infected", params.infected != overrides.infected),
+            (
+                "initial_seeding",
+                params.initial_seeding != overrides.initial_seeding,
+            ),
+            ("xdim", params.xdim != overrides.xdim),
+            ("ydim", params.ydim != overrides.ydim),
+            ("boundary", params.boundary != overrides.boundary),
+            ("dt", params.dt != overrides.dt),
+            (
+                "burn_in_ticks",
+                params.burn_in_ticks != overrides.burn_in_ticks,
+            ),
+            ("seeding", params.seeding != overrides.seeding),
+            ("vaccination", params.vaccination != overrides.vaccination),
+            ("vectors", params.vectors != overrides.vectors),
+            ("reservoirs", params.reservoirs != overrides.reservoirs),
+            ("compliance", params.compliance != overrides.compliance),
+            (
+                "heterogeneity",
+                params.heterogeneity != overrides.heterogeneity,
+            ),
+            (
+                "essential_fraction",
+                params.essential_fraction != overrides.essential_fraction,
+            ),
+            (
+                "corpse_handling",
+                params.corpse_handling != overrides.corpse_handling,
+            ),
+            ("seed", params.seed != overrides.seed),
+        ];
+        if let Some(&(name, _)) = changed.iter().find(|(_, changed)| *changed) {
+            return Err(ConfigError::SetupOnly { name });
+        }
+        Ok(Self {
+            duration: overrides.duration,
+            duration_model: overrides.duration_model.clone(),
+            incubation: overrides.incubation,
+            immunity_duration: overrides.immunity_duration,
+            p_death: overrides.p_death,
+            p_infect: overrides.p_infect,
+            infection_radius: overrides.infection_radius,
+            p_move: overrides.p_move,
+            p_spillover: overrides.p_spillover,
+            detection: overrides.detection.clone(),
+            quarantine: overrides.quarantine.clone(),
+            ppe: overrides.ppe.clone(),
+            isolation: overrides.isolation,
+            dose_severity: overrides.dose_severity.clone(),
+            lockdown: overrides.lockdown.clone(),
+            update_order: overrides.update_order,
+            params: overrides.clone(),
+            seed: new_seed.unwrap_or(self.seed),
+            ..self.clone()
+        })
+    }
+
+    /// Write the state of the environment as JSON, from which
+    /// [`Environment::load_checkpoint`] resumes the run at the current tick.
+    ///
+    /// A run resumed from a checkpoint continues exactly like the environment itself, as the
+    /// random numbers of each tick are drawn anew from the seed.
+    pub fn save_checkpoint<W: io::Write>(&self, writer: W) -> serde_json::Result<()>
+    where
+        X: Serialize,
+    {
+        serde_json::to_writer(writer, self)
+    }
+
+    /// Read an environment written by [`Environment::save_checkpoint`]
+    pub fn load_checkpoint<R: io::Read>(reader: R) -> serde_json::Result<Self>
+    where
+        X: serde::de::DeserializeOwned,
+    {
+        let mut environment: Self = serde_json::from_reader(reader)?;
+        environment.rng = tick_rng(environment.seed, environment.tick);
+        environment.movement_rng = stream_rng(environment.seed, environment.tick, MOVEMENT_STREAM);
+        Ok(environment)
+    }
+
+    /// Estimate of the heap memory held by the environment's own structures in bytes,
+    /// computed from their capacities
+    #[must_use]
+    pub fn heap_bytes(&self) -> usize {
+        fn bytes<T>(items: &Vec<T>) -> usize {
+            items.capacity() * std::mem::size_of::<T>()
+        }
+        let grid = bytes(&self.grid.cells) + self.grid.cells.iter().map(bytes).sum::<usize>();
+        let detection_log = bytes(&self.detection_log)
+            + self
+                .detection_log
+                .iter()
+                .map(|record| bytes(&record.protected))
+                .sum::<usize>();
+        let histories = self
+            .agents
+            .iter()
+            .map(|agent| agent.history.heap_bytes())
+            .sum::<usize>();
+        grid + bytes(&self.agents)
+            + histories
+            + bytes(&self.extensions)
+            + bytes(&self.seeding)
+            + bytes(&self.seeding_log)
+            + bytes(&self.vaccination)
+            + bytes(&self.vectors)
+            + bytes(&self.vector_record)
+            + bytes(&self.reservoirs)
+            + bytes(&self.spillover_log)
+            + detection_log
+            + self.cell_tracking.as_ref().map_or(0, |tracking| {
+                bytes(&tracking.visits) + bytes(&tracking.infections)
+            })
+            + self.infection_events.as_ref().map_or(0, bytes)
+            + self
+                .event_recorder
+                .as_ref()
+                .map_or(0, EventRecorder::heap_bytes)
+            + bytes(&self.scratch.outcomes)
+            + bytes(&self.scratch.infections)
+            + bytes(&self.scratch.infecting)
+            + bytes(&self.scratch.order)
+    }
+
+    /// Record each infection from now on, see [`Environment::infection_events`]. Recording is
+    /// off by default.
+    ///
+    /// The agents that are infected when recording starts, such as the initial infections, are
+    /// recorded first, without a source.
+    pub fn enable_infection_events(&mut self) {
+        if self.infection_events.is_some() {
+            return;
+        }
+        let events = self
+            .agents
+            .iter()
+            .enumerate()
+            .filter(|(_, agent)| matches!(agent.agent_type, AgentType::AgentE | AgentType::AgentI))
+            .filter_map(|(target, agent)| match agent.history.events().last() {
+                Some(&ImmuneEvent::Infection { tick, x, y }) => Some(InfectionEvent {
+                    source: None,
+                    target,
+                    tick,
+                    cell: (x, y),
+                }),
+                _ => None,
+            })
+            .collect();
+        self.infection_events = Some(events);
+    }
+
+    /// Infections since [`Environment::enable_infection_events`] in the order they happened,
+    /// or an empty slice if recording is off
+    #[must_use]
+    pub fn infection_events(&self) -> &[InfectionEvent] {
+        self.infection_events.as_deref().unwrap_or(&[])
+    }
+
+    /// Transmission tree of the [recorded infections](Environment::infection_events), mapping
+    /// each agent that passed on the infection to the agents it infected, in the order they
+    /// were infected. Infections without a source are left out.
+    #[must_use]
+    pub fn transmission_tree(&self) -> HashMap<usize, Vec<usize>> {
+        let mut tree: HashMap<usize, Vec<usize>> = HashMap::new();
+        for event in self.infection_events() {
+            if let Some(source) = event.source {
+                tree.entry(source).or_default().push(event.target);
+            }
+        }
+        tree
+    }
+
+    /// Number of secondary cases of each [recorded infection](Environment::infection_events),
+    /// being the infections passed on by its target until it was infected again
+    fn secondary_cases(&self) -> Vec<usize> {
+        let events = self.infection_events();
+        let mut cases = vec![0; events.len()];
+        // index of the latest infection of each agent
+        let mut latest = HashMap::new();
+        for (index, event) in events.iter().enumerate() {
+            if let Some(source) = event.source {
+                if let Some(&infection) = latest.get(&source) {
+                    cases[infection] += 1;
+                }
+            }
+            latest.insert(event.target, index);
+        }
+        cases
+    }
+
+    /// Cohort reproduction number of each tick up to the current one, being the mean number of
+    /// secondary cases of the agents infected at that tick, or NaN if no agent was infected.
+    ///
+    /// The secondary cases are those of the [recorded infections](Environment::infection_events),
+    /// such that the cohorts of the latest ticks are incomplete until their infections are
+    /// over.
+    #[must_use]
+    pub fn reproduction_number_by_tick(&self) -> Vec<f64> {
+        let mut cases = vec![0; self.tick + 1];
+        let mut cohorts = vec![0; self.tick + 1];
+        for (event, secondary) in self.infection_events().iter().zip(self.secondary_cases()) {
+            cases[event.tick] += secondary;
+            cohorts[event.tick] += 1;
+        }
+        cases
+            .iter()
+            .zip(&cohorts)
+            .map(|(&cases, &cohort)| match cohort {
+                0 => f64::NAN,
+                _ => cases as f64 / cohort as f64,
+            })
+            .collect()
+    }
+
+    /// Mean number of secondary cases of the first generation of cases, being the agents
+    /// infected at the tick of the earliest [recorded infection](Environment::infection_events),
+    /// or NaN if none was recorded
+    #[must_use]
+    pub fn estimated_r0(&self) -> f64 {
+        match self.infection_events().first() {
+            Some(first) => self.reproduction_number_by_tick()[first.tick],
+            None => f64::NAN,
+        }
+    }
+
+    /// Number of infections so far of agents that had been infected before, which only happen
+    /// when [immunity wanes](SimulationParams::immunity_duration)
+    #[must_use]
+    pub fn reinfections(&self) -> usize {
+        self.reinfections
+    }
+
+    /// Record the infection of agent `target` by `source` in its current cell, if recording
+    /// is on
+    fn record_infection(&mut self, source: Option<usize>, target: usize) {
+        if let Some(events) = &mut self.infection_events {
+            let agent = &self.agents[target];
+            events.push(InfectionEvent {
+                source,
+                target,
+                tick: self.tick,
+                cell: (agent.x, agent.y),
+            });
+        }
+    }
+
+    /// Record each change of state of the agents from now on, and with `positions` the
+    /// location of each agent in the grid at the end of each tick, starting with the current
+    /// one, see [`EventRecorder`]. Recording is off by default, and an environment that
+    /// records already keeps recording as before.
+    pub fn enable_event_recording(&mut self, positions: bool) {
+        if self.event_recorder.is_none() {
+            self.event_recorder = Some(EventRecorder::new(positions));
+            self.record_positions();
+        }
+    }
+
+    /// Rows recorded since [`Environment::enable_event_recording`], or `None` if recording is
+    /// off
+    #[must_use]
+    pub fn event_recorder(&self) -> Option<&EventRecorder> {
+        self.event_recorder.as_ref()
+    }
+
+    /// Rows recorded so far, which are removed from the environment while recording goes on,
+    /// such that a long run can be written in parts. `None` if recording is off.
+    pub fn take_events(&mut self) -> Option<EventRecorder> {
+        self.event_recorder.as_mut().map(|recorder| {
+            let empty = EventRecorder::new(recorder.records_positions());
+            std::mem::replace(recorder, empty)
+        })
+    }
+
+    /// Record the location of each agent in the grid at the current tick, if positions are
+    /// recorded
+    fn record_positions(&mut self) {
+        if let Some(recorder) = &mut self.event_recorder {
+            let removed = self.corpse_handling == CorpseHandling::Remove;
+            let agents = self.agents.iter().enumerate();
+            recorder.push_positions(
+                self.tick,
+                agents
+                    .filter(|(_, agent)| !(removed && agent.agent_type == AgentType::AgentD))
+                    .map(|(i, agent)| (i, (agent.x, agent.y))),
+            );
+        }
+    }
+
+    /// Count the visits and infections of each cell from now on, see
+    /// [`Environment::cell_visit_counts`] and [`Environment::cell_infection_counts`]. Tracking
+    /// is off by default.
+    pub fn enable_cell_tracking(&mut self) {
+        let cells = self.grid_size.0 * self.grid_size.1;
+        self.cell_tracking.get_or_insert_with(|| CellTracking {
+            visits: vec![0; cells],
+            infections: vec![0; cells],
+        });
+    }
+
+    /// Number of times that living agents occupied each cell after moving, since
+    /// [`Environment::enable_cell_tracking`], or an empty slice if tracking is off.
+    ///
+    /// The counts are in row-major order: the count of cell `(x, y)` is at index
+    /// `y * xdim + x`.
+    #[must_use]
+    pub fn cell_visit_counts(&self) -> &[usize] {
+        self.cell_tracking
+            .as_ref()
+            .map_or(&[], |tracking| &tracking.visits)
+    }
+
+    /// Number of infections by contact in each cell since
+    /// [`Environment::enable_cell_tracking`], or an empty slice if tracking is off, in the
+    /// row-major order of [`Environment::cell_visit_counts`].
+    #[must_use]
+    pub fn cell_infection_counts(&self) -> &[usize] {
+        self.cell_tracking
+            .as_ref()
+            .map_or(&[], |tracking| &tracking.infections)
+    }
+
+    /// States of the agents in each cell, as `ydim` rows of `xdim` cells each, such that
+    /// `snapshot[y][x]` is the cell at `(x, y)`, in the row-major order of
+    /// [`Environment::cell_visit_counts`]
+    #[must_use]
+    pub fn grid_snapshot(&self) -> Vec<Vec<CellState>> {
+        let (xdim, ydim) = self.grid_size;
+        let mut cells = vec![vec![CellState::default(); xdim]; ydim];
+        for agent in self.agents.iter().filter(|agent| !self.is_removed(agent)) {
+            cells[agent.y][agent.x].add(&agent.agent_type);
+        }
+        cells
+    }
+
+    /// Whether `agent` is dead and taken out of the grid, see [`CorpseHandling::Remove`]
+    fn is_removed(&self, agent: &Agent) -> bool {
+        agent.agent_type == AgentType::AgentD && self.corpse_handling == CorpseHandling::Remove
+    }
+
+    /// Current time tick
+    #[must_use]
+    pub fn tick(&self) -> usize {
+        self.tick
+    }
+
+    /// Seed of the random numbers, being [`SimulationParams::seed`] if it was given, and drawn
+    /// at random otherwise
+    #[must_use]
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Draw the random numbers of the following ticks from `seed`
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// Size of the grid in x- and y-dimension
+    #[must_use]
+    pub fn grid_size(&self) -> (usize, usize) {
+        self.grid_size
+    }
+
+    /// Number of ticks the agents moved around before the epidemic was seeded
+    #[must_use]
+    pub fn burn_in_ticks(&self) -> usize {
+        self.burn_in_ticks
+    }
+
+    /// Duration of the infections, unless drawn from a [`DurationModel`] or given by an agent
+    #[must_use]
+    pub fn duration(&self) -> usize {
+        self.duration
+    }
+
+    /// Probability of an infected agent dying at the end of its infection, unless given by the
+    /// agent
+    #[must_use]
+    pub fn p_death(&self) -> f64 {
+        self.p_death
+    }
+
+    /// Infect, recover and kill agents for the current tick, synchronously from the states at
+    /// its start
+    pub fn update_type(&mut self) {
+        let tick = self.tick;
+        let Scratch {
+            mut outcomes,
+            mut infections,
+            mut infecting,
+            mut order,
+        } = std::mem::take(&mut self.scratch);
+        order.clear();
+        order.extend(0..self.agents.len());
+        if self.update_order == UpdateOrder::Shuffled {
+            order.shuffle(&mut self.rng);
+        }
+        for &i in &order {
+            let agent = &mut self.agents[i];
+            if agent.agent_type == AgentType::AgentE && tick - agent.tick >= self.incubation {
+                agent.agent_type = AgentType::AgentI;
+                agent.tick = tick;
+                self.stats.transfer(&AgentType::AgentE, &AgentType::AgentI);
+                record_transition(&mut self.event_recorder, tick, i, AgentType::AgentE, agent);
+            } else if agent.agent_type == AgentType::AgentR
+                && self
+                    .immunity_duration
+                    .is_some_and(|immunity| tick - agent.tick > immunity)
+            {
+                agent.wane(tick);
+                self.stats.transfer(&AgentType::AgentR, &AgentType::AgentS);
+                record_transition(&mut self.event_recorder, tick, i, AgentType::AgentR, agent);
+            }
+        }
+        // quarantine and detect first, such that the cellmates are protected before being exposed
+        self.quarantine(&order);
+        self.detect(&order);
+        // all transitions are decided on the states at the start of the tick and only applied
+        // afterwards, such that they do not depend on the order of the agents
+        infecting.clear();
+        infecting.resize(self.agents.len(), false);
+        for &i in &order {
+            if let AgentType::AgentI | AgentType::AgentQ = self.agents[i].agent_type {
+                // agents infected by a hook of this tick have not been infected for any time
+                let agent = &self.agents[i];
+                if tick.saturating_sub(agent.tick) > agent.infectious_for(self.duration) {
+                    let p_base = agent.p_death.unwrap_or(self.p_death);
+                    let p_death = match agent.dose {
+                        Some(dose) => (p_base * self.dose_severity.multiplier(dose)).min(1.0),
+                        None => p_base,
+                    };
+                    outcomes.push((i, self.rng.gen_bool(p_death)));
+                } else {
+                    if tick == self.agents[i].tick
+                        || self.agents[i].agent_type == AgentType::AgentQ
+                        || self.vector_params.is_some()
+                        || self.agents[i].isolated
+                    {
+                        continue;
+                    }
+
+                    let location = (self.agents[i].x, self.agents[i].y);
+                    let cells = neighbourhood(
+                        location,
+                        self.infection_radius,
+                        self.grid_size,
+                        self.boundary,
+                    );
+                    for cell in cells {
+                        let dose = self.dose(cell);
+                        for k in 0..self.grid.agents(cell).len() {
+                            let j = self.grid.agents(cell)[k];
+                            if self.agents[j].agent_type != AgentType::AgentS || infecting[j] {
+                                continue;
+                            }
+                            self.agents[j].expose(tick);
+                            let p_infect = self.p_infect
+                                * self.agents[j].susceptibility(tick, self.ppe.as_ref());
+                            if p_infect == 1.0 || self.rng.gen_bool(p_infect) {
+                                infecting[j] = true;
+                                infections.push((i, j, cell, dose));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        for (i, dies) in outcomes.drain(..) {
+            let infected = self.agents[i].agent_type.clone();
+            if dies {
+                self.agents[i].die(tick)
+            } else {
+                self.agents[i].recover(tick)
+            }
+            self.stats.transfer(&infected, &self.agents[i].agent_type);
+            record_transition(&mut self.event_recorder, tick, i, infected, &self.agents[i]);
+        }
+        for (i, j, cell, dose) in infections.drain(..) {
+            let duration = draw_duration(self.duration_model.as_ref(), &mut self.rng);
+            self.agents[j].contract(tick, self.incubation, duration);
+            self.stats
+                .transfer(&AgentType::AgentS, &self.agents[j].agent_type);
+            record_transition(
+                &mut self.event_recorder,
+                tick,
+                j,
+                AgentType::AgentS,
+                &self.agents[j],
+            );
+            self.reinfections += usize::from(self.agents[j].is_reinfected());
+            self.record_infection(Some(i), j);
+            self.agents[j].dose = Some(dose);
+            if let Some(tracking) = &mut self.cell_tracking {
+                tracking.infections[cell.1 * self.grid_size.0 + cell.0] += 1;
+            }
+        }
+        self.scratch = Scratch {
+            outcomes,
+            infections,
+            infecting,
+            order,
+        };
+        self.update_vectors();
+        self.spill_over();
+    }
+
+    /// Quarantine with probability `p_detect` each infected agent that has been infectious for
+    /// `detection_delay` ticks, i.e. became infectious `detection_delay + 1` ticks ago, in
+    /// `order`
+    fn quarantine(&mut self, order: &[usize]) {
+        let (delay, p_detect) = match &self.quarantine {
+            Some(quarantine) => (quarantine.detection_delay, quarantine.p_detect),
+            None => return,
+        };
+        let tick = self.tick;
+        for &i in order {
+            let agent = &mut self.agents[i];
+            if agent.agent_type == AgentType::AgentI
+                && tick - agent.tick == delay + 1
+                && self.rng.gen_bool(p_detect)
+            {
+                // keeps the tick it became infectious, from which its infection runs its course
+                agent.agent_type = AgentType::AgentQ;
+                self.stats.transfer(&AgentType::AgentI, &AgentType::AgentQ);
+                record_transition(&mut self.event_recorder, tick, i, AgentType::AgentI, agent);
+            }
+        }
+    }
+
+    /// Number of infectious agents within the infection radius of `cell`, being those infected
+    /// before this tick that have neither recovered nor isolated
+    fn dose(&self, cell: (usize, usize)) -> usize {
+        neighbourhood(cell, self.infection_radius, self.grid_size, self.boundary)
+            .flat_map(|cell| self.grid.agents(cell))
+            .map(|&i| &self.agents[i])
+            .filter(|agent| {
+                agent.agent_type == AgentType::AgentI
+                    && agent.tick < self.tick
+                    && self.tick - agent.tick <= agent.infectious_for(self.duration)
+                    && !agent.isolated
+            })
+            .count()
+    }
+
+    /// Outcome of the infections of each infecting dose, in increasing order of the dose.
+    ///
+    /// Only infections by contact between agents have a dose, and each agent counts with its
+    /// latest infection.
+    #[must_use]
+    pub fn dose_outcomes(&self) -> Vec<DoseOutcome> {
+        let mut outcomes: BTreeMap<usize, DoseOutcome> = BTreeMap::new();
+        for agent in &self.agents {
+            if let Some(dose) = agent.dose {
+                let outcome = outcomes.entry(dose).or_insert(DoseOutcome {
+                    dose,
+                    infected: 0,
+                    recovered: 0,
+                    dead: 0,
+                });
+                outcome.infected += 1;
+                match agent.agent_type {
+                    AgentType::AgentR => outcome.recovered += 1,
+                    AgentType::AgentD => outcome.dead += 1,
+                    AgentType::AgentS
+                    | AgentType::AgentE
+                    | AgentType::AgentI
+                    | AgentType::AgentQ
+                    | AgentType::AgentV => {}
+                }
+            }
+        }
+        outcomes.into_values().collect()
+    }
+
+    /// Summary of `records` of a run of the environment, or `None` if `records` is empty, see
+    /// [`EpidemicSummary::from_records`]
+    #[must_use]
+    pub fn summarize(&self, records: &[TallyStates]) -> Option<EpidemicSummary> {
+        EpidemicSummary::from_records(records, self.agents.len())
+    }
+
+    /// Detect infected agents, and give prophylaxis to the susceptible cellmates of each
+    /// detected agent, in `order`
+    fn detect(&mut self, order: &[usize]) {
+        let p_detect = match &self.detection {
+            Some(detection) => detection.p_detect,
+            None => return,
+        };
+        let tick = self.tick;
+        for &i in order {
+            let agent = &self.agents[i];
+            if agent.agent_type != AgentType::AgentI
+                || agent.detected
+                || !self.rng.gen_bool(p_detect)
+            {
+                continue;
+            }
+            let (x, y) = (agent.x, agent.y);
+            let isolates = self.isolation && self.rng.gen_bool(agent.compliance);
+            self.agents[i].detected = true;
+            self.agents[i].isolated = isolates;
+
+            let mut protected = Vec::new();
+            if let Some(ppe) = &self.ppe {
+                for &j in self.grid.agents((x, y)) {
+                    let cellmate = &mut self.agents[j];
+                    if cellmate.agent_type == AgentType::AgentS
+                        && self.rng.gen_bool(cellmate.compliance)
+                    {
+                        cellmate.protected_until = Some(tick + ppe.duration);
+                        protected.push(j);
+                    }
+                }
+            }
+            self.detection_log.push(DetectionRecord {
+                tick,
+                agent: i,
+                x,
+                y,
+                protected,
+            });
+        }
+    }
+
+    /// Attack rate of each compliance quartile, from the least to the most compliant agents.
+    ///
+    /// Agents are ranked by their compliance, with ties in order of their index, and split
+    /// into four groups of equal size, up to one agent. The attack rate of a group is the
+    /// fraction of its agents that are no longer susceptible.
+    #[must_use]
+    pub fn attack_rate_by_compliance(&self) -> [f64; 4] {
+        let mut ranked: Vec<&Agent> = self.agents.iter().collect();
+        ranked.sort_by(|a, b| {
+            a.compliance
+                .partial_cmp(&b.compliance)
+                .expect("compliance is finite")
+        });
+        let mut infected = [0usize; 4];
+        let mut total = [0usize; 4];
+        for (rank, agent) in ranked.iter().enumerate() {
+            let quartile = rank * 4 / ranked.len();
+            total[quartile] += 1;
+            if agent.agent_type != AgentType::AgentS {
+                infected[quartile] += 1;
+            }
+        }
+        let mut rates = [0.0; 4];
+        for quartile in 0..4 {
+            if total[quartile] > 0 {
+                rates[quartile] = infected[quartile] as f64 / total[quartile] as f64;
+            }
+        }
+        rates
+    }
+
+    /// Attack rate of the essential workers and of the other agents, being the fraction of
+    /// each group that is no longer susceptible, or `None` for an empty group
+    #[must_use]
+    pub fn attack_rate_by_essential(&self) -> EssentialAttackRates {
+        let rate = |essential: bool| {
+            let group: Vec<&Agent> = self
+                .agents
+                .iter()
+                .filter(|agent| agent.essential == essential)
+                .collect();
+            if group.is_empty() {
+                return None;
+            }
+            let infected = group
+                .iter()
+                .filter(|agent| agent.agent_type != AgentType::AgentS)
+                .count();
+            Some(infected as f64 / group.len() as f64)
+        };
+        EssentialAttackRates {
+            essential: rate(true),
+            other: rate(false),
+        }
+    }
+
+    /// Histograms of the exposure of the agents that are still susceptible and of those that
+    /// are not, see [`AgentView::exposure`]
+    #[must_use]
+    pub fn exposure_distribution(&self) -> ExposureDistribution {
+        let mut distribution = ExposureDistribution::default();
+        for agent in &self.agents {
+            let histogram = if agent.agent_type == AgentType::AgentS {
+                &mut distribution.never_infected
+            } else {
+                &mut distribution.infected
+            };
+            if histogram.len() <= agent.exposure {
+                histogram.resize(agent.exposure + 1, 0);
+            }
+            histogram[agent.exposure] += 1;
+        }
+        distribution
+    }
+
+    /// Infected agents detected so far
+    #[must_use]
+    pub fn detection_log(&self) -> &[DetectionRecord] {
+        &self.detection_log
+    }
+
+    /// Infect susceptible agents standing in reservoir cells with probability `p_spillover`
+    fn spill_over(&mut self) {
+        if self.p_spillover == 0.0 {
+            return;
+        }
+        let tick = self.tick;
+        for &(x, y) in &self.reservoirs {
+            for &agent in self.grid.agents((x, y)) {
+                let agent_ref = &self.agents[agent];
+                if agent_ref.agent_type == AgentType::AgentS
+                    && self.rng.gen_bool(
+                        self.p_spillover * agent_ref.susceptibility(tick, self.ppe.as_ref()),
+                    )
+                {
+                    let duration = draw_duration(self.duration_model.as_ref(), &mut self.rng);
+                    self.agents[agent].contract(tick, self.incubation, duration);
+                    self.stats
+                        .transfer(&AgentType::AgentS, &self.agents[agent].agent_type);
+                    record_transition(
+                        &mut self.event_recorder,
+                        tick,
+                        agent,
+                        AgentType::AgentS,
+                        &self.agents[agent],
+                    );
+                    self.reinfections += usize::from(self.agents[agent].is_reinfected());
+                    self.spillover_log
+                        .push(SpilloverRecord { tick, agent, x, y });
+                    if let Some(events) = &mut self.infection_events {
+                        events.push(InfectionEvent {
+                            source: None,
+                            target: agent,
+                            tick,
+                            cell: (x, y),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Agents infected so far by spillover from the reservoir
+    #[must_use]
+    pub fn spillover_log(&self) -> &[SpilloverRecord] {
+        &self.spillover_log
+    }
+
+    /// Whether susceptible agents may still be infected by spillover
+    fn can_spill_over(&self) -> bool {
+        self.p_spillover > 0.0 && !self.reservoirs.is_empty() && self.stats.susceptible > 0
+    }
+
+    /// Turnover of the vectors, and transmission between vectors and agents sharing a cell
+    fn update_vectors(&mut self) {
+        let params = match &self.vector_params {
+            Some(params) => params.clone(),
+            None => return,
+        };
+        let tick = self.tick;
+        let (xdim, ydim) = self.grid_size;
+        let Environment {
+            vectors,
+            grid,
+            agents: all_agents,
+            ppe,
+            rng,
+            incubation,
+            duration_model,
+            infection_events,
+            event_recorder,
+            stats,
+            reinfections,
+            ..
+        } = self;
+        for vector in vectors.iter_mut() {
+            if rng.gen_bool(params.turnover) {
+                *vector = Vector {
+                    x: rng.gen_range(0, xdim),
+                    y: rng.gen_range(0, ydim),
+                    state: VectorState::Susceptible,
+                    tick,
+                };
+                continue;
+            }
+            let agents = grid.agents((vector.x, vector.y));
+            match vector.state {
+                VectorState::Susceptible => {
+                    // agents infected in this tick are not yet infectious
+                    let infectious = agents.iter().any(|&j| {
+                        let agent = &all_agents[j];
+                        agent.agent_type == AgentType::AgentI && agent.tick < tick
+                    });
+                    if infectious && rng.gen_bool(params.p_bite) {
+                        vector.state = VectorState::Infectious;
+                        vector.tick = tick;
+                    }
+                }
+                VectorState::Infectious => {
+                    if params
+                        .infectious_duration
+                        .is_some_and(|duration| tick - vector.tick > duration)
+                    {
+                        vector.state = VectorState::Spent;
+                        vector.tick = tick;
+                    } else if vector.tick < tick {
+                        for &j in agents {
+                            let agent = &mut all_agents[j];
+                            if agent.agent_type == AgentType::AgentS
+                                && rng.gen_bool(
+                                    params.p_transmit * agent.susceptibility(tick, ppe.as_ref()),
+                                )
+                            {
+                                let duration = draw_duration(duration_model.as_ref(), rng);
+                                agent.contract(tick, *incubation, duration);
+                                stats.transfer(&AgentType::AgentS, &agent.agent_type);
+                                record_transition(
+                                    event_recorder,
+                                    tick,
+                                    j,
+                                    AgentType::AgentS,
+                                    agent,
+                                );
+                                *reinfections += usize::from(agent.is_reinfected());
+                                if let Some(events) = infection_events {
+                                    events.push(InfectionEvent {
+                                        source: None,
+                                        target: j,
+                                        tick,
+                                        cell: (agent.x, agent.y),
+                                    });
+                                }
+                            }
+                        }
+                    }
+                }
+                VectorState::Spent => {}
+            }
+        }
+    }
+
+    /// Move each vector with probability [`VectorParams::p_move`]
+    fn move_vectors(&mut self) {
+        let p_move = match &self.vector_params {
+            Some(params) => params.p_move,
+            None => return,
+        };
+        for vector in self.vectors.iter_mut() {
+            if self.rng.gen_bool(p_move) {
+                let (x, y) = random_step(
+                    (vector.x, vector.y),
+                    self.grid_size,
+                    self.boundary,
+                    &mut self.rng,
+                );
+                vector.x = x;
+                vector.y = y;
+            }
+        }
+    }
+
+    /// Current tally of the vectors, all zero when the infection is not vector-borne
+    #[must_use]
+    pub fn vector_tally(&self) -> VectorTally {
+        self.vectors
+            .iter()
+            .fold(VectorTally::default(), |mut acc, vector| {
+                match vector.state {
+                    VectorState::Susceptible => acc.susceptible += 1,
+                    VectorState::Infectious => acc.infectious += 1,
+                    VectorState::Spent => acc.spent += 1,
+                }
+                acc
+            })
+    }
+
+    /// Tally of the vectors for each tick of the run so far, aligned with the tally of the
+    /// agents returned by [`Environment::run`], or empty if the infection is not vector-borne
+    #[must_use]
+    pub fn vector_record(&self) -> &[VectorTally] {
+        &self.vector_record
+    }
+
+    /// Whether infectious vectors may still infect susceptible agents
+    fn vectors_can_transmit(&self) -> bool {
+        match &self.vector_params {
+            Some(params) => {
+                params.p_transmit > 0.0
+                    && self.stats.susceptible > 0
+                    && self
+                        .vectors
+                        .iter()
+                        .any(|vector| vector.state == VectorState::Infectious)
+            }
+            None => false,
+        }
+    }
+
+    /// Describe the agent with index `id`, if there is one
+    #[must_use]
+    pub fn agent(&self, id: usize) -> Option<AgentView> {
+        self.agents.get(id).map(|agent| agent.view(id))
+    }
+
+    /// Indices of the agents occupying `cell`, unless it lies outside the grid
+    #[must_use]
+    pub fn cell_agents(&self, (x, y): (usize, usize)) -> Option<&[usize]> {
+        if x < self.grid_size.0 && y < self.grid_size.1 {
+            Some(self.grid.agents((x, y)))
+        } else {
+            None
+        }
+    }
+
+    /// Describe the agents occupying the cell at `x` and `y`, in the order they are listed in
+    /// it, being none outside the grid
+    pub fn agents_in_cell(&self, x: usize, y: usize) -> impl Iterator<Item = AgentView> + '_ {
+        self.cell_agents((x, y))
+            .unwrap_or(&[])
+            .iter()
+            .map(move |&id| self.agents[id].view(id))
+    }
+
+    /// Describe all agents, in order of their index, e.g. to compute the mean distance between
+    /// the infected agents
+    ///
+    /// ```
+    /// # use bkamins_sir_abm::julia_reimpl::{AgentType, Environment};
+    /// # use bkamins_sir_abm::presets;
+    /// let mut environment = Environment::from_params(&presets::blog_default());
+    /// for _ in 0..10 {
+    ///     environment.step();
+    /// }
+    /// let infected: Vec<_> = environment
+    ///     .iter_agents()
+    ///     .filter(|agent| agent.state == AgentType::AgentI)
+    ///     .collect();
+    /// let mut distances = Vec::new();
+    /// for (i, a) in infected.iter().enumerate() {
+    ///     for b in &infected[i + 1..] {
+    ///         let (dx, dy) = (a.x as f64 - b.x as f64, a.y as f64 - b.y as f64);
+    ///         distances.push((dx * dx + dy * dy).sqrt());
+    ///     }
+    /// }
+    /// let mean = distances.iter().sum::<f64>() / distances.len() as f64;
+    /// let (xdim, ydim) = environment.grid_size();
+    /// assert!(mean <= ((xdim * xdim + ydim * ydim) as f64).sqrt());
+    ///
+    /// let first = &infected[0];
+    /// assert!(environment
+    ///     .agents_in_cell(first.x, first.y)
+    ///     .any(|agent| agent.id == first.id));
+    /// ```
+    pub fn iter_agents(&self) -> impl Iterator<Item = AgentView> + '_ {
+        self.agents
+            .iter()
+            .enumerate()
+            .map(|(id, agent)| agent.view(id))
+    }
+
+    /// User-defined data of the agent with index `id`
+    #[must_use]
+    pub fn ext(&self, id: usize) -> Option<&X> {
+        self.extensions.get(id)
+    }
+
+    /// Mutable access to the user-defined data of the agent with index `id`
+    pub fn ext_mut(&mut self, id: usize) -> Option<&mut X> {
+        self.extensions.get_mut(id)
+    }
+
+    /// Tally of the agents in each state by counting all of them, whereas the tally of the
+    /// run is kept up to date as agents change state, and checked against this count by
+    /// [`Environment::check_invariants`]
+    #[must_use]
+    pub fn get_statistics(&self) -> TallyStates {
+        self.agents
+            .iter()
+            .fold(TallyStates::default(), |mut acc, x| {
+                match x.agent_type {
+                    AgentType::AgentS => {
+                        acc.susceptible += 1;
+                    }
+                    AgentType::AgentE => {
+                        acc.exposed += 1;
+                    }
+                    AgentType::AgentI => {
+                        acc.infected += 1;
+                    }
+                    AgentType::AgentQ => {
+                        acc.quarantined += 1;
+                    }
+                    AgentType::AgentR => {
+                        acc.recovered += 1;
+                    }
+                    AgentType::AgentV => {
+                        acc.vaccinated += 1;
+                    }
+                    AgentType::AgentD => {
+                        acc.dead += 1;
+                    }
+                };
+                acc
+            })
+    }
+
+    /// Infect the susceptible agent with index `id`, as if the infection was imported from outside.
+    ///
+    /// Returns `false` and leaves the agent untouched if it is not susceptible.
+    pub fn import_case(&mut self, id: usize) -> bool {
+        match self.agents.get_mut(id) {
+            Some(agent) if agent.agent_type == AgentType::AgentS => {
+                let duration = draw_duration(self.duration_model.as_ref(), &mut self.rng);
+                agent.infect(self.tick, duration);
+                self.stats.transfer(&AgentType::AgentS, &AgentType::AgentI);
+                record_transition(
+                    &mut self.event_recorder,
+                    self.tick,
+                    id,
+                    AgentType::AgentS,
+                    agent,
+                );
+                self.reinfections += usize::from(agent.is_reinfected());
+                self.record_infection(None, id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Make the susceptible agent with index `id` immune, by moving it to the vaccinated state,
+    /// which counts as a dose of vaccine in its [`ImmuneHistory`].
+    ///
+    /// Returns `false` and leaves the agent untouched if it is not susceptible.
+    pub fn immunize(&mut self, id: usize) -> bool {
+        match self.agents.get_mut(id) {
+            Some(agent) if agent.agent_type == AgentType::AgentS => {
+                agent.vaccinate(self.tick);
+                self.stats.transfer(&AgentType::AgentS, &AgentType::AgentV);
+                record_transition(
+                    &mut self.event_recorder,
+                    self.tick,
+                    id,
+                    AgentType::AgentS,
+                    agent,
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// [Immunize](Environment::immunize) `fraction` of the susceptible agents, rounded to the
+    /// nearest number of agents, chosen uniformly at random, and return how many were
+    /// vaccinated. Without susceptible agents, e.g. after an earlier campaign with `fraction`
+    /// one, no one is vaccinated.
+    ///
+    /// # Panics
+    ///
+    /// If `fraction` lies outside of `[0, 1]`.
+    pub fn vaccinate(&mut self, fraction: f64) -> usize {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "fraction of agents to vaccinate must be within [0, 1], got {}",
+            fraction
+        );
+        let candidates: Vec<usize> = self
+            .agents
+            .iter()
+            .enumerate()
+            .filter(|(_, agent)| agent.agent_type == AgentType::AgentS)
+            .map(|(i, _)| i)
+            .collect();
+        let count = (fraction * candidates.len() as f64).round() as usize;
+        let chosen: Vec<usize> = candidates
+            .choose_multiple(&mut self.rng, count)
+            .copied()
+            .collect();
+        for &agent in &chosen {
+            self.immunize(agent);
+        }
+        chosen.len()
+    }
+
+    /// [Vaccinate](Environment::vaccinate) `fraction` of the susceptible agents when the run
+    /// reaches `tick`, after the agents have moved, or at the next tick if `tick` has passed.
+    ///
+    /// Campaigns do not keep a run going, thus a campaign scheduled after the end of the
+    /// epidemic does not take place.
+    ///
+    /// # Panics
+    ///
+    /// If `fraction` lies outside of `[0, 1]`.
+    pub fn schedule_vaccination(&mut self, tick: usize, fraction: f64) {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "fraction of agents to vaccinate must be within [0, 1], got {}",
+            fraction
+        );
+        let pending = &self.vaccination[self.next_vaccination..];
+        let index = self.next_vaccination + pending.partition_point(|entry| entry.tick <= tick);
+        self.vaccination
+            .insert(index, VaccinationEntry { tick, fraction });
+    }
+
+    /// Let the agents move with the probability of the first window of `schedule` that
+    /// contains the tick, in place of [`SimulationParams::p_move`], which still holds outside
+    /// of the windows. The schedule replaces any earlier one, and a
+    /// [lockdown](SimulationParams::lockdown) still restricts the movement of compliant agents
+    /// within it.
+    ///
+    /// # Panics
+    ///
+    /// If any probability lies outside of `[0, 1]`.
+    pub fn set_movement_schedule(&mut self, schedule: Vec<(Range<usize>, f64)>) {
+        for (_, p_move) in &schedule {
+            assert!(
+                (0.0..=1.0).contains(p_move),
+                "probability of moving must be within [0, 1], got {}",
+                p_move
+            );
+        }
+        self.movement_schedule = schedule;
+    }
+
+    /// Whether vaccination campaigns are yet to take place
+    #[must_use]
+    pub fn has_pending_vaccination(&self) -> bool {
+        self.next_vaccination < self.vaccination.len()
+    }
+
+    /// Carry out the vaccination campaigns that are due by the current tick
+    fn apply_vaccination(&mut self) {
+        while let Some(entry) = self.vaccination.get(self.next_vaccination).cloned() {
+            if entry.tick > self.tick {
+                break;
+            }
+            self.next_vaccination += 1;
+            self.vaccinate(entry.fraction);
+        }
+    }
+
+    /// Agents infected so far by the seeding schedule
+    #[must_use]
+    pub fn seeding_log(&self) -> &[SeedingRecord] {
+        &self.seeding_log
+    }
+
+    /// Whether entries of the seeding schedule are yet to be applied
+    #[must_use]
+    pub fn has_pending_seeding(&self) -> bool {
+        self.next_seeding < self.seeding.len()
+    }
+
+    /// Apply the entries of the seeding schedule that are due by the current tick
+    fn apply_seeding(&mut self) {
+        let ((xdim, ydim), boundary) = (self.grid_size, self.boundary);
+        while let Some((entry_index, entry)) = self.seeding.get(self.next_seeding).cloned() {
+            if entry.tick > self.tick {
+                break;
+            }
+            self.next_seeding += 1;
+
+            let candidates: Vec<usize> = self
+                .agents
+                .iter()
+                .enumerate()
+                .filter(|(_, agent)| agent.agent_type == AgentType::AgentS)
+                .filter(|(_, agent)| match entry.location {
+                    SeedLocation::Anywhere => true,
+                    SeedLocation::Around { center, radius } => {
+                        distance(agent.x, center.0, xdim, boundary) <= radius
+                            && distance(agent.y, center.1, ydim, boundary) <= radius
+                    }
+                })
+                .map(|(i, _)| i)
+                .collect();
+
+            let chosen: Vec<usize> = candidates
+                .choose_multiple(&mut self.rng, entry.count)
+                .copied()
+                .collect();
+            for agent in chosen {
+                let &Agent { x, y, .. } = &self.agents[agent];
+                self.import_case(agent);
+                self.seeding_log.push(SeedingRecord {
+                    entry: entry_index,
+                    tick: self.tick,
+                    agent,
+                    x,
+                    y,
+                });
+            }
+        }
+    }
+
+    /// Run the simulation until it is no longer [active](Environment::is_active), or until
+    /// [`SimulationParams::max_ticks`] if given, returning the tally of each tick.
+    pub fn run(&mut self) -> Vec<TallyStates> {
+        self.run_with_hook(|_, _| {})
+    }
+
+    /// Run the simulation, calling `hook` once per tick after the agents have been updated and
+    /// moved, but before the tally of that tick is recorded.
+    ///
+    /// The hook may intervene through the public methods of the environment, e.g.
+    /// [`Environment::immunize`] or [`Environment::import_case`].
+    pub fn run_with_hook(&mut self, hook: impl FnMut(&mut Self, usize)) -> Vec<TallyStates> {
+        self.run_with_deadline(None, hook)
+    }
+
+    /// Run the simulation, calling `observer` once per tick with read access to the
+    /// environment, the tick and its freshly recorded tally, e.g. to compute custom metrics
+    /// from the [agents](Environment::iter_agents) and the [grid](Environment::cell_agents)
+    pub fn run_with_observer<F: FnMut(&Self, usize, &TallyStates)>(
+        &mut self,
+        observer: F,
+    ) -> Vec<TallyStates> {
+        let mut observer = observer;
+        self.run_to(
+            None,
+            self.params.max_ticks,
+            |_, _| {},
+            |env, tick, stats| {
+                observer(env, tick, stats);
+                false
+            },
+        )
+    }
+
+    /// Run the simulation until `stop` holds for the environment and the tally after a tick,
+    /// or until it would stop [running](Environment::run) otherwise, returning the tally of
+    /// each tick like [`Environment::run`].
+    ///
+    /// The environment can be run further afterwards, e.g. with another predicate, whose
+    /// record then starts with the last tally of this one.
+    ///
+    /// ```
+    /// # use bkamins_sir_abm::julia_reimpl::Environment;
+    /// # use bkamins_sir_abm::presets;
+    /// let mut environment = Environment::from_params(&presets::blog_default());
+    /// let record = environment.run_until(|_, stats| stats.dead >= 10);
+    /// assert!(record.last().unwrap().dead >= 10 || !environment.is_active());
+    /// ```
+    pub fn run_until<F: FnMut(&Self, &TallyStates) -> bool>(
+        &mut self,
+        mut stop: F,
+    ) -> Vec<TallyStates> {
+        self.run_to(
+            None,
+            self.params.max_ticks,
+            |_, _| {},
+            |env, _, stats| stop(env, stats),
+        )
+    }
+
+    /// Run like [`Environment::run`], also taking a [snapshot](Environment::grid_snapshot) of
+    /// the grid at tick zero and at every `every` ticks after it, which must be positive
+    pub fn run_with_snapshots(&mut self, every: usize) -> (Vec<TallyStates>, Vec<GridSnapshot>) {
+        assert!(every > 0, "snapshots must be taken every so many ticks");
+        let mut snapshots = vec![GridSnapshot {
+            tick: self.tick,
+            cells: self.grid_snapshot(),
+        }];
+        let start = self.tick;
+        let record = self.run_with_hook(|env, tick| {
+            if (tick - start) % every == 0 {
+                snapshots.push(GridSnapshot {
+                    tick,
+                    cells: env.grid_snapshot(),
+                });
+            }
+        });
+        (record, snapshots)
+    }
+
+    /// Run the simulation until it ends or `budget` of wall-clock time is exhausted, which is
+    /// checked before every tick. The environment remains [active](Environment::is_active)
+    /// if the run was stopped early, and the record holds the ticks up to that point.
+    pub fn run_with_budget(&mut self, budget: Duration) -> Vec<TallyStates> {
+        self.run_with_deadline(Some(Instant::now() + budget), |_, _| {})
+    }
+
+    /// Run like [`Environment::run`] for at most `max_ticks` ticks from the current one, giving
+    /// at most `max_ticks + 1` tallies, and report why the run stopped.
+    ///
+    /// The run also stops at [`SimulationParams::max_ticks`], if that comes first.
+    pub fn run_for(&mut self, max_ticks: usize) -> (Vec<TallyStates>, TerminationReason) {
+        let last_tick = self.tick + max_ticks;
+        let last_tick = self
+            .params
+            .max_ticks
+            .map_or(last_tick, |max| max.min(last_tick));
+        let record = self.run_to(None, Some(last_tick), |_, _| {}, |_, _, _| false);
+        let termination = if self.is_active() {
+            TerminationReason::MaxTicks
+        } else {
+            TerminationReason::Extinct
+        };
+        (record, termination)
+    }
+
+    /// Run like [`Environment::run_with_hook`], stopping before the first tick that would
+    /// start after `deadline`, if any
+    pub(crate) fn run_with_deadline(
+        &mut self,
+        deadline: Option<Instant>,
+        hook: impl FnMut(&mut Self, usize),
+    ) -> Vec<TallyStates> {
+        self.run_to(deadline, self.params.max_ticks, hook, |_, _, _| false)
+    }
+
+    /// Run like [`Environment::run_with_deadline`], where the run also stops at `last_tick`, if
+    /// any, and `observer` is called after the tally of each tick is recorded, stopping the
+    /// run when it returns true
+    fn run_to(
+        &mut self,
+        deadline: Option<Instant>,
+        last_tick: Option<usize>,
+        mut hook: impl FnMut(&mut Self, usize),
+        mut observer: impl FnMut(&Self, usize, &TallyStates) -> bool,
+    ) -> Vec<TallyStates> {
+        // max ticks for the default scenario is 300 ticks
+        let mut stats_ticks = vec![self.begin_record()];
+
+        while self.is_active() && last_tick.map_or(true, |last| self.tick < last) {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break;
+            }
+            self.advance(&mut hook);
+            stats_ticks.push(self.stats.clone());
+            if observer(self, self.tick, &self.stats) {
+                break;
+            }
+        }
+
+        stats_ticks
+    }
+
+    /// Record the tally of the vectors before the first tick of a run, if vector-borne, and
+    /// give the tally of the agents
+    fn begin_record(&mut self) -> TallyStates {
+        if self.vector_params.is_some() {
+            self.vector_record.push(self.vector_tally());
+        }
+        self.stats.clone()
+    }
+
+    /// Advance the simulation by exactly one tick, returning its tally.
+    ///
+    /// Stepping until the environment [is finished](Environment::is_finished) gives the same
+    /// tallies as [`Environment::run`], apart from the one at the start, see
+    /// [`Environment::ticks`].
+    pub fn step(&mut self) -> TallyStates {
+        self.advance(&mut |_, _| {});
+        self.stats.clone()
+    }
+
+    /// Whether a run would stop here, as the environment is no longer
+    /// [active](Environment::is_active) or has [reached](Environment::reached_max_ticks)
+    /// its maximum number of ticks
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        !self.is_active() || self.reached_max_ticks()
+    }
+
+    /// Iterate over the tallies of a run, starting with the current one and then
+    /// [stepping](Environment::step) until the environment is finished, such that collecting
+    /// it is equivalent to calling [`Environment::run`]
+    pub fn ticks(&mut self) -> impl Iterator<Item = TallyStates> + '_ {
+        let mut started = false;
+        std::iter::from_fn(move || {
+            if !started {
+                started = true;
+                Some(self.begin_record())
+            } else if self.is_finished() {
+                None
+            } else {
+                Some(self.step())
+            }
+        })
+    }
+
+    /// Whether the run has reached [`SimulationParams::max_ticks`]
+    #[must_use]
+    pub fn reached_max_ticks(&self) -> bool {
+        self.params.max_ticks.is_some_and(|max| self.tick >= max)
+    }
+
+    /// Whether the run continues, as there are infected individuals, or more may yet be
+    /// infected from outside of the population
+    #[must_use]
+    pub fn is_active(&self) -> bool {
+        self.stats.exposed > 0
+            || self.stats.infected > 0
+            || self.stats.quarantined > 0
+            || self.has_pending_seeding()
+            || self.vectors_can_transmit()
+            || self.can_spill_over()
+    }
+
+    /// Advance the simulation by a single tick of [`Environment::run_with_hook`]
+    pub(crate) fn advance(&mut self, hook: &mut impl FnMut(&mut Self, usize)) {
+        let dead = if self.invariant_checks {
+            self.dead_locations()
+        } else {
+            Vec::new()
+        };
+        self.tick += 1;
+        self.rng = tick_rng(self.seed, self.tick);
+        self.movement_rng = stream_rng(self.seed, self.tick, MOVEMENT_STREAM);
+        self.update_type();
+        move_all(self);
+        self.move_vectors();
+        self.apply_seeding();
+        self.apply_vaccination();
+        hook(self, self.tick);
+        self.record_positions();
+        if self.vector_params.is_some() {
+            self.vector_record.push(self.vector_tally());
+        }
+        if self.invariant_checks {
+            self.check_invariants();
+            self.check_dead_in_place(&dead);
+        }
+    }
+
+    /// Run [`Environment::check_invariants`] after every tick, which is the default with
+    /// `debug_assertions`
+    pub fn set_invariant_checks(&mut self, enabled: bool) {
+        self.invariant_checks = enabled;
+    }
+
+    /// Check the internal consistency of the environment between ticks.
+    ///
+    /// # Panics
+    ///
+    /// Naming the agent or cell involved, if an agent does not appear exactly once in the grid
+    /// in the cell of its location, if a location lies outside the grid, if the tally does
+    /// not match a recount of the agents or does not sum to their number, or if an agent
+    /// entered its state after the current tick.
+    pub fn check_invariants(&self) {
+        let mut listed = vec![0_usize; self.agents.len()];
+        assert!(
+            self.grid.xdim == self.grid_size.0
+                && self.grid.cells.len() == self.grid_size.0 * self.grid_size.1,
+            "grid of size {:?} has {} cells in rows of {}",
+            self.grid_size,
+            self.grid.cells.len(),
+            self.grid.xdim
+        );
+        for (id, agent) in self.agents.iter().enumerate() {
+            assert!(
+                agent.x < self.grid_size.0 && agent.y < self.grid_size.1,
+                "agent {} at {:?} lies outside the grid of size {:?}",
+                id,
+                (agent.x, agent.y),
+                self.grid_size
+            );
+        }
+        for (cell, ids) in self.grid.iter() {
+            for &id in ids {
+                let agent = self
+                    .agents
+                    .get(id)
+                    .unwrap_or_else(|| panic!("cell {:?} lists the unknown agent {}", cell, id));
+                assert!(
+                    (agent.x, agent.y) == cell,
+                    "agent {} is listed in cell {:?} but located at {:?}",
+                    id,
+                    cell,
+                    (agent.x, agent.y)
+                );
+                listed[id] += 1;
+            }
+        }
+        for (id, (agent, &count)) in self.agents.iter().zip(&listed).enumerate() {
+            let expected = if self.is_removed(agent) { 0 } else { 1 };
+            assert!(
+                count == expected,
+                "agent {} at {:?} is listed {} times in the grid",
+                id,
+                (agent.x, agent.y),
+                count
+            );
+            assert!(
+                agent.tick <= self.tick,
+                "agent {} entered its state at tick {}, after the current tick {}",
+                id,
+                agent.tick,
+                self.tick
+            );
+        }
+
+        let recount = self.get_statistics();
+        assert!(
+            self.stats == recount,
+            "tally {:?} does not match the recount {:?}",
+            self.stats,
+            recount
+        );
+        let TallyStates {
+            susceptible,
+            exposed,
+            infected,
+            quarantined,
+            recovered,
+            vaccinated,
+            dead,
+        } = self.stats;
+        assert!(
+            susceptible + exposed + infected + quarantined + recovered + vaccinated + dead
+                == self.agents.len(),
+            "tally {:?} does not sum to the {} agents",
+            self.stats,
+            self.agents.len()
+        );
+    }
+
+    /// Index and location of each dead agent
+    fn dead_locations(&self) -> Vec<(usize, (usize, usize))> {
+        self.agents
+            .iter()
+            .enumerate()
+            .filter(|(_, agent)| agent.agent_type == AgentType::AgentD)
+            .map(|(id, agent)| (id, (agent.x, agent.y)))
+            .collect()
+    }
+
+    /// Check that the agents that were dead at `dead` locations have not moved
+    fn check_dead_in_place(&self, dead: &[(usize, (usize, usize))]) {
+        for &(id, location) in dead {
+            let agent = &self.agents[id];
+            assert!(
+                (agent.x, agent.y) == location,
+                "dead agent {} moved from {:?} to {:?}",
+                id,
+                location,
+                (agent.x, agent.y)
+            );
+        }
+    }
+}
+
+use serde::{Deserialize, Serialize};
+use soa_derive::StructOfArray;
+
+/// Number of agents in each state at a tick, as recorded by [`Environment::run`].
+///
+/// ```
+/// use bkamins_sir_abm::julia_reimpl::Environment;
+/// use bkamins_sir_abm::presets;
+///
+/// let record = Environment::from_params(&presets::dense_small_grid()).run();
+/// let peak = record.iter().map(|tally| tally.infected).max().unwrap();
+/// assert!(peak >= record[0].infected);
+/// assert!(record.iter().all(|tally| tally.total() == record[0].total()));
+/// println!("{}", record[0]);
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq, StructOfArray, Serialize, Deserialize)]
+#[soa_derive = "Debug, Clone, PartialEq"]
+pub struct TallyStates {
+    pub susceptible: usize,
+    /// Zero unless there is an [incubation period](SimulationParams::incubation)
+    #[serde(default)]
+    pub exposed: usize,
+    pub infected: usize,
+    /// Zero unless infected agents are [quarantined](SimulationParams::quarantine)
+    #[serde(default)]
+    pub quarantined: usize,
+    pub recovered: usize,
+    /// Agents immunized by vaccination, see [`Environment::vaccinate`]
+    #[serde(default)]
+    pub vaccinated: usize,
+    pub dead: usize,
+}
+
+impl TallyStates {
+    fn count_mut(&mut self, state: &AgentType) -> &mut usize {
+        match state {
+            AgentType::AgentS => &mut self.susceptible,
+            AgentType::AgentE => &mut self.exposed,
+            AgentType::AgentI => &mut self.infected,
+            AgentType::AgentQ => &mut self.quarantined,
+            AgentType::AgentR => &mut self.recovered,
+            AgentType::AgentV => &mut self.vaccinated,
+            AgentType::AgentD => &mut self.dead,
+        }
+    }
+
+    /// Count an agent that changed from state `from` to state `to`
+    fn transfer(&mut self, from: &AgentType, to: &AgentType) {
+        *self.count_mut(from) -= 1;
+        *self.count_mut(to) += 1;
+    }
+
+    /// Number of agents in all states, dead or alive
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.susceptible
+            + self.exposed
+            + self.infected
+            + self.quarantined
+            + self.recovered
+            + self.vaccinated
+            + self.dead
+    }
+}
+
+impl fmt::Display for TallyStates {
+    /// Counts by the letters of the states, e.g. `S=1990 E=0 I=10 Q=0 R=0 V=0 D=0`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "S={} E={} I={} Q={} R={} V={} D={}",
+            self.susceptible,
+            self.exposed,
+            self.infected,
+            self.quarantined,
+            self.recovered,
+            self.vaccinated,
+            self.dead
+        )
+    }
+}
+
+/// Column-wise copy of `records`, e.g. to read the peak of the infected agents of a run.
+///
+/// ```
+/// use bkamins_sir_abm::julia_reimpl::{Environment, TallyStatesVec};
+/// use bkamins_sir_abm::presets;
+///
+/// let record = Environment::from_params(&presets::dense_small_grid()).run();
+/// let columns = TallyStatesVec::from(record.as_slice());
+/// let peak = columns.infected.iter().max().unwrap();
+/// assert_eq!(columns.len(), record.len());
+/// assert!(record.iter().any(|tally| tally.infected == *peak));
+/// ```
+impl From<&[TallyStates]> for TallyStatesVec {
+    fn from(records: &[TallyStates]) -> Self {
+        records.iter().cloned().collect()
+    }
+}
+
+/// Column-wise representation of [`TallyStatesVec`] for serde, as the vector of pointers
+/// generated by `soa_derive` cannot derive it.
+#[derive(Serialize)]
+#[serde(rename = "TallyStatesVec")]
+struct TallyColumnsRef<'a> {
+    susceptible: &'a [usize],
+    exposed: &'a [usize],
+    infected: &'a [usize],
+    quarantined: &'a [usize],
+    recovered: &'a [usize],
+    vaccinated: &'a [usize],
+    dead: &'a [usize],
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "TallyStatesVec")]
+struct TallyColumns {
+    susceptible: Vec<usize>,
+    /// Missing from records written before the exposed state was added
+    #[serde(default)]
+    exposed: Vec<usize>,
+    infected: Vec<usize>,
+    /// Missing from records written before the quarantined state was added
+    #[serde(default)]
+    quarantined: Vec<usize>,
+    recovered: Vec<usize>,
+    /// Missing from records written before the vaccinated state was added
+    #[serde(default)]
+    vaccinated: Vec<usize>,
+    dead: Vec<usize>,
+}
+
+impl Serialize for TallyStatesVec {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        TallyColumnsRef {
+            susceptible: &self.susceptible,
+            exposed: &self.exposed,
+            infected: &self.infected,
+            quarantined: &self.quarantined,
+            recovered: &self.recovered,
+            vaccinated: &self.vaccinated,
+            dead: &self.dead,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TallyStatesVec {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let columns = TallyColumns::deserialize(deserializer)?;
+        let len = columns.susceptible.len();
+        // columns added to the model later default to zero
+        let or_zero = |column: Vec<usize>| {
+            if column.is_empty() {
+                vec![0; len]
+            } else {
+                column
+            }
+        };
+        let exposed = or_zero(columns.exposed);
+        let quarantined = or_zero(columns.quarantined);
+        let vaccinated = or_zero(columns.vaccinated);
+        if [
+            &exposed,
+            &columns.infected,
+            &quarantined,
+            &columns.recovered,
+            &vaccinated,
+            &columns.dead,
+        ]
+        .iter()
+        .any(|column| column.len() != len)
+        {
+            return Err(serde::de::Error::custom(
+                "columns of TallyStatesVec differ in length",
+            ));
+        }
+        Ok(TallyStatesVec {
+            susceptible: columns.susceptible,
+            exposed,
+            infected: columns.infected,
+            quarantined,
+            recovered: columns.recovered,
+            vaccinated,
+            dead: columns.dead,
+        })
+    }
+}
+
+/// Write `records` as CSV, with the header
+/// `tick,susceptible,exposed,infected,quarantined,recovered,vaccinated,dead` followed by a row
+/// for each tick, and flush `writer`
+pub fn write_csv<W: io::Write>(records: &[TallyStates], mut writer: W) -> io::Result<()> {
+    writeln!(
+        writer,
+        "tick,susceptible,exposed,infected,quarantined,recovered,vaccinated,dead"
+    )?;
+    for (tick, record) in records.iter().enumerate() {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{}",
+            tick,
+            record.susceptible,
+            record.exposed,
+            record.infected,
+            record.quarantined,
+            record.recovered,
+            record.vaccinated,
+            record.dead
+        )?;
+    }
+    writer.flush()
+}
+
+/// Write `records` as CSV to a file created at `path`, see [`write_csv`]
+pub fn to_csv_file(records: &[TallyStates], path: impl AsRef<Path>) -> io::Result<()> {
+    write_csv(records, io::BufWriter::new(File::create(path)?))
+}
+
+fn move_all<X>(
+    Environment {
+        grid,
         grid_size,
+        boundary,
         agents,
+        tick,
+        lockdown,
+        p_move,
+        movement_schedule,
+        movement_rng: rng,
+        cell_tracking,
+        corpse_handling,
+        scratch,
         ..
-    }: &mut Environment,
+    }: &mut Environment<X>,
 ) {
     // all agents must move, thus all the locations in the grid are invalid
-    // let grid = HashMap::with_capacity(grid.len());
-    grid.drain();
+    grid.clear();
+
+    // the schedule applies to the whole tick
+    let p_base = movement_probability(movement_schedule, *p_move, *tick);
+    let p_move = match lockdown {
+        Some(lockdown) if (lockdown.start..lockdown.end).contains(tick) => lockdown.p_move,
+        _ => 1.0,
+    };
+    // in the order of the update of the tick, or of the index before the first update
+    let n = agents.len();
+    let order = &scratch.order;
+    for k in 0..n {
+        let i = if order.len() == n { order[k] } else { k };
+        let agent = &mut agents[i];
+        // removed corpses draw no random numbers, unlike those that stay in place
+        if agent.agent_type == AgentType::AgentD && *corpse_handling == CorpseHandling::Remove {
+            continue;
+        }
+        // the reduction of movement only applies to the extent the agent complies
+        let p_agent = if agent.essential {
+            p_base
+        } else {
+            p_base * (1.0 - agent.compliance * (1.0 - p_move))
+        };
+        if p_agent >= 1.0 || rng.gen_bool(p_agent) {
+            agent.move_agent(*grid_size, *boundary, rng);
+        }
+        if let Some(tracking) = cell_tracking {
+            if agent.agent_type != AgentType::AgentD {
+                tracking.visits[agent.y * grid_size.0 + agent.x] += 1;
+            }
+        }
+        grid.push((agent.x, agent.y), i);
+    }
+}
+
+/// Probability of moving at `tick` of the first window of `schedule` that contains it, or
+/// `p_move` outside of the windows
+fn movement_probability(schedule: &[(Range<usize>, f64)], p_move: f64, tick: usize) -> f64 {
+    schedule
+        .iter()
+        .find(|(ticks, _)| ticks.contains(&tick))
+        .map_or(p_move, |&(_, p_move)| p_move)
+}
+
+/// Distance between `a` and `b` on a periodic axis of length `dim`
+fn torus_distance(a: usize, b: usize, dim: usize) -> usize {
+    let d = a.abs_diff(b);
+    d.min(dim - d)
+}
+
+/// Distance between `a` and `b` on an axis of length `dim` with `boundary`
+fn distance(a: usize, b: usize, dim: usize, boundary: Boundary) -> usize {
+    match boundary {
+        Boundary::Torus => torus_distance(a, b, dim),
+        Boundary::Reflecting | Boundary::Clamped => a.abs_diff(b),
+    }
+}
+
+/// Return the fraction infected individuals throughout the simulation
+#[cfg(test)]
+fn fraction_infected(l: usize, p_infect: f64) -> f64 {
+    let params = SimulationParams {
+        duration: l,
+        p_infect,
+        ..crate::presets::blog_default()
+    };
+    let mut e = Environment::from_params(&params);
+    e.run();
+
+    1.0 - e.stats.susceptible as f64 / params.n as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::{Compliance, DoseSeverity, EnvironmentConfig, VectorParams};
+    use std::collections::HashSet;
+
+    /// Parameters of the dense small grid preset, as configured by `configure`
+    fn configured_params(
+        configure: impl FnOnce(EnvironmentConfig) -> EnvironmentConfig,
+    ) -> SimulationParams {
+        configure(EnvironmentConfig::from(crate::presets::dense_small_grid()))
+            .build_params()
+            .unwrap()
+            .0
+    }
+
+    /// Environment of the dense small grid preset, as configured by `configure`
+    fn configured(configure: impl FnOnce(EnvironmentConfig) -> EnvironmentConfig) -> Environment {
+        Environment::from_params(&configured_params(configure))
+    }
+
+    #[test]
+    fn test_init_environment() {
+        // let initial_environment = Environment::init(5, 2, 10, 0.5, 10, 10);
+        let initial_environment = Environment::init(25, 2, 10, 0.5, 10, 10);
+        assert_eq!(initial_environment.agents.len(), 25);
+        let placed: usize = initial_environment.grid.cells.iter().map(Vec::len).sum();
+        assert_eq!(placed, 25);
+        assert_eq!(initial_environment.stats.infected, 2);
+        assert_eq!(
+            initial_environment.stats,
+            initial_environment.get_statistics()
+        );
+    }
+
+    fn assert_views_agree(e: &Environment) {
+        assert_eq!(e.iter_agents().count(), e.agents.len());
+        for (view, agent) in e.iter_agents().zip(e.agents.iter()) {
+            assert_eq!(e.agent(view.id).as_ref(), Some(&view));
+            assert_eq!((view.x, view.y), (agent.x, agent.y));
+            assert_eq!(view.state, agent.agent_type);
+            assert_eq!(view.since_tick, agent.tick);
+        }
+    }
+
+    #[test]
+    fn test_agent_view() {
+        let mut e = Environment::init(100, 5, 5, 0.5, 10, 10);
+        assert_views_agree(&e);
+        assert!(e.agent(100).is_none());
+
+        while e.stats.infected > 0 {
+            e.tick += 1;
+            e.update_type();
+            move_all(&mut e);
+            e.stats = e.get_statistics();
+            assert_views_agree(&e);
+        }
+    }
+
+    #[test]
+    fn test_extension_exposure_counter() {
+        // count the ticks each agent spends in a cell with an infected agent
+        let mut e = Environment::init_with(200, 5, 5, 0.5, 10, 10, |_, _, _| 0_usize);
+        assert_eq!(e.ext(0), Some(&0));
+        assert!(e.ext(200).is_none());
+
+        while e.stats.infected > 0 {
+            e.tick += 1;
+            e.update_type();
+            move_all(&mut e);
+            e.stats = e.get_statistics();
+
+            let views: Vec<_> = e.iter_agents().collect();
+            for view in &views {
+                let exposed = views.iter().any(|other| {
+                    other.id != view.id
+                        && other.state == AgentType::AgentI
+                        && (other.x, other.y) == (view.x, view.y)
+                });
+                if exposed {
+                    *e.ext_mut(view.id).unwrap() += 1;
+                }
+            }
+        }
+
+        // dead agents stay addressable, so the data stays aligned
+        assert_eq!(e.extensions.len(), e.agents.len());
+        assert!(e.stats.dead > 0);
+        assert!(e.iter_agents().all(|view| e.ext(view.id).is_some()));
+        assert!(e.extensions.iter().any(|&exposure| exposure > 0));
+    }
+
+    #[test]
+    fn test_unit_extension_is_free() {
+        let e = Environment::init(200, 5, 5, 0.5, 10, 10);
+        // a vector of zero-sized values never allocates
+        assert_eq!(e.extensions.capacity(), usize::MAX);
+        assert_eq!(e.extensions.len(), e.agents.len());
+    }
+
+    #[test]
+    fn test_extension_sees_initial_location() {
+        let e = Environment::init_with(50, 5, 5, 0.5, 10, 10, |i, x, y| (i, x, y));
+        for view in e.iter_agents() {
+            assert_eq!(e.ext(view.id), Some(&(view.id, view.x, view.y)));
+        }
+    }
+
+    #[test]
+    fn test_hook_immunizes_susceptible() {
+        let mut e = Environment::init(2000, 10, 21, 0.05, 100, 100);
+        let records = e.run_with_hook(|env, _tick| {
+            let ids: Vec<_> = env
+                .iter_agents()
+                .filter(|agent| agent.state == AgentType::AgentS)
+                .take(10)
+                .map(|agent| agent.id)
+                .collect();
+            for id in ids {
+                assert!(env.immunize(id));
+            }
+        });
+
+        for window in records.windows(2) {
+            let (before, after) = (&window[0], &window[1]);
+            assert!(before.susceptible - after.susceptible >= before.susceptible.min(10));
+        }
+    }
+
+    #[test]
+    fn test_vaccination_campaigns() {
+        // nobody is infected after the start, so only vaccination changes the susceptible
+        let params = SimulationParams {
+            n: 400,
+            infected: 1,
+            duration: usize::MAX,
+            p_infect: 0.0,
+            xdim: 20,
+            ydim: 20,
+            max_ticks: Some(10),
+            seed: Some(12),
+            ..SimulationParams::default()
+        };
+        let mut e = Environment::from_params(&params);
+        e.schedule_vaccination(5, 1.0);
+        e.schedule_vaccination(3, 0.25);
+        e.schedule_vaccination(4, 0.0);
+        e.schedule_vaccination(7, 1.0);
+        let record = e.run();
+        assert!(!e.has_pending_vaccination());
+        assert_eq!(record[2].susceptible, 399);
+        assert_eq!(record[3].susceptible, 299);
+        assert_eq!(record[3].vaccinated, 100);
+        assert_eq!(record[4], record[3]);
+        assert_eq!(record[5].susceptible, 0);
+        assert_eq!(record[5].vaccinated, 399);
+        assert_eq!(record.last().unwrap(), &record[5]);
+        for agent in e.iter_agents() {
+            let doses = agent.immune_history.doses().count();
+            assert_eq!(doses, (agent.state == AgentType::AgentV) as usize);
+        }
+
+        // a campaign in the schedule of the parameters, at the start of the run
+        let mut params = params;
+        params.vaccination = vec![VaccinationEntry {
+            tick: 0,
+            fraction: 0.5,
+        }];
+        let e = Environment::from_params(&params);
+        assert_eq!(e.stats.vaccinated, 200);
+        assert_eq!(e.get_statistics(), e.stats);
+
+        // campaigns after the epidemic has died out do not take place
+        let mut e = Environment::init_with_seed(400, 5, 5, 0.0, 20, 20, 2);
+        e.schedule_vaccination(10_000, 0.5);
+        let record = e.run();
+        assert!(e.has_pending_vaccination());
+        assert_eq!(record.last().unwrap().vaccinated, 0);
+        let susceptible = e.stats.susceptible;
+        assert_eq!(
+            e.vaccinate(0.5),
+            (susceptible as f64 / 2.0).round() as usize
+        );
+    }
+
+    #[test]
+    fn test_mutators_reject_non_susceptible() {
+        let mut e = Environment::init(10, 0, 5, 0.5, 10, 10);
+        let before = e.stats.clone();
+        assert!(e.import_case(3));
+        assert!(!e.import_case(3));
+        assert!(!e.immunize(3));
+        assert!(e.immunize(4));
+        assert!(!e.immunize(10));
+        assert_eq!(e.stats.infected, before.infected + 1);
+        assert_eq!(e.stats.vaccinated, before.vaccinated + 1);
+        assert_eq!(e.stats.susceptible, before.susceptible - 2);
+        assert_eq!(e.get_statistics().vaccinated, 1);
+    }
+
+    #[test]
+    fn test_burn_in() {
+        let params = SimulationParams {
+            burn_in_ticks: 0,
+            ..crate::presets::dense_small_grid()
+        };
+        let e = Environment::from_params_with(&params, |_, x, y| (x, y));
+        assert_eq!(e.burn_in_ticks(), 0);
+        for view in e.iter_agents() {
+            assert_eq!(e.ext(view.id), Some(&(view.x, view.y)));
+        }
+
+        let params = SimulationParams {
+            burn_in_ticks: 50,
+            ..params
+        };
+        let mut e = Environment::from_params_with(&params, |_, x, y| (x, y));
+        assert_eq!(e.burn_in_ticks(), 50);
+        assert_eq!(e.tick, 0);
+        // seeded at their post-burn-in locations, and no transmission happened
+        for view in e.iter_agents() {
+            assert_eq!(e.ext(view.id), Some(&(view.x, view.y)));
+            assert_eq!(view.since_tick, 0);
+            let expected = if view.id < params.infected {
+                AgentType::AgentI
+            } else {
+                AgentType::AgentS
+            };
+            assert_eq!(view.state, expected);
+        }
+        let infected = e
+            .iter_agents()
+            .filter(|view| view.state == AgentType::AgentI)
+            .count();
+        assert_eq!(infected, params.infected);
+        assert_eq!(e.run()[0].susceptible, params.n - params.infected);
+    }
+
+    #[test]
+    fn test_no_initial_infections() {
+        let params = SimulationParams {
+            infected: 0,
+            ..crate::presets::dense_small_grid()
+        };
+        let mut e = Environment::from_params(&params);
+        let records = e.run();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].susceptible, params.n);
+        assert_eq!(e.get_statistics(), records[0]);
+    }
+
+    #[test]
+    fn test_seeding_schedule() {
+        let center = SeedLocation::Around {
+            center: (10, 10),
+            radius: 2,
+        };
+        let corner = SeedLocation::Around {
+            center: (0, 0),
+            radius: 2,
+        };
+        let mut e = configured(|config| config.seed_at(0, 5, center).seed_at(6, 5, corner));
+        assert_eq!(e.seeding_log().len(), 5);
+        assert!(e.has_pending_seeding());
+        e.run();
+        assert!(!e.has_pending_seeding());
+
+        let log = e.seeding_log();
+        assert!(log.len() <= 10);
+        for record in log {
+            let (tick, center) = if record.entry == 0 {
+                (0, (10, 10))
+            } else {
+                (6, (0, 0))
+            };
+            assert_eq!(record.tick, tick);
+            assert!(torus_distance(record.x, center.0, 20) <= 2);
+            assert!(torus_distance(record.y, center.1, 20) <= 2);
+        }
+    }
+
+    #[test]
+    fn test_seeding_targets_susceptible_only() {
+        let params = SimulationParams {
+            seeding: vec![SeedingEntry {
+                tick: 3,
+                count: 1000,
+                location: SeedLocation::Anywhere,
+            }],
+            ..crate::presets::dense_small_grid()
+        };
+        let mut e = Environment::from_params(&params);
+        let records = e.run();
+        assert_eq!(records[3].susceptible, 0);
+
+        let mut seeded: Vec<_> = e.seeding_log().iter().map(|record| record.agent).collect();
+        assert!(seeded.len() <= records[2].susceptible);
+        assert!(e.seeding_log().iter().all(|record| record.tick == 3));
+        seeded.sort_unstable();
+        seeded.dedup();
+        assert_eq!(seeded.len(), e.seeding_log().len());
+    }
+
+    #[test]
+    fn test_seeding_after_extinction() {
+        let params = SimulationParams {
+            seeding: vec![SeedingEntry {
+                tick: 200,
+                count: 5,
+                location: SeedLocation::Anywhere,
+            }],
+            ..crate::presets::sparse_fizzle()
+        };
+        let mut e = Environment::from_params(&params);
+        let records = e.run();
+        assert!(records.len() > 200);
+        assert!(records[200].infected >= 5);
+        assert_eq!(e.seeding_log().len(), 5);
+        assert!(e.seeding_log().iter().all(|record| record.tick == 200));
+    }
+
+    fn vector_borne(count: usize, p_move: f64) -> Environment {
+        configured(|config| {
+            config.vectors(VectorParams {
+                count,
+                p_move,
+                p_bite: 0.5,
+                p_transmit: 0.5,
+                infectious_duration: None,
+                turnover: 0.01,
+            })
+        })
+    }
+
+    #[test]
+    fn test_no_vectors_no_transmission() {
+        let mut e = vector_borne(0, 1.0);
+        let susceptible = e.get_statistics().susceptible;
+        let records = e.run();
+        assert_eq!(records.last().unwrap().susceptible, susceptible);
+        assert!(e
+            .vector_record()
+            .iter()
+            .all(|tally| tally == &VectorTally::default()));
+    }
+
+    #[test]
+    fn test_immobile_vectors_spread() {
+        let mut e = vector_borne(2000, 0.0);
+        let susceptible = e.get_statistics().susceptible;
+        let records = e.run();
+        assert!(records.last().unwrap().susceptible < susceptible / 2);
+
+        assert_eq!(e.vector_record().len(), records.len());
+        for (vectors, agents) in e.vector_record().iter().zip(&records) {
+            assert_eq!(
+                vectors.susceptible + vectors.infectious + vectors.spent,
+                2000
+            );
+            let total = agents.susceptible + agents.infected + agents.recovered + agents.dead;
+            assert_eq!(total, 500);
+        }
+        assert!(e.vector_record().iter().any(|tally| tally.infectious > 0));
+    }
+
+    #[test]
+    fn test_vectors_are_spent() {
+        let mut e = configured(|config| {
+            config.vectors(VectorParams {
+                count: 400,
+                p_move: 1.0,
+                p_bite: 1.0,
+                p_transmit: 0.1,
+                infectious_duration: Some(2),
+                turnover: 0.0,
+            })
+        });
+        e.run();
+        assert!(e.vector_record().iter().any(|tally| tally.spent > 0));
+        assert_eq!(e.vector_tally().infectious, 0);
+    }
+
+    fn with_reservoirs(cells: Vec<(usize, usize)>, p_spillover: f64) -> Environment {
+        configured(|config| config.reservoirs(cells, p_spillover))
+    }
+
+    #[test]
+    fn test_spillover_only_in_reservoirs() {
+        let cells = vec![(3, 3), (15, 7)];
+        let mut e = with_reservoirs(cells.clone(), 0.3);
+        e.run();
+        assert!(!e.spillover_log().is_empty());
+        for record in e.spillover_log() {
+            assert!(cells.contains(&(record.x, record.y)));
+        }
+    }
+
+    #[test]
+    fn test_zero_spillover_is_baseline() {
+        let mut e = with_reservoirs(vec![(3, 3)], 0.0);
+        e.run();
+        assert!(e.spillover_log().is_empty());
+        assert_eq!(e.get_statistics().infected, 0);
+    }
+
+    #[test]
+    fn test_spillover_log_counts_primary_cases() {
+        // with transmission between agents disabled every infection after the initial ones is
+        // a spillover
+        let mut params = configured_params(|config| {
+            config.p_infect(0.0).reservoirs(vec![(0, 0), (10, 10)], 0.2)
+        });
+        params.seed = Some(7);
+        let mut e = Environment::from_params(&params);
+        let susceptible = e.get_statistics().susceptible;
+        let records = e.run();
+        let primary = susceptible - records.last().unwrap().susceptible;
+        assert!(primary > 0);
+        assert_eq!(e.spillover_log().len(), primary);
+    }
+
+    #[test]
+    fn test_ppe_protects_cellmates() {
+        let layout = [
+            (0, 0, AgentType::AgentI),
+            (0, 0, AgentType::AgentS),
+            (0, 0, AgentType::AgentR),
+            (0, 0, AgentType::AgentS),
+            (1, 1, AgentType::AgentS),
+        ];
+        let mut e = Environment::from_layout(&layout, 5, 0.0, (3, 3), Some(1), |_, _, _| ());
+        e.detection = Some(DetectionParams { p_detect: 1.0 });
+        e.ppe = Some(PpeParams {
+            duration: 4,
+            efficacy_complement: 0.0,
+        });
+        e.tick = 7;
+        e.detect(&[0, 1, 2, 3, 4]);
+        assert_eq!(
+            e.detection_log(),
+            &[DetectionRecord {
+                tick: 7,
+                agent: 0,
+                x: 0,
+                y: 0,
+                protected: vec![1, 3],
+            }]
+        );
+        let protected: Vec<_> = e.iter_agents().map(|a| a.protected_until).collect();
+        assert_eq!(protected, vec![None, Some(11), None, Some(11), None]);
+        assert!(e.agent(0).unwrap().detected);
+
+        // detected agents are not detected again
+        e.tick = 8;
+        e.detect(&[0, 1, 2, 3, 4]);
+        assert_eq!(e.detection_log().len(), 1);
+    }
+
+    #[test]
+    fn test_compliance_gates_interventions() {
+        let layout = [
+            (0, 0, AgentType::AgentI),
+            (0, 0, AgentType::AgentS),
+            (0, 0, AgentType::AgentS),
+        ];
+        for &compliance in &[0.0, 1.0] {
+            let mut e = Environment::from_layout(&layout, 5, 0.0, (3, 3), Some(1), |_, _, _| ());
+            for agent in &mut e.agents {
+                agent.compliance = compliance;
+            }
+            e.detection = Some(DetectionParams { p_detect: 1.0 });
+            e.ppe = Some(PpeParams {
+                duration: 4,
+                efficacy_complement: 0.0,
+            });
+            e.isolation = true;
+            e.tick = 1;
+            e.detect(&[0, 1, 2]);
+
+            // detection itself does not depend on compliance
+            assert_eq!(e.detection_log().len(), 1);
+            let complies = compliance == 1.0;
+            let expected: Vec<usize> = if complies { vec![1, 2] } else { vec![] };
+            assert_eq!(e.detection_log()[0].protected, expected);
+            assert_eq!(e.agent(0).unwrap().isolated, complies);
+
+            move_all(&mut e);
+            if complies {
+                assert_eq!((e.agents[0].x, e.agents[0].y), (0, 0));
+            }
+            e.agents[0].recover(2);
+            assert!(!e.agent(0).unwrap().isolated);
+        }
+    }
+
+    #[test]
+    fn test_attack_rate_by_compliance() {
+        use AgentType::*;
+        let layout: Vec<_> = [
+            AgentS, AgentR, AgentI, AgentI, AgentS, AgentS, AgentD, AgentS,
+        ]
+        .iter()
+        .map(|state| (0, 0, state.clone()))
+        .collect();
+        let mut e = Environment::from_layout(&layout, 5, 0.0, (1, 1), Some(1), |_, _, _| ());
+        for (agent, &compliance) in e
+            .agents
+            .iter_mut()
+            .zip(&[0.9, 0.1, 0.2, 0.8, 0.7, 0.3, 0.6, 0.4])
+        {
+            agent.compliance = compliance;
+        }
+        // ranked: R, I | S, S | D, S | I, S
+        assert_eq!(e.attack_rate_by_compliance(), [1.0, 0.0, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_compliant_agents_are_infected_less() {
+        let params = configured_params(|config| {
+            config
+                .compliance(Compliance::Uniform {
+                    low: 0.0,
+                    high: 1.0,
+                })
+                .detection(1.0)
+                .ppe(30, 0.0)
+        });
+        let mut rates = [0.0; 4];
+        for _ in 0..5 {
+            let mut e = Environment::from_params(&params);
+            e.run();
+            for (total, rate) in rates.iter_mut().zip(&e.attack_rate_by_compliance()) {
+                *total += rate;
+            }
+        }
+        assert!(rates[0] > rates[3], "{:?}", rates);
+    }
+
+    fn with_dose_severity(dose_severity: DoseSeverity) -> Environment {
+        configured(|config| config.p_death(0.3).dose_severity(dose_severity))
+    }
+
+    #[test]
+    fn test_constant_dose_severity_is_baseline() {
+        let mut e = with_dose_severity(DoseSeverity::Constant(1.0));
+        e.run();
+        let outcomes = e.dose_outcomes();
+        assert!(outcomes.iter().all(|outcome| outcome.dose > 0));
+        let dead: usize = outcomes.iter().map(|outcome| outcome.dead).sum();
+        let resolved: usize = outcomes
+            .iter()
+            .map(|outcome| outcome.recovered + outcome.dead)
+            .sum();
+        assert!(resolved > 100);
+        let cfr = dead as f64 / resolved as f64;
+        assert!((cfr - 0.3).abs() < 0.1, "{}", cfr);
+    }
+
+    #[test]
+    fn test_deaths_concentrate_in_crowded_cells() {
+        // with p_death 0.3, the multiplier is close to 0 for a single infectious cellmate
+        // and caps the probability of death at 1 for more
+        let mut e = with_dose_severity(DoseSeverity::Logistic {
+            max: 10.0,
+            midpoint: 1.5,
+            steepness: 40.0,
+        });
+        e.run();
+        for agent in e.iter_agents() {
+            match (agent.state, agent.dose) {
+                (AgentType::AgentD, Some(dose)) => assert!(dose >= 2),
+                (AgentType::AgentR, Some(dose)) => assert_eq!(dose, 1),
+                _ => {}
+            }
+        }
+        let outcomes = e.dose_outcomes();
+        assert_eq!(outcomes[0].dose, 1);
+        assert_eq!(outcomes[0].cfr(), Some(0.0));
+        assert!(outcomes[1..]
+            .iter()
+            .all(|outcome| outcome.cfr() == Some(1.0)));
+    }
+
+    fn locked_down(essential_fraction: f64) -> Environment {
+        configured(|config| {
+            config
+                .lockdown(0, usize::MAX, 0.0)
+                .essential_fraction(essential_fraction)
+        })
+    }
+
+    #[test]
+    fn test_total_lockdown_freezes_spread() {
+        let mut e = locked_down(0.0);
+        assert!(e.iter_agents().all(|agent| !agent.essential));
+        let initial: Vec<_> = e.iter_agents().collect();
+        let seeded_cells: HashSet<_> = initial
+            .iter()
+            .filter(|agent| agent.state == AgentType::AgentI)
+            .map(|agent| (agent.x, agent.y))
+            .collect();
+        e.run();
+        for (agent, before) in e.iter_agents().zip(&initial) {
+            assert_eq!((agent.x, agent.y), (before.x, before.y));
+            if agent.state != AgentType::AgentS {
+                assert!(seeded_cells.contains(&(agent.x, agent.y)));
+            }
+        }
+        assert_eq!(e.attack_rate_by_essential().essential, None);
+    }
+
+    #[test]
+    fn test_p_move_applies_to_essential_workers() {
+        let mut e = configured(|config| config.p_move(0.0).essential_fraction(0.5));
+        let initial: Vec<_> = e.iter_agents().map(|agent| (agent.x, agent.y)).collect();
+        for _ in 0..5 {
+            e.advance(&mut |_, _| {});
+        }
+        let locations: Vec<_> = e.iter_agents().map(|agent| (agent.x, agent.y)).collect();
+        assert_eq!(locations, initial);
+
+        // about half the agents move in a tick
+        let mut e = configured(|config| config.p_move(0.5));
+        let initial: Vec<_> = e.iter_agents().map(|agent| (agent.x, agent.y)).collect();
+        e.advance(&mut |_, _| {});
+        let moved = e
+            .iter_agents()
+            .zip(&initial)
+            .filter(|(agent, &location)| (agent.x, agent.y) != location)
+            .count();
+        assert!((100..=300).contains(&moved), "{}", moved);
+    }
+
+    #[test]
+    fn test_high_risk_agents_die() {
+        let params = configured_params(|config| {
+            config.p_death(0.5).heterogeneity(AgentParams {
+                p_death: Some(AgentDistribution::TwoGroup {
+                    fraction: 0.5,
+                    high_risk: 1.0,
+                    other: 0.0,
+                }),
+                ..AgentParams::default()
+            })
+        });
+        let mut e = Environment::from_params(&SimulationParams {
+            seed: Some(8),
+            ..params
+        });
+        let high_risk = e
+            .iter_agents()
+            .filter(|agent| agent.p_death == Some(1.0))
+            .count();
+        assert!((200..=300).contains(&high_risk), "{}", high_risk);
+        let last = e.run().pop().unwrap();
+        for agent in e.iter_agents() {
+            if agent.state == AgentType::AgentD {
+                assert_eq!(agent.p_death, Some(1.0));
+            }
+        }
+        // virtually every agent is infected on the dense grid
+        assert!(last.dead <= high_risk);
+        assert!(
+            last.dead * 20 >= high_risk * 19,
+            "{} of {}",
+            last.dead,
+            high_risk
+        );
+    }
+
+    #[test]
+    fn test_agent_susceptibility_and_duration() {
+        let mut e = configured(|config| {
+            config.heterogeneity(AgentParams {
+                susceptibility: Some(AgentDistribution::TwoGroup {
+                    fraction: 0.2,
+                    high_risk: 0.0,
+                    other: 1.0,
+                }),
+                duration: Some(AgentDistribution::Uniform {
+                    low: 1.6,
+                    high: 2.4,
+                }),
+                ..AgentParams::default()
+            })
+        });
+        e.run();
+        for agent in e.iter_agents() {
+            assert_eq!(agent.duration, Some(2));
+            let infections: Vec<_> = agent.immune_history.infections().collect();
+            if agent.susceptibility == Some(0.0) {
+                // only the initial infections hit immune agents
+                assert!(infections.iter().all(|&tick| tick == 0));
+            }
+            if let (Some(&infected), Some(immunized)) =
+                (infections.first(), agent.immune_history.last_immunized())
+            {
+                assert_eq!(immunized - infected, 3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_movement_schedule() {
+        let schedule = [(0..20, 1.0), (20..60, 0.2), (40..80, 0.5)];
+        let p_move = |tick| movement_probability(&schedule, 0.9, tick);
+        assert_eq!(p_move(0), 1.0);
+        assert_eq!(p_move(19), 1.0);
+        assert_eq!(p_move(20), 0.2);
+        // the first window wins where windows overlap
+        assert_eq!(p_move(59), 0.2);
+        assert_eq!(p_move(60), 0.5);
+        assert_eq!(p_move(80), 0.9);
+    }
+
+    #[test]
+    fn test_frozen_movement_confines_the_epidemic() {
+        let mut e = configured(|config| config);
+        e.set_movement_schedule(vec![(0..usize::MAX, 0.0)]);
+        let initial: Vec<_> = e.iter_agents().collect();
+        let seeded_cells: HashSet<_> = initial
+            .iter()
+            .filter(|agent| agent.state == AgentType::AgentI)
+            .map(|agent| (agent.x, agent.y))
+            .collect();
+        e.run();
+        for (agent, before) in e.iter_agents().zip(&initial) {
+            assert_eq!((agent.x, agent.y), (before.x, before.y));
+            // every agent that shares a cell with an initial infection is infected, and no one
+            // else
+            assert_eq!(
+                agent.state != AgentType::AgentS,
+                seeded_cells.contains(&(agent.x, agent.y))
+            );
+        }
+    }
+
+    #[test]
+    fn test_essential_workers_ignore_lockdown() {
+        let mut e = locked_down(1.0);
+        let initial: Vec<_> = e.iter_agents().collect();
+        for tick in 1..=10 {
+            e.tick = tick;
+            move_all(&mut e);
+        }
+        let moved = e
+            .iter_agents()
+            .zip(&initial)
+            .filter(|(agent, before)| (agent.x, agent.y) != (before.x, before.y))
+            .count();
+        assert!(moved > initial.len() * 9 / 10, "{}", moved);
+        assert_eq!(e.attack_rate_by_essential().other, None);
+    }
+
+    /// Message of the panic of the invariant checks on a copy of `e` corrupted by `corrupt`
+    fn violation(e: &Environment, corrupt: impl FnOnce(&mut Environment)) -> String {
+        let mut e = e.clone();
+        let dead = e.dead_locations();
+        corrupt(&mut e);
+        let error = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            e.check_invariants();
+            e.check_dead_in_place(&dead);
+        }))
+        .expect_err("the corruption is caught");
+        *error.downcast::<String>().unwrap()
+    }
+
+    /// Final tally and number of records of a run of the dense small grid with the given
+    /// parameters
+    fn degenerate(
+        n: usize,
+        infected: usize,
+        duration: usize,
+        p_death: f64,
+        dim: usize,
+    ) -> (TallyStates, usize) {
+        let params = SimulationParams {
+            n,
+            infected,
+            duration,
+            p_death,
+            xdim: dim,
+            ydim: dim,
+            ..crate::presets::dense_small_grid()
+        };
+        params.validate().unwrap();
+        let mut e = Environment::from_params(&params);
+        e.set_invariant_checks(true);
+        let records = e.run();
+        let last = records.last().unwrap().clone();
+        assert_eq!(
+            last.susceptible + last.infected + last.recovered + last.dead,
+            n
+        );
+        assert_eq!(last.infected, 0);
+        (last, records.len())
+    }
+
+    #[test]
+    fn test_degenerate_configurations() {
+        let tally = |susceptible, recovered, dead| TallyStates {
+            susceptible,
+            exposed: 0,
+            infected: 0,
+            quarantined: 0,
+            recovered,
+            vaccinated: 0,
+            dead,
+        };
+        // nothing happens without infections, or without agents
+        assert_eq!(degenerate(500, 0, 5, 0.1, 20), (tally(500, 0, 0), 1));
+        assert_eq!(degenerate(0, 0, 5, 0.1, 20), (tally(0, 0, 0), 1));
+        assert_eq!(degenerate(0, 0, 0, 1.0, 1), (tally(0, 0, 0), 1));
+        let run = crate::result::run(&SimulationParams {
+            n: 0,
+            infected: 0,
+            ..crate::presets::dense_small_grid()
+        });
+        assert_eq!(run.record.len(), 1);
+        assert_eq!(run.compliance_attack_rates, [0.0; 4]);
+        // without a duration, the initial cases end before they infect anyone
+        let (last, ticks) = degenerate(500, 10, 0, 0.5, 20);
+        assert_eq!(
+            (last.susceptible, last.recovered + last.dead, ticks),
+            (490, 10, 2)
+        );
+        assert_eq!(degenerate(500, 10, 0, 1.0, 1), (tally(490, 0, 10), 2));
+        // every infection is fatal
+        let (last, _) = degenerate(500, 10, 3, 1.0, 20);
+        assert_eq!(last.recovered, 0);
+        assert!(last.dead >= 10);
+        // on a single cell everyone is infected at the first tick
+        assert_eq!(degenerate(50, 1, 3, 0.0, 1), (tally(0, 50, 0), 6));
+        assert_eq!(degenerate(50, 1, 3, 1.0, 1), (tally(0, 0, 50), 6));
+        assert_eq!(degenerate(50, 50, 2, 0.0, 1), (tally(0, 50, 0), 4));
+        assert_eq!(degenerate(1, 1, 0, 0.0, 1), (tally(0, 1, 0), 2));
+    }
+
+    #[test]
+    fn test_more_infected_than_agents() {
+        let e = Environment::init(10, 20, 5, 0.1, 5, 5);
+        assert_eq!(e.get_statistics().infected, 10);
+        e.check_invariants();
+    }
+
+    #[test]
+    fn test_imported_case_without_duration() {
+        let mut e = Environment::init(20, 1, 0, 0.0, 5, 5);
+        e.run_with_hook(|env, tick| {
+            if tick == 1 {
+                assert!(env.import_case(1));
+            }
+        });
+        assert_eq!(e.get_statistics().recovered, 2);
+    }
+
+    #[test]
+    fn test_invariant_checks() {
+        let mut e = Environment::from_params(&crate::presets::dense_small_grid());
+        e.set_invariant_checks(true);
+        for _ in 0..5 {
+            e.advance(&mut |_, _| {});
+        }
+        e.check_invariants();
+        e.agents[7].agent_type = AgentType::AgentD;
+        e.stats = e.get_statistics();
+
+        let message = violation(&e, |e| e.agents[3].x = (e.agents[3].x + 1) % 20);
+        assert!(
+            message.starts_with("agent 3 is listed in cell"),
+            "{}",
+            message
+        );
+        let message = violation(&e, |e| {
+            let cell = e.agents[3].y * 20 + e.agents[3].x;
+            e.grid.cells[cell].retain(|&id| id != 3);
+        });
+        assert!(message.contains("agent 3 at") && message.ends_with("listed 0 times in the grid"));
+        let message = violation(&e, |e| {
+            let cell = (e.agents[3].x, e.agents[3].y);
+            e.grid.push(cell, 3);
+        });
+        assert!(
+            message.ends_with("listed 2 times in the grid"),
+            "{}",
+            message
+        );
+        let message = violation(&e, |e| e.grid.cells.push(Vec::new()));
+        assert!(
+            message.starts_with("grid of size (20, 20) has 401 cells"),
+            "{}",
+            message
+        );
+        let message = violation(&e, |e| e.agents[3].y = 20);
+        assert!(
+            message.starts_with("agent 3 at")
+                && message.ends_with("lies outside the grid of size (20, 20)"),
+            "{}",
+            message
+        );
+        let message = violation(&e, |e| e.stats.infected += 1);
+        assert!(
+            message.contains("does not match the recount"),
+            "{}",
+            message
+        );
+        let message = violation(&e, |e| e.agents[3].tick = e.tick + 1);
+        assert!(
+            message.starts_with("agent 3 entered its state at tick 6"),
+            "{}",
+            message
+        );
+        let message = violation(&e, |e| {
+            // the grid follows, such that only the move of the dead agent is at fault
+            e.agents[7].x = (e.agents[7].x + 1) % 20;
+            e.grid.clear();
+            for (id, agent) in e.agents.iter().enumerate() {
+                e.grid.push((agent.x, agent.y), id);
+            }
+        });
+        assert!(message.starts_with("dead agent 7 moved"), "{}", message);
+
+        // a clean run passes the checks after every tick
+        e.run();
+        e.check_invariants();
+    }
+
+    #[test]
+    fn test_lockdown_scales_with_compliance() {
+        let mut e = locked_down(0.0);
+        for (i, agent) in e.agents.iter_mut().enumerate() {
+            agent.compliance = if i % 2 == 0 { 0.0 } else { 1.0 };
+        }
+        let initial: Vec<_> = e.iter_agents().collect();
+        let mut moves = [0; 2];
+        for tick in 1..=10 {
+            e.tick = tick;
+            let before: Vec<_> = e.iter_agents().collect();
+            move_all(&mut e);
+            for (i, (agent, before)) in e.iter_agents().zip(&before).enumerate() {
+                if (agent.x, agent.y) != (before.x, before.y) {
+                    moves[i % 2] += 1;
+                }
+            }
+        }
+        // agents that do not comply move as without a lockdown, those that do stay put
+        assert!(moves[0] > initial.len() / 2 * 10 / 2, "{:?}", moves);
+        assert_eq!(moves[1], 0);
+    }
+
+    #[test]
+    fn test_epidemic_percolates_through_essential_workers() {
+        let (mut essential, mut other) = (0.0, 0.0);
+        for _ in 0..5 {
+            let mut e = locked_down(0.3);
+            assert_eq!(e.iter_agents().filter(|agent| agent.essential).count(), 150);
+            e.run();
+            let rates = e.attack_rate_by_essential();
+            essential += rates.essential.unwrap();
+            other += rates.other.unwrap();
+        }
+        assert!(essential > other, "{} {}", essential, other);
+    }
+
+    #[test]
+    fn test_certain_infection_leaves_no_near_misses() {
+        let mut e = Environment::from_params(&crate::presets::dense_small_grid());
+        e.run();
+        let distribution = e.exposure_distribution();
+        assert!(distribution.never_infected.len() <= 1);
+        assert_eq!(distribution.infected.len(), 2);
+        let agents = distribution.never_infected.iter().sum::<usize>()
+            + distribution.infected.iter().sum::<usize>();
+        assert_eq!(agents, 500);
+    }
+
+    #[test]
+    fn test_zero_p_infect_never_transmits() {
+        let mut e = Environment::from_params(&SimulationParams {
+            p_infect: 0.0,
+            ..crate::presets::dense_small_grid()
+        });
+        let record = e.run();
+        assert!(record.iter().all(|tally| tally.susceptible == 495));
+        assert_eq!(record.last().unwrap().infected, 0);
+        assert!(e
+            .iter_agents()
+            .all(|agent| agent.immune_history.infections().all(|tick| tick == 0)));
+    }
+
+    #[test]
+    fn test_p_infect_per_contact() {
+        // one infected agent among 2000 susceptible agents in the same cell
+        let layout: Vec<_> = std::iter::once((2, 2, AgentType::AgentI))
+            .chain(std::iter::repeat((2, 2, AgentType::AgentS)).take(2000))
+            .collect();
+        let mut e = Environment::from_layout(&layout, 10, 0.0, (5, 5), Some(1), |_, _, _| ());
+        e.p_infect = 0.3;
+        e.tick = 1;
+        e.update_type();
+        let infected = e
+            .iter_agents()
+            .filter(|agent| agent.state == AgentType::AgentI)
+            .count()
+            - 1;
+        // 600 expected, with a standard deviation of about 20
+        assert!((500..=700).contains(&infected), "{}", infected);
+    }
+
+    /// Move agent `index` to `cell` between ticks
+    fn relocate(e: &mut Environment, index: usize, cell: (usize, usize)) {
+        e.agents[index].x = cell.0;
+        e.agents[index].y = cell.1;
+        e.grid.clear();
+        for (i, agent) in e.agents.iter().enumerate() {
+            e.grid.push((agent.x, agent.y), i);
+        }
+    }
+
+    /// Every order of `0..n`
+    fn permutations(n: usize) -> Vec<Vec<usize>> {
+        (0..n).fold(vec![Vec::new()], |orders, _| {
+            let mut longer = Vec::new();
+            for order in orders {
+                for i in (0..n).filter(|i| !order.contains(i)) {
+                    let mut order = order.clone();
+                    order.push(i);
+                    longer.push(order);
+                }
+            }
+            longer
+        })
+    }
+
+    #[test]
+    fn test_update_is_synchronous() {
+        use AgentType::*;
+        // two infected agents, each with a susceptible cellmate, where the second one was
+        // infected in the first tick by a hook
+        let agents = [
+            (0, 0, AgentI),
+            (0, 0, AgentS),
+            (1, 1, AgentS),
+            (1, 1, AgentI),
+        ];
+        let expected = [
+            [AgentI, AgentI, AgentS, AgentI],
+            [AgentD, AgentI, AgentI, AgentI],
+            [AgentD, AgentD, AgentI, AgentD],
+            [AgentD, AgentD, AgentD, AgentD],
+        ];
+        for order in permutations(agents.len()) {
+            let layout: Vec<_> = order.iter().map(|&id| agents[id].clone()).collect();
+            let mut e = Environment::from_layout(&layout, 1, 1.0, (2, 2), Some(1), |_, _, _| ());
+            let index = |id| order.iter().position(|&other| other == id).unwrap();
+            e.agents[index(3)].tick = 1;
+            for (tick, expected) in (1..).zip(&expected) {
+                e.tick = tick;
+                e.update_type();
+                let states: Vec<_> = (0..agents.len())
+                    .map(|id| e.agents[index(id)].agent_type.clone())
+                    .collect();
+                assert_eq!(&states, expected, "tick {} in order {:?}", tick, order);
+            }
+            let infected_at: Vec<_> = (0..agents.len())
+                .map(|id| e.agents[index(id)].history.events()[0])
+                .collect();
+            assert_eq!(
+                infected_at,
+                [(0, 0, 0), (1, 0, 0), (2, 1, 1), (0, 1, 1)]
+                    .iter()
+                    .map(|&(tick, x, y)| ImmuneEvent::Infection { tick, x, y })
+                    .collect::<Vec<_>>(),
+                "order {:?}",
+                order
+            );
+        }
+    }
+
+    #[test]
+    fn test_dose_is_counted_before_transitions() {
+        use AgentType::*;
+        // the first agent recovers in the second tick, before which a susceptible agent moves
+        // in, which is then infected by the second one only
+        let agents = [(0, 0, AgentI), (0, 0, AgentI), (1, 0, AgentS)];
+        for order in permutations(agents.len()) {
+            let layout: Vec<_> = order.iter().map(|&id| agents[id].clone()).collect();
+            let mut e = Environment::from_layout(&layout, 1, 0.0, (2, 2), Some(1), |_, _, _| ());
+            let index = |id| order.iter().position(|&other| other == id).unwrap();
+            e.agents[index(1)].tick = 1;
+            e.tick = 1;
+            e.update_type();
+            assert_eq!(e.agents[index(2)].agent_type, AgentS);
+
+            let susceptible = index(2);
+            relocate(&mut e, susceptible, (0, 0));
+            e.tick = 2;
+            e.update_type();
+            let states: Vec<_> = (0..agents.len())
+                .map(|id| e.agents[index(id)].agent_type.clone())
+                .collect();
+            assert_eq!(states, [AgentR, AgentI, AgentI], "order {:?}", order);
+            assert_eq!(e.agents[susceptible].dose, Some(1), "order {:?}", order);
+        }
+    }
+
+    #[test]
+    fn test_infection_radius_wraps() {
+        let layout = [
+            (0, 0, AgentType::AgentI),
+            (99, 99, AgentType::AgentS),
+            (98, 98, AgentType::AgentS),
+        ];
+        let infected = |radius, boundary| {
+            let mut e =
+                Environment::from_layout(&layout, 10, 0.0, (100, 100), Some(1), |_, _, _| ());
+            e.infection_radius = radius;
+            e.boundary = boundary;
+            e.tick = 1;
+            e.update_type();
+            e.iter_agents()
+                .map(|agent| agent.state == AgentType::AgentI)
+                .collect::<Vec<_>>()
+        };
+        // only cellmates are infected by default
+        assert_eq!(infected(0, Boundary::Torus), [true, false, false]);
+        // (99, 99) is diagonally adjacent to (0, 0) on the torus
+        assert_eq!(infected(1, Boundary::Torus), [true, true, false]);
+        assert_eq!(infected(2, Boundary::Torus), [true, true, true]);
+        // but lies across the grid if it is bounded
+        assert_eq!(infected(2, Boundary::Clamped), [true, false, false]);
+        assert_eq!(infected(2, Boundary::Reflecting), [true, false, false]);
+    }
+
+    #[test]
+    fn test_neighbourhood_covers_each_cell_once() {
+        let mut cells: Vec<_> = neighbourhood((1, 0), 1, (4, 2), Boundary::Torus).collect();
+        cells.sort_unstable();
+        assert_eq!(cells, [(0, 0), (0, 1), (1, 0), (1, 1), (2, 0), (2, 1)],);
+        assert_eq!(
+            neighbourhood((0, 0), 0, (4, 2), Boundary::Torus).collect::<Vec<_>>(),
+            [(0, 0)]
+        );
+        // a bounded grid cuts the neighbourhood off at the edges
+        let mut cells: Vec<_> = neighbourhood((0, 1), 1, (4, 3), Boundary::Clamped).collect();
+        cells.sort_unstable();
+        assert_eq!(cells, [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (1, 2)]);
+        assert_eq!(
+            neighbourhood((3, 2), 5, (4, 3), Boundary::Reflecting).count(),
+            12
+        );
+    }
 
-    for (i, agent) in agents.iter_mut().enumerate() {
-        agent.move_agent(*grid_size);
-        grid.entry((agent.x, agent.y))
-            .and_modify(|x| x.push(i))
-            .or_insert_with(|| vec![i]);
+    #[test]
+    fn test_attack_rate_increases_with_p_infect() {
+        let attack_rate = |p_infect: f64| {
+            (0..4)
+                .map(|seed| {
+                    let mut e = Environment::from_params(&SimulationParams {
+                        p_infect,
+                        seed: Some(seed),
+                        ..crate::presets::dense_small_grid()
+                    });
+                    e.run();
+                    1.0 - e.stats.susceptible as f64 / 500.0
+                })
+                .sum::<f64>()
+                / 4.0
+        };
+        let rates: Vec<f64> = [0.02, 0.1, 1.0].iter().map(|&p| attack_rate(p)).collect();
+        assert!(
+            rates.windows(2).all(|pair| pair[0] < pair[1]),
+            "{:?}",
+            rates
+        );
+        assert!(fraction_infected(21, 1.0) > fraction_infected(21, 0.05));
     }
-}
 
-/// Return the fraction infected individuals throughout the simulation
-fn fraction_infected(l: usize) -> f64 {
-    let mut e = Environment::init(2000, 10, l, 0.05, 100, 100);
-    e.run();
+    #[test]
+    fn test_exposure_matches_brute_force() {
+        let (params, _) = EnvironmentConfig::new()
+            .population(60)
+            .initial_infected(3)
+            .duration(5)
+            .grid(6, 6)
+            .detection(1.0)
+            .ppe(3, 0.5)
+            .build_params()
+            .unwrap();
+        let mut e = Environment::from_params(&params);
+        let duration = e.duration;
+        // exposure at the next tick, given the agents at the end of the previous one
+        let exposed_next = |env: &Environment| -> Vec<bool> {
+            let tick = env.tick() + 1;
+            let agents: Vec<AgentView> = env.iter_agents().collect();
+            agents
+                .iter()
+                .map(|agent| {
+                    agent.state == AgentType::AgentS
+                        && agents.iter().any(|other| {
+                            (other.x, other.y) == (agent.x, agent.y)
+                                && other.state == AgentType::AgentI
+                                && other.since_tick < tick
+                                && tick - other.since_tick <= duration
+                        })
+                })
+                .collect()
+        };
+        let mut expected = vec![0; 60];
+        let mut pending = exposed_next(&e);
+        e.run_with_hook(|env, _| {
+            for (count, &exposed) in expected.iter_mut().zip(&pending) {
+                *count += exposed as usize;
+            }
+            pending = exposed_next(env);
+        });
+        let exposure: Vec<usize> = e.iter_agents().map(|agent| agent.exposure).collect();
+        assert_eq!(exposure, expected);
+        assert!(exposure.iter().any(|&ticks| ticks > 1));
+    }
 
-    1.0 - e.stats.susceptible as f64 / 2000.0
-}
+    #[test]
+    fn test_ppe_susceptibility() {
+        let ppe = PpeParams {
+            duration: 4,
+            efficacy_complement: 0.25,
+        };
+        let mut agent = Agent::new(0, 0, AgentType::AgentS);
+        assert_eq!(agent.susceptibility(3, Some(&ppe)), 1.0);
+        agent.protected_until = Some(10);
+        assert_eq!(agent.susceptibility(10, Some(&ppe)), 0.25);
+        assert_eq!(agent.susceptibility(11, Some(&ppe)), 1.0);
+        assert_eq!(agent.susceptibility(10, None), 1.0);
+        // without efficacy, the agent is as susceptible as without prophylaxis
+        let no_efficacy = PpeParams {
+            efficacy_complement: 1.0,
+            ..ppe
+        };
+        assert_eq!(agent.susceptibility(10, Some(&no_efficacy)), 1.0);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_perfect_ppe_prevents_infection() {
+        let mut e = configured(|config| config.detection(0.5).ppe(10, 0.0));
+        e.run_with_hook(|env, tick| {
+            for agent in env.iter_agents() {
+                if agent.state == AgentType::AgentI && agent.since_tick == tick {
+                    assert!(agent.protected_until.is_none_or(|until| until < tick));
+                }
+            }
+        });
+        assert!(!e.detection_log().is_empty());
+        assert!(e
+            .detection_log()
+            .iter()
+            .any(|record| !record.protected.is_empty()));
+    }
 
     #[test]
-    fn test_init_environment() {
-        // let initial_environment = Environment::init(5, 2, 10, 0.5, 10, 10);
-        let initial_environment = Environment::init(25, 2, 10, 0.5, 10, 10);
-        println!("Agents:\n\t{:#?}", initial_environment.agents);
-        println!("Grid:\n\t{:#?}", initial_environment.grid);
-        println!("Stats/State tally:\n\t{:?}", initial_environment.stats);
+    fn test_tally_serde() {
+        let tally = TallyStates {
+            susceptible: 3,
+            exposed: 0,
+            infected: 2,
+            quarantined: 0,
+            recovered: 1,
+            vaccinated: 0,
+            dead: 0,
+        };
+        let json = serde_json::to_value(&tally).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "susceptible": 3, "exposed": 0, "infected": 2, "quarantined": 0, "recovered": 1,
+                "vaccinated": 0, "dead": 0
+            })
+        );
+        assert_eq!(serde_json::from_value::<TallyStates>(json).unwrap(), tally);
+        let sir = serde_json::json!({"susceptible": 3, "infected": 2, "recovered": 1, "dead": 0});
+        assert_eq!(serde_json::from_value::<TallyStates>(sir).unwrap(), tally);
+        let bytes = bincode::serialize(&tally).unwrap();
+        assert_eq!(bincode::deserialize::<TallyStates>(&bytes).unwrap(), tally);
+
+        let mut record = TallyStatesVec::new();
+        record.push(tally.clone());
+        record.push(TallyStates::default());
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "susceptible": [3, 0], "exposed": [0, 0], "infected": [2, 0],
+                "quarantined": [0, 0], "recovered": [1, 0], "vaccinated": [0, 0], "dead": [0, 0]
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<TallyStatesVec>(json).unwrap(),
+            record
+        );
+        let sir = serde_json::json!({
+            "susceptible": [3, 0], "infected": [2, 0], "recovered": [1, 0], "dead": [0, 0]
+        });
+        assert_eq!(
+            serde_json::from_value::<TallyStatesVec>(sir).unwrap(),
+            record
+        );
+        let bytes = bincode::serialize(&record).unwrap();
+        assert_eq!(
+            bincode::deserialize::<TallyStatesVec>(&bytes).unwrap(),
+            record
+        );
+    }
+
+    #[test]
+    fn test_tally_display() {
+        let tally = TallyStates {
+            susceptible: 1990,
+            infected: 8,
+            dead: 2,
+            ..TallyStates::default()
+        };
+        assert_eq!(tally.to_string(), "S=1990 E=0 I=8 Q=0 R=0 V=0 D=2");
+        assert_eq!(tally.total(), 2000);
+        let record = TallyStatesVec::from(&[tally.clone(), TallyStates::default()][..]);
+        assert_eq!(record.infected, [8, 0]);
+        assert_eq!(record.dead, [2, 0]);
+    }
+
+    #[test]
+    fn test_tally_serde_rejects_ragged() {
+        let ragged =
+            r#"{"susceptible": [3, 0], "infected": [2], "recovered": [1, 0], "dead": [0, 0]}"#;
+        assert!(serde_json::from_str::<TallyStatesVec>(ragged).is_err());
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let records = vec![
+            TallyStates {
+                susceptible: 3,
+                exposed: 0,
+                infected: 2,
+                quarantined: 0,
+                recovered: 0,
+                vaccinated: 0,
+                dead: 0,
+            },
+            TallyStates {
+                susceptible: 0,
+                exposed: 1,
+                infected: 3,
+                quarantined: 0,
+                recovered: 0,
+                vaccinated: 0,
+                dead: 1,
+            },
+        ];
+        let mut csv = Vec::new();
+        write_csv(&records, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "tick,susceptible,exposed,infected,quarantined,recovered,vaccinated,dead\n0,3,0,2,0,0,0,0\n1,0,1,3,0,0,0,1\n"
+        );
+
+        let mut csv = Vec::new();
+        write_csv(&[], &mut csv).unwrap();
+        assert_eq!(
+            csv,
+            b"tick,susceptible,exposed,infected,quarantined,recovered,vaccinated,dead\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let records = configured(|config| config).run();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tallies.csv");
+        to_csv_file(&records, &path).unwrap();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("tick,susceptible,exposed,infected,quarantined,recovered,vaccinated,dead")
+        );
+        let parsed: Vec<_> = lines
+            .enumerate()
+            .map(|(tick, line)| {
+                let row: Vec<usize> = line.split(',').map(|cell| cell.parse().unwrap()).collect();
+                assert_eq!(row[0], tick);
+                TallyStates {
+                    susceptible: row[1],
+                    exposed: row[2],
+                    infected: row[3],
+                    quarantined: row[4],
+                    recovered: row[5],
+                    vaccinated: row[6],
+                    dead: row[7],
+                }
+            })
+            .collect();
+        assert_eq!(parsed, records);
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let mut params = configured_params(|config| config.detection(0.2).isolation());
+        params.seed = Some(11);
+        let mut e = Environment::from_params(&params);
+        for _ in 0..10 {
+            e.advance(&mut |_, _| ());
+        }
+        assert!(e.is_active());
+        let mut checkpoint = Vec::new();
+        e.save_checkpoint(&mut checkpoint).unwrap();
+        let mut resumed: Environment = Environment::load_checkpoint(checkpoint.as_slice()).unwrap();
+
+        assert_eq!(resumed.tick(), 10);
+        assert_eq!(resumed.grid, e.grid);
+        assert!(resumed.iter_agents().eq(e.iter_agents()));
+        assert_eq!(resumed.stats, e.stats);
+        assert_eq!(resumed.get_statistics(), resumed.stats);
+        resumed.check_invariants();
+
+        // the resumed run starts at the saved tally and continues like the original
+        let records = resumed.run();
+        assert_eq!(records[0], e.stats);
+        assert_eq!(records, e.run());
+        assert_eq!(resumed.tick(), e.tick());
+        assert_eq!(resumed.detection_log(), e.detection_log());
+    }
+
+    #[test]
+    fn test_checkpoint_with_extensions() {
+        let mut e = Environment::init_with(20, 2, 5, 0.5, 4, 4, |i, x, y| (i, x + y));
+        e.advance(&mut |_, _| ());
+        let mut checkpoint = Vec::new();
+        e.save_checkpoint(&mut checkpoint).unwrap();
+        let resumed: Environment<(usize, usize)> =
+            Environment::load_checkpoint(checkpoint.as_slice()).unwrap();
+        assert_eq!(resumed.extensions, e.extensions);
+        assert!(Environment::<()>::load_checkpoint(&b"{}"[..]).is_err());
+    }
+
+    #[test]
+    fn test_run_for_caps_endless_run() {
+        // without recovery the infected agents remain infected forever
+        let params = SimulationParams {
+            n: 30,
+            infected: 3,
+            duration: usize::MAX,
+            p_death: 0.0,
+            xdim: 2,
+            ydim: 2,
+            seed: Some(1),
+            ..SimulationParams::default()
+        };
+        let mut e = Environment::from_params(&params);
+        let (records, termination) = e.run_for(50);
+        assert_eq!(records.len(), 51);
+        assert_eq!(termination, TerminationReason::MaxTicks);
+        assert_eq!(e.tick(), 50);
+        assert!(e.is_active());
+        assert!(!e.reached_max_ticks());
+
+        // the cap counts from the current tick, and the run can be continued
+        let (records, _) = e.run_for(10);
+        assert_eq!(records.len(), 11);
+        assert_eq!(e.tick(), 60);
+
+        let mut e = Environment::from_params(&SimulationParams {
+            max_ticks: Some(20),
+            ..params
+        });
+        assert_eq!(e.run().len(), 21);
+        assert!(e.reached_max_ticks());
+        assert_eq!(e.run_for(5).0.len(), 1);
+    }
+
+    #[test]
+    fn test_run_for_extinct() {
+        let mut e = configured(|config| config);
+        let (records, termination) = e.run_for(10_000);
+        assert_eq!(termination, TerminationReason::Extinct);
+        assert_eq!(records.len(), e.tick() + 1);
+        assert_eq!(records.last().unwrap().infected, 0);
+    }
+
+    #[test]
+    fn test_run_until() {
+        let params = SimulationParams {
+            seed: Some(42),
+            ..crate::presets::blog_default()
+        };
+        let mut e = Environment::from_params(&params);
+        let mut ticks = Vec::new();
+        let mut record = e.run_until(|env, stats| {
+            ticks.push(env.tick());
+            stats.infected >= 100
+        });
+        let (last, before) = record.split_last().unwrap();
+        assert!(last.infected >= 100);
+        assert!(before.iter().all(|stats| stats.infected < 100));
+        assert!(e.is_active());
+
+        // resuming continues from the last tally, until the infection dies out
+        let resumed = e.run_until(|env, _| {
+            ticks.push(env.tick());
+            false
+        });
+        assert_eq!(&resumed[0], last);
+        record.extend(resumed.into_iter().skip(1));
+        assert_eq!(ticks, (1..=e.tick()).collect::<Vec<_>>());
+        assert_eq!(record.len(), e.tick() + 1);
+        assert!(!e.is_active());
+        assert!(record
+            .windows(2)
+            .all(|w| w[0].recovered + w[0].dead <= w[1].recovered + w[1].dead));
+        assert_eq!(record, Environment::from_params(&params).run());
+    }
+
+    #[test]
+    fn test_infection_events() {
+        let mut e = configured(|config| config.p_death(0.0));
+        assert!(e.infection_events().is_empty());
+        e.enable_infection_events();
+        let initial = e.get_statistics().infected;
+        let last = e.run().pop().unwrap();
+        let events = e.infection_events();
+        assert_eq!(events.len(), last.recovered);
+        let secondary = events.iter().filter(|event| event.source.is_some()).count();
+        assert_eq!(secondary, last.recovered - initial);
+        assert!(events[..initial].iter().all(|event| event.source.is_none()));
+
+        let mut targets = HashSet::new();
+        for event in events {
+            assert!(targets.insert(event.target), "{:?}", event);
+            let history = &e.agents[event.target].history;
+            assert_eq!(history.infections().collect::<Vec<_>>(), [event.tick]);
+            if let Some(source) = event.source {
+                // agents pass on the infection only after their own infection
+                let source = events.iter().find(|other| other.target == source).unwrap();
+                assert!(source.tick < event.tick);
+            }
+        }
+        let tree = e.transmission_tree();
+        assert_eq!(tree.values().map(Vec::len).sum::<usize>(), secondary);
+    }
+
+    #[test]
+    fn test_reproduction_number_by_tick() {
+        use AgentType::*;
+        let layout = [
+            (0, 0, AgentI),
+            (0, 0, AgentS),
+            (0, 0, AgentS),
+            (1, 1, AgentS),
+        ];
+        let mut e = Environment::from_layout(&layout, 10, 0.0, (2, 2), Some(1), |_, _, _| ());
+        assert!(e.estimated_r0().is_nan());
+        e.enable_infection_events();
+        // the first agent infects the second and third, the second of which then moves on
+        // to infect the fourth
+        e.tick = 1;
+        e.update_type();
+        relocate(&mut e, 1, (1, 1));
+        for tick in 2..=3 {
+            e.tick = tick;
+            e.update_type();
+        }
+        let rt = e.reproduction_number_by_tick();
+        assert_eq!(rt.len(), 4);
+        assert_eq!(rt[..3], [2.0, 0.5, 0.0]);
+        assert!(rt[3].is_nan());
+        assert_eq!(e.estimated_r0(), 2.0);
+    }
+
+    #[test]
+    fn test_reproduction_numbers_add_up() {
+        let mut e = configured(|config| config.p_death(0.0));
+        e.enable_infection_events();
+        e.run();
+        let mut cohorts = vec![0; e.tick + 1];
+        for event in e.infection_events() {
+            cohorts[event.tick] += 1;
+        }
+        // every infection after the initial ones is the secondary case of another one
+        let secondary: f64 = e
+            .reproduction_number_by_tick()
+            .iter()
+            .zip(&cohorts)
+            .filter(|(_, &cohort)| cohort > 0)
+            .map(|(rt, &cohort)| rt * cohort as f64)
+            .sum();
+        assert_eq!(
+            secondary.round() as usize,
+            e.infection_events().len() - cohorts[0]
+        );
+        assert!(e.estimated_r0() > 0.0);
+    }
+
+    #[test]
+    fn test_cell_tracking() {
+        let mut e = configured(|config| config.p_death(0.0));
+        assert!(e.cell_visit_counts().is_empty());
+        e.enable_cell_tracking();
+        let infected = e.get_statistics().infected;
+        let records = e.run();
+        let ticks = records.len() - 1;
+        let (xdim, ydim) = e.grid_size();
+        assert_eq!(e.cell_visit_counts().len(), xdim * ydim);
+        // nobody dies, thus every agent visits a cell in each tick
+        assert_eq!(
+            e.cell_visit_counts().iter().sum::<usize>(),
+            e.iter_agents().count() * ticks
+        );
+        let last = records.last().unwrap();
+        assert_eq!(
+            e.cell_infection_counts().iter().sum::<usize>(),
+            last.recovered - infected
+        );
+    }
+
+    #[test]
+    fn test_cell_tracking_skips_the_dead() {
+        let mut e = Environment::init_with_seed(50, 5, 2, 0.5, 3, 2, 9);
+        e.enable_cell_tracking();
+        let mut alive = 0;
+        while e.is_active() {
+            e.advance(&mut |_, _| ());
+            // the agents that died in this tick did not move
+            alive += e
+                .iter_agents()
+                .filter(|agent| agent.state != AgentType::AgentD)
+                .count();
+        }
+        assert!(e.get_statistics().dead > 0);
+        assert_eq!(e.cell_visit_counts().iter().sum::<usize>(), alive);
+        assert_eq!(e.cell_infection_counts().len(), 6);
+
+        // the count of cell (x, y) is at y * xdim + x
+        let mut e = Environment::init_with_seed(1, 0, 2, 0.0, 3, 2, 9);
+        e.enable_cell_tracking();
+        e.advance(&mut |_, _| ());
+        let agent = e.agent(0).unwrap();
+        let visits = e.cell_visit_counts();
+        assert_eq!(visits[agent.y * 3 + agent.x], 1);
+        assert_eq!(visits.iter().sum::<usize>(), 1);
+    }
+
+    #[test]
+    fn test_grid_snapshot() {
+        let layout = [
+            (0, 0, AgentType::AgentI),
+            (0, 0, AgentType::AgentS),
+            (2, 0, AgentType::AgentR),
+            (1, 2, AgentType::AgentD),
+            (1, 2, AgentType::AgentS),
+            (2, 1, AgentType::AgentD),
+        ];
+        let e = Environment::from_layout(&layout, 5, 0.0, (3, 3), Some(1), |_, _, _| ());
+        let snapshot = e.grid_snapshot();
+        assert_eq!(snapshot.len(), 3);
+        assert!(snapshot.iter().all(|row| row.len() == 3));
+        let cell = |susceptible, infected, recovered, dead| CellState {
+            susceptible,
+            exposed: 0,
+            infected,
+            quarantined: 0,
+            recovered,
+            vaccinated: 0,
+            dead,
+        };
+        let empty = CellState::default();
+        assert_eq!(
+            snapshot,
+            vec![
+                vec![cell(1, 1, 0, 0), empty, cell(0, 0, 1, 0)],
+                vec![empty, empty, cell(0, 0, 0, 1)],
+                vec![empty, cell(1, 0, 0, 1), empty],
+            ]
+        );
+        let dominant: Vec<Vec<_>> = snapshot
+            .iter()
+            .map(|row| row.iter().map(CellState::dominant).collect())
+            .collect();
+        use AgentType::*;
+        assert_eq!(
+            dominant,
+            vec![
+                vec![Some(AgentI), None, Some(AgentR)],
+                vec![None, None, Some(AgentD)],
+                vec![None, Some(AgentS), None],
+            ]
+        );
+        // dead agents are told apart from empty cells
+        assert!(!snapshot[1][2].is_empty());
+        assert!(snapshot[1][1].is_empty());
+    }
+
+    #[test]
+    fn test_incubation_delays_infectiousness() {
+        let params = |incubation| SimulationParams {
+            n: 400,
+            infected: 5,
+            duration: 30,
+            incubation,
+            xdim: 10,
+            ydim: 10,
+            seed: Some(4),
+            ..SimulationParams::default()
+        };
+        let record = Environment::from_params(&params(10)).run();
+        for tally in &record[..=10] {
+            assert_eq!(tally.infected, 5);
+        }
+        assert!(record[10].exposed > 0);
+        assert!(record[11].infected > 5);
+        let last = record.last().unwrap();
+        assert_eq!((last.exposed, last.infected), (0, 0));
+
+        let record = Environment::from_params(&params(0)).run();
+        assert!(record.iter().all(|tally| tally.exposed == 0));
+        assert!(record[1].infected > 5);
+    }
+
+    #[test]
+    fn test_step_matches_run() {
+        let init = || {
+            Environment::from_params(&SimulationParams {
+                n: 400,
+                infected: 5,
+                xdim: 20,
+                ydim: 20,
+                seed: Some(8),
+                ..SimulationParams::default()
+            })
+        };
+        let record = init().run();
+
+        let mut e = init();
+        let mut stepped = vec![e.stats.clone()];
+        while !e.is_finished() {
+            stepped.push(e.step());
+        }
+        assert_eq!(stepped, record);
+        assert_eq!(e.tick(), record.len() - 1);
+        assert_eq!(init().ticks().collect::<Vec<_>>(), record);
+
+        let mut capped = init();
+        capped.params.max_ticks = Some(10);
+        let record = capped.clone().run();
+        assert_eq!(record.len(), 11);
+        assert_eq!(capped.ticks().collect::<Vec<_>>(), record);
+        assert!(capped.is_finished() && capped.is_active());
+    }
+
+    #[test]
+    fn test_run_with_observer() {
+        let mut e = Environment::init_with_seed(400, 10, 21, 0.05, 20, 20, 11);
+        let mut ticks = Vec::new();
+        let mut centroids = Vec::new();
+        let record = e.run_with_observer(|env, tick, tally| {
+            ticks.push(tick);
+            let infected: Vec<_> = env
+                .iter_agents()
+                .filter(|agent| agent.state == AgentType::AgentI)
+                .collect();
+            assert_eq!(infected.len(), tally.infected);
+            for agent in &infected {
+                let cellmates = env.cell_agents((agent.x, agent.y)).unwrap();
+                assert!(cellmates.contains(&agent.id));
+            }
+            if !infected.is_empty() {
+                let n = infected.len() as f64;
+                let x = infected.iter().map(|agent| agent.x as f64).sum::<f64>() / n;
+                let y = infected.iter().map(|agent| agent.y as f64).sum::<f64>() / n;
+                centroids.push((x, y));
+            }
+        });
+        assert_eq!(ticks, (1..record.len()).collect::<Vec<_>>());
+        assert!(!centroids.is_empty());
+        assert!(centroids
+            .iter()
+            .all(|&(x, y)| (0.0..20.0).contains(&x) && (0.0..20.0).contains(&y)));
+        assert_eq!(e.cell_agents((20, 0)), None);
+    }
+
+    #[test]
+    fn test_agents_in_cell() {
+        let mut e = configured(|config| config.p_death(0.2));
+        assert_eq!((e.duration(), e.p_death()), (10, 0.2));
+        for _ in 0..5 {
+            e.step();
+        }
+        let mut seen = 0;
+        for (x, y) in (0..20).flat_map(|x| (0..20).map(move |y| (x, y))) {
+            let ids: Vec<usize> = e.agents_in_cell(x, y).map(|agent| agent.id).collect();
+            assert_eq!(ids, e.cell_agents((x, y)).unwrap());
+            assert!(e
+                .agents_in_cell(x, y)
+                .all(|agent| (agent.x, agent.y) == (x, y)));
+            seen += ids.len();
+        }
+        assert_eq!(seen, e.iter_agents().count());
+        assert_eq!(e.agents_in_cell(20, 0).count(), 0);
+    }
+
+    #[test]
+    fn test_from_agents() {
+        use AgentType::*;
+        let spec = |x, y, state| AgentSpec { x, y, state };
+        let specs = vec![
+            spec(1, 1, AgentI),
+            spec(1, 1, AgentS),
+            spec(1, 1, AgentR),
+            spec(1, 1, AgentD),
+            spec(0, 2, AgentS),
+        ];
+        let mut e = Environment::from_agents(specs.clone(), 3, 0.0, 4, 3, Some(1)).unwrap();
+        assert_eq!(e.grid_size(), (4, 3));
+        assert_eq!(
+            e.iter_agents()
+                .map(|agent| spec(agent.x, agent.y, agent.state))
+                .collect::<Vec<_>>(),
+            specs
+        );
+        assert_eq!(e.cell_agents((1, 1)).unwrap(), [0, 1, 2, 3]);
+        let tally = |susceptible, infected, recovered| TallyStates {
+            susceptible,
+            infected,
+            recovered,
+            dead: 1,
+            ..TallyStates::default()
+        };
+        assert_eq!(e.get_statistics(), tally(2, 1, 1));
+
+        // the cellmate is infected in the first tick, the recovered and dead agents are not
+        assert_eq!(e.step(), tally(1, 2, 1));
+        let states: Vec<_> = e.iter_agents().map(|agent| agent.state).collect();
+        assert_eq!(states, [AgentI, AgentI, AgentR, AgentD, AgentS]);
+        assert_eq!(e.agent(1).unwrap().since_tick, 1);
+
+        // the certain infection is kept by a branch, and can be changed by it
+        let kept = e.branch(&e.params.clone(), None).unwrap();
+        assert_eq!((kept.p_infect, kept.p_move), (1.0, 1.0));
+        let mut isolated = Environment::from_agents(specs.clone(), 3, 0.0, 4, 3, Some(1)).unwrap();
+        let overrides = SimulationParams {
+            p_infect: 0.0,
+            ..isolated.params.clone()
+        };
+        isolated = isolated.branch(&overrides, None).unwrap();
+        assert_eq!(isolated.step(), tally(2, 1, 1));
+
+        // all random numbers are drawn from the seed
+        let run = |seed| {
+            let mut e = Environment::from_agents(specs.clone(), 3, 0.5, 4, 3, seed).unwrap();
+            (e.seed(), e.run())
+        };
+        let (seed, record) = run(Some(7));
+        assert_eq!((seed, record), run(Some(7)));
+        assert_eq!(seed, 7);
+    }
+
+    #[test]
+    fn test_from_agents_rejects_invalid_setup() {
+        let spec = |x, y| AgentSpec {
+            x,
+            y,
+            state: AgentType::AgentS,
+        };
+        assert_eq!(
+            Environment::from_agents(vec![spec(0, 0), spec(3, 1)], 3, 0.0, 3, 3, None).err(),
+            Some(ConfigError::OutOfBounds { x: 3, y: 1 })
+        );
+        assert_eq!(
+            Environment::from_agents(vec![spec(0, 5)], 3, 0.0, 3, 3, None).err(),
+            Some(ConfigError::OutOfBounds { x: 0, y: 5 })
+        );
+        assert_eq!(
+            Environment::from_agents(Vec::new(), 3, 0.0, 0, 3, None).err(),
+            Some(ConfigError::EmptyGrid { xdim: 0, ydim: 3 })
+        );
+        assert!(matches!(
+            Environment::from_agents(vec![spec(0, 0)], 3, 1.5, 3, 3, None),
+            Err(ConfigError::InvalidProbability {
+                name: "p_death",
+                ..
+            })
+        ));
+        let e = Environment::from_agents(Vec::new(), 3, 0.0, 3, 3, None).unwrap();
+        assert!(!e.is_active());
+    }
+
+    #[test]
+    fn test_run_with_snapshots() {
+        let mut params = configured_params(|config| config);
+        params.seed = Some(2);
+        let mut e = Environment::from_params(&params);
+        let (record, snapshots) = e.run_with_snapshots(3);
+        assert_eq!(record, Environment::from_params(&params).run());
+        let ticks: Vec<usize> = snapshots.iter().map(|snapshot| snapshot.tick).collect();
+        assert_eq!(ticks, (0..record.len()).step_by(3).collect::<Vec<_>>());
+        for snapshot in &snapshots {
+            let mut total = CellState::default();
+            for cell in snapshot.cells.iter().flatten() {
+                total.susceptible += cell.susceptible;
+                total.infected += cell.infected;
+                total.recovered += cell.recovered;
+                total.dead += cell.dead;
+            }
+            let tally = &record[snapshot.tick];
+            assert_eq!(
+                (
+                    total.susceptible,
+                    total.infected,
+                    total.recovered,
+                    total.dead
+                ),
+                (
+                    tally.susceptible,
+                    tally.infected,
+                    tally.recovered,
+                    tally.dead
+                )
+            );
+        }
     }
 
     #[test]
     fn test_mod1() {
-        // assert_eq!(0 % 10, 10);
-        // assert_eq!(11 % 10, 1);
-        use num::integer::mod_floor as mod1;
+        // unlike `%`, the floored modulus wraps negative coordinates onto the grid
+        use num::integer::mod_floor;
 
-        assert_eq!(mod1(0, 10), 10);
-        assert_eq!(mod1(11, 10), 1);
+        assert_eq!(mod_floor(0, 10), 0);
+        assert_eq!(mod_floor(11, 10), 1);
+        assert_eq!(mod_floor(-1, 10), 9);
     }
 
     #[test]
-    fn test_running_the_model() {
-        let mut e = Environment::init(2000, 10, 21, 0.05, 100, 100);
-        let states_record = e.run();
-        use plotly::{Plot, Scatter};
-        use std::iter::FromIterator;
+    fn test_wrap_at_edges() {
+        assert_eq!(wrap(0, -1, 10), 9);
+        assert_eq!(wrap(9, 1, 10), 0);
+        assert_eq!(wrap(0, 1, 10), 1);
+        assert_eq!(wrap(9, -1, 10), 8);
+        assert_eq!(wrap(4, 0, 10), 4);
+        // a single cell wraps onto itself
+        assert_eq!(wrap(0, -1, 1), 0);
+    }
+
+    #[test]
+    fn test_random_step_wraps_from_corners() {
+        let dim = (10, 20);
+        let mut rng = StdRng::seed_from_u64(3);
+        for &corner in &[(0, 0), (dim.0 - 1, dim.1 - 1)] {
+            let mut counts = std::collections::HashMap::new();
+            let draws = 9000;
+            for _ in 0..draws {
+                *counts
+                    .entry(random_step(corner, dim, Boundary::Torus, &mut rng))
+                    .or_insert(0) += 1;
+            }
+            // all nine neighbours, including across both edges, are equally likely
+            let neighbours: Vec<_> = (-1..=1)
+                .flat_map(|dx| (-1..=1).map(move |dy| (dx, dy)))
+                .map(|(dx, dy)| (wrap(corner.0, dx, dim.0), wrap(corner.1, dy, dim.1)))
+                .collect();
+            assert_eq!(counts.len(), 9);
+            for neighbour in &neighbours {
+                let frequency = counts[neighbour] as f64 / draws as f64;
+                assert!((frequency - 1.0 / 9.0).abs() < 0.02, "{:?}", counts);
+            }
+        }
+    }
+
+    #[test]
+    fn test_step_within_bounds() {
+        for &boundary in &[Boundary::Reflecting, Boundary::Clamped] {
+            assert_eq!(step_within(4, 1, 10, boundary), 5);
+            assert_eq!(step_within(4, -1, 10, boundary), 3);
+            assert_eq!(step_within(0, 0, 1, boundary), 0);
+            assert_eq!(step_within(0, -1, 1, boundary), 0);
+            assert_eq!(step_within(0, 1, 1, boundary), 0);
+        }
+        assert_eq!(step_within(0, -1, 10, Boundary::Clamped), 0);
+        assert_eq!(step_within(9, 1, 10, Boundary::Clamped), 9);
+        assert_eq!(step_within(0, -1, 10, Boundary::Reflecting), 1);
+        assert_eq!(step_within(9, 1, 10, Boundary::Reflecting), 8);
+        assert_eq!(step_within(0, -1, 10, Boundary::Torus), 9);
+    }
+
+    #[test]
+    fn test_bounded_walk_never_wraps() {
+        for &boundary in &[Boundary::Reflecting, Boundary::Clamped] {
+            let mut e = Environment::from_layout(
+                &[(0, 0, AgentType::AgentS)],
+                5,
+                0.0,
+                (10, 10),
+                Some(1),
+                |_, _, _| (),
+            );
+            e.boundary = boundary;
+            let mut location = (0, 0);
+            for tick in 1..=1000 {
+                e.tick = tick;
+                move_all(&mut e);
+                let agent = e.agent(0).unwrap();
+                assert!(agent.x < 10 && agent.y < 10);
+                // a single step at most, never across the grid
+                assert!(agent.x.abs_diff(location.0) <= 1 && agent.y.abs_diff(location.1) <= 1);
+                location = (agent.x, agent.y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_edge_occupancy_by_boundary() {
+        // fraction of the agents on the two vertical edges over many ticks
+        let edge_occupancy = |boundary| {
+            let mut e = Environment::from_params(&SimulationParams {
+                seed: Some(6),
+                ..configured_params(|config| config.boundary(boundary))
+            });
+            let (xdim, _) = e.grid_size();
+            let mut edge = 0;
+            let ticks = 200;
+            for tick in 1..=ticks {
+                e.tick = tick;
+                move_all(&mut e);
+                edge += e
+                    .iter_agents()
+                    .filter(|agent| agent.x == 0 || agent.x == xdim - 1)
+                    .count();
+            }
+            edge as f64 / (ticks * e.iter_agents().count()) as f64
+        };
+        // the steps of the torus and of the clamped grid keep the agents uniformly distributed,
+        // such that the edges hold 2 of the 20 columns, whereas agents only visit an edge of a
+        // reflecting grid half as often
+        for &boundary in &[Boundary::Torus, Boundary::Clamped] {
+            let occupancy = edge_occupancy(boundary);
+            assert!(
+                (occupancy - 0.1).abs() < 0.015,
+                "{:?}: {}",
+                boundary,
+                occupancy
+            );
+        }
+        let occupancy = edge_occupancy(Boundary::Reflecting);
+        assert!((occupancy - 1.0 / 19.0).abs() < 0.015, "{}", occupancy);
+    }
+
+    #[test]
+    fn test_positions_stay_uniform() {
+        // with wrap-around on both sides no edge of the grid accumulates agents
+        let mut e = Environment::init_with_seed(2000, 0, 10, 0.0, 10, 10, 5);
+        for _ in 0..50 {
+            e.update_type();
+        }
+        let edge = e
+            .iter_agents()
+            .filter(|agent| agent.x == 0 || agent.y == 0)
+            .count();
+        // 19 of the 100 cells lie on the two 0 edges
+        assert!((edge as f64 / 2000.0 - 0.19).abs() < 0.04, "{}", edge);
+    }
 
-        let ticks: Vec<_> = (0..states_record.len()).collect();
-        let soa_records: TallyStatesVec = TallyStatesVec::from_iter(states_record.into_iter());
+    #[test]
+    fn test_seeded_runs_are_reproducible() {
+        let params = SimulationParams {
+            seed: Some(42),
+            burn_in_ticks: 2,
+            ..crate::presets::dense_small_grid()
+        };
+        let mut first = Environment::from_params(&params);
+        let mut second = Environment::from_params(&params);
+        assert_eq!(first.seed(), 42);
+        let record = first.run();
+        assert_eq!(record, second.run());
+        let locations = |env: &Environment| {
+            env.iter_agents()
+                .map(|agent| (agent.x, agent.y, agent.since_tick))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(locations(&first), locations(&second));
+        let json = |run: &crate::result::RunResult| serde_json::to_vec(&run.records()).unwrap();
+        let run = crate::result::run(&params);
+        assert_eq!(run.metadata.seed, Some(42));
+        assert_eq!(json(&run), json(&crate::result::run(&params)));
+
+        // another seed gives another run
+        let other = Environment::from_params(&SimulationParams {
+            seed: Some(43),
+            ..params.clone()
+        });
+        assert_ne!(
+            locations(&other),
+            locations(&Environment::from_params(&params))
+        );
 
-        let susceptible_trace =
-            Scatter::new(ticks.clone(), soa_records.susceptible).name("susceptible");
-        let infected_trace = Scatter::new(ticks.clone(), soa_records.infected).name("infected");
-        let recovered_trace = Scatter::new(ticks.clone(), soa_records.recovered).name("recovered");
-        let dead_trace = Scatter::new(ticks, soa_records.dead).name("dead");
+        // an unseeded run records its seed, which reproduces it
+        let mut unseeded = Environment::from_params(&crate::presets::dense_small_grid());
+        let record = unseeded.run();
+        let mut reproduced = Environment::init_with_seed(500, 5, 10, 0.05, 20, 20, unseeded.seed());
+        assert_eq!(record, reproduced.run());
+    }
 
-        let mut states_plots = Plot::new();
-        states_plots.add_trace(susceptible_trace);
-        states_plots.add_trace(infected_trace);
-        states_plots.add_trace(recovered_trace);
-        states_plots.add_trace(dead_trace);
+    #[test]
+    fn test_reseed() {
+        let params = SimulationParams {
+            seed: Some(7),
+            ..crate::presets::dense_small_grid()
+        };
+        let mut first = Environment::from_params(&params);
+        first.advance(&mut |_, _| {});
+        let mut second = first.clone();
+        let mut third = first.clone();
+        third.reseed(8);
+        assert_eq!(first.run(), second.run());
+        assert_eq!(third.seed(), 8);
+        assert_ne!(
+            first.iter_agents().map(|agent| agent.x).collect::<Vec<_>>(),
+            third.iter_agents().map(|agent| agent.x).collect::<Vec<_>>()
+        );
+    }
 
-        states_plots.show();
+    #[test]
+    fn test_running_the_model() {
+        let mut e = Environment::from_params(&crate::presets::blog_default());
+        let states_record = e.run();
+        assert_eq!(states_record.last().unwrap().infected, 0);
 
-        // Annotate the legends
+        #[cfg(feature = "plotting")]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("states.html");
+            crate::plot::save_trajectory_html(&states_record, &path);
+            assert!(path.exists());
+        }
     }
 
     #[test]
     fn test_fraction_infected() {
-        let len = 5..=30;
-        let runs = 16;
+        let len: Vec<usize> = (5..=30).step_by(5).collect();
+        let runs = 4;
 
         let inf = len
-            .clone()
-            .map(|l| (1..=runs).map(|_r| fraction_infected(l)).sum::<f64>() / runs as f64)
+            .iter()
+            .map(|&l| (1..=runs).map(|_r| fraction_infected(l, 1.0)).sum::<f64>() / runs as f64)
             .collect::<Vec<_>>();
+        assert!(inf.iter().all(|fraction| (0.0..=1.0).contains(fraction)));
+        // longer infections infect more agents
+        assert!(inf[0] < inf[inf.len() - 1]);
+
+        #[cfg(feature = "plotting")]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("fraction.html");
+            crate::plot::plot_fraction_infected(&len, &inf).to_html(&path);
+            assert!(path.exists());
+        }
+    }
 
-        use plotly::{Plot, Scatter};
-        let mut fraction_plot = Plot::new();
+    #[test]
+    fn test_clustered_initial_seeding() {
+        let params = EnvironmentConfig::from(crate::presets::blog_default())
+            .initial_seeding(SeedingStrategy::Clustered {
+                center: (50, 50),
+                radius: 2,
+            })
+            .build_params()
+            .unwrap()
+            .0;
+        let e = Environment::from_params(&params);
+        e.check_invariants();
+        let infected: Vec<_> = e
+            .iter_agents()
+            .filter(|agent| agent.state != AgentType::AgentS)
+            .collect();
+        assert_eq!(infected.len(), params.infected);
+        for agent in infected {
+            assert!(
+                agent.x.max(50) - agent.x.min(50) <= 2 && agent.y.max(50) - agent.y.min(50) <= 2,
+                "({}, {})",
+                agent.x,
+                agent.y
+            );
+        }
+    }
+
+    #[test]
+    fn test_explicit_initial_seeding() {
+        let cells: Vec<_> = (0..5).map(|i| (i, 19 - i)).collect();
+        let e =
+            configured(|config| config.initial_seeding(SeedingStrategy::Explicit(cells.clone())));
+        e.check_invariants();
+        let placed: Vec<_> = e
+            .iter_agents()
+            .filter(|agent| agent.state != AgentType::AgentS)
+            .map(|agent| (agent.x, agent.y))
+            .collect();
+        assert_eq!(placed, cells);
+    }
+
+    /// Number of ticks each recovered agent was infectious, from its infection and recovery
+    fn infectious_periods(e: &Environment) -> Vec<(usize, Option<usize>)> {
+        e.iter_agents()
+            .filter_map(|agent| match *agent.immune_history.events() {
+                [ImmuneEvent::Infection { tick: infected, .. }, ImmuneEvent::Recovery { tick: recovered }] => {
+                    Some((recovered - infected - 1, agent.infection_duration))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_duration_model() {
+        let params = SimulationParams {
+            seed: Some(42),
+            ..crate::presets::blog_default()
+        };
+        let fixed = SimulationParams {
+            duration_model: Some(DurationModel::Fixed(params.duration)),
+            ..params.clone()
+        };
+        assert_eq!(
+            Environment::from_params(&params).run(),
+            Environment::from_params(&fixed).run()
+        );
+
+        let mut e = configured(|config| {
+            config
+                .p_death(0.0)
+                .duration_model(DurationModel::Uniform { min: 3, max: 6 })
+        });
+        e.run();
+        let periods = infectious_periods(&e);
+        assert!(periods.len() > e.params.infected);
+        for (period, drawn) in periods {
+            assert!((3..=6).contains(&period), "{}", period);
+            assert_eq!(drawn, Some(period));
+        }
+    }
+
+    #[test]
+    fn test_geometric_duration_mean() {
+        let params = EnvironmentConfig::from(crate::presets::blog_default())
+            .grid(20, 20)
+            .p_death(0.0)
+            .duration_model(DurationModel::Geometric { p: 0.2 })
+            .build_params()
+            .unwrap()
+            .0;
+        let mut e = Environment::from_params(&SimulationParams {
+            seed: Some(3),
+            ..params
+        });
+        e.run();
+        let periods = infectious_periods(&e);
+        assert!(periods.len() > 1000, "{}", periods.len());
+        assert!(periods.iter().all(|&(period, drawn)| drawn == Some(period)));
+        let mean = periods
+            .iter()
+            .map(|&(period, _)| period as f64)
+            .sum::<f64>()
+            / periods.len() as f64;
+        assert!((mean - 5.0).abs() < 0.3, "{}", mean);
+        assert_eq!(periods.iter().map(|&(period, _)| period).min(), Some(1));
+    }
+
+    #[test]
+    fn test_quarantine_stops_spread() {
+        let params = EnvironmentConfig::from(crate::presets::blog_default())
+            .quarantine(0, 1.0)
+            .build_params()
+            .unwrap()
+            .0;
+        let mut e = Environment::from_params(&params);
+        let initial: Vec<_> = e
+            .iter_agents()
+            .filter(|agent| agent.state == AgentType::AgentI)
+            .collect();
+        let record = e.run_with_hook(|e, _| {
+            for agent in &initial {
+                let now = e.iter_agents().nth(agent.id).unwrap();
+                if now.state == AgentType::AgentQ {
+                    assert_eq!((now.x, now.y), (agent.x, agent.y));
+                }
+            }
+        });
+        assert_eq!(record[1].quarantined, params.infected);
+        assert!(record.iter().all(
+            |tally| tally.infected + tally.quarantined <= params.infected && tally.exposed == 0
+        ));
+        // the quarantined recover or die on schedule, where they were quarantined
+        assert_eq!(record.len(), params.duration + 2);
+        let last = record.last().unwrap();
+        assert_eq!(last.susceptible, params.n - params.infected);
+        assert_eq!(last.recovered + last.dead, params.infected);
+    }
+
+    #[test]
+    fn test_quarantine_delay() {
+        let mut e = configured(|config| config.quarantine(2, 1.0));
+        e.enable_infection_events();
+        e.run();
+        let events = e.infection_events();
+        assert!(events.iter().any(|event| event.source.is_some()));
+        for event in events {
+            if let Some(source) = event.source {
+                let infectious_since = events
+                    .iter()
+                    .find(|other| other.target == source)
+                    .unwrap()
+                    .tick;
+                assert!(
+                    (1..=2).contains(&(event.tick - infectious_since)),
+                    "{:?}",
+                    event
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_tally_is_kept_up_to_date() {
+        let mut e = configured(|config| {
+            config
+                .incubation(2)
+                .quarantine(1, 0.5)
+                .reservoirs(vec![(3, 3)], 0.2)
+                .vaccinate_at(4, 0.1)
+        });
+        e.set_invariant_checks(false);
+        while e.is_active() {
+            e.update_type();
+            assert_eq!(e.stats, e.get_statistics());
+            e.step();
+            assert_eq!(e.stats, e.get_statistics());
+            if e.tick() > 500 {
+                break;
+            }
+        }
+        assert!(e.stats.recovered > 0);
+    }
+
+    #[test]
+    fn test_waning_immunity() {
+        let immunity = 3;
+        let mut e = configured(|config| {
+            config
+                .p_death(0.0)
+                .immunity_duration(immunity)
+                .max_ticks(150)
+        });
+        let record = e.run();
+        assert_eq!(record.len(), 151);
+        let mut reinfected = 0;
+        for agent in e.iter_agents() {
+            let events = agent.immune_history.events();
+            // each agent goes through infection, recovery and waning immunity in turn
+            for (k, event) in events.iter().enumerate() {
+                match (k % 3, *event) {
+                    (0, ImmuneEvent::Infection { .. }) | (1, ImmuneEvent::Recovery { .. }) => {}
+                    (2, ImmuneEvent::Waning { tick }) => {
+                        assert_eq!(tick - events[k - 1].tick(), immunity + 1);
+                    }
+                    _ => panic!("unexpected history {:?}", events),
+                }
+            }
+            if agent.immune_history.infections().count() > 1 {
+                reinfected += 1;
+            }
+            if let Some(ImmuneEvent::Waning { tick }) = events.last() {
+                assert_eq!(agent.state, AgentType::AgentS);
+                assert_eq!(agent.since_tick, *tick);
+            }
+        }
+        assert!(reinfected > 0);
+    }
 
-        let fraction_trace =
-            Scatter::new(len.collect::<Vec<_>>(), inf).name("fraction of infected");
-        fraction_plot.add_trace(fraction_trace);
+    #[test]
+    fn test_remove_corpses() {
+        for &handling in &[CorpseHandling::Keep, CorpseHandling::Remove] {
+            let mut e = configured(|config| config.p_death(0.5).corpse_handling(handling));
+            let n = e.iter_agents().count();
+            let mut listed_dead = 0;
+            let record = e.run_with_observer(|env, _, tally| {
+                assert_eq!(tally.total(), n);
+                let (xdim, ydim) = env.grid_size();
+                for cell in (0..xdim).flat_map(|x| (0..ydim).map(move |y| (x, y))) {
+                    for &id in env.cell_agents(cell).unwrap() {
+                        if env.agent(id).unwrap().state == AgentType::AgentD {
+                            listed_dead += 1;
+                        }
+                    }
+                }
+                let snapshot_dead: usize = env
+                    .grid_snapshot()
+                    .iter()
+                    .flatten()
+                    .map(|cell| cell.dead)
+                    .sum();
+                assert_eq!(
+                    snapshot_dead > 0,
+                    handling == CorpseHandling::Keep && tally.dead > 0
+                );
+            });
+            assert!(record.last().unwrap().dead > 0);
+            assert_eq!(listed_dead > 0, handling == CorpseHandling::Keep);
+        }
+    }
+    #[test]
+    fn test_update_order() {
+        // all agents share a single cell, where half of them are infected at the start
+        let replicates = 400;
+        let frequencies = |update_order| {
+            let (mut deaths, mut credits) = (vec![0.0; 20], vec![0.0; 20]);
+            for seed in 0..replicates {
+                let mut e = Environment::from_params(&SimulationParams {
+                    n: 40,
+                    infected: 20,
+                    xdim: 1,
+                    ydim: 1,
+                    duration: 1,
+                    p_death: 0.5,
+                    p_infect: 0.1,
+                    update_order,
+                    seed: Some(seed),
+                    ..SimulationParams::default()
+                });
+                e.enable_infection_events();
+                let infected: Vec<_> = e
+                    .iter_agents()
+                    .filter(|agent| agent.state == AgentType::AgentI)
+                    .map(|agent| agent.id)
+                    .collect();
+                e.run();
+                for (rank, &id) in infected.iter().enumerate() {
+                    if e.agent(id).unwrap().state == AgentType::AgentD {
+                        deaths[rank] += 1.0 / replicates as f64;
+                    }
+                    let infections = e
+                        .infection_events()
+                        .iter()
+                        .filter(|event| event.source == Some(id))
+                        .count();
+                    credits[rank] += infections as f64 / replicates as f64;
+                }
+            }
+            (deaths, credits)
+        };
+        let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
 
-        fraction_plot.show();
+        for &order in &[UpdateOrder::Indexed, UpdateOrder::Shuffled] {
+            let (deaths, credits) = frequencies(order);
+            // deaths are drawn from the states at the start of the tick, whatever the order
+            for (rank, death) in deaths.iter().enumerate() {
+                assert!((death - 0.5).abs() < 0.1, "{:?} {} {}", order, rank, death);
+            }
+            // the infected agent processed first gets the susceptible ones it infects first
+            let (first, last) = (mean(&credits[..5]), mean(&credits[15..]));
+            match order {
+                UpdateOrder::Indexed => assert!(first > 3.0 * last, "{} {}", first, last),
+                UpdateOrder::Shuffled => {
+                    assert!(
+                        (first - last).abs() < 0.15 * mean(&credits),
+                        "{:?}",
+                        credits
+                    )
+                }
+            }
+        }
     }
 }
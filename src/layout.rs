@@ -0,0 +1,551 @@
+//! Initial agent layouts stored as CSV files.
+//!
+//! A layout has a header row followed by one row per agent, `x,y,state[,age][,attributes...]`,
+//! where the state is one of `S`, `E`, `I`, `Q`, `R`, `V`, `D` or the full names
+//! `susceptible`, `exposed`, `infected`, `quarantined`, `recovered`, `vaccinated`, `dead` in
+//! any case. An `age` column right after `state` holds the age of each agent in years, and any
+//! further columns are named attributes, all of which are kept as the [`AgentAttributes`] of
+//! the agents but not used by the model. Optional fields may be empty or left out at the end
+//! of a row. [`write_layout`] writes all of these columns.
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::julia_reimpl::{AgentType, Environment};
+use crate::params::{ConfigError, SimulationParams};
+use crate::strata::Attributes;
+
+/// Error while reading a layout, with `row` the line number in the file, counting the header
+/// as row one
+#[derive(Debug)]
+pub enum LayoutError {
+    Io(io::Error),
+    /// The header does not start with `x,y,state`, or names an attribute twice or not at all
+    InvalidHeader {
+        header: String,
+    },
+    /// The parameters of the environment are invalid
+    InvalidParams(ConfigError),
+    /// The row has fewer than three columns
+    MissingField {
+        row: usize,
+        field: &'static str,
+    },
+    /// A coordinate is not a non-negative integer
+    InvalidCoordinate {
+        row: usize,
+        value: String,
+    },
+    /// The location lies outside the grid
+    OutOfBounds {
+        row: usize,
+        x: usize,
+        y: usize,
+    },
+    /// The state is not one of the recognised names
+    UnknownState {
+        row: usize,
+        state: String,
+    },
+    /// The age is not a non-negative integer
+    InvalidAge {
+        row: usize,
+        value: String,
+    },
+    /// The row has more columns than the header
+    ExtraField {
+        row: usize,
+    },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::Io(error) => write!(f, "failed to read layout: {}", error),
+            LayoutError::InvalidHeader { header } => {
+                write!(
+                    f,
+                    "header `{}` must start with `x,y,state` followed by distinct names",
+                    header
+                )
+            }
+            LayoutError::InvalidParams(error) => write!(f, "invalid parameters: {}", error),
+            LayoutError::MissingField { row, field } => {
+                write!(f, "row {}: missing field `{}`", row, field)
+            }
+            LayoutError::InvalidCoordinate { row, value } => {
+                write!(f, "row {}: invalid coordinate `{}`", row, value)
+            }
+            LayoutError::OutOfBounds { row, x, y } => {
+                write!(
+                    f,
+                    "row {}: location ({}, {}) is outside the grid",
+                    row, x, y
+                )
+            }
+            LayoutError::UnknownState { row, state } => {
+                write!(f, "row {}: unknown state `{}`", row, state)
+            }
+            LayoutError::InvalidAge { row, value } => {
+                write!(f, "row {}: invalid age `{}`", row, value)
+            }
+            LayoutError::ExtraField { row } => {
+                write!(f, "row {}: more fields than columns in the header", row)
+            }
+        }
+    }
+}
+
+impl Error for LayoutError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LayoutError::Io(error) => Some(error),
+            LayoutError::InvalidParams(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LayoutError {
+    fn from(error: io::Error) -> Self {
+        LayoutError::Io(error)
+    }
+}
+
+/// Optional columns of an agent in a layout
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AgentAttributes {
+    /// Age in years, if the layout has an `age` column with a value for the agent
+    pub age: Option<u32>,
+    /// Values of the further columns by their name, leaving out empty values
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// An agent of a layout
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutAgent {
+    pub x: usize,
+    pub y: usize,
+    pub state: AgentType,
+    pub attributes: AgentAttributes,
+}
+
+fn parse_state(row: usize, state: &str) -> Result<AgentType, LayoutError> {
+    match state.to_ascii_lowercase().as_str() {
+        "s" | "susceptible" => Ok(AgentType::AgentS),
+        "e" | "exposed" => Ok(AgentType::AgentE),
+        "i" | "infected" => Ok(AgentType::AgentI),
+        "q" | "quarantined" => Ok(AgentType::AgentQ),
+        "r" | "recovered" => Ok(AgentType::AgentR),
+        "v" | "vaccinated" => Ok(AgentType::AgentV),
+        "d" | "dead" => Ok(AgentType::AgentD),
+        _ => Err(LayoutError::UnknownState {
+            row,
+            state: state.to_string(),
+        }),
+    }
+}
+
+pub(crate) fn state_code(state: &AgentType) -> &'static str {
+    match state {
+        AgentType::AgentS => "S",
+        AgentType::AgentE => "E",
+        AgentType::AgentI => "I",
+        AgentType::AgentQ => "Q",
+        AgentType::AgentR => "R",
+        AgentType::AgentV => "V",
+        AgentType::AgentD => "D",
+    }
+}
+
+/// Read the agents of a layout on a grid of size `grid_size`
+pub fn read_layout<R: BufRead>(
+    reader: R,
+    grid_size: (usize, usize),
+) -> Result<Vec<LayoutAgent>, LayoutError> {
+    let mut lines = reader.lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let columns: Vec<_> = header.split(',').map(str::trim).collect();
+    if columns.len() < 3 || columns[..3] != ["x", "y", "state"] {
+        return Err(LayoutError::InvalidHeader { header });
+    }
+    let has_age = columns.get(3) == Some(&"age");
+    let names = &columns[if has_age { 4 } else { 3 }..];
+    let mut distinct: Vec<_> = names.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    if distinct.len() < names.len() || names.iter().any(|name| name.is_empty() || *name == "age") {
+        return Err(LayoutError::InvalidHeader { header });
+    }
+    let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+
+    let mut layout = Vec::new();
+    for (index, line) in lines.enumerate() {
+        let row = index + 2;
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.split(',').map(str::trim);
+        let mut coordinate = |field| {
+            let value = fields
+                .next()
+                .ok_or(LayoutError::MissingField { row, field })?;
+            value
+                .parse::<usize>()
+                .map_err(|_| LayoutError::InvalidCoordinate {
+                    row,
+                    value: value.to_string(),
+                })
+        };
+        let x = coordinate("x")?;
+        let y = coordinate("y")?;
+        let state = fields.next().ok_or(LayoutError::MissingField {
+            row,
+            field: "state",
+        })?;
+        let state = parse_state(row, state)?;
+        if x >= grid_size.0 || y >= grid_size.1 {
+            return Err(LayoutError::OutOfBounds { row, x, y });
+        }
+
+        let mut attributes = AgentAttributes::default();
+        if has_age {
+            match fields.next() {
+                Some(value) if !value.is_empty() => {
+                    attributes.age = Some(value.parse().map_err(|_| LayoutError::InvalidAge {
+                        row,
+                        value: value.to_string(),
+                    })?);
+                }
+                _ => {}
+            }
+        }
+        for name in &names {
+            match fields.next() {
+                Some(value) if !value.is_empty() => {
+                    attributes
+                        .attributes
+                        .insert(name.clone(), value.to_string());
+                }
+                _ => {}
+            }
+        }
+        if fields.next().is_some() {
+            return Err(LayoutError::ExtraField { row });
+        }
+        layout.push(LayoutAgent {
+            x,
+            y,
+            state,
+            attributes,
+        });
+    }
+    Ok(layout)
+}
+
+/// Check that `text` can be written as a field of a layout and read back as is
+fn check_field(text: &str) -> io::Result<()> {
+    if text.is_empty() || text.trim() != text || text.contains([',', '\n', '\r']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("`{}` cannot be written as a field of a layout", text),
+        ));
+    }
+    Ok(())
+}
+
+/// Write the current location, state and user-defined data of each agent of `env` as a
+/// layout. There is an `age` column if any agent has an age, and a column for each attribute
+/// of which any agent has a value, in the order of their names.
+///
+/// Fails with [`io::ErrorKind::InvalidInput`] if an attribute is named `age`, or an attribute
+/// name or value is empty, has surrounding whitespace, or holds a comma or a line break, none
+/// of which a layout can hold.
+pub fn write_layout<X: Attributes, W: Write>(
+    env: &Environment<X>,
+    mut writer: W,
+) -> io::Result<()> {
+    let data = |id| env.ext(id).expect("every agent has user-defined data");
+    let has_age = env
+        .iter_agents()
+        .any(|agent| data(agent.id).age().is_some());
+    let names: BTreeSet<&str> = env
+        .iter_agents()
+        .flat_map(|agent| data(agent.id).attribute_names())
+        .collect();
+    for name in &names {
+        check_field(name)?;
+        if *name == "age" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "an attribute of a layout cannot be named `age`",
+            ));
+        }
+    }
+
+    write!(writer, "x,y,state")?;
+    if has_age {
+        write!(writer, ",age")?;
+    }
+    for name in &names {
+        write!(writer, ",{}", name)?;
+    }
+    writeln!(writer)?;
+    for agent in env.iter_agents() {
+        let data = data(agent.id);
+        write!(
+            writer,
+            "{},{},{}",
+            agent.x,
+            agent.y,
+            state_code(&agent.state)
+        )?;
+        if has_age {
+            write!(writer, ",")?;
+            if let Some(age) = data.age() {
+                write!(writer, "{}", age)?;
+            }
+        }
+        for name in &names {
+            write!(writer, ",")?;
+            if let Some(value) = data.attribute(name) {
+                check_field(value)?;
+                write!(writer, "{}", value)?;
+            }
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+impl Environment<AgentAttributes> {
+    /// Set up an environment with the agents of the layout at `path`, see the
+    /// [module documentation](crate::layout) for the format, with the optional columns of each
+    /// agent as its user-defined data.
+    ///
+    /// All agents entered their state at tick zero. All random numbers are drawn from `seed`,
+    /// or from a random seed without one.
+    pub fn from_agents_csv<P: AsRef<Path>>(
+        path: P,
+        duration: usize,
+        p_death: f64,
+        xdim: usize,
+        ydim: usize,
+        seed: Option<u64>,
+    ) -> Result<Self, LayoutError> {
+        SimulationParams {
+            n: 0,
+            infected: 0,
+            duration,
+            p_death,
+            xdim,
+            ydim,
+            ..SimulationParams::default()
+        }
+        .validate()
+        .map_err(LayoutError::InvalidParams)?;
+        let layout = read_layout(BufReader::new(File::open(path)?), (xdim, ydim))?;
+        let cells: Vec<_> = layout
+            .iter()
+            .map(|agent| (agent.x, agent.y, agent.state.clone()))
+            .collect();
+        Ok(Self::from_layout(
+            &cells,
+            duration,
+            p_death,
+            (xdim, ydim),
+            seed,
+            |index, _, _| layout[index].attributes.clone(),
+        ))
+    }
+}
+
+impl<X: Attributes> Environment<X> {
+    /// Write the current location, state and user-defined data of each agent as a layout at
+    /// `path`, which can be read back with [`Environment::from_agents_csv`], see
+    /// [`write_layout`].
+    pub fn export_agents_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        write_layout(self, &mut writer)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    const FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/small_layout.csv"
+    );
+
+    fn read(csv: &str) -> Result<Vec<LayoutAgent>, LayoutError> {
+        read_layout(csv.as_bytes(), (5, 5))
+    }
+
+    #[test]
+    fn test_fixture() {
+        let e = Environment::from_agents_csv(FIXTURE, 10, 0.1, 5, 5, Some(1)).unwrap();
+        let agents: Vec<_> = e.iter_agents().map(|a| (a.x, a.y, a.state)).collect();
+        assert_eq!(
+            agents,
+            vec![
+                (0, 0, AgentType::AgentS),
+                (0, 0, AgentType::AgentI),
+                (1, 2, AgentType::AgentS),
+                (4, 3, AgentType::AgentR),
+                (2, 1, AgentType::AgentD),
+                (3, 3, AgentType::AgentI),
+            ]
+        );
+        let stats = e.get_statistics();
+        assert_eq!(
+            (
+                stats.susceptible,
+                stats.infected,
+                stats.recovered,
+                stats.dead
+            ),
+            (2, 2, 1, 1)
+        );
+        let ages: Vec<_> = (0..6).map(|id| e.ext(id).unwrap().age).collect();
+        assert_eq!(
+            ages,
+            vec![Some(34), Some(61), Some(27), Some(45), Some(80), Some(19)]
+        );
+    }
+
+    #[test]
+    fn test_attributes() {
+        let agents =
+            read("x, y, state, age, household, job\n0,0,S,,7,teacher\n1,1,I,40\n").unwrap();
+        assert_eq!(agents[0].attributes.age, None);
+        assert_eq!(
+            agents[0].attributes.attributes,
+            vec![
+                ("household".to_string(), "7".to_string()),
+                ("job".to_string(), "teacher".to_string())
+            ]
+            .into_iter()
+            .collect()
+        );
+        assert_eq!(agents[1].attributes.age, Some(40));
+        assert!(agents[1].attributes.attributes.is_empty());
+
+        // without an age column, all further columns are attributes
+        let agents = read("x,y,state,household\n0,0,S,7\n").unwrap();
+        assert_eq!(agents[0].attributes.age, None);
+        assert_eq!(agents[0].attributes.attributes["household"], "7");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let e = Environment::from_params(&presets::dense_small_grid());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layout.csv");
+        e.export_agents_csv(&path).unwrap();
+
+        let read = Environment::from_agents_csv(&path, 10, 0.1, 20, 20, Some(1)).unwrap();
+        let read: Vec<_> = read.iter_agents().map(|a| (a.x, a.y, a.state)).collect();
+        let written: Vec<_> = e.iter_agents().map(|a| (a.x, a.y, a.state)).collect();
+        assert_eq!(read, written);
+        let csv = std::fs::read_to_string(&path).unwrap();
+        assert!(csv.starts_with("x,y,state\n"), "{}", csv);
+    }
+
+    #[test]
+    fn test_round_trip_of_attributes() {
+        let mut e = Environment::from_agents_csv(FIXTURE, 10, 0.1, 5, 5, Some(1)).unwrap();
+        e.ext_mut(1).unwrap().age = None;
+        for (id, household) in [(0, "7"), (2, "7"), (3, "12")].iter().copied() {
+            let attributes = &mut e.ext_mut(id).unwrap().attributes;
+            attributes.insert("household".to_string(), household.to_string());
+        }
+        let job = &mut e.ext_mut(4).unwrap().attributes;
+        job.insert("job".to_string(), "teacher".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layout.csv");
+        e.export_agents_csv(&path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        assert!(csv.starts_with("x,y,state,age,household,job\n0,0,S,34,7,\n0,0,I,,,\n"));
+
+        let read = Environment::from_agents_csv(&path, 10, 0.1, 5, 5, Some(1)).unwrap();
+        let agents = |e: &Environment<AgentAttributes>| -> Vec<_> {
+            e.iter_agents()
+                .map(|a| (a.x, a.y, a.state, e.ext(a.id).unwrap().clone()))
+                .collect()
+        };
+        assert_eq!(agents(&read), agents(&e));
+
+        e.ext_mut(5)
+            .unwrap()
+            .attributes
+            .insert("job".to_string(), "nurse, night shifts".to_string());
+        let error = e.export_agents_csv(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            read("a,b,c\n"),
+            Err(LayoutError::InvalidHeader { .. })
+        ));
+        assert!(matches!(
+            read("x,y,state\n0,0,S\n1,1\n"),
+            Err(LayoutError::MissingField {
+                row: 3,
+                field: "state"
+            })
+        ));
+        assert!(matches!(
+            read("x,y,state\n-1,0,S\n"),
+            Err(LayoutError::InvalidCoordinate { row: 2, .. })
+        ));
+        assert!(matches!(
+            read("x,y,state\n0,0,S\n0,5,I\n"),
+            Err(LayoutError::OutOfBounds { row: 3, x: 0, y: 5 })
+        ));
+        assert!(matches!(
+            read("x,y,state\n0,0,X\n"),
+            Err(LayoutError::UnknownState { row: 2, .. })
+        ));
+        assert!(matches!(
+            read("x,y,state,age\n0,0,S,old\n"),
+            Err(LayoutError::InvalidAge { row: 2, .. })
+        ));
+        assert!(matches!(
+            read("x,y,state\n0,0,S,34\n"),
+            Err(LayoutError::ExtraField { row: 2 })
+        ));
+        for header in &["x,y,state,job,job", "x,y,state,,job", "x,y,state,job,age"] {
+            assert!(matches!(
+                read(header),
+                Err(LayoutError::InvalidHeader { .. })
+            ));
+        }
+        assert!(matches!(
+            Environment::from_agents_csv("does/not/exist.csv", 10, 0.1, 5, 5, None),
+            Err(LayoutError::Io(_))
+        ));
+        assert!(matches!(
+            Environment::from_agents_csv(FIXTURE, 10, 1.5, 5, 5, None),
+            Err(LayoutError::InvalidParams(
+                ConfigError::InvalidProbability {
+                    name: "p_death",
+                    ..
+                }
+            ))
+        ));
+        assert!(matches!(
+            Environment::from_agents_csv(FIXTURE, 10, 0.1, 0, 5, None),
+            Err(LayoutError::InvalidParams(ConfigError::EmptyGrid { .. }))
+        ));
+    }
+}
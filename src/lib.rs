@@ -1 +1,38 @@
+pub mod alloc_profile;
+pub mod branching;
+pub mod comparison;
+#[cfg(feature = "polars")]
+pub mod dataframe;
+pub mod ensemble;
+pub mod events;
+#[cfg(feature = "frames")]
+pub mod frames;
+pub mod geojson;
+#[cfg(feature = "hdf5")]
+pub mod hdf5_output;
+#[cfg(feature = "image")]
+pub mod image_maps;
+pub mod immunity;
 pub mod julia_reimpl;
+pub mod layout;
+pub mod params;
+pub mod particle_filter;
+pub mod phases;
+#[cfg(feature = "plotting")]
+pub mod plot;
+pub mod presets;
+#[cfg(feature = "render")]
+pub mod render;
+pub mod replay;
+pub mod result;
+#[cfg(feature = "spatial")]
+pub mod spatial;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_output;
+pub mod strata;
+pub mod summary;
+pub mod sweep;
+pub mod variance;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod waves;
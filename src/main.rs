@@ -1,11 +1,427 @@
-
+use bkamins_sir_abm::ensemble::{self, EnsembleSummary};
 use bkamins_sir_abm::julia_reimpl::Environment;
+use bkamins_sir_abm::params::{EnvironmentConfig, SimulationParams};
+use bkamins_sir_abm::presets;
+use bkamins_sir_abm::result;
+use bkamins_sir_abm::sweep::{self, SweepParameter};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "alloc-profile")]
+#[global_allocator]
+static ALLOCATOR: bkamins_sir_abm::alloc_profile::CountingAllocator =
+    bkamins_sir_abm::alloc_profile::CountingAllocator;
+
+/// Exit code after an interrupted run that wrote the completed replicates
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Value following the flag `name`, if the flag is present
+fn flag_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .map(|i| args.get(i + 1).map(String::as_str).unwrap_or_default())
+}
+
+/// Exit with a usage error
+fn fail(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(2);
+}
+
+/// Parse the value of the flag `name`, or use `default` if it is absent
+fn parse_flag<T: std::str::FromStr>(args: &[String], name: &str, default: Option<T>) -> T {
+    match flag_value(args, name) {
+        Some(value) => value
+            .parse()
+            .unwrap_or_else(|_| fail(&format!("invalid value `{}` for `{}`", value, name))),
+        None => default.unwrap_or_else(|| fail(&format!("missing `{}`", name))),
+    }
+}
+
+/// Overall time budget given by `--budget-secs`, if any
+fn budget(args: &[String]) -> Option<Duration> {
+    flag_value(args, "--budget-secs").map(|_| {
+        let seconds: f64 = parse_flag(args, "--budget-secs", None);
+        Duration::try_from_secs_f64(seconds)
+            .unwrap_or_else(|_| fail(&format!("invalid value `{}` for `--budget-secs`", seconds)))
+    })
+}
 
+/// `params` with the seed given by `--seed`, if any
+fn seeded(args: &[String], params: SimulationParams) -> SimulationParams {
+    match flag_value(args, "--seed") {
+        Some(_) => SimulationParams {
+            seed: Some(parse_flag(args, "--seed", None)),
+            ..params
+        },
+        None => params,
+    }
+}
+
+/// Scenario of `--config` or `--preset`, the blog scenario by default, seeded by `--seed`
+fn preset(args: &[String]) -> SimulationParams {
+    seeded(args, scenario(args))
+}
+
+/// `params` with the population, initial infections, duration, probability of death and grid
+/// size given by their flags, if any
+fn overridden(args: &[String], params: SimulationParams) -> SimulationParams {
+    let flag = |name, default| parse_flag(args, name, Some(default));
+    SimulationParams {
+        n: flag("--population", params.n),
+        infected: flag("--initial-infected", params.infected),
+        duration: flag("--duration", params.duration),
+        p_death: parse_flag(args, "--p-death", Some(params.p_death)),
+        xdim: flag("--width", params.xdim),
+        ydim: flag("--height", params.ydim),
+        ..params
+    }
+}
+
+fn scenario(args: &[String]) -> SimulationParams {
+    if let Some(path) = flag_value(args, "--config") {
+        let config = std::fs::read_to_string(path)
+            .unwrap_or_else(|error| fail(&format!("cannot read `{}`: {}", path, error)));
+        return toml::from_str(&config)
+            .unwrap_or_else(|error| fail(&format!("invalid config `{}`: {}", path, error)));
+    }
+    match flag_value(args, "--preset") {
+        Some(name) => presets::by_name(name).unwrap_or_else(|| {
+            fail(&format!(
+                "unknown preset `{}`, expected one of: {}",
+                name,
+                presets::NAMES.join(", ")
+            ))
+        }),
+        None => presets::blog_default(),
+    }
+}
+
+/// `sweep --param <name> --from <value> --to <value> [--step <value>] --replicates <count>
+/// --out <csv> [--plot <html>] [--preset <name>] [--seed <seed>] [--budget-secs <seconds>]`
+fn run_sweep(args: &[String]) {
+    let parameter: SweepParameter = flag_value(args, "--param")
+        .unwrap_or_else(|| fail("missing `--param`"))
+        .parse()
+        .unwrap_or_else(|error: sweep::UnknownParameter| fail(&error.to_string()));
+    let from: f64 = parse_flag(args, "--from", None);
+    let to: f64 = parse_flag(args, "--to", None);
+    let step: f64 = parse_flag(args, "--step", Some(1.0));
+    let replicates: usize = parse_flag(args, "--replicates", Some(16));
+    let out: String = parse_flag(args, "--out", None);
+
+    let values = sweep::values(from, to, step);
+    if values.is_empty() || replicates == 0 {
+        fail("the sweep is empty");
+    }
+    let points =
+        sweep::sweep_with_budget(&preset(args), parameter, &values, replicates, budget(args))
+            .unwrap_or_else(|error| fail(&error.to_string()));
+    if points.len() < values.len() || points.iter().any(|point| point.replicates < replicates) {
+        eprintln!("time budget exhausted, the sweep is incomplete");
+    }
+
+    sweep::write_csv(create(Path::new(&out)), parameter, &points)
+        .unwrap_or_else(|error| fail(&format!("cannot write `{}`: {}", out, error)));
+
+    if let Some(path) = flag_value(args, "--plot") {
+        plot_sweep(&points, path);
+    }
+}
+
+/// Write the attack rate of each point of a sweep as HTML to `path`
+#[cfg(feature = "plotting")]
+fn plot_sweep(points: &[sweep::SweepPoint], path: &str) {
+    use plotly::common::{ErrorData, ErrorType};
+    use plotly::{Plot, Scatter};
+
+    let x: Vec<f64> = points.iter().map(|point| point.value).collect();
+    let y: Vec<f64> = points.iter().map(|point| point.mean_attack_rate).collect();
+    let sd: Vec<f64> = points.iter().map(|point| point.sd_attack_rate).collect();
+    let mut plot = Plot::new();
+    plot.add_trace(
+        Scatter::new(x, y)
+            .name("fraction infected")
+            .error_y(ErrorData::new(ErrorType::Data).array(sd)),
+    );
+    plot.to_html(path);
+}
+
+#[cfg(not(feature = "plotting"))]
+fn plot_sweep(_: &[sweep::SweepPoint], _: &str) {
+    fail("`--plot` requires the `plotting` feature");
+}
+
+/// Create the file at `path`, or exit
+fn create(path: &Path) -> BufWriter<File> {
+    let file = File::create(path)
+        .unwrap_or_else(|error| fail(&format!("cannot create `{}`: {}", path.display(), error)));
+    BufWriter::new(file)
+}
+
+/// `ensemble --replicates <count> --out-dir <dir> [--per-replicate] [--summary-only]
+/// [--preset <name>] [--seed <seed>] [--budget-secs <seconds>]`
+fn run_ensemble(args: &[String]) {
+    let replicates: usize = parse_flag(args, "--replicates", Some(16));
+    let out_dir: String = parse_flag(args, "--out-dir", None);
+    let summary_only = args.iter().any(|arg| arg == "--summary-only");
+    let per_replicate = args.iter().any(|arg| arg == "--per-replicate");
+    let (params, _) = EnvironmentConfig::from(preset(args))
+        .build_params()
+        .unwrap_or_else(|error| fail(&error.to_string()));
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    let handler = Arc::clone(&cancel);
+    ctrlc::set_handler(move || handler.store(true, Ordering::Relaxed))
+        .unwrap_or_else(|error| fail(&format!("cannot handle Ctrl-C: {}", error)));
+
+    let runs = ensemble::run_replicates_with_budget(&params, replicates, &cancel, budget(args));
+    let out_dir = Path::new(&out_dir);
+    std::fs::create_dir_all(out_dir)
+        .unwrap_or_else(|error| fail(&format!("cannot create `{}`: {}", out_dir.display(), error)));
+    let completed = runs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, run)| run.as_ref().map(|run| (i, run)));
+    let written = if summary_only {
+        Ok(())
+    } else if per_replicate {
+        completed.into_iter().try_for_each(|(i, run)| {
+            let path = out_dir.join(format!("replicate_{:04}.csv", i));
+            ensemble::write_replicate_csv(create(&path), run)
+        })
+    } else {
+        ensemble::write_long_csv(create(&out_dir.join("tallies.csv")), completed)
+    };
+    written.unwrap_or_else(|error| fail(&format!("cannot write tallies: {}", error)));
+
+    let summary = EnsembleSummary::new(&runs);
+    serde_json::to_writer_pretty(create(&out_dir.join("summary.json")), &summary)
+        .unwrap_or_else(|error| fail(&format!("cannot write summary: {}", error)));
+
+    if summary.completed.len() < replicates && !cancel.load(Ordering::Relaxed) {
+        eprintln!(
+            "time budget exhausted after {} of {} replicates",
+            summary.completed.len(),
+            replicates
+        );
+    } else if summary.completed.len() < replicates {
+        eprintln!(
+            "interrupted after {} of {} replicates",
+            summary.completed.len(),
+            replicates
+        );
+        std::process::exit(EXIT_INTERRUPTED);
+    }
+}
+
+/// `animate [--config <toml> | --preset <name>] (--out <gif> | --frames-dir <dir>)
+/// [--every <ticks>] [--cell-pixels <pixels>] [--downsample <cells>]
+/// [--color-scheme <name>] [--max-frames <count>] [--frame-delay <ms>] [--seed <seed>]`
+#[cfg(feature = "frames")]
+fn run_animate(args: &[String]) {
+    use bkamins_sir_abm::frames::{self, ColorScheme, FrameError, FrameOptions};
+    use bkamins_sir_abm::julia_reimpl::AgentType;
+
+    let scheme = flag_value(args, "--color-scheme").map_or(ColorScheme::Classic, |name| {
+        ColorScheme::from_name(name).unwrap_or_else(|| {
+            fail(&format!(
+                "unknown color scheme `{}`, expected one of: {}",
+                name,
+                ColorScheme::NAMES.join(", ")
+            ))
+        })
+    });
+    let options = FrameOptions {
+        every: parse_flag(args, "--every", Some(1)),
+        cell_pixels: parse_flag(args, "--cell-pixels", Some(4)),
+        downsample: parse_flag(args, "--downsample", Some(1)),
+        scheme,
+        max_frames: flag_value(args, "--max-frames")
+            .map(|_| parse_flag(args, "--max-frames", None)),
+    };
+    let frame_delay: u32 = parse_flag(args, "--frame-delay", Some(100));
+    let (params, _) = EnvironmentConfig::from(preset(args))
+        .build_params()
+        .unwrap_or_else(|error| fail(&error.to_string()));
+
+    let mut e = Environment::from_params(&params);
+    let frames = match (flag_value(args, "--out"), flag_value(args, "--frames-dir")) {
+        (Some(out), None) => {
+            frames::export_gif(&mut e, out, &options, frame_delay).map(|t| t.len())
+        }
+        (None, Some(dir)) => frames::export_frames(&mut e, dir, &options).map(|m| m.frames.len()),
+        _ => fail("expected exactly one of `--out` and `--frames-dir`"),
+    };
+    let frames = frames.unwrap_or_else(|error| match error {
+        FrameError::TooLarge { .. } => fail(&format!(
+            "{}, try a smaller `--cell-pixels` or a larger `--downsample`",
+            error
+        )),
+        error => fail(&error.to_string()),
+    });
+
+    let mut tally = [0; 7];
+    for agent in e.iter_agents() {
+        let state = match agent.state {
+            AgentType::AgentS => 0,
+            AgentType::AgentE => 1,
+            AgentType::AgentI => 2,
+            AgentType::AgentQ => 3,
+            AgentType::AgentR => 4,
+            AgentType::AgentV => 5,
+            AgentType::AgentD => 6,
+        };
+        tally[state] += 1;
+    }
+    println!("frames: {}", frames);
+    println!(
+        "ticks: {}, susceptible: {}, exposed: {}, infected: {}, quarantined: {}, recovered: {}, \
+         vaccinated: {}, dead: {}",
+        e.tick(),
+        tally[0],
+        tally[1],
+        tally[2],
+        tally[3],
+        tally[4],
+        tally[5],
+        tally[6]
+    );
+}
+
+/// Write `{"error": {"kind": kind, "message": message}}` to stdout and exit
+fn fail_json(kind: &str, message: &str) -> ! {
+    let error = serde_json::json!({ "error": { "kind": kind, "message": message } });
+    println!("{}", error);
+    eprintln!("error: {}", message);
+    std::process::exit(2);
+}
+
+/// `pipe [--stream] [--seed <seed>]`: read a scenario as JSON from stdin and write the
+/// RunResult to stdout, or with `--stream` the tally of each tick as a line of JSON. The seed
+/// overrides that of the scenario.
+fn run_pipe(args: &[String]) {
+    use std::io::Write;
+
+    let stream = args.iter().any(|arg| arg == "--stream");
+    let params: SimulationParams = serde_json::from_reader(std::io::stdin().lock())
+        .unwrap_or_else(|error| fail_json("invalid_input", &error.to_string()));
+    let (params, warnings) = EnvironmentConfig::from(seeded(args, params))
+        .build_params()
+        .unwrap_or_else(|error| fail_json("invalid_config", &error.to_string()));
+    for warning in &warnings {
+        eprintln!("warning: {}", warning);
+    }
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let written = if stream {
+        let mut line = |env: &Environment| {
+            let mut tally = serde_json::to_value(env.get_statistics()).expect("tally is valid");
+            tally["tick"] = env.tick().into();
+            writeln!(stdout, "{}", tally)
+        };
+        let mut e = Environment::from_params(&params);
+        let mut written = line(&e);
+        e.run_with_hook(|env, _| {
+            if written.is_ok() {
+                written = line(env);
+            }
+        });
+        written
+    } else {
+        serde_json::to_writer(&mut stdout, &result::run(&params))
+            .map_err(std::io::Error::from)
+            .and_then(|()| writeln!(stdout))
+    };
+    written.unwrap_or_else(|error| fail(&format!("cannot write to stdout: {}", error)));
+}
+
+/// Format of the file given by `--output`
+enum OutputFormat {
+    /// Tallies in long format with a replicate column, see [`ensemble::write_long_csv`]
+    Csv,
+    /// Array of the [`RunResult`](result::RunResult) of each replicate
+    Json,
+}
+
+impl OutputFormat {
+    /// Format of `path` by its extension, or exit
+    fn of(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => OutputFormat::Csv,
+            Some("json") => OutputFormat::Json,
+            _ => fail(&format!(
+                "cannot tell the format of `{}`, expected a `.csv` or `.json` file",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// `[--config <toml> | --preset <name>] [--population <count>] [--initial-infected <count>]
+/// [--duration <ticks>] [--p-death <probability>] [--width <cells>] [--height <cells>]
+/// [--seed <seed>] [--replicates <count>] [--output <csv|json>] [--quiet]`: run the replicates
+/// of the scenario, ten by default for basic benchmarking, and write their tallies to the
+/// output, or the final tally of each replicate as a line of JSON to stdout unless `--quiet`
+fn run_scenario(args: &[String]) {
+    let quiet = args.iter().any(|arg| arg == "--quiet");
+    let replicates: usize = parse_flag(args, "--replicates", Some(10));
+    let output = flag_value(args, "--output")
+        .map(|path| (Path::new(path), OutputFormat::of(Path::new(path))));
+    let (params, warnings) = EnvironmentConfig::from(overridden(args, preset(args)))
+        .build_params()
+        .unwrap_or_else(|error| fail(&error.to_string()));
+    if !quiet {
+        for warning in &warnings {
+            eprintln!("warning: {}", warning);
+        }
+    }
+
+    let runs: Vec<_> = ensemble::run_replicates(&params, replicates, &AtomicBool::new(false))
+        .into_iter()
+        .map(|run| run.expect("replicates are not cancelled"))
+        .collect();
+    match output {
+        Some((path, OutputFormat::Csv)) => {
+            ensemble::write_long_csv(create(path), runs.iter().enumerate()).unwrap_or_else(
+                |error| fail(&format!("cannot write `{}`: {}", path.display(), error)),
+            )
+        }
+        Some((path, OutputFormat::Json)) => serde_json::to_writer(create(path), &runs)
+            .unwrap_or_else(|error| fail(&format!("cannot write `{}`: {}", path.display(), error))),
+        None if quiet => {}
+        None => {
+            for (replicate, run) in runs.iter().enumerate() {
+                let last = run
+                    .records()
+                    .pop()
+                    .expect("a run records its initial tally");
+                let mut line = serde_json::to_value(last).expect("tally is valid");
+                line["replicate"] = replicate.into();
+                line["ticks"] = (run.record.len() - 1).into();
+                println!("{}", line);
+            }
+        }
+    }
+}
 
 fn main() {
-    // For basic benchmarking, run the default scenario for ten times
-    for _ in 0..10 {
-        let mut e = Environment::init(2000, 10, 21, 0.05, 100, 100);
-        let states_record = e.run();
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("sweep") => return run_sweep(&args[2..]),
+        Some("ensemble") => return run_ensemble(&args[2..]),
+        Some("pipe") => return run_pipe(&args[2..]),
+        #[cfg(feature = "frames")]
+        Some("animate") => return run_animate(&args[2..]),
+        #[cfg(not(feature = "frames"))]
+        Some("animate") => fail("animate requires building with the `frames` feature"),
+        _ => {}
     }
+
+    run_scenario(&args[1..]);
 }
@@ -0,0 +1,1930 @@
+//! Parameters of a simulation, and a builder that validates them.
+//!
+//! Validation happens in two tiers: a [`ConfigError`] is returned for configurations the model
+//! cannot run at all, while a [`ConfigWarning`] flags configurations that are technically valid
+//! but almost certainly not what the user intended.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::julia_reimpl::Environment;
+
+/// Expected number of secondary cases per case below which the epidemic is expected to fizzle.
+pub const FIZZLE_R0: f64 = 0.5;
+/// Mean number of agents per cell above which the grid behaves as a single, well-mixed cell.
+pub const SATURATED_DENSITY: f64 = 5.0;
+
+/// Full set of parameters needed to set up an [`Environment`]
+///
+/// Parameters missing when deserializing are taken from [`SimulationParams::default`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationParams {
+    /// Number of agents
+    pub n: usize,
+    /// Number of initially infected agents
+    pub infected: usize,
+    /// Where the initially infected agents are placed on the grid
+    pub initial_seeding: SeedingStrategy,
+    /// Duration of agents within infected state
+    pub duration: usize,
+    /// Distribution of the duration of each infection, drawn when the agent is infected, in
+    /// place of the fixed `duration`
+    pub duration_model: Option<DurationModel>,
+    /// Ticks for which an agent infected during the run is exposed, being infected but not yet
+    /// infectious, where zero makes it infectious at once as in the blogpost. Initially
+    /// infected, seeded and imported cases are infectious at once.
+    pub incubation: usize,
+    /// Ticks for which a recovered agent stays immune, after which it becomes susceptible
+    /// again, or `None` for lifelong immunity as in the blogpost. Runs in which immunity wanes
+    /// may never end without [`SimulationParams::max_ticks`].
+    pub immunity_duration: Option<usize>,
+    /// Probability of death of an agent after duration of infection has elapsed.
+    pub p_death: f64,
+    /// What happens to the agents that die
+    pub corpse_handling: CorpseHandling,
+    /// Order in which the agents are processed within a tick
+    pub update_order: UpdateOrder,
+    /// Probability of a susceptible agent being infected by each infected agent sharing its
+    /// cell in a tick, where 1 infects on every contact as in the blogpost
+    pub p_infect: f64,
+    /// Chebyshev distance in cells, wrapping around the grid, within which infected agents
+    /// infect susceptible ones, where zero only infects cellmates as in the blogpost
+    pub infection_radius: usize,
+    /// Probability of an agent moving in a tick, where 1 moves every agent in every tick as in
+    /// the blogpost
+    pub p_move: f64,
+    /// Size of the grid in x-dimension
+    pub xdim: usize,
+    /// Size of the grid in y-dimension
+    pub ydim: usize,
+    /// What happens to agents stepping off an edge of the grid
+    pub boundary: Boundary,
+    /// Days per tick, when the parameters were given as rates per day
+    pub dt: Option<f64>,
+    /// Ticks of movement only, before the initial infections are seeded
+    pub burn_in_ticks: usize,
+    /// Tick at which a run stops even if it is still active, or `None` to run until no
+    /// further infections can occur
+    pub max_ticks: Option<usize>,
+    /// Further infections introduced during the run, on top of the initially infected agents
+    pub seeding: Vec<SeedingEntry>,
+    /// Vaccination campaigns during the run
+    pub vaccination: Vec<VaccinationEntry>,
+    /// Transmit through a population of vectors instead of directly between agents
+    pub vectors: Option<VectorParams>,
+    /// Cells of an animal reservoir, where susceptible agents are infected by spillover
+    pub reservoirs: Vec<(usize, usize)>,
+    /// Probability per tick of a susceptible agent in a reservoir cell being infected by
+    /// spillover, regardless of the infected agents around it
+    pub p_spillover: f64,
+    /// Detection of infected agents
+    pub detection: Option<DetectionParams>,
+    /// Post-exposure prophylaxis given to the cellmates of detected agents, which requires
+    /// `detection`
+    pub ppe: Option<PpeParams>,
+    /// Whether agents isolate once their infection is detected, which requires `detection`
+    pub isolation: bool,
+    /// Quarantine of infected agents detected after they have been infectious for a while
+    pub quarantine: Option<QuarantineParams>,
+    /// Distribution of the compliance of the agents with behavioural interventions
+    pub compliance: Compliance,
+    /// Distributions of the parameters of each agent in place of those above
+    pub heterogeneity: AgentParams,
+    /// Multiplier of `p_death` by the infecting dose
+    pub dose_severity: DoseSeverity,
+    /// Restriction of the movement of the agents that are not essential workers
+    pub lockdown: Option<Lockdown>,
+    /// Fraction of the agents that are essential workers, exempt from the lockdown but not
+    /// from isolation
+    pub essential_fraction: f64,
+    /// Attributes by which the tallies of a run are also recorded, see
+    /// [`strata`](crate::strata)
+    pub stratify: Vec<Stratification>,
+    /// Seed of the random numbers, such that the same parameters give the same run, or
+    /// `None` for a seed drawn at random
+    pub seed: Option<u64>,
+}
+
+/// Attribute of the agents by which a tally is split into strata
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stratification {
+    /// Essential workers, `essential`, and the `other` agents
+    Essential,
+    /// Agents that were given at least one dose of vaccine, `vaccinated`, and the
+    /// `unvaccinated` ones
+    Vaccinated,
+    /// Age groups starting at each of the increasing `bounds`, e.g. `0-17`, `18-64` and `65+`
+    /// for bounds `[18, 65]`, where agents without an age are `unknown`
+    Age { bounds: Vec<u32> },
+    /// Value of the column `name` of a [layout](crate::layout), where agents without it are
+    /// `unknown`
+    Attribute { name: String },
+}
+
+impl Stratification {
+    /// Name of the stratification, being the name of its attribute
+    #[must_use]
+    pub fn name(&self) -> &str {
+        match self {
+            Stratification::Essential => "essential",
+            Stratification::Vaccinated => "vaccinated",
+            Stratification::Age { .. } => "age",
+            Stratification::Attribute { name } => name,
+        }
+    }
+}
+
+/// Restriction of movement during the ticks from `start` up to, but excluding, `end`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lockdown {
+    pub start: usize,
+    pub end: usize,
+    /// Probability per tick of a fully compliant agent that is not an essential worker moving,
+    /// where zero is a total lockdown. The reduction of movement is scaled by the compliance
+    /// of each agent, such that agents without compliance move as usual
+    pub p_move: f64,
+}
+
+/// Multiplier of the probability of death of an agent, by the number of infectious agents
+/// in its cell at the moment it was infected.
+///
+/// The probability of death is capped at one. Agents that were not infected by contact, e.g.
+/// seeded or by spillover, die with probability `p_death`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DoseSeverity {
+    /// The same multiplier regardless of the dose
+    Constant(f64),
+    /// `max / (1 + exp(-steepness * (dose - midpoint)))`
+    Logistic {
+        max: f64,
+        midpoint: f64,
+        steepness: f64,
+    },
+}
+
+impl DoseSeverity {
+    /// Multiplier of `p_death` for an infection with `dose`
+    #[must_use]
+    pub fn multiplier(&self, dose: usize) -> f64 {
+        match *self {
+            DoseSeverity::Constant(multiplier) => multiplier,
+            DoseSeverity::Logistic {
+                max,
+                midpoint,
+                steepness,
+            } => max / (1.0 + (-steepness * (dose as f64 - midpoint)).exp()),
+        }
+    }
+}
+
+impl Default for DoseSeverity {
+    /// Mortality that does not depend on the dose
+    fn default() -> Self {
+        DoseSeverity::Constant(1.0)
+    }
+}
+
+/// Distribution from which the compliance of each agent is drawn when it is set up.
+///
+/// The compliance of an agent is the probability of it taking part in a behavioural
+/// intervention, being isolation once detected and accepting prophylaxis.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Compliance {
+    /// Every agent has the same compliance
+    Constant(f64),
+    /// Compliance uniformly distributed between `low` and `high`
+    Uniform { low: f64, high: f64 },
+    /// Compliance following a beta distribution with shape parameters `alpha` and `beta`
+    Beta { alpha: f64, beta: f64 },
+}
+
+impl Default for Compliance {
+    /// Full compliance, such that interventions apply uniformly
+    fn default() -> Self {
+        Compliance::Constant(1.0)
+    }
+}
+
+/// Edges of the grid, see [`SimulationParams::boundary`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Boundary {
+    /// A step off one edge enters at the opposite edge, and agents near opposite edges are
+    /// neighbours
+    Torus,
+    /// A step off an edge bounces back off it
+    Reflecting,
+    /// A step off an edge stays at the edge
+    Clamped,
+}
+
+impl Default for Boundary {
+    /// The torus of the blogpost
+    fn default() -> Self {
+        Boundary::Torus
+    }
+}
+
+/// What happens to the agents that die, see [`SimulationParams::corpse_handling`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CorpseHandling {
+    /// Dead agents stay in the cell they died in, where they neither move nor infect anyone
+    Keep,
+    /// Dead agents are taken out of the grid, such that they are neither moved nor visited
+    /// by the infections, while they are still counted as dead
+    Remove,
+}
+
+impl Default for CorpseHandling {
+    /// The dead stay in place as in the blogpost
+    fn default() -> Self {
+        CorpseHandling::Keep
+    }
+}
+
+/// Order in which the agents are processed within a tick, see
+/// [`SimulationParams::update_order`].
+///
+/// The transitions of a tick are decided from the states at its start, such that the order
+/// only decides which of the infected cellmates of a susceptible agent infects it, and the
+/// order of the agents within each cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UpdateOrder {
+    /// In the order of their index, such that agents with a lower index infect first
+    Indexed,
+    /// In an order drawn anew at the start of each tick, which the movement of the tick
+    /// follows as well
+    Shuffled,
+}
+
+impl Default for UpdateOrder {
+    /// The order of the index as in the blogpost
+    fn default() -> Self {
+        UpdateOrder::Indexed
+    }
+}
+
+/// Distribution from which a parameter of each agent is drawn when it is set up, see
+/// [`AgentParams`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AgentDistribution {
+    /// Every agent has the same value
+    Constant(f64),
+    /// Values uniformly distributed between `low` and `high`
+    Uniform { low: f64, high: f64 },
+    /// Each agent belongs to a high-risk group with probability `fraction`, and takes the
+    /// value `high_risk` if it does or `other` if it does not
+    TwoGroup {
+        fraction: f64,
+        high_risk: f64,
+        other: f64,
+    },
+}
+
+/// Parameters of each agent in place of those of the environment, drawn from their
+/// distributions when the agents are set up, where `None` leaves the agents with the
+/// parameter of the environment
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AgentParams {
+    /// Factor within `[0, 1]` of the probability of the agent being infected
+    pub susceptibility: Option<AgentDistribution>,
+    /// Probability of death of the agent in place of [`SimulationParams::p_death`]
+    pub p_death: Option<AgentDistribution>,
+    /// Duration of the infections of the agent in place of [`SimulationParams::duration`],
+    /// rounded to the nearest tick
+    pub duration: Option<AgentDistribution>,
+}
+
+/// Detection of the infection of agents, e.g. by testing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DetectionParams {
+    /// Probability per tick of an undetected infected agent being detected
+    pub p_detect: f64,
+}
+
+/// Quarantine of infected agents, each of which is tested once, after it has been infectious
+/// for a while.
+///
+/// Unlike [isolation](SimulationParams::isolation), it does not depend on the compliance of
+/// the agents, and the quarantined agents have a [state](crate::julia_reimpl::AgentType::AgentQ)
+/// of their own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuarantineParams {
+    /// Ticks for which an infected agent is infectious before it is tested, where zero tests it
+    /// before it can infect anyone
+    pub detection_delay: usize,
+    /// Probability of the test detecting the infection, upon which the agent is quarantined
+    pub p_detect: f64,
+}
+
+/// Post-exposure prophylaxis, given to the susceptible agents sharing a cell with an infected
+/// agent at the moment its infection is detected.
+///
+/// Unlike immunization, the agents stay susceptible, only less so for a while.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PpeParams {
+    /// Number of ticks after the detection that the prophylaxis lasts, `ppe_duration`
+    pub duration: usize,
+    /// Factor of the probability of infection while protected, `ppe_efficacy_complement`,
+    /// where zero is perfect protection and one is no protection at all
+    pub efficacy_complement: f64,
+}
+
+/// Vectors, e.g. mosquitoes, that carry the infection between agents.
+///
+/// In this mode agents do not infect each other directly. A susceptible vector sharing a cell with
+/// an infected agent becomes infectious with probability `p_bite` per tick, and an infectious
+/// vector infects each susceptible agent in its cell with probability `p_transmit` per tick.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorParams {
+    /// Number of vectors, which is kept constant by the turnover
+    pub count: usize,
+    /// Probability of a vector moving in a tick, with the same step as the agents
+    pub p_move: f64,
+    /// Probability per tick of a susceptible vector becoming infectious when sharing a cell
+    /// with an infected agent
+    pub p_bite: f64,
+    /// Probability per tick of an infectious vector infecting a susceptible agent in its cell
+    pub p_transmit: f64,
+    /// Ticks that a vector stays infectious, after which it is spent, or `None` for its
+    /// whole life
+    pub infectious_duration: Option<usize>,
+    /// Probability per tick of a vector dying and being replaced by a susceptible one at a
+    /// uniformly chosen cell
+    pub turnover: f64,
+}
+
+/// Infections introduced at a given tick, see [`SimulationParams::seeding`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeedingEntry {
+    /// Tick at which the infections are introduced, where zero is the start of the epidemic
+    pub tick: usize,
+    /// Number of susceptible agents to infect
+    pub count: usize,
+    /// Where the infected agents are chosen from
+    pub location: SeedLocation,
+}
+
+/// Vaccination campaign at a given tick, see [`SimulationParams::vaccination`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VaccinationEntry {
+    /// Tick at which the campaign takes place, after the agents have moved
+    pub tick: usize,
+    /// Fraction of the agents susceptible at that tick that are vaccinated
+    pub fraction: f64,
+}
+
+/// Number of ticks an agent stays infectious, drawn anew for each of its infections
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DurationModel {
+    /// The same duration for every infection, as in the blogpost
+    Fixed(usize),
+    /// Uniformly at random from `min` to `max`, inclusively
+    Uniform { min: usize, max: usize },
+    /// Recovering or dying with probability `p` in each tick after becoming infectious,
+    /// which is within (0, 1]
+    Geometric { p: f64 },
+}
+
+impl DurationModel {
+    /// Expected number of ticks an agent stays infectious
+    #[must_use]
+    pub fn mean(&self) -> f64 {
+        match *self {
+            DurationModel::Fixed(duration) => duration as f64,
+            DurationModel::Uniform { min, max } => (min + max) as f64 / 2.0,
+            DurationModel::Geometric { p } => 1.0 / p,
+        }
+    }
+}
+
+/// Placement of the initially infected agents, while the susceptible agents are placed
+/// uniformly at random
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SeedingStrategy {
+    /// Uniformly at random like the susceptible agents, as in the blogpost
+    Random,
+    /// Uniformly at random within Chebyshev distance `radius` of `center`, which must lie
+    /// within the grid without wrapping around it
+    Clustered {
+        center: (usize, usize),
+        radius: usize,
+    },
+    /// At the given cells, one for each initially infected agent
+    Explicit(Vec<(usize, usize)>),
+}
+
+impl Default for SeedingStrategy {
+    /// Random placement, such that the initial infections are scattered
+    fn default() -> Self {
+        SeedingStrategy::Random
+    }
+}
+
+/// Area in which agents are chosen to be infected by a [`SeedingEntry`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SeedLocation {
+    /// Susceptible agents anywhere on the grid
+    Anywhere,
+    /// Susceptible agents within Chebyshev distance `radius` of `center`, wrapping around the grid
+    Around {
+        center: (usize, usize),
+        radius: usize,
+    },
+}
+
+impl Default for SimulationParams {
+    /// The scenario presented in the blogpost, see [`blog_default`](crate::presets::blog_default).
+    fn default() -> Self {
+        crate::presets::blog_default()
+    }
+}
+
+impl SimulationParams {
+    /// Mean number of agents per cell
+    #[must_use]
+    pub fn density(&self) -> f64 {
+        self.n as f64 / (self.xdim * self.ydim) as f64
+    }
+
+    /// Number of cells within the [infection radius](SimulationParams::infection_radius) of
+    /// any cell, including itself
+    #[must_use]
+    pub fn neighbourhood_cells(&self) -> usize {
+        let side = 2 * self.infection_radius + 1;
+        side.min(self.xdim) * side.min(self.ydim)
+    }
+
+    /// Expected number of ticks an infected agent is infectious, from the duration model if
+    /// there is one
+    #[must_use]
+    pub fn mean_duration(&self) -> f64 {
+        self.duration_model
+            .as_ref()
+            .map_or(self.duration as f64, DurationModel::mean)
+    }
+
+    /// Heuristic for the expected number of secondary infections caused by a single case.
+    ///
+    /// An infected agent is infectious for its mean duration in ticks, and in each tick the
+    /// cells within its infection radius hold `density` other agents each on average, each of
+    /// which is infected with probability `p_infect`.
+    #[must_use]
+    pub fn expected_r0(&self) -> f64 {
+        self.p_infect * self.density() * self.neighbourhood_cells() as f64 * self.mean_duration()
+    }
+
+    /// Check that the model can be run with these parameters.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(dt) = self.dt {
+            if !(dt > 0.0 && dt.is_finite()) {
+                return Err(ConfigError::InvalidTimeStep { dt });
+            }
+        }
+        if self.xdim == 0 || self.ydim == 0 {
+            return Err(ConfigError::EmptyGrid {
+                xdim: self.xdim,
+                ydim: self.ydim,
+            });
+        }
+        if self.infected > self.n {
+            return Err(ConfigError::InfectedExceedsPopulation {
+                infected: self.infected,
+                n: self.n,
+            });
+        }
+        if !(0.0..=1.0).contains(&self.p_death) {
+            return Err(ConfigError::InvalidProbability {
+                name: "p_death",
+                value: self.p_death,
+            });
+        }
+        check_probability("p_infect", self.p_infect)?;
+        check_probability("p_move", self.p_move)?;
+        if !(0.0..=1.0).contains(&self.p_spillover) {
+            return Err(ConfigError::InvalidProbability {
+                name: "p_spillover",
+                value: self.p_spillover,
+            });
+        }
+        if let Some(detection) = &self.detection {
+            check_probability("detection.p_detect", detection.p_detect)?;
+        }
+        if let Some(ppe) = &self.ppe {
+            check_probability("ppe.efficacy_complement", ppe.efficacy_complement)?;
+            if self.detection.is_none() {
+                return Err(ConfigError::Requires {
+                    name: "ppe",
+                    requires: "detection",
+                });
+            }
+        }
+        if let Some(quarantine) = &self.quarantine {
+            check_probability("quarantine.p_detect", quarantine.p_detect)?;
+        }
+        if self.isolation && self.detection.is_none() {
+            return Err(ConfigError::Requires {
+                name: "isolation",
+                requires: "detection",
+            });
+        }
+        match self.compliance {
+            Compliance::Constant(value) => check_probability("compliance", value)?,
+            Compliance::Uniform { low, high } => {
+                check_probability("compliance.low", low)?;
+                check_probability("compliance.high", high)?;
+                if low > high {
+                    return Err(ConfigError::InvalidRange {
+                        name: "compliance",
+                        low,
+                        high,
+                    });
+                }
+            }
+            Compliance::Beta { alpha, beta } => {
+                for &(name, value) in &[("compliance.alpha", alpha), ("compliance.beta", beta)] {
+                    if value <= 0.0 || !value.is_finite() {
+                        return Err(ConfigError::InvalidShape { name, value });
+                    }
+                }
+            }
+        }
+        match self.duration_model {
+            None | Some(DurationModel::Fixed(_)) => {}
+            Some(DurationModel::Uniform { min, max }) => {
+                if min > max {
+                    return Err(ConfigError::InvalidRange {
+                        name: "duration_model",
+                        low: min as f64,
+                        high: max as f64,
+                    });
+                }
+            }
+            Some(DurationModel::Geometric { p }) => {
+                if !(p > 0.0 && p <= 1.0) {
+                    return Err(ConfigError::InvalidProbability {
+                        name: "duration_model.p",
+                        value: p,
+                    });
+                }
+            }
+        }
+        let heterogeneity = &self.heterogeneity;
+        for &(name, distribution, check) in &[
+            (
+                "heterogeneity.susceptibility",
+                &heterogeneity.susceptibility,
+                check_probability as fn(&'static str, f64) -> Result<(), ConfigError>,
+            ),
+            (
+                "heterogeneity.p_death",
+                &heterogeneity.p_death,
+                check_probability,
+            ),
+            (
+                "heterogeneity.duration",
+                &heterogeneity.duration,
+                check_rate,
+            ),
+        ] {
+            match *distribution {
+                Some(AgentDistribution::Constant(value)) => check(name, value)?,
+                Some(AgentDistribution::Uniform { low, high }) => {
+                    check(name, low)?;
+                    check(name, high)?;
+                    if low > high {
+                        return Err(ConfigError::InvalidRange { name, low, high });
+                    }
+                }
+                Some(AgentDistribution::TwoGroup {
+                    fraction,
+                    high_risk,
+                    other,
+                }) => {
+                    check_probability("heterogeneity.fraction", fraction)?;
+                    check(name, high_risk)?;
+                    check(name, other)?;
+                }
+                None => {}
+            }
+        }
+        if let Some(lockdown) = &self.lockdown {
+            check_probability("lockdown.p_move", lockdown.p_move)?;
+        }
+        check_probability("essential_fraction", self.essential_fraction)?;
+        match self.dose_severity {
+            DoseSeverity::Constant(multiplier) => check_rate("dose_severity", multiplier)?,
+            DoseSeverity::Logistic {
+                max,
+                midpoint,
+                steepness,
+            } => {
+                check_rate("dose_severity.max", max)?;
+                for &(name, value) in &[
+                    ("dose_severity.midpoint", midpoint),
+                    ("dose_severity.steepness", steepness),
+                ] {
+                    if !value.is_finite() {
+                        return Err(ConfigError::InvalidRate { name, value });
+                    }
+                }
+            }
+        }
+        for &(x, y) in &self.reservoirs {
+            if x >= self.xdim || y >= self.ydim {
+                return Err(ConfigError::OutOfBounds { x, y });
+            }
+        }
+        if let Some(vectors) = &self.vectors {
+            for &(name, value) in &[
+                ("vectors.p_move", vectors.p_move),
+                ("vectors.p_bite", vectors.p_bite),
+                ("vectors.p_transmit", vectors.p_transmit),
+                ("vectors.turnover", vectors.turnover),
+            ] {
+                check_probability(name, value)?;
+            }
+        }
+        for stratification in &self.stratify {
+            if let Stratification::Age { bounds } = stratification {
+                if let Some(pair) = bounds.windows(2).find(|pair| pair[0] >= pair[1]) {
+                    return Err(ConfigError::InvalidRange {
+                        name: "stratify.age",
+                        low: f64::from(pair[0]),
+                        high: f64::from(pair[1]),
+                    });
+                }
+            }
+        }
+        for entry in &self.vaccination {
+            check_probability("vaccination.fraction", entry.fraction)?;
+        }
+        match &self.initial_seeding {
+            SeedingStrategy::Random => {}
+            &SeedingStrategy::Clustered { center, radius } => {
+                if center.0 < radius
+                    || center.1 < radius
+                    || center.0 + radius >= self.xdim
+                    || center.1 + radius >= self.ydim
+                {
+                    return Err(ConfigError::ClusterExceedsGrid { center, radius });
+                }
+            }
+            SeedingStrategy::Explicit(cells) => {
+                if cells.len() != self.infected {
+                    return Err(ConfigError::PlacementMismatch {
+                        cells: cells.len(),
+                        infected: self.infected,
+                    });
+                }
+                if let Some(&(x, y)) = cells
+                    .iter()
+                    .find(|&&(x, y)| x >= self.xdim || y >= self.ydim)
+                {
+                    return Err(ConfigError::OutOfBounds { x, y });
+                }
+            }
+        }
+        for entry in &self.seeding {
+            if let SeedLocation::Around { center, .. } = entry.location {
+                if center.0 >= self.xdim || center.1 >= self.ydim {
+                    return Err(ConfigError::OutOfBounds {
+                        x: center.0,
+                        y: center.1,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Soft warnings for configurations that are valid, yet likely to be a mistake.
+    ///
+    /// The parameters are assumed to have passed [`SimulationParams::validate`].
+    #[must_use]
+    pub fn warnings(&self) -> Vec<ConfigWarning> {
+        let mut warnings = Vec::new();
+
+        if self.duration == 0 {
+            warnings.push(ConfigWarning::ZeroDuration);
+        } else if self.n > 0 && self.expected_r0() < FIZZLE_R0 {
+            warnings.push(ConfigWarning::LikelyFizzle {
+                expected_r0: self.expected_r0(),
+            });
+        }
+        if self.density() >= SATURATED_DENSITY {
+            warnings.push(ConfigWarning::SaturatedGrid {
+                density: self.density(),
+            });
+        }
+        if self.n > 0 && self.infected == self.n {
+            warnings.push(ConfigWarning::AllInfected);
+        }
+        if self.immunity_duration.is_some() && self.max_ticks.is_none() {
+            warnings.push(ConfigWarning::EndlessWaning);
+        }
+        if let Some(max_ticks) = self.max_ticks {
+            if max_ticks < self.duration {
+                warnings.push(ConfigWarning::EndsBeforeRecovery {
+                    max_ticks,
+                    duration: self.duration,
+                });
+            }
+        }
+
+        warnings
+    }
+}
+
+/// Probability of an event with the given `rate` per day occurring within `dt` days
+#[must_use]
+pub fn rate_to_probability(rate: f64, dt: f64) -> f64 {
+    1.0 - (-rate * dt).exp()
+}
+
+/// Number of ticks of `dt` days each that is closest to `days`
+#[must_use]
+pub fn days_to_ticks(days: f64, dt: f64) -> usize {
+    (days / dt).round() as usize
+}
+
+/// Builder for [`SimulationParams`] starting from the blogpost scenario.
+///
+/// Parameters can either be given per tick, or as rates and durations in days together with a
+/// [time step](EnvironmentConfig::time_step), in which case they are converted to per-tick
+/// parameters when built.
+#[derive(Debug, Clone, Default)]
+pub struct EnvironmentConfig {
+    params: SimulationParams,
+    /// Duration of the infection in days
+    duration_days: Option<f64>,
+    /// Hazard of dying per day of infection
+    mortality_hazard: Option<f64>,
+    /// Rate of infection per day of sharing a cell with an infected agent
+    transmission_rate: Option<f64>,
+    /// Rate of moving per day
+    movement_rate: Option<f64>,
+}
+
+impl From<SimulationParams> for EnvironmentConfig {
+    /// Start building from existing parameters, e.g. one of the [presets](crate::presets).
+    fn from(params: SimulationParams) -> Self {
+        Self {
+            params,
+            duration_days: None,
+            mortality_hazard: None,
+            transmission_rate: None,
+            movement_rate: None,
+        }
+    }
+}
+
+impl EnvironmentConfig {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn population(mut self, n: usize) -> Self {
+        self.params.n = n;
+        self
+    }
+
+    #[must_use]
+    pub fn initial_infected(mut self, infected: usize) -> Self {
+        self.params.infected = infected;
+        self
+    }
+
+    #[must_use]
+    pub fn duration(mut self, duration: usize) -> Self {
+        self.params.duration = duration;
+        self
+    }
+
+    /// Draw the duration of each infection from `model`
+    #[must_use]
+    pub fn duration_model(mut self, model: DurationModel) -> Self {
+        self.params.duration_model = Some(model);
+        self
+    }
+
+    #[must_use]
+    pub fn p_death(mut self, p_death: f64) -> Self {
+        self.params.p_death = p_death;
+        self
+    }
+
+    /// Keep or remove the agents that die, see [`CorpseHandling`]
+    #[must_use]
+    pub fn corpse_handling(mut self, handling: CorpseHandling) -> Self {
+        self.params.corpse_handling = handling;
+        self
+    }
+
+    /// Process the agents in the order of their index or in a shuffled order, see
+    /// [`UpdateOrder`]
+    #[must_use]
+    pub fn update_order(mut self, order: UpdateOrder) -> Self {
+        self.params.update_order = order;
+        self
+    }
+
+    /// Infect susceptible agents with probability `p_infect` per infected cellmate and tick
+    #[must_use]
+    pub fn p_infect(mut self, p_infect: f64) -> Self {
+        self.params.p_infect = p_infect;
+        self
+    }
+
+    /// Infect susceptible agents within Chebyshev distance `radius` of an infected agent,
+    /// instead of only its cellmates
+    #[must_use]
+    pub fn infection_radius(mut self, radius: usize) -> Self {
+        self.params.infection_radius = radius;
+        self
+    }
+
+    /// Move each agent with probability `p_move` per tick
+    #[must_use]
+    pub fn p_move(mut self, p_move: f64) -> Self {
+        self.params.p_move = p_move;
+        self
+    }
+
+    #[must_use]
+    pub fn grid(mut self, xdim: usize, ydim: usize) -> Self {
+        self.params.xdim = xdim;
+        self.params.ydim = ydim;
+        self
+    }
+
+    /// Let the agents move for `ticks` before seeding the initial infections
+    #[must_use]
+    pub fn burn_in_ticks(mut self, ticks: usize) -> Self {
+        self.params.burn_in_ticks = ticks;
+        self
+    }
+
+    /// Keep agents infected during the run exposed for `ticks` before they become infectious
+    #[must_use]
+    pub fn incubation(mut self, ticks: usize) -> Self {
+        self.params.incubation = ticks;
+        self
+    }
+
+    /// Let recovered agents become susceptible again once they have been immune for `ticks`
+    #[must_use]
+    pub fn immunity_duration(mut self, ticks: usize) -> Self {
+        self.params.immunity_duration = Some(ticks);
+        self
+    }
+
+    /// Stop a run at tick `ticks`, even if infected agents remain
+    #[must_use]
+    pub fn max_ticks(mut self, ticks: usize) -> Self {
+        self.params.max_ticks = Some(ticks);
+        self
+    }
+
+    /// Infect `count` susceptible agents at `location` when reaching `tick`
+    #[must_use]
+    pub fn seed_at(mut self, tick: usize, count: usize, location: SeedLocation) -> Self {
+        self.params.seeding.push(SeedingEntry {
+            tick,
+            count,
+            location,
+        });
+        self
+    }
+
+    /// Vaccinate `fraction` of the susceptible agents when reaching `tick`
+    #[must_use]
+    pub fn vaccinate_at(mut self, tick: usize, fraction: f64) -> Self {
+        self.params
+            .vaccination
+            .push(VaccinationEntry { tick, fraction });
+        self
+    }
+
+    /// Spread the infection through vectors instead of directly between agents
+    #[must_use]
+    pub fn vectors(mut self, vectors: VectorParams) -> Self {
+        self.params.vectors = Some(vectors);
+        self
+    }
+
+    /// Infect susceptible agents in the reservoir `cells` by spillover with probability
+    /// `p_spillover` per tick
+    #[must_use]
+    pub fn reservoirs(mut self, cells: Vec<(usize, usize)>, p_spillover: f64) -> Self {
+        self.params.reservoirs = cells;
+        self.params.p_spillover = p_spillover;
+        self
+    }
+
+    /// Detect each infected agent with probability `p_detect` per tick
+    #[must_use]
+    pub fn detection(mut self, p_detect: f64) -> Self {
+        self.params.detection = Some(DetectionParams { p_detect });
+        self
+    }
+
+    /// Quarantine each infected agent with probability `p_detect` once it has been infectious
+    /// for `detection_delay` ticks
+    #[must_use]
+    pub fn quarantine(mut self, detection_delay: usize, p_detect: f64) -> Self {
+        self.params.quarantine = Some(QuarantineParams {
+            detection_delay,
+            p_detect,
+        });
+        self
+    }
+
+    /// Give prophylaxis to the susceptible cellmates of detected agents for `duration` ticks,
+    /// multiplying their probability of infection by `efficacy_complement`
+    #[must_use]
+    pub fn ppe(mut self, duration: usize, efficacy_complement: f64) -> Self {
+        self.params.ppe = Some(PpeParams {
+            duration,
+            efficacy_complement,
+        });
+        self
+    }
+
+    /// Isolate detected agents, each with a probability of its compliance
+    #[must_use]
+    pub fn isolation(mut self) -> Self {
+        self.params.isolation = true;
+        self
+    }
+
+    /// Bound the grid by `boundary` instead of wrapping it around as a torus
+    #[must_use]
+    pub fn boundary(mut self, boundary: Boundary) -> Self {
+        self.params.boundary = boundary;
+        self
+    }
+
+    /// Place the initially infected agents by `strategy`
+    #[must_use]
+    pub fn initial_seeding(mut self, strategy: SeedingStrategy) -> Self {
+        self.params.initial_seeding = strategy;
+        self
+    }
+
+    /// Draw the compliance of each agent from `compliance`
+    #[must_use]
+    pub fn compliance(mut self, compliance: Compliance) -> Self {
+        self.params.compliance = compliance;
+        self
+    }
+
+    /// Draw the parameters of each agent from `heterogeneity`, in place of those of the
+    /// environment
+    #[must_use]
+    pub fn heterogeneity(mut self, heterogeneity: AgentParams) -> Self {
+        self.params.heterogeneity = heterogeneity;
+        self
+    }
+
+    /// Multiply `p_death` by `dose_severity` of the infecting dose
+    #[must_use]
+    pub fn dose_severity(mut self, dose_severity: DoseSeverity) -> Self {
+        self.params.dose_severity = dose_severity;
+        self
+    }
+
+    /// Restrict the movement of agents that are not essential workers to `p_move` per tick,
+    /// from tick `start` up to, but excluding, `end`
+    #[must_use]
+    pub fn lockdown(mut self, start: usize, end: usize, p_move: f64) -> Self {
+        self.params.lockdown = Some(Lockdown { start, end, p_move });
+        self
+    }
+
+    /// Make `fraction` of the agents essential workers, exempt from the lockdown
+    #[must_use]
+    pub fn essential_fraction(mut self, fraction: f64) -> Self {
+        self.params.essential_fraction = fraction;
+        self
+    }
+
+    /// Number of days per tick, defaults to one
+    #[must_use]
+    pub fn time_step(mut self, dt: f64) -> Self {
+        self.params.dt = Some(dt);
+        self
+    }
+
+    /// Duration of the infection in days, rounded to the nearest number of ticks.
+    ///
+    /// Overrides [`EnvironmentConfig::duration`].
+    #[must_use]
+    pub fn duration_days(mut self, days: f64) -> Self {
+        self.duration_days = Some(days);
+        self
+    }
+
+    /// Hazard of dying per day of infection.
+    ///
+    /// The probability of death at the end of the infection is then
+    /// `1 - exp(-hazard * duration * dt)`, and overrides [`EnvironmentConfig::p_death`].
+    #[must_use]
+    pub fn mortality_hazard(mut self, hazard: f64) -> Self {
+        self.mortality_hazard = Some(hazard);
+        self
+    }
+
+    /// Rate of infection per day of sharing a cell with an infected agent.
+    ///
+    /// The probability of infection per tick is then `1 - exp(-rate * dt)`, which overrides
+    /// [`EnvironmentConfig::p_infect`].
+    #[must_use]
+    pub fn transmission_rate(mut self, rate: f64) -> Self {
+        self.transmission_rate = Some(rate);
+        self
+    }
+
+    /// Rate of moving per day of each agent.
+    ///
+    /// The probability of moving per tick is then `1 - exp(-rate * dt)`, which overrides
+    /// [`EnvironmentConfig::p_move`].
+    #[must_use]
+    pub fn movement_rate(mut self, rate: f64) -> Self {
+        self.movement_rate = Some(rate);
+        self
+    }
+
+    /// Validate the configuration, returning the parameters alongside any feasibility warnings.
+    pub fn build_params(mut self) -> Result<(SimulationParams, Vec<ConfigWarning>), ConfigError> {
+        let dt = self.params.dt.unwrap_or(1.0);
+        if let Some(days) = self.duration_days {
+            check_rate("duration_days", days)?;
+            self.params.duration = days_to_ticks(days, dt);
+        }
+        if let Some(hazard) = self.mortality_hazard {
+            check_rate("mortality_hazard", hazard)?;
+            self.params.p_death = rate_to_probability(hazard, self.params.duration as f64 * dt);
+        }
+        if let Some(rate) = self.transmission_rate {
+            check_rate("transmission_rate", rate)?;
+            self.params.p_infect = rate_to_probability(rate, dt);
+        }
+        if let Some(rate) = self.movement_rate {
+            check_rate("movement_rate", rate)?;
+            self.params.p_move = rate_to_probability(rate, dt);
+        }
+        self.params.validate()?;
+        let warnings = self.params.warnings();
+        Ok((self.params, warnings))
+    }
+
+    /// Validate the configuration and set up the environment it describes.
+    ///
+    /// Feasibility warnings are discarded, see [`EnvironmentConfig::build_params`] to inspect them.
+    pub fn build(self) -> Result<Environment, ConfigError> {
+        let (params, _) = self.build_params()?;
+        Ok(Environment::from_params(&params))
+    }
+}
+
+fn check_probability(name: &'static str, value: f64) -> Result<(), ConfigError> {
+    if (0.0..=1.0).contains(&value) {
+        Ok(())
+    } else {
+        Err(ConfigError::InvalidProbability { name, value })
+    }
+}
+
+fn check_rate(name: &'static str, value: f64) -> Result<(), ConfigError> {
+    if value >= 0.0 && value.is_finite() {
+        Ok(())
+    } else {
+        Err(ConfigError::InvalidRate { name, value })
+    }
+}
+
+/// Configurations that the model cannot be run with
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// At least one of the grid dimensions is zero
+    EmptyGrid { xdim: usize, ydim: usize },
+    /// More initially infected agents than there are agents
+    InfectedExceedsPopulation { infected: usize, n: usize },
+    /// A probability outside of `[0, 1]`
+    InvalidProbability { name: &'static str, value: f64 },
+    /// A rate or duration that is negative or not finite
+    InvalidRate { name: &'static str, value: f64 },
+    /// A time step that is not positive
+    InvalidTimeStep { dt: f64 },
+    /// A range whose lower bound exceeds its upper bound
+    InvalidRange {
+        name: &'static str,
+        low: f64,
+        high: f64,
+    },
+    /// A shape parameter of a distribution that is not positive and finite
+    InvalidShape { name: &'static str, value: f64 },
+    /// A location outside of the grid
+    OutOfBounds { x: usize, y: usize },
+    /// A parameter that has no effect without another one
+    Requires {
+        name: &'static str,
+        requires: &'static str,
+    },
+    /// A parameter that cannot change once the environment is set up
+    SetupOnly { name: &'static str },
+    /// A cluster of initial infections reaching beyond the grid
+    ClusterExceedsGrid {
+        center: (usize, usize),
+        radius: usize,
+    },
+    /// Explicit cells of the initial infections that are not one for each of them
+    PlacementMismatch { cells: usize, infected: usize },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::EmptyGrid { xdim, ydim } => {
+                write!(f, "grid of size {}×{} has no cells", xdim, ydim)
+            }
+            ConfigError::InfectedExceedsPopulation { infected, n } => write!(
+                f,
+                "{} initially infected agents exceeds the population of {}",
+                infected, n
+            ),
+            ConfigError::InvalidProbability { name, value } => {
+                write!(f, "`{}` must be within [0, 1], got {}", name, value)
+            }
+            ConfigError::InvalidRate { name, value } => {
+                write!(
+                    f,
+                    "`{}` must be non-negative and finite, got {}",
+                    name, value
+                )
+            }
+            ConfigError::InvalidTimeStep { dt } => {
+                write!(f, "time step must be positive, got {}", dt)
+            }
+            ConfigError::InvalidRange { name, low, high } => {
+                write!(f, "`{}` has a lower bound {} above {}", name, low, high)
+            }
+            ConfigError::InvalidShape { name, value } => {
+                write!(f, "`{}` must be positive and finite, got {}", name, value)
+            }
+            ConfigError::OutOfBounds { x, y } => {
+                write!(f, "location ({}, {}) is outside of the grid", x, y)
+            }
+            ConfigError::Requires { name, requires } => {
+                write!(f, "`{}` requires `{}` to be set", name, requires)
+            }
+            ConfigError::SetupOnly { name } => {
+                write!(
+                    f,
+                    "`{}` cannot change after the environment is set up",
+                    name
+                )
+            }
+            ConfigError::ClusterExceedsGrid { center, radius } => write!(
+                f,
+                "cluster of radius {} around ({}, {}) reaches beyond the grid",
+                radius, center.0, center.1
+            ),
+            ConfigError::PlacementMismatch { cells, infected } => write!(
+                f,
+                "{} cells given for {} initially infected agents",
+                cells, infected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Configurations that are valid, but almost certainly not what was intended
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigWarning {
+    /// Expected number of secondary infections per case is well below one
+    LikelyFizzle { expected_r0: f64 },
+    /// So many agents per cell that the grid is effectively a single cell
+    SaturatedGrid { density: f64 },
+    /// Infected agents recover (or die) before they can infect anyone
+    ZeroDuration,
+    /// There are no susceptible agents to infect
+    AllInfected,
+    /// The run stops before the initially infected agents can recover or die
+    EndsBeforeRecovery { max_ticks: usize, duration: usize },
+    /// Immunity wanes without a maximum number of ticks, such that the run may never end
+    EndlessWaning,
+}
+
+impl fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigWarning::LikelyFizzle { expected_r0 } => write!(
+                f,
+                "expected secondary infections per case is {:.3}, the epidemic will likely fizzle out",
+                expected_r0
+            ),
+            ConfigWarning::SaturatedGrid { density } => write!(
+                f,
+                "{:.1} agents per cell on average, the grid is effectively a single cell",
+                density
+            ),
+            ConfigWarning::ZeroDuration => {
+                write!(f, "duration is zero, infected agents never infect anyone")
+            }
+            ConfigWarning::AllInfected => {
+                write!(f, "every agent is initially infected, no one is susceptible")
+            }
+            ConfigWarning::EndsBeforeRecovery {
+                max_ticks,
+                duration,
+            } => write!(
+                f,
+                "the run stops after {} ticks, before any infection of {} ticks resolves",
+                max_ticks, duration
+            ),
+            ConfigWarning::EndlessWaning => write!(
+                f,
+                "immunity wanes and max_ticks is not set, the run may never end"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_has_no_warnings() {
+        let (params, warnings) = EnvironmentConfig::new().build_params().unwrap();
+        assert_eq!(params, SimulationParams::default());
+        assert!(warnings.is_empty(), "{:?}", warnings);
+
+        let (_, warnings) = EnvironmentConfig::new()
+            .population(500)
+            .initial_infected(5)
+            .duration(10)
+            .grid(30, 30)
+            .build_params()
+            .unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn test_expected_r0() {
+        // 0.2 agents per cell, infectious for 21 ticks
+        let r0 = SimulationParams::default().expected_r0();
+        assert!((r0 - 4.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_expected_r0_scales_with_p_infect() {
+        let params = SimulationParams {
+            p_infect: 0.25,
+            ..SimulationParams::default()
+        };
+        assert!((params.expected_r0() - 1.05).abs() < 1e-12);
+        // nine cells within a radius of one
+        let params = SimulationParams {
+            infection_radius: 1,
+            ..params
+        };
+        assert!((params.expected_r0() - 9.45).abs() < 1e-12);
+        // the neighbourhood does not exceed the grid
+        let params = SimulationParams {
+            infection_radius: 100,
+            xdim: 10,
+            ydim: 4,
+            ..params
+        };
+        assert_eq!(params.neighbourhood_cells(), 40);
+        let (_, warnings) = EnvironmentConfig::new()
+            .p_infect(0.1)
+            .build_params()
+            .unwrap();
+        assert!(matches!(
+            warnings[..],
+            [ConfigWarning::LikelyFizzle { expected_r0 }] if expected_r0 < FIZZLE_R0
+        ));
+    }
+
+    #[test]
+    fn test_warning_likely_fizzle() {
+        let (_, warnings) = EnvironmentConfig::new()
+            .population(100)
+            .duration(5)
+            .build_params()
+            .unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            matches!(warnings[0], ConfigWarning::LikelyFizzle { expected_r0 } if expected_r0 < FIZZLE_R0)
+        );
+    }
+
+    #[test]
+    fn test_warning_saturated_grid() {
+        let (_, warnings) = EnvironmentConfig::new()
+            .grid(10, 10)
+            .build_params()
+            .unwrap();
+        assert_eq!(
+            warnings,
+            vec![ConfigWarning::SaturatedGrid { density: 20.0 }]
+        );
+    }
+
+    #[test]
+    fn test_warning_zero_duration() {
+        let (_, warnings) = EnvironmentConfig::new().duration(0).build_params().unwrap();
+        assert_eq!(warnings, vec![ConfigWarning::ZeroDuration]);
+    }
+
+    #[test]
+    fn test_warning_all_infected() {
+        let (_, warnings) = EnvironmentConfig::new()
+            .initial_infected(2000)
+            .build_params()
+            .unwrap();
+        assert_eq!(warnings, vec![ConfigWarning::AllInfected]);
+    }
+
+    #[test]
+    fn test_warning_ends_before_recovery() {
+        let (_, warnings) = EnvironmentConfig::new()
+            .max_ticks(20)
+            .build_params()
+            .unwrap();
+        assert_eq!(
+            warnings,
+            vec![ConfigWarning::EndsBeforeRecovery {
+                max_ticks: 20,
+                duration: 21
+            }]
+        );
+        let (_, warnings) = EnvironmentConfig::new()
+            .max_ticks(21)
+            .build_params()
+            .unwrap();
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_warning_endless_waning() {
+        let (_, warnings) = EnvironmentConfig::new()
+            .immunity_duration(50)
+            .build_params()
+            .unwrap();
+        assert_eq!(warnings, vec![ConfigWarning::EndlessWaning]);
+        let (_, warnings) = EnvironmentConfig::new()
+            .immunity_duration(50)
+            .max_ticks(500)
+            .build_params()
+            .unwrap();
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_rate_conversions() {
+        assert_eq!(rate_to_probability(0.0, 1.0), 0.0);
+        // 1 - e^{-1}
+        assert!((rate_to_probability(0.5, 2.0) - 0.632_120_558_828_557_7).abs() < 1e-12);
+        // 1 - e^{-0.05}
+        assert!((rate_to_probability(0.1, 0.5) - 0.048_770_575_499_285_984).abs() < 1e-12);
+
+        assert_eq!(days_to_ticks(21.0, 1.0), 21);
+        assert_eq!(days_to_ticks(21.0, 0.5), 42);
+        assert_eq!(days_to_ticks(10.0, 3.0), 3);
+        assert_eq!(days_to_ticks(10.5, 3.0), 4);
+    }
+
+    #[test]
+    fn test_rate_based_params() {
+        let (params, _) = EnvironmentConfig::new()
+            .time_step(0.5)
+            .duration_days(21.0)
+            .mortality_hazard(0.01)
+            .build_params()
+            .unwrap();
+        assert_eq!(params.dt, Some(0.5));
+        assert_eq!(params.duration, 42);
+        assert!((params.p_death - rate_to_probability(0.01, 21.0)).abs() < 1e-12);
+
+        let (params, _) = EnvironmentConfig::new()
+            .time_step(0.5)
+            .transmission_rate(0.5)
+            .movement_rate(2.0)
+            .build_params()
+            .unwrap();
+        // 1 - e^{-0.25} and 1 - e^{-1}
+        assert!((params.p_infect - 0.221_199_216_928_595).abs() < 1e-12);
+        assert!((params.p_move - 0.632_120_558_828_557_7).abs() < 1e-12);
+
+        // the raw per-tick interface is untouched
+        let (params, _) = EnvironmentConfig::new().build_params().unwrap();
+        assert_eq!(params.dt, None);
+        assert_eq!(params.duration, 21);
+        assert_eq!((params.p_infect, params.p_move), (1.0, 1.0));
+    }
+
+    #[test]
+    fn test_attack_rate_is_invariant_to_time_step() {
+        // the same epidemic in days, resolved by ticks of a day and of half a day
+        let attack_rate = |dt: f64| {
+            let (params, _) = EnvironmentConfig::new()
+                .population(300)
+                .initial_infected(3)
+                .grid(18, 18)
+                .time_step(dt)
+                .duration_days(10.0)
+                .transmission_rate(0.3)
+                .movement_rate(1.0)
+                .build_params()
+                .unwrap();
+            let replicates = 24;
+            (0..replicates)
+                .map(|seed| {
+                    let run = crate::result::run(&SimulationParams {
+                        seed: Some(seed),
+                        ..params.clone()
+                    });
+                    1.0 - *run.record.susceptible.last().unwrap() as f64 / 300.0
+                })
+                .sum::<f64>()
+                / replicates as f64
+        };
+        let (day, half_day) = (attack_rate(1.0), attack_rate(0.5));
+        assert!((0.2..0.95).contains(&day), "{}", day);
+        assert!((day - half_day).abs() < 0.1, "{} {}", day, half_day);
+    }
+
+    #[test]
+    fn test_invalid_rates() {
+        for &dt in [0.0, -1.0, f64::NAN, f64::INFINITY].iter() {
+            assert!(matches!(
+                EnvironmentConfig::new().time_step(dt).build_params(),
+                Err(ConfigError::InvalidTimeStep { .. })
+            ));
+        }
+        assert!(matches!(
+            EnvironmentConfig::new().duration_days(-1.0).build_params(),
+            Err(ConfigError::InvalidRate {
+                name: "duration_days",
+                ..
+            })
+        ));
+        assert!(matches!(
+            EnvironmentConfig::new()
+                .mortality_hazard(f64::NAN)
+                .build_params(),
+            Err(ConfigError::InvalidRate {
+                name: "mortality_hazard",
+                ..
+            })
+        ));
+        assert!(matches!(
+            EnvironmentConfig::new()
+                .transmission_rate(-0.5)
+                .build_params(),
+            Err(ConfigError::InvalidRate {
+                name: "transmission_rate",
+                ..
+            })
+        ));
+        assert!(matches!(
+            EnvironmentConfig::new()
+                .movement_rate(f64::INFINITY)
+                .build_params(),
+            Err(ConfigError::InvalidRate {
+                name: "movement_rate",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_seeding_out_of_bounds() {
+        let location = SeedLocation::Around {
+            center: (100, 5),
+            radius: 1,
+        };
+        assert_eq!(
+            EnvironmentConfig::new()
+                .seed_at(10, 5, location)
+                .build_params()
+                .unwrap_err(),
+            ConfigError::OutOfBounds { x: 100, y: 5 }
+        );
+    }
+
+    #[test]
+    fn test_invalid_vaccination() {
+        assert_eq!(
+            EnvironmentConfig::new()
+                .vaccinate_at(10, 1.5)
+                .build_params()
+                .unwrap_err(),
+            ConfigError::InvalidProbability {
+                name: "vaccination.fraction",
+                value: 1.5
+            }
+        );
+        assert!(EnvironmentConfig::new()
+            .vaccinate_at(10, 1.0)
+            .vaccinate_at(5, 0.0)
+            .build_params()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_invalid_heterogeneity() {
+        let config = |heterogeneity| EnvironmentConfig::new().heterogeneity(heterogeneity);
+        assert_eq!(
+            config(AgentParams {
+                p_death: Some(AgentDistribution::TwoGroup {
+                    fraction: 0.1,
+                    high_risk: 1.2,
+                    other: 0.0,
+                }),
+                ..AgentParams::default()
+            })
+            .build_params()
+            .unwrap_err(),
+            ConfigError::InvalidProbability {
+                name: "heterogeneity.p_death",
+                value: 1.2
+            }
+        );
+        assert_eq!(
+            config(AgentParams {
+                duration: Some(AgentDistribution::Uniform {
+                    low: 10.0,
+                    high: 5.0,
+                }),
+                ..AgentParams::default()
+            })
+            .build_params()
+            .unwrap_err(),
+            ConfigError::InvalidRange {
+                name: "heterogeneity.duration",
+                low: 10.0,
+                high: 5.0
+            }
+        );
+        // durations are not probabilities
+        assert!(config(AgentParams {
+            duration: Some(AgentDistribution::Constant(30.0)),
+            susceptibility: Some(AgentDistribution::Uniform {
+                low: 0.0,
+                high: 1.0
+            }),
+            ..AgentParams::default()
+        })
+        .build_params()
+        .is_ok());
+    }
+
+    #[test]
+    fn test_invalid_vector_params() {
+        let vectors = VectorParams {
+            count: 10,
+            p_move: 0.5,
+            p_bite: 1.5,
+            p_transmit: 0.5,
+            infectious_duration: None,
+            turnover: 0.0,
+        };
+        assert_eq!(
+            EnvironmentConfig::new().vectors(vectors).build_params(),
+            Err(ConfigError::InvalidProbability {
+                name: "vectors.p_bite",
+                value: 1.5
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_reservoirs() {
+        assert_eq!(
+            EnvironmentConfig::new()
+                .reservoirs(vec![(3, 4)], 1.5)
+                .build_params(),
+            Err(ConfigError::InvalidProbability {
+                name: "p_spillover",
+                value: 1.5
+            })
+        );
+        assert_eq!(
+            EnvironmentConfig::new()
+                .reservoirs(vec![(3, 100)], 0.1)
+                .build_params(),
+            Err(ConfigError::OutOfBounds { x: 3, y: 100 })
+        );
+    }
+
+    #[test]
+    fn test_invalid_ppe() {
+        assert_eq!(
+            EnvironmentConfig::new().ppe(5, 0.2).build_params(),
+            Err(ConfigError::Requires {
+                name: "ppe",
+                requires: "detection"
+            })
+        );
+        assert_eq!(
+            EnvironmentConfig::new()
+                .detection(0.5)
+                .ppe(5, -0.1)
+                .build_params(),
+            Err(ConfigError::InvalidProbability {
+                name: "ppe.efficacy_complement",
+                value: -0.1
+            })
+        );
+        assert!(EnvironmentConfig::new()
+            .detection(0.5)
+            .ppe(5, 0.2)
+            .build_params()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_invalid_compliance() {
+        assert_eq!(
+            EnvironmentConfig::new().isolation().build_params(),
+            Err(ConfigError::Requires {
+                name: "isolation",
+                requires: "detection"
+            })
+        );
+        assert_eq!(
+            EnvironmentConfig::new()
+                .compliance(Compliance::Uniform {
+                    low: 0.8,
+                    high: 0.2
+                })
+                .build_params(),
+            Err(ConfigError::InvalidRange {
+                name: "compliance",
+                low: 0.8,
+                high: 0.2
+            })
+        );
+        assert_eq!(
+            EnvironmentConfig::new()
+                .compliance(Compliance::Beta {
+                    alpha: 0.0,
+                    beta: 2.0
+                })
+                .build_params(),
+            Err(ConfigError::InvalidShape {
+                name: "compliance.alpha",
+                value: 0.0
+            })
+        );
+        assert!(EnvironmentConfig::new()
+            .compliance(Compliance::Constant(0.5))
+            .detection(0.1)
+            .isolation()
+            .build_params()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_dose_severity_multiplier() {
+        assert_eq!(DoseSeverity::Constant(1.0).multiplier(7), 1.0);
+        let logistic = DoseSeverity::Logistic {
+            max: 2.0,
+            midpoint: 3.0,
+            steepness: 1.0,
+        };
+        assert_eq!(logistic.multiplier(3), 1.0);
+        assert!(logistic.multiplier(1) < logistic.multiplier(5));
+        assert_eq!(
+            EnvironmentConfig::new()
+                .dose_severity(DoseSeverity::Constant(-1.0))
+                .build_params(),
+            Err(ConfigError::InvalidRate {
+                name: "dose_severity",
+                value: -1.0
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_lockdown() {
+        assert_eq!(
+            EnvironmentConfig::new().lockdown(5, 10, 1.5).build_params(),
+            Err(ConfigError::InvalidProbability {
+                name: "lockdown.p_move",
+                value: 1.5
+            })
+        );
+        assert_eq!(
+            EnvironmentConfig::new()
+                .essential_fraction(-0.5)
+                .build_params(),
+            Err(ConfigError::InvalidProbability {
+                name: "essential_fraction",
+                value: -0.5
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            EnvironmentConfig::new()
+                .grid(0, 10)
+                .build_params()
+                .unwrap_err(),
+            ConfigError::EmptyGrid { xdim: 0, ydim: 10 }
+        );
+        assert_eq!(
+            EnvironmentConfig::new()
+                .population(5)
+                .initial_infected(6)
+                .build_params()
+                .unwrap_err(),
+            ConfigError::InfectedExceedsPopulation { infected: 6, n: 5 }
+        );
+        assert!(matches!(
+            EnvironmentConfig::new().p_death(1.5).build_params(),
+            Err(ConfigError::InvalidProbability {
+                name: "p_death",
+                ..
+            })
+        ));
+        assert!(matches!(
+            EnvironmentConfig::new().p_infect(-0.1).build_params(),
+            Err(ConfigError::InvalidProbability {
+                name: "p_infect",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_build_rejects_invalid() {
+        let rejected = |config: EnvironmentConfig| config.build().err().unwrap();
+        assert_eq!(
+            rejected(EnvironmentConfig::new().grid(10, 0)),
+            ConfigError::EmptyGrid { xdim: 10, ydim: 0 }
+        );
+        assert_eq!(
+            rejected(EnvironmentConfig::new().grid(0, 0)),
+            ConfigError::EmptyGrid { xdim: 0, ydim: 0 }
+        );
+        assert_eq!(
+            rejected(EnvironmentConfig::new().population(0).initial_infected(1)),
+            ConfigError::InfectedExceedsPopulation { infected: 1, n: 0 }
+        );
+        for &p_death in &[-0.05, 1.5, f64::NAN] {
+            assert!(matches!(
+                rejected(EnvironmentConfig::new().p_death(p_death)),
+                ConfigError::InvalidProbability {
+                    name: "p_death",
+                    ..
+                }
+            ));
+        }
+        assert!(matches!(
+            rejected(EnvironmentConfig::new().p_move(2.0)),
+            ConfigError::InvalidProbability { name: "p_move", .. }
+        ));
+        assert_eq!(
+            rejected(EnvironmentConfig::new().time_step(0.0)),
+            ConfigError::InvalidTimeStep { dt: 0.0 }
+        );
+        // a population entirely infected from the start is valid
+        assert!(EnvironmentConfig::new()
+            .population(10)
+            .initial_infected(10)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_build_round_trip() {
+        let environment = EnvironmentConfig::new()
+            .population(2000)
+            .initial_infected(10)
+            .duration(21)
+            .p_death(0.05)
+            .grid(100, 100)
+            .build()
+            .unwrap();
+        let tally = environment.get_statistics();
+        assert_eq!(
+            (
+                tally.susceptible,
+                tally.infected,
+                tally.recovered,
+                tally.dead
+            ),
+            (1990, 10, 0, 0)
+        );
+        assert_eq!(environment.iter_agents().count(), 2000);
+        assert!(environment
+            .iter_agents()
+            .all(|agent| agent.x < 100 && agent.y < 100));
+    }
+
+    #[test]
+    fn test_invalid_initial_seeding() {
+        let config = |strategy| {
+            EnvironmentConfig::new()
+                .grid(10, 10)
+                .initial_infected(2)
+                .initial_seeding(strategy)
+        };
+        assert!(config(SeedingStrategy::Clustered {
+            center: (2, 7),
+            radius: 2
+        })
+        .build_params()
+        .is_ok());
+        assert_eq!(
+            config(SeedingStrategy::Clustered {
+                center: (1, 5),
+                radius: 2
+            })
+            .build_params()
+            .unwrap_err(),
+            ConfigError::ClusterExceedsGrid {
+                center: (1, 5),
+                radius: 2
+            }
+        );
+        assert_eq!(
+            config(SeedingStrategy::Explicit(vec![(0, 0), (3, 10)]))
+                .build_params()
+                .unwrap_err(),
+            ConfigError::OutOfBounds { x: 3, y: 10 }
+        );
+        assert_eq!(
+            config(SeedingStrategy::Explicit(vec![(0, 0)]))
+                .build_params()
+                .unwrap_err(),
+            ConfigError::PlacementMismatch {
+                cells: 1,
+                infected: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_duration_model() {
+        let config = |model| EnvironmentConfig::new().duration_model(model);
+        let (params, _) = config(DurationModel::Geometric { p: 0.25 })
+            .build_params()
+            .unwrap();
+        assert_eq!(params.mean_duration(), 4.0);
+        assert_eq!(
+            config(DurationModel::Uniform { min: 2, max: 8 })
+                .build_params()
+                .unwrap()
+                .0
+                .mean_duration(),
+            5.0
+        );
+        assert_eq!(
+            config(DurationModel::Geometric { p: 0.0 })
+                .build_params()
+                .unwrap_err(),
+            ConfigError::InvalidProbability {
+                name: "duration_model.p",
+                value: 0.0
+            }
+        );
+        assert_eq!(
+            config(DurationModel::Uniform { min: 8, max: 2 })
+                .build_params()
+                .unwrap_err(),
+            ConfigError::InvalidRange {
+                name: "duration_model",
+                low: 8.0,
+                high: 2.0
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_quarantine() {
+        assert_eq!(
+            EnvironmentConfig::new()
+                .quarantine(3, 1.5)
+                .build_params()
+                .unwrap_err(),
+            ConfigError::InvalidProbability {
+                name: "quarantine.p_detect",
+                value: 1.5
+            }
+        );
+    }
+}
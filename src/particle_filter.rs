@@ -0,0 +1,617 @@
+//! Assimilation of observed case counts into an ensemble of running environments.
+//!
+//! Each particle is an [`Environment`] of the same scenario that is advanced tick by tick
+//! alongside the observations. At every observation, a particle is weighted by the
+//! [`Likelihood`] of the observed number of reported cases, given the number of cases the
+//! particle reported since the previous observation. Cases are reported as they are detected, see
+//! [`SimulationParams::detection`], or as they are infected if there is no detection. When the
+//! effective sample size of the weights drops too low, the particles are resampled.
+//!
+//! With a [`ParameterPrior`], each particle runs with its own value of a parameter, and the
+//! weights of the particles are a sample of the posterior of that parameter.
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::julia_reimpl::{Environment, TallyStates};
+use crate::params::{ConfigError, SimulationParams};
+use crate::sweep::SweepParameter;
+
+/// Distribution of the observed number of reported cases, given the number of cases reported
+/// by a particle as its mean
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Likelihood {
+    Poisson,
+    /// Negative binomial, with a variance of `mean + mean² / dispersion` that allows for
+    /// reporting that is noisier than the model of detection
+    NegativeBinomial {
+        dispersion: f64,
+    },
+}
+
+impl Likelihood {
+    /// Log of the probability of observing `k` cases, given the `mean`
+    fn log_probability(self, k: usize, mean: f64) -> f64 {
+        let log_factorial: f64 = (2..=k).map(|i| (i as f64).ln()).sum();
+        match self {
+            Likelihood::Poisson => k as f64 * mean.ln() - mean - log_factorial,
+            Likelihood::NegativeBinomial { dispersion } => {
+                // log of Γ(k + dispersion) / Γ(dispersion) / k!
+                let log_coefficient: f64 =
+                    (0..k).map(|i| (dispersion + i as f64).ln()).sum::<f64>() - log_factorial;
+                log_coefficient
+                    + dispersion * (dispersion / (dispersion + mean)).ln()
+                    + k as f64 * (mean / (dispersion + mean)).ln()
+            }
+        }
+    }
+}
+
+/// Rules of a [`ParticleFilter`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterOptions {
+    pub likelihood: Likelihood,
+    /// Fraction of the number of particles below which the effective sample size triggers
+    /// resampling, where 1 resamples at every observation
+    pub resample_threshold: f64,
+    /// Smallest expected number of reported cases of a particle, such that an observed case
+    /// is not impossible in a particle that reported none
+    pub reporting_floor: f64,
+}
+
+impl FilterOptions {
+    /// Check that the options give every particle a finite likelihood
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Likelihood::NegativeBinomial { dispersion } = self.likelihood {
+            if !(dispersion > 0.0 && dispersion.is_finite()) {
+                return Err(ConfigError::InvalidShape {
+                    name: "dispersion",
+                    value: dispersion,
+                });
+            }
+        }
+        if !(0.0..=1.0).contains(&self.resample_threshold) {
+            return Err(ConfigError::InvalidProbability {
+                name: "resample_threshold",
+                value: self.resample_threshold,
+            });
+        }
+        // without a floor, a particle that reported no cases has a likelihood of zero, or an
+        // undefined one if none were observed
+        if !(self.reporting_floor > 0.0 && self.reporting_floor.is_finite()) {
+            return Err(ConfigError::InvalidShape {
+                name: "reporting_floor",
+                value: self.reporting_floor,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for FilterOptions {
+    /// A negative binomial likelihood of dispersion 10, resampling below half the particles,
+    /// and an expectation of at least half a case
+    fn default() -> Self {
+        Self {
+            likelihood: Likelihood::NegativeBinomial { dispersion: 10.0 },
+            resample_threshold: 0.5,
+            reporting_floor: 0.5,
+        }
+    }
+}
+
+/// Uniform prior of a parameter that is perturbed across the particles
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterPrior {
+    pub parameter: SweepParameter,
+    pub low: f64,
+    pub high: f64,
+}
+
+/// Outcome of [`ParticleFilter::assimilate`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assimilation {
+    pub tick: usize,
+    /// Log of the mean likelihood of the observation across the weighted particles
+    pub log_likelihood: f64,
+    /// Effective sample size of the weights before any resampling
+    pub effective_sample_size: f64,
+    pub resampled: bool,
+}
+
+#[derive(Clone)]
+struct Particle {
+    environment: Environment,
+    /// Value of the perturbed parameter, if any
+    parameter: Option<f64>,
+}
+
+/// Filter of the state of an epidemic, see the [module documentation](self)
+pub struct ParticleFilter {
+    particles: Vec<Particle>,
+    /// Normalised weights of the particles
+    weights: Vec<f64>,
+    options: FilterOptions,
+    /// Whether cases are reported as they are detected, rather than as they are infected
+    detected: bool,
+    /// Tick of the latest observation
+    tick: usize,
+    rng: StdRng,
+}
+
+impl ParticleFilter {
+    /// Set up `n_particles` particles of the scenario `params` with equal weight.
+    ///
+    /// The `seed` drives all draws, i.e. the prior and resampling of the filter and the seed
+    /// of each particle, such that the same seed and observations give the same estimates.
+    /// Particles that are resampled more than once continue under seeds of their own.
+    ///
+    /// Fails if `params` are invalid.
+    ///
+    /// # Panics
+    ///
+    /// If there are no particles.
+    pub fn new(
+        params: &SimulationParams,
+        n_particles: usize,
+        seed: u64,
+    ) -> Result<Self, ConfigError> {
+        Self::with_prior(params, n_particles, seed, None)
+    }
+
+    /// Like [`ParticleFilter::new`], where each particle draws its value of the parameter
+    /// of `prior`, if any.
+    ///
+    /// Fails if `params` are invalid, or if the bounds of the prior are not finite, out of
+    /// order, or give invalid parameters.
+    ///
+    /// # Panics
+    ///
+    /// If there are no particles.
+    pub fn with_prior(
+        params: &SimulationParams,
+        n_particles: usize,
+        seed: u64,
+        prior: Option<ParameterPrior>,
+    ) -> Result<Self, ConfigError> {
+        assert!(n_particles > 0, "a particle filter needs particles");
+        params.validate()?;
+        if let Some(prior) = &prior {
+            if !(prior.low.is_finite() && prior.high.is_finite() && prior.low <= prior.high) {
+                return Err(ConfigError::InvalidRange {
+                    name: prior.parameter.name(),
+                    low: prior.low,
+                    high: prior.high,
+                });
+            }
+            prior.parameter.apply(params, prior.low).validate()?;
+            prior.parameter.apply(params, prior.high).validate()?;
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        let seeded = |params: SimulationParams, rng: &mut StdRng| SimulationParams {
+            seed: Some(rng.gen()),
+            ..params
+        };
+        let particles = (0..n_particles)
+            .map(|_| match &prior {
+                Some(prior) => {
+                    let value = if prior.low < prior.high {
+                        rng.gen_range(prior.low, prior.high)
+                    } else {
+                        prior.low
+                    };
+                    let params = seeded(prior.parameter.apply(params, value), &mut rng);
+                    Particle {
+                        environment: Environment::from_params(&params),
+                        parameter: Some(value),
+                    }
+                }
+                None => Particle {
+                    environment: Environment::from_params(&seeded(params.clone(), &mut rng)),
+                    parameter: None,
+                },
+            })
+            .collect();
+        Ok(Self {
+            particles,
+            weights: vec![1.0 / n_particles as f64; n_particles],
+            options: FilterOptions::default(),
+            detected: params.detection.is_some(),
+            tick: 0,
+            rng,
+        })
+    }
+
+    /// Use `options` instead of the default ones, failing if they are invalid
+    pub fn with_options(mut self, options: FilterOptions) -> Result<Self, ConfigError> {
+        options.validate()?;
+        self.options = options;
+        Ok(self)
+    }
+
+    /// Tick of the latest observation
+    #[must_use]
+    pub fn tick(&self) -> usize {
+        self.tick
+    }
+
+    /// Normalised weights of the particles
+    #[must_use]
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+
+    /// Effective sample size of the weights, from one up to the number of particles
+    #[must_use]
+    pub fn effective_sample_size(&self) -> f64 {
+        1.0 / self.weights.iter().map(|w| w * w).sum::<f64>()
+    }
+
+    /// Advance the particles to `tick`, and weight them by the `observed` number of cases
+    /// reported after the previous observation up to and including `tick`.
+    ///
+    /// # Panics
+    ///
+    /// If `tick` is not after the previous observation.
+    pub fn assimilate(&mut self, tick: usize, observed: usize) -> Assimilation {
+        assert!(
+            tick > self.tick,
+            "observation at tick {} is not after tick {}",
+            tick,
+            self.tick
+        );
+        let ticks = tick - self.tick;
+        let detected = self.detected;
+        let FilterOptions {
+            likelihood,
+            reporting_floor,
+            ..
+        } = self.options;
+        let log_likelihoods: Vec<f64> = self
+            .particles
+            .iter_mut()
+            .map(|particle| {
+                let reported = advance(&mut particle.environment, ticks, detected);
+                likelihood.log_probability(observed, (reported as f64).max(reporting_floor))
+            })
+            .collect();
+        self.tick = tick;
+
+        // weights in log space, relative to the most likely particle
+        let max = log_likelihoods
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+        let unnormalised: Vec<f64> = self
+            .weights
+            .iter()
+            .zip(&log_likelihoods)
+            .map(|(weight, log_likelihood)| weight * (log_likelihood - max).exp())
+            .collect();
+        let total: f64 = unnormalised.iter().sum();
+        self.weights = unnormalised.iter().map(|weight| weight / total).collect();
+
+        let effective_sample_size = self.effective_sample_size();
+        let resampled =
+            effective_sample_size < self.options.resample_threshold * self.particles.len() as f64;
+        if resampled {
+            self.resample();
+        }
+        Assimilation {
+            tick,
+            log_likelihood: max + total.ln(),
+            effective_sample_size,
+            resampled,
+        }
+    }
+
+    /// Systematic resampling of the particles by their weights, after which all particles
+    /// have equal weight
+    fn resample(&mut self) {
+        let n = self.particles.len();
+        let offset: f64 = self.rng.gen_range(0.0, 1.0 / n as f64);
+        let mut selected: Vec<Particle> = Vec::with_capacity(n);
+        let mut cumulative = self.weights[0];
+        let mut index = 0;
+        let mut previous = None;
+        for i in 0..n {
+            let position = offset + i as f64 / n as f64;
+            while cumulative < position && index + 1 < n {
+                index += 1;
+                cumulative += self.weights[index];
+            }
+            let mut particle = self.particles[index].clone();
+            // copies of a particle would otherwise share their future
+            if previous == Some(index) {
+                particle.environment.reseed(self.rng.gen());
+            }
+            previous = Some(index);
+            selected.push(particle);
+        }
+        self.particles = selected;
+        self.weights = vec![1.0 / n as f64; n];
+    }
+
+    /// Number of infected agents in each particle, aligned with [`ParticleFilter::weights`]
+    #[must_use]
+    pub fn prevalence(&self) -> Vec<usize> {
+        self.particles
+            .iter()
+            .map(|particle| particle.environment.get_statistics().infected)
+            .collect()
+    }
+
+    /// Weighted mean of the number of infected agents
+    #[must_use]
+    pub fn prevalence_mean(&self) -> f64 {
+        self.prevalence()
+            .iter()
+            .zip(&self.weights)
+            .map(|(&infected, weight)| infected as f64 * weight)
+            .sum()
+    }
+
+    /// Weighted quantile `q` of the number of infected agents
+    #[must_use]
+    pub fn prevalence_quantile(&self, q: f64) -> f64 {
+        let prevalence: Vec<f64> = self.prevalence().iter().map(|&i| i as f64).collect();
+        weighted_quantile(&prevalence, &self.weights, q)
+    }
+
+    /// Value of the perturbed parameter in each particle, aligned with
+    /// [`ParticleFilter::weights`], if there is a prior
+    #[must_use]
+    pub fn parameter_posterior(&self) -> Option<Vec<f64>> {
+        self.particles
+            .iter()
+            .map(|particle| particle.parameter)
+            .collect()
+    }
+
+    /// Weighted mean of the perturbed parameter, if there is a prior
+    #[must_use]
+    pub fn parameter_mean(&self) -> Option<f64> {
+        self.parameter_posterior().map(|values| {
+            values
+                .iter()
+                .zip(&self.weights)
+                .map(|(value, weight)| value * weight)
+                .sum()
+        })
+    }
+
+    /// Weighted quantile `q` of the perturbed parameter, if there is a prior
+    #[must_use]
+    pub fn parameter_quantile(&self, q: f64) -> Option<f64> {
+        self.parameter_posterior()
+            .map(|values| weighted_quantile(&values, &self.weights, q))
+    }
+}
+
+/// Advance `environment` by `ticks` ticks, returning the number of cases it reported
+fn advance(environment: &mut Environment, ticks: usize, detected: bool) -> usize {
+    let detections = environment.detection_log().len();
+    let mut infections = 0;
+    for _ in 0..ticks {
+        let uninfected = |tally: TallyStates| tally.susceptible + tally.vaccinated;
+        let before = uninfected(environment.get_statistics());
+        environment.advance(&mut |_, _| {});
+        infections += before.saturating_sub(uninfected(environment.get_statistics()));
+    }
+    if detected {
+        environment.detection_log().len() - detections
+    } else {
+        infections
+    }
+}
+
+/// Smallest of `values` at which the cumulative `weights` reach `q`
+fn weighted_quantile(values: &[f64], weights: &[f64], q: f64) -> f64 {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].partial_cmp(&values[b]).expect("finite values"));
+    let mut cumulative = 0.0;
+    for &i in &order {
+        cumulative += weights[i];
+        if cumulative >= q {
+            return values[i];
+        }
+    }
+    values[*order.last().expect("at least one value")]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::EnvironmentConfig;
+    use crate::presets;
+
+    fn detected_scenario() -> SimulationParams {
+        EnvironmentConfig::from(presets::dense_small_grid())
+            .detection(0.8)
+            .build_params()
+            .unwrap()
+            .0
+    }
+
+    /// Fraction of the ticks of a simulated truth at which the 95% interval of the filtered
+    /// prevalence covers the prevalence of the truth
+    fn coverage(params: &SimulationParams, seed: u64) -> f64 {
+        let mut truth = Environment::from_params(&SimulationParams {
+            seed: Some(seed + 100),
+            ..params.clone()
+        });
+        let mut filter = ParticleFilter::new(params, 40, seed).unwrap();
+        let (mut covered, mut observations) = (0, 0);
+        while truth.is_active() {
+            let reported = advance(&mut truth, 1, true);
+            let assimilation = filter.assimilate(truth.tick(), reported);
+            assert!(assimilation.log_likelihood.is_finite());
+            let infected = truth.get_statistics().infected as f64;
+            if (filter.prevalence_quantile(0.025)..=filter.prevalence_quantile(0.975))
+                .contains(&infected)
+            {
+                covered += 1;
+            }
+            observations += 1;
+        }
+        f64::from(covered) / f64::from(observations)
+    }
+
+    #[test]
+    fn test_filter_covers_truth() {
+        let params = detected_scenario();
+        let mut coverages: Vec<f64> = (0..3).map(|seed| coverage(&params, seed)).collect();
+        coverages.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!(coverages[1] >= 0.5, "coverages {:?}", coverages);
+    }
+
+    #[test]
+    fn test_resampling_preserves_particles() {
+        let mut filter = ParticleFilter::new(&detected_scenario(), 20, 1)
+            .unwrap()
+            .with_options(FilterOptions {
+                resample_threshold: 1.0,
+                ..FilterOptions::default()
+            })
+            .unwrap();
+        for tick in 1..=5 {
+            let assimilation = filter.assimilate(tick, 1);
+            assert!(assimilation.effective_sample_size <= 20.0 + 1e-9);
+            assert_eq!(filter.prevalence().len(), 20);
+            assert_eq!(filter.weights(), &[1.0 / 20.0; 20][..]);
+        }
+        assert_eq!(filter.tick(), 5);
+        assert_eq!(filter.parameter_posterior(), None);
+    }
+
+    #[test]
+    fn test_seed_reproduces_filter() {
+        let params = detected_scenario();
+        let filter = |seed| {
+            let mut filter = ParticleFilter::new(&params, 10, seed)
+                .unwrap()
+                .with_options(FilterOptions {
+                    resample_threshold: 1.0,
+                    ..FilterOptions::default()
+                })
+                .unwrap();
+            let assimilations: Vec<Assimilation> =
+                (1..=5).map(|tick| filter.assimilate(tick, 2)).collect();
+            (assimilations, filter.prevalence())
+        };
+        let (assimilations, prevalence) = filter(4);
+        assert_eq!(filter(4), (assimilations.clone(), prevalence));
+        assert_ne!(filter(5).0, assimilations);
+    }
+
+    #[test]
+    fn test_parameter_posterior() {
+        let prior = ParameterPrior {
+            parameter: SweepParameter::PDeath,
+            low: 0.0,
+            high: 0.5,
+        };
+        let mut filter =
+            ParticleFilter::with_prior(&presets::dense_small_grid(), 30, 3, Some(prior)).unwrap();
+        let values = filter.parameter_posterior().unwrap();
+        assert_eq!(values.len(), 30);
+        assert!(values.iter().all(|value| (0.0..0.5).contains(value)));
+        filter.assimilate(3, 10);
+        let mean = filter.parameter_mean().unwrap();
+        assert!((0.0..0.5).contains(&mean));
+        assert!(filter.parameter_quantile(0.5).unwrap() <= 0.5);
+    }
+
+    #[test]
+    fn test_invalid_configuration() {
+        let params = presets::dense_small_grid();
+        let prior = |low, high| {
+            ParticleFilter::with_prior(
+                &params,
+                5,
+                0,
+                Some(ParameterPrior {
+                    parameter: SweepParameter::PDeath,
+                    low,
+                    high,
+                }),
+            )
+            .err()
+        };
+        assert_eq!(
+            prior(0.5, 1.5),
+            Some(ConfigError::InvalidProbability {
+                name: "p_death",
+                value: 1.5
+            })
+        );
+        assert_eq!(
+            prior(0.5, 0.1),
+            Some(ConfigError::InvalidRange {
+                name: "p-death",
+                low: 0.5,
+                high: 0.1
+            })
+        );
+        assert!(matches!(
+            prior(0.0, f64::NAN),
+            Some(ConfigError::InvalidRange { .. })
+        ));
+        assert_eq!(prior(0.1, 0.1), None);
+
+        let options = |options| {
+            ParticleFilter::new(&params, 5, 0)
+                .unwrap()
+                .with_options(options)
+                .err()
+        };
+        assert_eq!(
+            options(FilterOptions {
+                reporting_floor: 0.0,
+                ..FilterOptions::default()
+            }),
+            Some(ConfigError::InvalidShape {
+                name: "reporting_floor",
+                value: 0.0
+            })
+        );
+        assert!(matches!(
+            options(FilterOptions {
+                likelihood: Likelihood::NegativeBinomial { dispersion: -1.0 },
+                ..FilterOptions::default()
+            }),
+            Some(ConfigError::InvalidShape {
+                name: "dispersion",
+                ..
+            })
+        ));
+        assert!(matches!(
+            options(FilterOptions {
+                resample_threshold: 2.0,
+                ..FilterOptions::default()
+            }),
+            Some(ConfigError::InvalidProbability { .. })
+        ));
+    }
+
+    #[test]
+    fn test_weighted_quantile() {
+        let values = [3.0, 1.0, 2.0];
+        let weights = [0.5, 0.25, 0.25];
+        assert_eq!(weighted_quantile(&values, &weights, 0.2), 1.0);
+        assert_eq!(weighted_quantile(&values, &weights, 0.5), 2.0);
+        assert_eq!(weighted_quantile(&values, &weights, 0.6), 3.0);
+    }
+
+    #[test]
+    fn test_likelihoods() {
+        let poisson = Likelihood::Poisson.log_probability(2, 1.0);
+        assert!((poisson - (-1.0 - 2f64.ln())).abs() < 1e-12);
+        // a negative binomial of dispersion 1 is geometric
+        let geometric = Likelihood::NegativeBinomial { dispersion: 1.0 }.log_probability(2, 1.0);
+        assert!((geometric - 0.125f64.ln()).abs() < 1e-12);
+        // and tends to the Poisson as the dispersion grows
+        let nearly_poisson =
+            Likelihood::NegativeBinomial { dispersion: 1e9 }.log_probability(2, 1.0);
+        assert!((nearly_poisson - poisson).abs() < 1e-6);
+    }
+}
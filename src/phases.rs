@@ -0,0 +1,300 @@
+//! Classification of the ticks of a run into the phases of an epidemic.
+//!
+//! Phases are assigned on the smoothed incidence, i.e. the number of new infections per tick,
+//! by the relative growth rate from one tick to the next. Every tick gets exactly one
+//! [`Phase`], and the phases always follow each other in the order of the variants, where any
+//! of them may be absent.
+use serde::{Deserialize, Serialize};
+
+use crate::julia_reimpl::TallyStatesVec;
+
+/// Phase of an epidemic at a tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Phase {
+    /// Before the incidence starts to grow
+    PreGrowth,
+    /// Incidence growing faster than the growth threshold
+    Growth,
+    /// Incidence around its maximum, changing by less than the growth threshold
+    Peak,
+    /// Incidence falling after the peak
+    Decline,
+    /// Incidence below the extinction level for the remainder of the run
+    Extinct,
+}
+
+/// Rules of [`annotate`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhaseOptions {
+    /// Width in ticks of the centred moving average that smooths the incidence
+    pub window: usize,
+    /// Relative change of the smoothed incidence per tick above which it is growing, and below
+    /// the negative of which it is declining
+    pub growth_threshold: f64,
+    /// Smoothed incidence below which there is no epidemic to speak of
+    pub extinction_level: f64,
+}
+
+impl Default for PhaseOptions {
+    /// A week-long window, a threshold of 5% per tick and an extinction level of half a case
+    fn default() -> Self {
+        Self {
+            window: 7,
+            growth_threshold: 0.05,
+            extinction_level: 0.5,
+        }
+    }
+}
+
+/// Ticks from `start` up to and including `end` that are in `phase`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseSpan {
+    pub phase: Phase,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl PhaseSpan {
+    /// Number of ticks in the span
+    #[must_use]
+    pub fn duration(&self) -> usize {
+        self.end - self.start + 1
+    }
+}
+
+/// Phase of each tick, together with the spans of the phases that occur
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseAnnotation {
+    pub labels: Vec<Phase>,
+    /// Spans of the phases that occur, in order
+    pub spans: Vec<PhaseSpan>,
+}
+
+impl PhaseAnnotation {
+    /// Span of `phase`, if it occurs
+    #[must_use]
+    pub fn span(&self, phase: Phase) -> Option<PhaseSpan> {
+        self.spans.iter().find(|span| span.phase == phase).copied()
+    }
+}
+
+/// Number of new infections at each tick of `record`, being the decrease of the agents that
+/// were never infected, i.e. the susceptible and vaccinated ones, where the first tick has none.
+///
+/// When [immunity wanes](crate::params::SimulationParams::immunity_duration), agents return to
+/// the susceptible ones and the decrease undercounts the infections, which are then better
+/// counted from [`Environment::infection_events`](crate::julia_reimpl::Environment::infection_events).
+#[must_use]
+pub fn incidence(record: &TallyStatesVec) -> Vec<f64> {
+    let uninfected: Vec<usize> = record
+        .susceptible
+        .iter()
+        .zip(&record.vaccinated)
+        .map(|(susceptible, vaccinated)| susceptible + vaccinated)
+        .collect();
+    (0..uninfected.len())
+        .map(|tick| {
+            if tick == 0 {
+                0.0
+            } else {
+                uninfected[tick - 1].saturating_sub(uninfected[tick]) as f64
+            }
+        })
+        .collect()
+}
+
+/// Centred moving average of `series` over `window` ticks, which is truncated at the ends
+#[must_use]
+pub fn smooth(series: &[f64], window: usize) -> Vec<f64> {
+    let before = window.saturating_sub(1) / 2;
+    let after = window.saturating_sub(1) - before;
+    (0..series.len())
+        .map(|tick| {
+            let values = &series[tick.saturating_sub(before)..(tick + after + 1).min(series.len())];
+            values.iter().sum::<f64>() / values.len() as f64
+        })
+        .collect()
+}
+
+/// Classify each tick of `incidence` into a [`Phase`].
+///
+/// The peak is the first maximum of the smoothed incidence, extended to the neighbouring
+/// ticks while the incidence changes by no more than the growth threshold. Growth starts at
+/// the first tick before the peak where the incidence grows faster than the threshold, and
+/// the epidemic is extinct from the tick after which the incidence stays below the extinction
+/// level. An incidence that is still growing at its final tick has no peak yet.
+#[must_use]
+pub fn annotate(incidence: &[f64], options: &PhaseOptions) -> PhaseAnnotation {
+    let smoothed = smooth(incidence, options.window.max(1));
+    let ticks = smoothed.len();
+    let threshold = options.growth_threshold;
+    let level = options.extinction_level;
+    // relative growth from the previous tick
+    let rate = |tick: usize| {
+        if tick == 0 {
+            0.0
+        } else if smoothed[tick - 1] > 0.0 {
+            smoothed[tick] / smoothed[tick - 1] - 1.0
+        } else if smoothed[tick] > 0.0 {
+            f64::INFINITY
+        } else {
+            0.0
+        }
+    };
+
+    let mut labels = vec![Phase::Extinct; ticks];
+    let peak = (0..ticks).fold(None, |peak: Option<usize>, tick| match peak {
+        Some(peak) if smoothed[peak] >= smoothed[tick] => Some(peak),
+        _ => Some(tick),
+    });
+    if let Some(peak) = peak.filter(|&peak| smoothed[peak] >= level) {
+        let mut start = peak;
+        while start > 0 && rate(start) <= threshold {
+            start -= 1;
+        }
+        let mut end = peak;
+        while end + 1 < ticks && rate(end + 1) >= -threshold {
+            end += 1;
+        }
+        let still_growing = peak == ticks - 1 && rate(peak) > threshold;
+        let growth = (0..start)
+            .find(|&tick| rate(tick) > threshold && smoothed[tick] >= level)
+            .unwrap_or(start);
+        let extinct = (end + 1..ticks)
+            .rev()
+            .take_while(|&tick| smoothed[tick] < level)
+            .last()
+            .unwrap_or(ticks);
+
+        for (tick, label) in labels.iter_mut().enumerate() {
+            *label = if tick < growth {
+                Phase::PreGrowth
+            } else if tick < start || (still_growing && tick == peak) {
+                Phase::Growth
+            } else if tick <= end {
+                Phase::Peak
+            } else if tick < extinct {
+                Phase::Decline
+            } else {
+                Phase::Extinct
+            };
+        }
+    }
+
+    let mut spans: Vec<PhaseSpan> = Vec::new();
+    for (tick, &phase) in labels.iter().enumerate() {
+        match spans.last_mut() {
+            Some(span) if span.phase == phase => span.end = tick,
+            _ => spans.push(PhaseSpan {
+                phase,
+                start: tick,
+                end: tick,
+            }),
+        }
+    }
+    PhaseAnnotation { labels, spans }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::julia_reimpl::Environment;
+    use crate::presets;
+
+    fn unsmoothed() -> PhaseOptions {
+        PhaseOptions {
+            window: 1,
+            ..PhaseOptions::default()
+        }
+    }
+
+    fn span(phase: Phase, start: usize, end: usize) -> PhaseSpan {
+        PhaseSpan { phase, start, end }
+    }
+
+    #[test]
+    fn test_exponential() {
+        let incidence: Vec<f64> = (0..35)
+            .map(|tick| if tick < 5 { 0.0 } else { 1.2f64.powi(tick - 5) })
+            .collect();
+        let annotation = annotate(&incidence, &unsmoothed());
+        assert_eq!(
+            annotation.spans,
+            vec![span(Phase::PreGrowth, 0, 4), span(Phase::Growth, 5, 34)]
+        );
+        assert_eq!(annotation.span(Phase::Peak), None);
+    }
+
+    #[test]
+    fn test_logistic_like() {
+        let incidence = [
+            0.0, 0.0, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 32.0, 32.0, 16.0, 8.0, 4.0, 2.0, 1.0, 0.0,
+            0.0, 0.0,
+        ];
+        let annotation = annotate(&incidence, &unsmoothed());
+        assert_eq!(
+            annotation.spans,
+            vec![
+                span(Phase::PreGrowth, 0, 1),
+                span(Phase::Growth, 2, 6),
+                span(Phase::Peak, 7, 9),
+                span(Phase::Decline, 10, 14),
+                span(Phase::Extinct, 15, 17),
+            ]
+        );
+        assert_eq!(annotation.span(Phase::Peak).unwrap().duration(), 3);
+    }
+
+    #[test]
+    fn test_immediate_extinction() {
+        let annotation = annotate(&[3.0, 1.0, 0.0, 0.0, 0.0], &unsmoothed());
+        assert_eq!(
+            annotation.labels,
+            vec![
+                Phase::Peak,
+                Phase::Decline,
+                Phase::Extinct,
+                Phase::Extinct,
+                Phase::Extinct
+            ]
+        );
+        let annotation = annotate(&[0.0; 4], &PhaseOptions::default());
+        assert_eq!(annotation.spans, vec![span(Phase::Extinct, 0, 3)]);
+        assert!(annotate(&[], &PhaseOptions::default()).labels.is_empty());
+    }
+
+    #[test]
+    fn test_smooth() {
+        assert_eq!(smooth(&[3.0, 0.0, 3.0, 6.0], 3), vec![1.5, 2.0, 3.0, 4.5]);
+        assert_eq!(smooth(&[1.0, 2.0], 1), vec![1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_incidence_leaves_out_vaccination() {
+        // a campaign that empties the susceptible does not count as infections
+        let params = crate::params::SimulationParams {
+            p_infect: 0.0,
+            ..presets::dense_small_grid()
+        };
+        let mut e = Environment::from_params(&params);
+        e.schedule_vaccination(1, 1.0);
+        let record: TallyStatesVec = e.run().into_iter().collect();
+        assert_eq!(record.susceptible[1], 0);
+        assert!(incidence(&record).iter().all(|&cases| cases == 0.0));
+    }
+
+    #[test]
+    fn test_default_run() {
+        let mut e = Environment::from_params(&presets::dense_small_grid());
+        let record: TallyStatesVec = e.run().into_iter().collect();
+        let incidence = incidence(&record);
+        assert_eq!(incidence.len(), record.len());
+        let annotation = annotate(&incidence, &PhaseOptions::default());
+        assert_eq!(annotation.labels.len(), record.len());
+        assert!(annotation.labels.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(annotation.span(Phase::Peak).is_some());
+        assert_eq!(annotation.labels.last(), Some(&Phase::Extinct));
+        let covered: usize = annotation.spans.iter().map(PhaseSpan::duration).sum();
+        assert_eq!(covered, record.len());
+    }
+}
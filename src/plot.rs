@@ -0,0 +1,286 @@
+//! Figures of the outputs of the model, built without showing them, which requires the
+//! `plotting` feature.
+//!
+//! The figures are plotly [`Plot`]s, which can be written to HTML with [`Plot::to_html`].
+use plotly::common::color::{NamedColor, Rgb, Rgba};
+use plotly::common::{Fill, Line, Marker, Mode};
+use plotly::{Layout, Plot, Scatter};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use crate::ensemble::QuantileCurve;
+use crate::julia_reimpl::{AgentType, TallyStates, TallyStatesVec};
+
+/// Appearance of a [`fan_chart`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FanChartOptions {
+    /// Pairs of lower and upper quantiles between which bands are shaded, from the outermost
+    /// to the innermost band
+    pub bands: Vec<(f64, f64)>,
+    /// Colour of the bands and the median, as red, green and blue
+    pub color: (u8, u8, u8),
+    /// Opacity of the outermost band, which increases towards the innermost band
+    pub opacity: f64,
+}
+
+impl Default for FanChartOptions {
+    /// Bands between the 5–95 and 25–75 percentiles in blue
+    fn default() -> Self {
+        Self {
+            bands: vec![(0.05, 0.95), (0.25, 0.75)],
+            color: (31, 119, 180),
+            opacity: 0.2,
+        }
+    }
+}
+
+/// Observed counts overlaid on a [`fan_chart`], e.g. to compare a calibration to data
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObservedSeries {
+    pub name: String,
+    pub ticks: Vec<usize>,
+    pub counts: Vec<f64>,
+}
+
+/// A quantile needed by the options is not among the quantile curves
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingQuantile(pub f64);
+
+impl fmt::Display for MissingQuantile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no quantile curve at {}", self.0)
+    }
+}
+
+impl Error for MissingQuantile {}
+
+/// Name of `state` in the titles of the figures
+fn state_name(state: &AgentType) -> &'static str {
+    match state {
+        AgentType::AgentS => "susceptible",
+        AgentType::AgentE => "exposed",
+        AgentType::AgentI => "infected",
+        AgentType::AgentQ => "quarantined",
+        AgentType::AgentR => "recovered",
+        AgentType::AgentV => "vaccinated",
+        AgentType::AgentD => "dead",
+    }
+}
+
+/// Fan chart of the number of agents in `state`, with the median line on top of shaded bands
+/// between the quantiles of `options`, as in [`EnsembleSummary::quantiles`].
+///
+/// The figure has two traces per band, its lower bound and its shaded upper bound, followed
+/// by the median and the `observed` series, if any.
+///
+/// [`EnsembleSummary::quantiles`]: crate::ensemble::EnsembleSummary::quantiles
+pub fn fan_chart(
+    quantiles: &[QuantileCurve],
+    state: &AgentType,
+    options: &FanChartOptions,
+    observed: Option<&ObservedSeries>,
+) -> Result<Plot, MissingQuantile> {
+    let curve = |q: f64| {
+        quantiles
+            .iter()
+            .find(|curve| (curve.quantile - q).abs() < 1e-9)
+            .map(|curve| curve.curves.state(state).to_vec())
+            .ok_or(MissingQuantile(q))
+    };
+    let (r, g, b) = options.color;
+    let median = curve(0.5)?;
+    let ticks: Vec<usize> = (0..median.len()).collect();
+
+    let mut plot = Plot::new();
+    for (band, &(lower, upper)) in options.bands.iter().enumerate() {
+        let opacity = (options.opacity * (band + 1) as f64).min(1.0);
+        let name = format!("{}–{}%", lower * 100.0, upper * 100.0);
+        plot.add_trace(
+            Scatter::new(ticks.clone(), curve(lower)?)
+                .mode(Mode::Lines)
+                .line(Line::new().width(0.0))
+                .show_legend(false)
+                .name(&name),
+        );
+        plot.add_trace(
+            Scatter::new(ticks.clone(), curve(upper)?)
+                .mode(Mode::Lines)
+                .line(Line::new().width(0.0))
+                .fill(Fill::ToNextY)
+                .fill_color(Rgba::new(r, g, b, opacity))
+                .name(&name),
+        );
+    }
+    plot.add_trace(
+        Scatter::new(ticks, median)
+            .mode(Mode::Lines)
+            .line(Line::new().color(Rgb::new(r, g, b)))
+            .name("median"),
+    );
+    if let Some(observed) = observed {
+        plot.add_trace(
+            Scatter::new(observed.ticks.clone(), observed.counts.clone())
+                .mode(Mode::Markers)
+                .marker(Marker::new().color(NamedColor::Black))
+                .name(&observed.name),
+        );
+    }
+    plot.set_layout(Layout::new().title(state_name(state).into()));
+    Ok(plot)
+}
+
+/// Number of susceptible, infected, recovered and dead agents at each tick of `records`, as
+/// returned by [`Environment::run`](crate::julia_reimpl::Environment::run)
+#[must_use]
+pub fn plot_trajectory(records: &[TallyStates]) -> Plot {
+    let ticks: Vec<usize> = (0..records.len()).collect();
+    let columns = TallyStatesVec::from(records);
+    let mut plot = Plot::new();
+    for (state, counts) in [
+        (AgentType::AgentS, columns.susceptible),
+        (AgentType::AgentI, columns.infected),
+        (AgentType::AgentR, columns.recovered),
+        (AgentType::AgentD, columns.dead),
+    ] {
+        plot.add_trace(Scatter::new(ticks.clone(), counts).name(state_name(&state)));
+    }
+    plot
+}
+
+/// Write the [`plot_trajectory`] of `records` as HTML to a file created at `path`
+///
+/// # Panics
+///
+/// If the file cannot be written.
+pub fn save_trajectory_html(records: &[TallyStates], path: impl AsRef<Path>) {
+    plot_trajectory(records).to_html(path);
+}
+
+/// Fraction of the agents ever infected against the duration of the infection, e.g. the mean
+/// of several runs for each duration
+#[must_use]
+pub fn plot_fraction_infected(durations: &[usize], fractions: &[f64]) -> Plot {
+    let mut plot = Plot::new();
+    plot.add_trace(
+        Scatter::new(durations.to_vec(), fractions.to_vec()).name("fraction of infected"),
+    );
+    plot
+}
+
+/// A [`fan_chart`] for each state, in the order susceptible, infected, recovered and dead
+pub fn fan_charts(
+    quantiles: &[QuantileCurve],
+    options: &FanChartOptions,
+) -> Result<Vec<(AgentType, Plot)>, MissingQuantile> {
+    [
+        AgentType::AgentS,
+        AgentType::AgentI,
+        AgentType::AgentR,
+        AgentType::AgentD,
+    ]
+    .iter()
+    .map(|state| Ok((state.clone(), fan_chart(quantiles, state, options, None)?)))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ensemble::{StateCurves, QUANTILES};
+    use serde_json::Value;
+
+    /// Curves where the number of infected agents at `tick` and quantile `q` is
+    /// `100 * q + tick`
+    fn synthetic() -> Vec<QuantileCurve> {
+        QUANTILES
+            .iter()
+            .map(|&q| {
+                let infected: Vec<f64> = (0..10).map(|tick| 100.0 * q + tick as f64).collect();
+                QuantileCurve {
+                    quantile: q,
+                    curves: StateCurves {
+                        infected,
+                        ..StateCurves::default()
+                    },
+                }
+            })
+            .collect()
+    }
+
+    fn traces(plot: &Plot) -> Vec<Value> {
+        let json: Value = serde_json::from_str(&plot.to_json()).unwrap();
+        json["data"].as_array().unwrap().clone()
+    }
+
+    #[test]
+    fn test_fan_chart_traces() {
+        let options = FanChartOptions::default();
+        let plot = fan_chart(&synthetic(), &AgentType::AgentI, &options, None).unwrap();
+        let traces = traces(&plot);
+        assert_eq!(traces.len(), 5);
+        // outer band, inner band and the median
+        for (trace, (q, filled)) in traces.iter().zip(&[
+            (0.05, false),
+            (0.95, true),
+            (0.25, false),
+            (0.75, true),
+            (0.5, false),
+        ]) {
+            assert_eq!(trace["y"][7].as_f64().unwrap(), 100.0 * q + 7.0);
+            assert_eq!(trace["fill"] == "tonexty", *filled);
+        }
+        assert_eq!(traces[4]["name"], "median");
+    }
+
+    #[test]
+    fn test_observed_overlay() {
+        let observed = ObservedSeries {
+            name: "reported".to_string(),
+            ticks: vec![2, 4],
+            counts: vec![30.0, 50.0],
+        };
+        let options = FanChartOptions {
+            bands: vec![(0.05, 0.95)],
+            ..FanChartOptions::default()
+        };
+        let plot = fan_chart(&synthetic(), &AgentType::AgentI, &options, Some(&observed)).unwrap();
+        let traces = traces(&plot);
+        assert_eq!(traces.len(), 4);
+        assert_eq!(traces[3]["name"], "reported");
+        assert_eq!(traces[3]["mode"], "markers");
+        assert_eq!(traces[3]["y"][1].as_f64().unwrap(), 50.0);
+    }
+
+    #[test]
+    fn test_missing_quantile() {
+        let options = FanChartOptions {
+            bands: vec![(0.1, 0.9)],
+            ..FanChartOptions::default()
+        };
+        assert_eq!(
+            fan_chart(&synthetic(), &AgentType::AgentI, &options, None).err(),
+            Some(MissingQuantile(0.1))
+        );
+        assert_eq!(
+            fan_charts(&synthetic(), &FanChartOptions::default())
+                .unwrap()
+                .len(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_trajectory_traces() {
+        let record =
+            crate::julia_reimpl::Environment::from_params(&crate::presets::dense_small_grid())
+                .run();
+        let traces = traces(&plot_trajectory(&record));
+        let names: Vec<_> = traces.iter().map(|trace| trace["name"].clone()).collect();
+        assert_eq!(names, ["susceptible", "infected", "recovered", "dead"]);
+        for trace in &traces {
+            assert_eq!(trace["x"].as_array().unwrap().len(), record.len());
+        }
+        assert_eq!(traces[1]["y"][0], record[0].infected);
+    }
+}
@@ -0,0 +1,223 @@
+//! Ready-made scenarios, so that the examples, benchmarks, and figures share the same numbers.
+//!
+//! Each preset is documented with the qualitative behaviour it is meant to demonstrate.
+use crate::params::{
+    AgentParams, Boundary, Compliance, CorpseHandling, DoseSeverity, SeedingStrategy,
+    SimulationParams, UpdateOrder,
+};
+
+/// Names of all presets, as accepted by [`by_name`]
+pub const NAMES: [&str; 5] = [
+    "blog_default",
+    "dense_small_grid",
+    "sparse_fizzle",
+    "high_mortality",
+    "long_endemic",
+];
+
+/// Look up a preset by its name
+#[must_use]
+pub fn by_name(name: &str) -> Option<SimulationParams> {
+    match name {
+        "blog_default" => Some(blog_default()),
+        "dense_small_grid" => Some(dense_small_grid()),
+        "sparse_fizzle" => Some(sparse_fizzle()),
+        "high_mortality" => Some(high_mortality()),
+        "long_endemic" => Some(long_endemic()),
+        _ => None,
+    }
+}
+
+/// The scenario of the blogpost: 2000 agents, 10 of which are infected, on a 100×100 grid.
+///
+/// The epidemic takes off, and infects most of the population before dying out.
+#[must_use]
+pub fn blog_default() -> SimulationParams {
+    SimulationParams {
+        n: 2000,
+        infected: 10,
+        initial_seeding: SeedingStrategy::Random,
+        duration: 21,
+        duration_model: None,
+        incubation: 0,
+        immunity_duration: None,
+        p_death: 0.05,
+        corpse_handling: CorpseHandling::Keep,
+        update_order: UpdateOrder::Indexed,
+        p_infect: 1.0,
+        infection_radius: 0,
+        p_move: 1.0,
+        xdim: 100,
+        ydim: 100,
+        boundary: Boundary::Torus,
+        dt: None,
+        burn_in_ticks: 0,
+        max_ticks: None,
+        seeding: Vec::new(),
+        vaccination: Vec::new(),
+        vectors: None,
+        reservoirs: Vec::new(),
+        p_spillover: 0.0,
+        detection: None,
+        ppe: None,
+        isolation: false,
+        quarantine: None,
+        compliance: Compliance::Constant(1.0),
+        heterogeneity: AgentParams::default(),
+        dose_severity: DoseSeverity::Constant(1.0),
+        lockdown: None,
+        essential_fraction: 0.0,
+        stratify: Vec::new(),
+        seed: None,
+    }
+}
+
+/// 500 agents crammed on a 20×20 grid.
+///
+/// Contacts are frequent, thus virtually everyone is infected within a few ticks.
+#[must_use]
+pub fn dense_small_grid() -> SimulationParams {
+    SimulationParams {
+        n: 500,
+        infected: 5,
+        duration: 10,
+        xdim: 20,
+        ydim: 20,
+        ..blog_default()
+    }
+}
+
+/// 200 agents spread out on a 100×100 grid with a short infection.
+///
+/// An infected agent rarely meets anyone before recovering, so the outbreak dies out
+/// after a handful of cases.
+#[must_use]
+pub fn sparse_fizzle() -> SimulationParams {
+    SimulationParams {
+        n: 200,
+        infected: 1,
+        duration: 10,
+        ..blog_default()
+    }
+}
+
+/// The blogpost scenario where half of the infected agents die.
+///
+/// The epidemic is cut short compared to [`blog_default`], and deaths are comparable to recoveries.
+#[must_use]
+pub fn high_mortality() -> SimulationParams {
+    SimulationParams {
+        p_death: 0.5,
+        ..blog_default()
+    }
+}
+
+/// The blogpost scenario where immunity wanes 60 ticks after recovery, and 1% of the infected
+/// agents die.
+///
+/// Reinfections keep the infection circulating in recurring waves, such that the run only
+/// stops at its maximum of 500 ticks.
+#[must_use]
+pub fn long_endemic() -> SimulationParams {
+    SimulationParams {
+        p_death: 0.01,
+        immunity_duration: Some(60),
+        max_ticks: Some(500),
+        ..blog_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::julia_reimpl::{Environment, TallyStates};
+
+    /// `params` seeded by `seed`
+    fn seeded(params: &SimulationParams, seed: u64) -> SimulationParams {
+        SimulationParams {
+            seed: Some(seed),
+            ..params.clone()
+        }
+    }
+
+    fn final_tally(params: &SimulationParams, seed: u64) -> TallyStates {
+        let mut e = Environment::from_params(&seeded(params, seed));
+        e.run().pop().unwrap()
+    }
+
+    #[test]
+    fn test_presets_validate() {
+        for name in NAMES.iter() {
+            let params = by_name(name).unwrap();
+            assert!(params.validate().is_ok(), "{}", name);
+        }
+        assert!(by_name("no_such_preset").is_none());
+    }
+
+    #[test]
+    fn test_presets_run_to_completion() {
+        for (seed, name) in NAMES.iter().enumerate() {
+            let params = by_name(name).unwrap();
+            let mut e = Environment::from_params(&seeded(&params, seed as u64));
+            let tally = e.run().pop().unwrap();
+            assert!(e.is_finished(), "{}", name);
+            // only a capped run may stop while infected agents remain
+            if params.max_ticks.is_none() {
+                assert_eq!(tally.infected, 0, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dense_small_grid_infects_everyone() {
+        let params = dense_small_grid();
+        let tally = final_tally(&params, 1);
+        assert!(tally.susceptible * 10 < params.n, "{:?}", tally);
+    }
+
+    #[test]
+    fn test_sparse_fizzle_goes_extinct() {
+        let params = sparse_fizzle();
+        let replicates = 50;
+        // a run that infected less than a tenth of the population counts as extinct
+        let extinct = (0..replicates)
+            .filter(|&seed| {
+                (params.n - final_tally(&params, seed as u64).susceptible) * 10 < params.n
+            })
+            .count();
+        assert!(extinct * 2 > replicates, "{} of {}", extinct, replicates);
+    }
+
+    #[test]
+    fn test_high_mortality_kills_many() {
+        let mut deaths = 0;
+        let mut recoveries = 0;
+        for seed in 0..4 {
+            let tally = final_tally(&high_mortality(), seed);
+            deaths += tally.dead;
+            recoveries += tally.recovered;
+        }
+        assert!(
+            deaths * 3 > recoveries,
+            "{} dead, {} recovered",
+            deaths,
+            recoveries
+        );
+    }
+
+    #[test]
+    fn test_long_endemic_persists() {
+        let params = long_endemic();
+        for seed in 0..2 {
+            let mut e = Environment::from_params(&seeded(&params, seed));
+            let tally = e.run().pop().unwrap();
+            assert_eq!(e.tick(), 500);
+            assert!(tally.infected > 0, "{:?}", tally);
+            let reinfected = e
+                .iter_agents()
+                .filter(|agent| agent.immune_history.infections().count() > 1)
+                .count();
+            assert!(reinfected * 2 > params.n, "{} reinfected", reinfected);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Frames of the grid rendered from [snapshots](GridSnapshot), as a sequence of PNG images.
+//!
+//! Each cell is coloured by its dominant state, being the state of most of the agents in it:
+//! blue susceptible, orange exposed, red infected, dark red quarantined, green recovered,
+//! purple vaccinated and black dead agents, or white if the cell is empty. Ties go to the
+//! state that comes first in the order infected, quarantined, exposed, susceptible, recovered,
+//! vaccinated and dead, as in the frames of a run with the `frames` feature.
+//!
+//! The pixels in column `x` and row `y` of cells show the cell `(x, y)`, as in
+//! [`GridSnapshot::cells`], such that row zero is at the top of the image.
+//!
+//! ```no_run
+//! # use bkamins_sir_abm::julia_reimpl::Environment;
+//! # use bkamins_sir_abm::presets;
+//! # use std::path::Path;
+//! let mut environment = Environment::from_params(&presets::blog_default());
+//! let (_, snapshots) = environment.run_with_snapshots(5);
+//! bkamins_sir_abm::render::render_frames(&snapshots, Path::new("frames"), 4).unwrap();
+//! ```
+use image::{ImageError, Rgb, RgbImage};
+use std::convert::TryFrom;
+use std::io;
+use std::path::Path;
+
+use crate::julia_reimpl::{CellState, GridSnapshot};
+
+/// Colour of an empty cell
+pub const EMPTY: Rgb<u8> = Rgb([255, 255, 255]);
+
+/// Colours of the susceptible, exposed, infected, quarantined, recovered, vaccinated and dead
+/// states
+pub const COLOURS: [Rgb<u8>; 7] = [
+    Rgb([40, 90, 220]),
+    Rgb([240, 150, 30]),
+    Rgb([220, 30, 30]),
+    Rgb([120, 20, 40]),
+    Rgb([40, 170, 60]),
+    Rgb([140, 70, 190]),
+    Rgb([0, 0, 0]),
+];
+
+/// Colour of `cell` by its dominant state
+#[must_use]
+pub fn cell_colour(cell: &CellState) -> Rgb<u8> {
+    let counts = [
+        cell.susceptible,
+        cell.exposed,
+        cell.infected,
+        cell.quarantined,
+        cell.recovered,
+        cell.vaccinated,
+        cell.dead,
+    ];
+    // the states in the order of precedence on ties
+    let mut dominant = None;
+    for &state in &[2, 3, 1, 0, 4, 5, 6] {
+        if counts[state] > dominant.map_or(0, |dominant: usize| counts[dominant]) {
+            dominant = Some(state);
+        }
+    }
+    dominant.map_or(EMPTY, |state| COLOURS[state])
+}
+
+/// Image of `snapshot` in which each cell is a square of `cell_px` pixels
+///
+/// # Panics
+///
+/// If a side of the image would exceed `u32::MAX` pixels.
+#[must_use]
+pub fn render_snapshot(snapshot: &GridSnapshot, cell_px: u32) -> RgbImage {
+    let ydim = snapshot.cells.len();
+    let xdim = snapshot.cells.first().map_or(0, Vec::len);
+    let side = |cells: usize| {
+        u32::try_from(cells)
+            .ok()
+            .and_then(|cells| cells.checked_mul(cell_px))
+            .expect("image exceeds u32::MAX pixels per side")
+    };
+    RgbImage::from_fn(side(xdim), side(ydim), |x, y| {
+        let cell = &snapshot.cells[(y / cell_px) as usize][(x / cell_px) as usize];
+        cell_colour(cell)
+    })
+}
+
+/// File name of the frame of `tick`
+fn frame_name(tick: usize) -> String {
+    format!("frame_{:06}.png", tick)
+}
+
+/// Write a PNG of each of `env_snapshots` into `dir`, named after its tick as
+/// `frame_000005.png`, in which each cell is a square of `cell_px` pixels.
+///
+/// The directory is created if it does not exist. Collect the snapshots with
+/// [`Environment::run_with_snapshots`](crate::julia_reimpl::Environment::run_with_snapshots).
+pub fn render_frames(env_snapshots: &[GridSnapshot], dir: &Path, cell_px: u32) -> io::Result<()> {
+    if cell_px == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cells must be at least one pixel wide",
+        ));
+    }
+    std::fs::create_dir_all(dir)?;
+    for snapshot in env_snapshots {
+        render_snapshot(snapshot, cell_px)
+            .save(dir.join(frame_name(snapshot.tick)))
+            .map_err(|error| match error {
+                ImageError::IoError(error) => error,
+                error => io::Error::new(io::ErrorKind::Other, error),
+            })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::julia_reimpl::Environment;
+    use crate::params::SimulationParams;
+
+    /// Snapshot of 5x5 cells, with known agents in the corners and the centre
+    fn placements() -> GridSnapshot {
+        let mut cells = vec![vec![CellState::default(); 5]; 5];
+        cells[0][0].susceptible = 2;
+        cells[0][4] = CellState {
+            infected: 1,
+            susceptible: 1,
+            ..CellState::default()
+        };
+        cells[4][0].recovered = 3;
+        cells[4][4] = CellState {
+            dead: 2,
+            recovered: 1,
+            ..CellState::default()
+        };
+        cells[2][3].infected = 1;
+        GridSnapshot { tick: 7, cells }
+    }
+
+    #[test]
+    fn test_render_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        render_frames(&[placements()], dir.path(), 3).unwrap();
+        let image = image::open(dir.path().join("frame_000007.png"))
+            .unwrap()
+            .into_rgb8();
+        assert_eq!(image.dimensions(), (15, 15));
+        // the pixels of the cell (x, y) start at column 3 * x and row 3 * y
+        let pixel = |x: u32, y: u32| *image.get_pixel(3 * x + 1, 3 * y + 2);
+        assert_eq!(pixel(0, 0), COLOURS[0]);
+        // a tie goes to the infected agent
+        assert_eq!(pixel(4, 0), COLOURS[2]);
+        assert_eq!(pixel(0, 4), COLOURS[4]);
+        assert_eq!(pixel(4, 4), COLOURS[6]);
+        assert_eq!(pixel(3, 2), COLOURS[2]);
+        assert_eq!(pixel(2, 3), EMPTY);
+        assert_eq!(pixel(1, 1), EMPTY);
+
+        assert_eq!(
+            render_frames(&[placements()], dir.path(), 0)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_render_run_on_wide_grid() {
+        let params = SimulationParams {
+            n: 40,
+            infected: 4,
+            xdim: 8,
+            ydim: 3,
+            seed: Some(3),
+            ..SimulationParams::default()
+        };
+        let (_, snapshots) = Environment::from_params(&params).run_with_snapshots(5);
+        let dir = tempfile::tempdir().unwrap();
+        render_frames(&snapshots, dir.path(), 2).unwrap();
+        assert_eq!(
+            std::fs::read_dir(dir.path()).unwrap().count(),
+            snapshots.len()
+        );
+        for snapshot in &snapshots {
+            let path = dir.path().join(frame_name(snapshot.tick));
+            let image = image::open(path).unwrap().into_rgb8();
+            assert_eq!(image.dimensions(), (16, 6));
+            for (y, row) in snapshot.cells.iter().enumerate() {
+                for (x, cell) in row.iter().enumerate() {
+                    let pixel = *image.get_pixel(2 * x as u32 + 1, 2 * y as u32);
+                    assert_eq!(pixel, cell_colour(cell));
+                }
+            }
+        }
+    }
+}
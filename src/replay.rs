@@ -0,0 +1,315 @@
+//! Compressed history of the states and locations of all agents, from which the full state of
+//! any recorded tick can be replayed, see [`StateHistory`].
+//!
+//! Most agents change state only a few times during a run, thus the states are stored per
+//! agent as a run-length encoded sequence of the states it entered and the ticks at which
+//! it entered them. Locations are stored as the new location of each agent that moved in a
+//! tick, such that agents that stay in place, e.g. the dead or those in lockdown, take no
+//! memory. The memory of a history thus scales with the number of events rather than with
+//! the number of ticks times the number of agents, as a [`Snapshot`] per tick would.
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+
+use crate::julia_reimpl::{AgentType, Environment, TallyStates};
+
+/// Location and state of each agent at a tick
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub tick: usize,
+    /// Location in x- and y-dimension and state of each agent, in order of their index
+    pub agents: Vec<(usize, usize, AgentType)>,
+}
+
+impl Snapshot {
+    /// Current locations and states of the agents of `env`
+    #[must_use]
+    pub fn capture<X>(env: &Environment<X>) -> Self {
+        Self {
+            tick: env.tick(),
+            agents: env
+                .iter_agents()
+                .map(|agent| (agent.x, agent.y, agent.state))
+                .collect(),
+        }
+    }
+
+    /// Heap memory held by the snapshot in bytes
+    #[must_use]
+    pub fn heap_bytes(&self) -> usize {
+        self.agents.capacity() * std::mem::size_of::<(usize, usize, AgentType)>()
+    }
+}
+
+/// New location of an agent that moved in a tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Move {
+    agent: u32,
+    x: u32,
+    y: u32,
+}
+
+/// History of the states and locations of the agents over consecutive ticks, see the
+/// [module documentation](crate::replay)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateHistory {
+    /// First recorded tick
+    start: usize,
+    /// Locations of the agents at `start`
+    initial: Vec<(u32, u32)>,
+    /// States entered by each agent with the tick at which it entered them, from `start`
+    states: Vec<SmallVec<[(u32, AgentType); 3]>>,
+    /// Agents that moved by the end of each tick after `start`
+    moves: Vec<Vec<Move>>,
+    /// Locations of the agents at the last recorded tick
+    current: Vec<(u32, u32)>,
+}
+
+impl StateHistory {
+    /// Start a history at the current tick of `env`
+    #[must_use]
+    pub fn new<X>(env: &Environment<X>) -> Self {
+        let initial: Vec<(u32, u32)> = env
+            .iter_agents()
+            .map(|agent| (agent.x as u32, agent.y as u32))
+            .collect();
+        let tick = env.tick() as u32;
+        Self {
+            start: env.tick(),
+            states: env
+                .iter_agents()
+                .map(|agent| std::iter::once((tick, agent.state)).collect())
+                .collect(),
+            current: initial.clone(),
+            initial,
+            moves: Vec::new(),
+        }
+    }
+
+    /// First recorded tick
+    #[must_use]
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Last recorded tick
+    #[must_use]
+    pub fn end(&self) -> usize {
+        self.start + self.moves.len()
+    }
+
+    /// Record the tick that `env` just advanced to.
+    ///
+    /// # Panics
+    ///
+    /// If the tick of `env` does not follow the last recorded tick, or its number of agents
+    /// changed.
+    pub fn record<X>(&mut self, env: &Environment<X>) {
+        assert_eq!(
+            env.tick(),
+            self.end() + 1,
+            "ticks must be recorded consecutively"
+        );
+        assert_eq!(
+            env.iter_agents().count(),
+            self.current.len(),
+            "the number of agents changed"
+        );
+        let tick = env.tick() as u32;
+        let mut moves = Vec::new();
+        for (agent, (current, states)) in env
+            .iter_agents()
+            .zip(self.current.iter_mut().zip(&mut self.states))
+        {
+            let location = (agent.x as u32, agent.y as u32);
+            if location != *current {
+                *current = location;
+                moves.push(Move {
+                    agent: agent.id as u32,
+                    x: location.0,
+                    y: location.1,
+                });
+            }
+            if states.last().map(|(_, state)| state) != Some(&agent.state) {
+                states.push((tick, agent.state));
+            }
+        }
+        moves.shrink_to_fit();
+        self.moves.push(moves);
+    }
+
+    /// Full state of the agents at `tick`, or `None` if the tick was not recorded
+    #[must_use]
+    pub fn reconstruct(&self, tick: usize) -> Option<Snapshot> {
+        if !(self.start..=self.end()).contains(&tick) {
+            return None;
+        }
+        let mut locations = self.initial.clone();
+        for moves in &self.moves[..tick - self.start] {
+            for step in moves {
+                locations[step.agent as usize] = (step.x, step.y);
+            }
+        }
+        let agents = locations
+            .into_iter()
+            .zip(&self.states)
+            .map(|((x, y), states)| {
+                let (_, state) = states
+                    .iter()
+                    .rev()
+                    .find(|(since, _)| *since as usize <= tick)
+                    .expect("the first state is entered at the first recorded tick");
+                (x as usize, y as usize, state.clone())
+            })
+            .collect();
+        Some(Snapshot { tick, agents })
+    }
+
+    /// Heap memory held by the history in bytes, computed from the capacities of its buffers
+    #[must_use]
+    pub fn heap_bytes(&self) -> usize {
+        fn bytes<T>(items: &Vec<T>) -> usize {
+            items.capacity() * std::mem::size_of::<T>()
+        }
+        let states = self
+            .states
+            .iter()
+            .filter(|states| states.spilled())
+            .map(|states| states.capacity() * std::mem::size_of::<(u32, AgentType)>())
+            .sum::<usize>();
+        bytes(&self.initial)
+            + bytes(&self.current)
+            + bytes(&self.states)
+            + states
+            + bytes(&self.moves)
+            + self.moves.iter().map(bytes).sum::<usize>()
+    }
+}
+
+/// Run `env` like [`Environment::run_with_hook`], while recording its history from the current
+/// tick, after `hook` has intervened.
+///
+/// Returns the tally record of the run together with the history.
+pub fn run_with_history<X>(
+    env: &mut Environment<X>,
+    mut hook: impl FnMut(&mut Environment<X>, usize),
+) -> (Vec<TallyStates>, StateHistory) {
+    let mut history = StateHistory::new(env);
+    let record = env.run_with_hook(|env, tick| {
+        hook(env, tick);
+        history.record(env);
+    });
+    (record, history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::{Lockdown, SimulationParams};
+    use crate::presets;
+
+    #[test]
+    fn test_replay_matches_snapshots() {
+        let params = SimulationParams {
+            burn_in_ticks: 3,
+            seed: Some(3),
+            ..presets::dense_small_grid()
+        };
+        let mut environment = Environment::from_params(&params);
+        let mut live = vec![Snapshot::capture(&environment)];
+        let (record, history) = run_with_history(&mut environment, |environment, tick| {
+            if tick % 10 == 0 {
+                live.push(Snapshot::capture(environment));
+            }
+        });
+        let last = record.len() - 1;
+        assert_eq!((history.start(), history.end()), (0, last));
+        for snapshot in &live {
+            assert_eq!(history.reconstruct(snapshot.tick).as_ref(), Some(snapshot));
+        }
+        assert_eq!(
+            history.reconstruct(last),
+            Some(Snapshot::capture(&environment))
+        );
+        assert_eq!(history.reconstruct(last + 1), None);
+
+        // the tally of a reconstructed tick matches the record
+        let snapshot = history.reconstruct(last / 2).unwrap();
+        let infected = snapshot
+            .agents
+            .iter()
+            .filter(|(_, _, state)| *state == AgentType::AgentI)
+            .count();
+        assert_eq!(infected, record[last / 2].infected);
+
+        // a replayed tick is the state of a new run with the same seed at that tick
+        let mut rerun = Environment::from_params(&params);
+        for _ in 0..last / 2 {
+            rerun.advance(&mut |_, _| {});
+        }
+        assert_eq!(
+            Some(Snapshot::capture(&rerun)),
+            history.reconstruct(last / 2)
+        );
+    }
+
+    #[test]
+    fn test_memory_scales_with_events() {
+        // a long run in total lockdown, where no agent moves and few change state
+        let params = SimulationParams {
+            duration: 400,
+            lockdown: Some(Lockdown {
+                start: 0,
+                end: usize::MAX,
+                p_move: 0.0,
+            }),
+            ..presets::dense_small_grid()
+        };
+        let mut environment = Environment::from_params(&params);
+        let mut dense = vec![Snapshot::capture(&environment)];
+        let (record, history) = run_with_history(&mut environment, |environment, _| {
+            dense.push(Snapshot::capture(environment));
+        });
+        assert!(record.len() > 400);
+        let dense_bytes: usize = dense.iter().map(Snapshot::heap_bytes).sum();
+        assert!(
+            history.heap_bytes() * 20 < dense_bytes,
+            "{} bytes against {} bytes of snapshots",
+            history.heap_bytes(),
+            dense_bytes
+        );
+        assert_eq!(history.reconstruct(history.end()), dense.pop());
+        assert_eq!(history.reconstruct(0).as_ref(), dense.first());
+    }
+
+    #[test]
+    fn test_hook_changes_are_recorded() {
+        // a history started mid-run replays from that tick
+        let mut environment = Environment::from_params(&SimulationParams {
+            infected: 1,
+            ..presets::dense_small_grid()
+        });
+        environment.advance(&mut |_, _| {});
+        let (_, history) = run_with_history(&mut environment, |environment, tick| {
+            if tick == 2 {
+                let id = environment
+                    .iter_agents()
+                    .find(|agent| agent.state == AgentType::AgentS)
+                    .map(|agent| agent.id)
+                    .unwrap();
+                environment.immunize(id);
+            }
+        });
+        assert_eq!(history.start(), 1);
+        assert_eq!(history.reconstruct(0), None);
+        let before = history.reconstruct(1).unwrap();
+        let after = history.reconstruct(2).unwrap();
+        let vaccinated = |snapshot: &Snapshot| {
+            snapshot
+                .agents
+                .iter()
+                .filter(|(_, _, state)| *state == AgentType::AgentV)
+                .count()
+        };
+        assert!(vaccinated(&after) > vaccinated(&before));
+    }
+}
@@ -0,0 +1,327 @@
+//! Results of a run, bundled with a description of how they were produced.
+//!
+//! A [`RunResult`] serializes to a single, self-describing JSON document.
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::iter::FromIterator;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::alloc_profile::{AllocationReport, Profiler};
+use crate::julia_reimpl::{
+    DetectionRecord, DoseOutcome, Environment, EssentialAttackRates, ExposureDistribution,
+    SeedingRecord, SpilloverRecord, TallyStates, TallyStatesVec, VectorTally,
+};
+use crate::params::SimulationParams;
+use crate::strata::StratifiedTally;
+use crate::summary::EpidemicSummary;
+
+/// Why a run came to an end
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TerminationReason {
+    /// No infected agents were left, and no further infections were scheduled
+    Extinct,
+    /// The time budget of the run was exhausted, see [`run_with_budget`]
+    TimedOut,
+    /// The run reached [`SimulationParams::max_ticks`], or the ticks of
+    /// [`Environment::run_for`], while infections could still occur
+    MaxTicks,
+}
+
+/// How a run was produced
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunMetadata {
+    /// Parameters the environment was set up with
+    pub params: SimulationParams,
+    /// Seed of the random numbers, being `params.seed` if it was given, which reproduces the
+    /// run when given as `params.seed`
+    pub seed: Option<u64>,
+    /// Version of this crate that produced the run
+    pub crate_version: String,
+    /// Wall-clock time at the start of the run, in milliseconds since the Unix epoch
+    pub started_at_ms: u64,
+    /// Wall-clock time at the end of the run, in milliseconds since the Unix epoch
+    pub finished_at_ms: u64,
+    /// Duration of the run in seconds
+    pub elapsed_secs: f64,
+    /// Why the run ended
+    pub termination: TerminationReason,
+    /// Heap allocations of the run, with the `alloc-profile` feature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allocations: Option<AllocationReport>,
+}
+
+impl RunMetadata {
+    /// Write the crate version, the seed if any, and the parameters as JSON as comment lines
+    /// starting with `#`, to precede the rows of a CSV file
+    pub fn write_comments<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "# crate_version: {}", self.crate_version)?;
+        if let Some(seed) = self.seed {
+            writeln!(writer, "# seed: {}", seed)?;
+        }
+        writeln!(writer, "# params: {}", serde_json::to_string(&self.params)?)
+    }
+}
+
+/// Tally record of a run, together with its metadata
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunResult {
+    /// Tally of the states for each tick
+    pub record: TallyStatesVec,
+    pub metadata: RunMetadata,
+    /// Agents infected by the seeding schedule
+    pub seeding_log: Vec<SeedingRecord>,
+    /// Tally of the vectors for each tick, empty unless the infection is vector-borne
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vector_record: Vec<VectorTally>,
+    /// Agents infected by spillover from reservoir cells
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spillover_log: Vec<SpilloverRecord>,
+    /// Infected agents whose infection was detected
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub detection_log: Vec<DetectionRecord>,
+    /// Attack rate of each compliance quartile at the end of the run, see
+    /// [`Environment::attack_rate_by_compliance`]
+    #[serde(default)]
+    pub compliance_attack_rates: [f64; 4],
+    /// Outcome of the infections by contact, by infecting dose
+    #[serde(default)]
+    pub dose_outcomes: Vec<DoseOutcome>,
+    /// Attack rates of the essential workers and the other agents at the end of the run
+    #[serde(default)]
+    pub essential_attack_rates: EssentialAttackRates,
+    /// Exposure of the agents that were and were not infected, at the end of the run
+    #[serde(default)]
+    pub exposure: ExposureDistribution,
+    /// Tally of each tick by each of [`SimulationParams::stratify`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub strata: Vec<StratifiedTally>,
+}
+
+impl RunResult {
+    /// The tally record as returned by [`Environment::run`]
+    #[must_use]
+    pub fn records(&self) -> Vec<TallyStates> {
+        (0..self.record.len())
+            .map(|tick| TallyStates {
+                susceptible: self.record.susceptible[tick],
+                exposed: self.record.exposed[tick],
+                infected: self.record.infected[tick],
+                quarantined: self.record.quarantined[tick],
+                recovered: self.record.recovered[tick],
+                vaccinated: self.record.vaccinated[tick],
+                dead: self.record.dead[tick],
+            })
+            .collect()
+    }
+
+    /// Summary of the tally record, see [`EpidemicSummary::from_records`]
+    ///
+    /// # Panics
+    ///
+    /// If the record is empty, which it never is for a run of this crate, as it starts with
+    /// the tally before the first tick.
+    #[must_use]
+    pub fn summary(&self) -> EpidemicSummary {
+        EpidemicSummary::from_records(&self.records(), self.metadata.params.n)
+            .expect("the record starts with the tally before the first tick")
+    }
+
+    pub fn write_json<W: io::Write>(&self, writer: W) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(writer, self)
+    }
+
+    pub fn read_json<R: io::Read>(reader: R) -> serde_json::Result<Self> {
+        serde_json::from_reader(reader)
+    }
+}
+
+fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Set up an environment according to `params` and run it to the end
+#[must_use]
+pub fn run(params: &SimulationParams) -> RunResult {
+    run_until(params, None, |_, _| {})
+}
+
+/// Like [`run`], stopping once `budget` of wall-clock time is exhausted, as checked before
+/// every tick. A run that was stopped holds the ticks up to that point and ends with
+/// [`TerminationReason::TimedOut`].
+#[must_use]
+pub fn run_with_budget(params: &SimulationParams, budget: Duration) -> RunResult {
+    run_until(params, Some(Instant::now() + budget), |_, _| {})
+}
+
+/// Like [`run`], stopping before the first tick after `deadline`, and calling `observer`
+/// once per tick as the hook of [`Environment::run_with_hook`]
+pub(crate) fn run_until(
+    params: &SimulationParams,
+    deadline: Option<Instant>,
+    mut observer: impl FnMut(&mut Environment, usize),
+) -> RunResult {
+    let started_at = SystemTime::now();
+    let timer = Instant::now();
+
+    let allocations = Profiler::counts();
+    let mut environment = Environment::from_params(params);
+    let mut profiler = Profiler::start(allocations);
+    let mut strata: Vec<StratifiedTally> = params
+        .stratify
+        .iter()
+        .cloned()
+        .map(StratifiedTally::new)
+        .collect();
+    for tally in &mut strata {
+        tally.record(&environment);
+    }
+    let record = environment.run_with_deadline(deadline, |environment, tick| {
+        observer(environment, tick);
+        for tally in &mut strata {
+            tally.record(environment);
+        }
+        profiler.tick(environment, tick);
+    });
+    let allocations = profiler.report(&environment);
+    let termination = if !environment.is_active() {
+        TerminationReason::Extinct
+    } else if environment.reached_max_ticks() {
+        TerminationReason::MaxTicks
+    } else {
+        TerminationReason::TimedOut
+    };
+
+    let elapsed = timer.elapsed();
+    RunResult {
+        record: TallyStatesVec::from_iter(record),
+        metadata: RunMetadata {
+            params: params.clone(),
+            seed: Some(environment.seed()),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at_ms: unix_ms(started_at),
+            finished_at_ms: unix_ms(started_at + elapsed),
+            elapsed_secs: elapsed.as_secs_f64(),
+            termination,
+            allocations,
+        },
+        seeding_log: environment.seeding_log().to_vec(),
+        vector_record: environment.vector_record().to_vec(),
+        spillover_log: environment.spillover_log().to_vec(),
+        detection_log: environment.detection_log().to_vec(),
+        compliance_attack_rates: environment.attack_rate_by_compliance(),
+        dose_outcomes: environment.dose_outcomes(),
+        essential_attack_rates: environment.attack_rate_by_essential(),
+        exposure: environment.exposure_distribution(),
+        strata,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::{SeedLocation, SeedingEntry};
+
+    #[test]
+    fn test_metadata_matches_inputs() {
+        let params = crate::presets::dense_small_grid();
+        let result = run(&params);
+
+        assert_eq!(result.metadata.params, params);
+        assert_eq!(result.metadata.crate_version, env!("CARGO_PKG_VERSION"));
+        assert!(result.metadata.started_at_ms <= result.metadata.finished_at_ms);
+        assert_eq!(result.metadata.termination, TerminationReason::Extinct);
+        assert_eq!(result.records().len(), result.record.len());
+        assert_eq!(result.records().last().unwrap().infected, 0);
+
+        // the recorded seed of an unseeded run reproduces it
+        let seed = result.metadata.seed.unwrap();
+        let reproduced = run(&SimulationParams {
+            seed: Some(seed),
+            ..params
+        });
+        assert_eq!(reproduced.metadata.seed, Some(seed));
+        assert_eq!(reproduced.records(), result.records());
+
+        let mut csv = Vec::new();
+        reproduced.metadata.write_comments(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.contains(&format!("# seed: {}\n", seed)));
+    }
+
+    #[test]
+    fn test_timed_out_run() {
+        let params = crate::presets::dense_small_grid();
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let result = run_until(&params, Some(deadline), |_, _| {
+            std::thread::sleep(Duration::from_millis(20))
+        });
+        assert_eq!(result.metadata.termination, TerminationReason::TimedOut);
+        // the deadline passes during the third tick, unless the ticks are slower still
+        assert!(
+            (2..=4).contains(&result.record.len()),
+            "{}",
+            result.record.len()
+        );
+        assert!(result.records().last().unwrap().infected > 0);
+        assert!(result.metadata.elapsed_secs >= 0.05);
+
+        let result = run_with_budget(&params, Duration::from_secs(600));
+        assert_eq!(result.metadata.termination, TerminationReason::Extinct);
+        let result = run_with_budget(&params, Duration::from_secs(0));
+        assert_eq!(result.metadata.termination, TerminationReason::TimedOut);
+        assert_eq!(result.record.len(), 1);
+    }
+
+    #[test]
+    fn test_max_ticks_run() {
+        let params = SimulationParams {
+            max_ticks: Some(5),
+            ..crate::presets::dense_small_grid()
+        };
+        let result = run(&params);
+        assert_eq!(result.metadata.termination, TerminationReason::MaxTicks);
+        assert_eq!(result.record.len(), 6);
+
+        // a cap beyond the end of the epidemic changes nothing
+        let params = SimulationParams {
+            max_ticks: Some(10_000),
+            seed: Some(4),
+            ..params
+        };
+        let result = run(&params);
+        assert_eq!(result.metadata.termination, TerminationReason::Extinct);
+        let uncapped = run(&SimulationParams {
+            max_ticks: None,
+            ..params
+        });
+        assert_eq!(result.records(), uncapped.records());
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let params = SimulationParams {
+            seeding: vec![SeedingEntry {
+                tick: 2,
+                count: 3,
+                location: SeedLocation::Anywhere,
+            }],
+            ..crate::presets::dense_small_grid()
+        };
+        let result = run(&params);
+        assert!(!result.seeding_log.is_empty());
+
+        let mut json = Vec::new();
+        result.write_json(&mut json).unwrap();
+        let parsed = RunResult::read_json(json.as_slice()).unwrap();
+        assert_eq!(parsed, result);
+
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["metadata"]["params"]["n"], params.n);
+        assert_eq!(
+            value["record"]["susceptible"].as_array().unwrap().len(),
+            result.record.len()
+        );
+    }
+}
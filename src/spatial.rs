@@ -0,0 +1,258 @@
+//! Per-cell time series of the grid as `ndarray` arrays.
+//!
+//! A [`CellRecorder`] counts a chosen [`CellQuantity`] in every cell, every `k` ticks, and
+//! collects the counts into an array of shape (recorded ticks, xdim, ydim), indexed as
+//! `[frame, x, y]`. Such arrays can be written to `.npy` files with [`write_npy`].
+//!
+//! Large grids can be coarse-grained into blocks of `block`×`block` cells, either afterwards
+//! with [`coarse_grain`] or while recording with [`CellRecorder::with_blocks`], which only
+//! ever stores the blocks. Where a dimension is not a multiple of `block`, the blocks at the
+//! edge are truncated: they cover the remaining cells only, as though the grid were padded
+//! with empty cells. Block counts saturate at `u16::MAX`, like the counts of cells.
+use ndarray::Array3;
+use std::path::Path;
+
+use crate::julia_reimpl::{AgentType, Environment};
+
+pub use ndarray_npy::{read_npy, ReadNpyError, WriteNpyError};
+
+/// What is counted in each cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellQuantity {
+    /// Number of infected agents
+    Infected,
+    /// Number of agents, dead or alive
+    Occupancy,
+    /// Number of agents that were infected in this tick, located at the cell they occupy at
+    /// the end of the tick
+    NewInfections,
+}
+
+/// Records a [`CellQuantity`] of the grid every `every` ticks
+#[derive(Debug, Clone)]
+pub struct CellRecorder {
+    quantity: CellQuantity,
+    every: usize,
+    grid_size: (usize, usize),
+    /// Side of the blocks that are counted, one for cells
+    block: usize,
+    /// Ticks at which a frame was recorded
+    ticks: Vec<usize>,
+    /// Frames of the counts of all blocks one after another, indexed as `x * ydim + y` on the
+    /// grid of blocks
+    counts: Vec<u16>,
+}
+
+impl CellRecorder {
+    /// Record `quantity` on a grid of size `grid_size`, at every tick that is a multiple of
+    /// `every`.
+    ///
+    /// # Panics
+    ///
+    /// If `every` is zero.
+    #[must_use]
+    pub fn new(quantity: CellQuantity, every: usize, grid_size: (usize, usize)) -> Self {
+        assert!(every > 0, "must record at least every tick");
+        Self {
+            quantity,
+            every,
+            grid_size,
+            block: 1,
+            ticks: Vec::new(),
+            counts: Vec::new(),
+        }
+    }
+
+    /// Record the sums over blocks of `block`×`block` cells instead of each cell, see
+    /// [`coarse_grain`].
+    ///
+    /// # Panics
+    ///
+    /// If `block` is zero.
+    #[must_use]
+    pub fn with_blocks(mut self, block: usize) -> Self {
+        assert!(block > 0, "blocks must contain at least one cell");
+        self.block = block;
+        self
+    }
+
+    /// Size of the recorded grid, being the grid of blocks
+    fn blocks_size(&self) -> (usize, usize) {
+        let (xdim, ydim) = self.grid_size;
+        (xdim.div_ceil(self.block), ydim.div_ceil(self.block))
+    }
+
+    /// Record a frame of `env`, if its current tick is due.
+    ///
+    /// Call this once before running the environment, and then from the hook of
+    /// [`Environment::run_with_hook`].
+    pub fn observe<X>(&mut self, env: &Environment<X>) {
+        let tick = env.tick();
+        if tick % self.every != 0 {
+            return;
+        }
+        assert_eq!(
+            env.grid_size(),
+            self.grid_size,
+            "recording a different grid"
+        );
+        let (xblocks, yblocks) = self.blocks_size();
+
+        let frame_start = self.counts.len();
+        self.counts.resize(frame_start + xblocks * yblocks, 0);
+        let frame = &mut self.counts[frame_start..];
+        for agent in env.iter_agents() {
+            let counted = match self.quantity {
+                CellQuantity::Infected => agent.state == AgentType::AgentI,
+                CellQuantity::Occupancy => true,
+                CellQuantity::NewInfections => {
+                    agent.state == AgentType::AgentI && agent.since_tick == tick && tick > 0
+                }
+            };
+            if counted {
+                let count = &mut frame[agent.x / self.block * yblocks + agent.y / self.block];
+                *count = count.saturating_add(1);
+            }
+        }
+        self.ticks.push(tick);
+    }
+
+    /// Ticks at which the frames were recorded
+    #[must_use]
+    pub fn ticks(&self) -> &[usize] {
+        &self.ticks
+    }
+
+    /// The recorded frames as an array of shape (recorded ticks, xdim, ydim), where the
+    /// dimensions are those of the grid of blocks when recording blocks
+    #[must_use]
+    pub fn to_array(&self) -> Array3<u16> {
+        let (xblocks, yblocks) = self.blocks_size();
+        Array3::from_shape_vec((self.ticks.len(), xblocks, yblocks), self.counts.clone())
+            .expect("every frame has a count for each block")
+    }
+}
+
+/// Sum the counts of `array`, indexed as `[frame, x, y]`, over blocks of `block`×`block`
+/// cells, truncating the blocks at the edges.
+///
+/// # Panics
+///
+/// If `block` is zero.
+#[must_use]
+pub fn coarse_grain(array: &Array3<u16>, block: usize) -> Array3<u16> {
+    assert!(block > 0, "blocks must contain at least one cell");
+    let (frames, xdim, ydim) = array.dim();
+    let mut blocks = Array3::zeros((frames, xdim.div_ceil(block), ydim.div_ceil(block)));
+    for ((frame, x, y), &count) in array.indexed_iter() {
+        let sum = &mut blocks[[frame, x / block, y / block]];
+        *sum = count.saturating_add(*sum);
+    }
+    blocks
+}
+
+/// Write `array` to an `.npy` file at `path`
+pub fn write_npy<P: AsRef<Path>>(path: P, array: &Array3<u16>) -> Result<(), WriteNpyError> {
+    ndarray_npy::write_npy(path, array.view())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    fn record(quantity: CellQuantity, every: usize) -> (CellRecorder, Vec<usize>) {
+        let mut e = Environment::from_params(&presets::dense_small_grid());
+        let mut recorder = CellRecorder::new(quantity, every, e.grid_size());
+        recorder.observe(&e);
+        let records = e.run_with_hook(|env, _| recorder.observe(env));
+        (
+            recorder,
+            records.iter().map(|tally| tally.infected).collect(),
+        )
+    }
+
+    #[test]
+    fn test_shape_and_stride() {
+        let (recorder, infected) = record(CellQuantity::Occupancy, 3);
+        let array = recorder.to_array();
+        let frames = infected.len().div_ceil(3);
+        assert_eq!(array.shape(), &[frames, 20, 20]);
+        assert_eq!(
+            recorder.ticks(),
+            (0..infected.len())
+                .step_by(3)
+                .collect::<Vec<_>>()
+                .as_slice()
+        );
+        for frame in array.outer_iter() {
+            assert_eq!(
+                frame.iter().map(|&count| count as usize).sum::<usize>(),
+                500
+            );
+        }
+    }
+
+    #[test]
+    fn test_sums_agree_with_tally() {
+        let (recorder, infected) = record(CellQuantity::Infected, 1);
+        let array = recorder.to_array();
+        assert_eq!(array.shape()[0], infected.len());
+        for (tick, frame) in array.outer_iter().enumerate() {
+            let sum: usize = frame.iter().map(|&count| count as usize).sum();
+            assert_eq!(sum, infected[tick], "tick {}", tick);
+        }
+
+        let (recorder, _) = record(CellQuantity::NewInfections, 1);
+        let new_infections: usize = recorder.to_array().iter().map(|&c| c as usize).sum();
+        assert!(new_infections > 0);
+        assert!(new_infections <= 500);
+    }
+
+    #[test]
+    fn test_coarse_grain() {
+        // a single frame of 5x3 cells, counting 1..=15
+        let array = Array3::from_shape_fn((1, 5, 3), |(_, x, y)| (x * 3 + y + 1) as u16);
+        let blocks = coarse_grain(&array, 2);
+        assert_eq!(blocks.dim(), (1, 3, 2));
+        // full blocks, then truncated blocks along y, x and both
+        assert_eq!(blocks[[0, 0, 0]], 1 + 2 + 4 + 5);
+        assert_eq!(blocks[[0, 1, 0]], 7 + 8 + 10 + 11);
+        assert_eq!(blocks[[0, 0, 1]], 3 + 6);
+        assert_eq!(blocks[[0, 2, 0]], 13 + 14);
+        assert_eq!(blocks[[0, 2, 1]], 15);
+        assert_eq!(blocks.sum(), array.sum());
+
+        assert_eq!(coarse_grain(&array, 1), array);
+        assert_eq!(coarse_grain(&array, 10).into_raw_vec(), vec![120]);
+    }
+
+    #[test]
+    fn test_block_recorder_matches_coarse_grain() {
+        let mut e = Environment::from_params(&presets::dense_small_grid());
+        let mut cells = CellRecorder::new(CellQuantity::Infected, 2, e.grid_size());
+        let mut blocks = CellRecorder::new(CellQuantity::Infected, 2, e.grid_size()).with_blocks(3);
+        cells.observe(&e);
+        blocks.observe(&e);
+        e.run_with_hook(|env, _| {
+            cells.observe(env);
+            blocks.observe(env);
+        });
+        assert_eq!(blocks.ticks(), cells.ticks());
+        let coarse = blocks.to_array();
+        assert_eq!(coarse.shape(), &[cells.ticks().len(), 7, 7]);
+        assert_eq!(coarse, coarse_grain(&cells.to_array(), 3));
+    }
+
+    #[test]
+    fn test_npy_round_trip() {
+        let (recorder, _) = record(CellQuantity::Infected, 2);
+        let array = recorder.to_array();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("infected.npy");
+        write_npy(&path, &array).unwrap();
+        let read: Array3<u16> = read_npy(&path).unwrap();
+        assert_eq!(read, array);
+    }
+}
@@ -0,0 +1,307 @@
+//! SQLite database of results, for managing many experiments in one place.
+//!
+//! The tables are created on demand when the database is opened:
+//!
+//! - `runs (run_id, params, seed, ticks, peak_infected, peak_tick, attack_rate, deaths)`, where
+//!   `params` holds the [`SimulationParams`] as JSON, and the peak, attack rate and deaths are
+//!   those of the [`EpidemicSummary`](crate::summary::EpidemicSummary) of the run
+//! - `tallies (run_id, tick, s, e, i, q, r, v, d)`, with the counts of
+//!   [`TallyStates`](crate::julia_reimpl::TallyStates)
+//! - `events (run_id, tick, agent, transition)`, filled from the seeding log (`seeded`) and the
+//!   spillover log (`spillover`) of each run
+//!
+//! Each call to [`SqliteSink::write_runs`] is a single transaction, so that runs of an
+//! ensemble can be written in batches as they finish, as [`SqliteSink::write_replicates`]
+//! does for each replicate.
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+
+use rusqlite::{params, Connection, Transaction};
+
+use crate::ensemble;
+use crate::params::SimulationParams;
+use crate::result::RunResult;
+
+pub use rusqlite::Result;
+
+/// Results database that runs are written into
+pub struct SqliteSink {
+    connection: Connection,
+}
+
+impl SqliteSink {
+    /// Open the database at `path`, creating it and its tables if they do not exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Use an existing connection, e.g. to an in-memory database.
+    pub fn from_connection(connection: Connection) -> Result<Self> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS runs (
+                run_id INTEGER PRIMARY KEY,
+                params TEXT NOT NULL,
+                seed INTEGER,
+                ticks INTEGER NOT NULL,
+                peak_infected INTEGER NOT NULL,
+                peak_tick INTEGER NOT NULL,
+                attack_rate REAL NOT NULL,
+                deaths INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS tallies (
+                run_id INTEGER NOT NULL REFERENCES runs (run_id),
+                tick INTEGER NOT NULL,
+                s INTEGER NOT NULL,
+                e INTEGER NOT NULL,
+                i INTEGER NOT NULL,
+                q INTEGER NOT NULL,
+                r INTEGER NOT NULL,
+                v INTEGER NOT NULL,
+                d INTEGER NOT NULL,
+                PRIMARY KEY (run_id, tick)
+            );
+            CREATE TABLE IF NOT EXISTS events (
+                run_id INTEGER NOT NULL REFERENCES runs (run_id),
+                tick INTEGER NOT NULL,
+                agent INTEGER NOT NULL,
+                transition TEXT NOT NULL
+            );",
+        )?;
+        Ok(Self { connection })
+    }
+
+    /// The underlying connection, for querying the results
+    #[must_use]
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Write a single run, returning its `run_id`
+    pub fn write_run(&mut self, run: &RunResult) -> Result<i64> {
+        Ok(self.write_runs(std::slice::from_ref(run))?[0])
+    }
+
+    /// Write `runs` in a single transaction, returning their `run_id`s
+    pub fn write_runs(&mut self, runs: &[RunResult]) -> Result<Vec<i64>> {
+        let transaction = self.connection.transaction()?;
+        let run_ids = runs
+            .iter()
+            .map(|run| insert_run(&transaction, run))
+            .collect::<Result<Vec<_>>>()?;
+        transaction.commit()?;
+        Ok(run_ids)
+    }
+
+    /// Run `replicates` replicates of `params` as
+    /// [`run_replicates`](ensemble::run_replicates) does, writing each of them in its own
+    /// transaction as soon as it finishes, and returning their `run_id`s in the order of the
+    /// replicates, where those that were cancelled are `None`.
+    ///
+    /// Once a write fails, the replicates that finish afterwards are no longer written, and
+    /// the error is returned after all replicates have finished.
+    pub fn write_replicates(
+        &mut self,
+        params: &SimulationParams,
+        replicates: usize,
+        cancel: &AtomicBool,
+    ) -> Result<Vec<Option<i64>>> {
+        let mut run_ids = vec![None; replicates];
+        let mut error = None;
+        ensemble::run_replicates_with(params, replicates, cancel, None, |replicate, run| {
+            if error.is_none() {
+                match self.write_run(run) {
+                    Ok(run_id) => run_ids[replicate] = Some(run_id),
+                    Err(err) => error = Some(err),
+                }
+            }
+        });
+        error.map_or(Ok(run_ids), Err)
+    }
+}
+
+fn insert_run(transaction: &Transaction, run: &RunResult) -> Result<i64> {
+    let record = &run.record;
+    let metadata = &run.metadata;
+    let params_json = serde_json::to_string(&metadata.params)
+        .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?;
+
+    let summary = run.summary();
+
+    transaction.execute(
+        "INSERT INTO runs (params, seed, ticks, peak_infected, peak_tick, attack_rate, deaths)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            params_json,
+            metadata.seed.map(|seed| seed as i64),
+            record.len() as i64,
+            summary.peak_infected as i64,
+            summary.peak_tick as i64,
+            summary.attack_rate,
+            summary.deaths as i64,
+        ],
+    )?;
+    let run_id = transaction.last_insert_rowid();
+
+    let mut insert_tally = transaction.prepare_cached(
+        "INSERT INTO tallies (run_id, tick, s, e, i, q, r, v, d)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+    for tick in 0..record.len() {
+        insert_tally.execute(params![
+            run_id,
+            tick as i64,
+            record.susceptible[tick] as i64,
+            record.exposed[tick] as i64,
+            record.infected[tick] as i64,
+            record.quarantined[tick] as i64,
+            record.recovered[tick] as i64,
+            record.vaccinated[tick] as i64,
+            record.dead[tick] as i64,
+        ])?;
+    }
+
+    let mut insert_event = transaction.prepare_cached(
+        "INSERT INTO events (run_id, tick, agent, transition) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for seeded in &run.seeding_log {
+        insert_event.execute(params![
+            run_id,
+            seeded.tick as i64,
+            seeded.agent as i64,
+            "seeded"
+        ])?;
+    }
+    for spillover in &run.spillover_log {
+        insert_event.execute(params![
+            run_id,
+            spillover.tick as i64,
+            spillover.agent as i64,
+            "spillover"
+        ])?;
+    }
+
+    Ok(run_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::julia_reimpl::TallyStates;
+    use crate::params::{SeedLocation, SeedingEntry};
+    use crate::result::run;
+
+    /// Tallies of the run `run_id` in `connection`, in the order of their ticks
+    fn tallies(connection: &Connection, run_id: i64) -> Vec<(usize, TallyStates)> {
+        let mut statement = connection
+            .prepare(
+                "SELECT tick, s, e, i, q, r, v, d FROM tallies WHERE run_id = ?1 ORDER BY tick",
+            )
+            .unwrap();
+        statement
+            .query_map([run_id], |row| {
+                let count = |column| -> Result<usize> { Ok(row.get::<_, i64>(column)? as usize) };
+                Ok((
+                    count(0)?,
+                    TallyStates {
+                        susceptible: count(1)?,
+                        exposed: count(2)?,
+                        infected: count(3)?,
+                        quarantined: count(4)?,
+                        recovered: count(5)?,
+                        vaccinated: count(6)?,
+                        dead: count(7)?,
+                    },
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_write_ensemble() {
+        let params = SimulationParams {
+            seeding: vec![SeedingEntry {
+                tick: 1,
+                count: 2,
+                location: SeedLocation::Anywhere,
+            }],
+            incubation: 2,
+            ..crate::presets::dense_small_grid()
+        };
+        let runs: Vec<_> = (0..4)
+            .map(|seed| {
+                run(&SimulationParams {
+                    seed: Some(seed),
+                    ..params.clone()
+                })
+            })
+            .collect();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = SqliteSink::open(dir.path().join("results.sqlite")).unwrap();
+        // incrementally, as the replicates finish
+        let mut run_ids = sink.write_runs(&runs[..1]).unwrap();
+        run_ids.extend(sink.write_runs(&runs[1..3]).unwrap());
+        run_ids.push(sink.write_run(&runs[3]).unwrap());
+
+        let connection = sink.connection();
+        let count = |sql: &str| -> i64 { connection.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM runs"), 4);
+        let total_ticks: usize = runs.iter().map(|run| run.record.len()).sum();
+        assert_eq!(count("SELECT COUNT(*) FROM tallies") as usize, total_ticks);
+        let seeded: usize = runs.iter().map(|run| run.seeding_log.len()).sum();
+        assert_eq!(count("SELECT COUNT(*) FROM events") as usize, seeded);
+
+        let expected = &runs[2];
+        let curve = tallies(connection, run_ids[2]);
+        assert_eq!(
+            curve,
+            expected
+                .records()
+                .into_iter()
+                .enumerate()
+                .collect::<Vec<_>>()
+        );
+        assert!(curve.iter().any(|(_, tally)| tally.exposed > 0));
+
+        let (json, peak, attack_rate, deaths): (String, i64, f64, i64) = connection
+            .query_row(
+                "SELECT params, peak_infected, attack_rate, deaths FROM runs WHERE run_id = ?1",
+                [run_ids[2]],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<SimulationParams>(&json).unwrap(),
+            expected.metadata.params
+        );
+        let summary = expected.summary();
+        assert_eq!(peak as usize, summary.peak_infected);
+        assert_eq!(attack_rate, summary.attack_rate);
+        assert_eq!(deaths as usize, summary.deaths);
+    }
+
+    #[test]
+    fn test_write_replicates() {
+        let params = SimulationParams {
+            seed: Some(9),
+            ..crate::presets::dense_small_grid()
+        };
+        let mut sink = SqliteSink::from_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let run_ids = sink
+            .write_replicates(&params, 3, &AtomicBool::new(false))
+            .unwrap();
+        assert_eq!(run_ids.len(), 3);
+        for (replicate, run_id) in run_ids.into_iter().enumerate() {
+            let expected = run(&ensemble::replicate_params(&params, replicate)).records();
+            let curve = tallies(sink.connection(), run_id.unwrap());
+            assert_eq!(curve, expected.into_iter().enumerate().collect::<Vec<_>>());
+        }
+
+        let cancelled = sink
+            .write_replicates(&params, 2, &AtomicBool::new(true))
+            .unwrap();
+        assert_eq!(cancelled, vec![None, None]);
+    }
+}
@@ -0,0 +1,382 @@
+//! Tallies stratified by an attribute of the agents, see [`Stratification`].
+//!
+//! A [`StratifiedTally`] splits the tally of each tick into one series per value of the
+//! attribute, a stratum, which together sum to the tally of the whole population. Agents
+//! whose attribute changes during a run, e.g. by being vaccinated, count in their new
+//! stratum from the tick of the change. Only the stratifications listed in
+//! [`SimulationParams::stratify`](crate::params::SimulationParams::stratify) are recorded by
+//! [`result::run`](crate::result::run).
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::julia_reimpl::{AgentType, AgentView, Environment, TallyStates, TallyStatesVec};
+use crate::layout::AgentAttributes;
+use crate::params::Stratification;
+
+/// Stratum of agents without a value for the attribute
+pub const UNKNOWN: &str = "unknown";
+
+/// User-defined data of an agent that carries attributes to stratify by
+pub trait Attributes {
+    /// Age in years, if known
+    fn age(&self) -> Option<u32> {
+        None
+    }
+
+    /// Value of the attribute `name`, if any
+    fn attribute(&self, _name: &str) -> Option<&str> {
+        None
+    }
+
+    /// Names of all attributes with a value
+    fn attribute_names(&self) -> Vec<&str> {
+        Vec::new()
+    }
+}
+
+impl Attributes for () {}
+
+impl Attributes for AgentAttributes {
+    fn age(&self) -> Option<u32> {
+        self.age
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+
+    fn attribute_names(&self) -> Vec<&str> {
+        self.attributes.keys().map(String::as_str).collect()
+    }
+}
+
+/// Label of the age group of `age`, where the groups start at each of `bounds`
+fn age_group(bounds: &[u32], age: u32) -> String {
+    let group = bounds.iter().take_while(|&&bound| bound <= age).count();
+    let low = if group == 0 { 0 } else { bounds[group - 1] };
+    match bounds.get(group) {
+        Some(&high) => format!("{}-{}", low, high - 1),
+        None => format!("{}+", low),
+    }
+}
+
+/// Stratum of `agent` with user-defined data `data`
+fn stratum(stratification: &Stratification, agent: &AgentView, data: &impl Attributes) -> String {
+    let label = match stratification {
+        Stratification::Essential if agent.essential => "essential",
+        Stratification::Essential => "other",
+        Stratification::Vaccinated if agent.immune_history.doses().next().is_some() => "vaccinated",
+        Stratification::Vaccinated => "unvaccinated",
+        Stratification::Age { bounds } => {
+            return data
+                .age()
+                .map_or_else(|| UNKNOWN.to_string(), |age| age_group(bounds, age))
+        }
+        Stratification::Attribute { name } => data.attribute(name).unwrap_or(UNKNOWN),
+    };
+    label.to_string()
+}
+
+/// Tally of each tick split by a [`Stratification`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StratifiedTally {
+    pub stratification: Stratification,
+    /// Tally of each stratum for each recorded tick, which is zero at the ticks the stratum
+    /// had no agents
+    pub strata: BTreeMap<String, TallyStatesVec>,
+    /// Number of recorded ticks
+    ticks: usize,
+}
+
+impl StratifiedTally {
+    #[must_use]
+    pub fn new(stratification: Stratification) -> Self {
+        Self {
+            stratification,
+            strata: BTreeMap::new(),
+            ticks: 0,
+        }
+    }
+
+    /// Number of recorded ticks
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.ticks
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.ticks == 0
+    }
+
+    /// Record the current tally of each stratum of `env`
+    pub fn record<X: Attributes>(&mut self, env: &Environment<X>) {
+        let mut counts: BTreeMap<String, TallyStates> = BTreeMap::new();
+        for agent in env.iter_agents() {
+            let data = env.ext(agent.id).expect("every agent has its data");
+            let tally = counts
+                .entry(stratum(&self.stratification, &agent, data))
+                .or_default();
+            match agent.state {
+                AgentType::AgentS => tally.susceptible += 1,
+                AgentType::AgentE => tally.exposed += 1,
+                AgentType::AgentI => tally.infected += 1,
+                AgentType::AgentQ => tally.quarantined += 1,
+                AgentType::AgentR => tally.recovered += 1,
+                AgentType::AgentV => tally.vaccinated += 1,
+                AgentType::AgentD => tally.dead += 1,
+            }
+        }
+        let ticks = self.ticks;
+        for (label, tally) in counts {
+            self.strata
+                .entry(label)
+                .or_insert_with(|| (0..ticks).map(|_| TallyStates::default()).collect())
+                .push(tally);
+        }
+        for series in self.strata.values_mut() {
+            if series.len() == ticks {
+                series.push(TallyStates::default());
+            }
+        }
+        self.ticks += 1;
+    }
+
+    /// Sum of the strata at recorded tick `tick`
+    ///
+    /// # Panics
+    ///
+    /// If the tick was not recorded.
+    #[must_use]
+    pub fn total(&self, tick: usize) -> TallyStates {
+        assert!(tick < self.ticks, "tick {} was not recorded", tick);
+        self.strata
+            .values()
+            .fold(TallyStates::default(), |mut acc, series| {
+                acc.susceptible += series.susceptible[tick];
+                acc.infected += series.infected[tick];
+                acc.recovered += series.recovered[tick];
+                acc.dead += series.dead[tick];
+                acc
+            })
+    }
+}
+
+/// Run `env` like [`Environment::run_with_hook`], while recording the tally of each of
+/// `stratifications` at every tick from the current one, after `hook` has intervened.
+///
+/// Returns the tally record of the run together with the stratified tallies, aligned with it.
+pub fn run_stratified<X: Attributes>(
+    env: &mut Environment<X>,
+    stratifications: &[Stratification],
+    mut hook: impl FnMut(&mut Environment<X>, usize),
+) -> (Vec<TallyStates>, Vec<StratifiedTally>) {
+    let mut tallies: Vec<StratifiedTally> = stratifications
+        .iter()
+        .cloned()
+        .map(StratifiedTally::new)
+        .collect();
+    for tally in &mut tallies {
+        tally.record(env);
+    }
+    let record = env.run_with_hook(|env, tick| {
+        hook(env, tick);
+        for tally in &mut tallies {
+            tally.record(env);
+        }
+    });
+    (record, tallies)
+}
+
+/// Write `tallies` as a single CSV table in long format, with one row per stratification,
+/// stratum and tick
+pub fn write_long_csv<W: io::Write>(mut writer: W, tallies: &[StratifiedTally]) -> io::Result<()> {
+    writeln!(
+        writer,
+        "stratification,stratum,tick,susceptible,infected,recovered,dead"
+    )?;
+    for tally in tallies {
+        for (stratum, series) in &tally.strata {
+            for tick in 0..series.len() {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{}",
+                    tally.stratification.name(),
+                    stratum,
+                    tick,
+                    series.susceptible[tick],
+                    series.infected[tick],
+                    series.recovered[tick],
+                    series.dead[tick]
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::SimulationParams;
+    use crate::presets;
+    use crate::result;
+
+    #[test]
+    fn test_age_groups() {
+        let bounds = [18, 65];
+        assert_eq!(age_group(&bounds, 0), "0-17");
+        assert_eq!(age_group(&bounds, 18), "18-64");
+        assert_eq!(age_group(&bounds, 64), "18-64");
+        assert_eq!(age_group(&bounds, 90), "65+");
+        assert_eq!(age_group(&[], 40), "0+");
+        assert_eq!(age_group(&[0, 10], 3), "0-9");
+    }
+
+    #[test]
+    fn test_strata_sum_to_tally() {
+        // agents of ages 0 to 99 on a small grid, every tenth infected
+        let layout: Vec<_> = (0..400)
+            .map(|i| {
+                let state = if i % 10 == 0 {
+                    AgentType::AgentI
+                } else {
+                    AgentType::AgentS
+                };
+                (i % 20, i / 20, state)
+            })
+            .collect();
+        let mut environment =
+            Environment::from_layout(&layout, 5, 0.1, (20, 20), Some(1), |i, _, _| {
+                AgentAttributes {
+                    age: if i % 7 == 0 {
+                        None
+                    } else {
+                        Some(i as u32 % 100)
+                    },
+                    attributes: std::iter::once((
+                        "risk".to_string(),
+                        if i % 3 == 0 { "high" } else { "low" }.to_string(),
+                    ))
+                    .collect(),
+                }
+            });
+        let stratifications = [
+            Stratification::Age {
+                bounds: vec![18, 65],
+            },
+            Stratification::Attribute {
+                name: "risk".to_string(),
+            },
+            Stratification::Attribute {
+                name: "missing".to_string(),
+            },
+        ];
+        let (record, tallies) = run_stratified(&mut environment, &stratifications, |_, _| {});
+        assert!(record.len() > 2);
+        for tally in &tallies {
+            assert_eq!(tally.len(), record.len());
+            for (tick, expected) in record.iter().enumerate() {
+                assert_eq!(&tally.total(tick), expected, "{:?}", tally.stratification);
+            }
+        }
+        let labels = |tally: &StratifiedTally| tally.strata.keys().cloned().collect::<Vec<_>>();
+        assert_eq!(labels(&tallies[0]), vec!["0-17", "18-64", "65+", UNKNOWN]);
+        assert_eq!(labels(&tallies[1]), vec!["high", "low"]);
+        assert_eq!(labels(&tallies[2]), vec![UNKNOWN]);
+    }
+
+    #[test]
+    fn test_vaccination_moves_stratum() {
+        let mut environment = Environment::from_params(&presets::dense_small_grid());
+        let mut vaccinated = None;
+        let (record, tallies) = run_stratified(
+            &mut environment,
+            &[Stratification::Vaccinated],
+            |environment, tick| {
+                if tick == 2 {
+                    let id = environment
+                        .iter_agents()
+                        .find(|agent| agent.state == AgentType::AgentS)
+                        .map(|agent| agent.id);
+                    if let Some(id) = id {
+                        assert!(environment.immunize(id));
+                        vaccinated = Some(id);
+                    }
+                }
+            },
+        );
+        assert!(vaccinated.is_some());
+        let strata = &tallies[0].strata;
+        let series = &strata["vaccinated"];
+        assert_eq!(series.len(), record.len());
+        // the stratum is empty up to the tick of the dose, and holds the immunized agent after
+        assert_eq!(series.vaccinated[..2], [0, 0]);
+        assert!(series.vaccinated[2..]
+            .iter()
+            .all(|&vaccinated| vaccinated == 1));
+        let others = &strata["unvaccinated"];
+        assert_eq!(others.vaccinated[2] + 1, record[2].vaccinated);
+        assert_eq!(others.recovered[2], record[2].recovered);
+    }
+
+    #[test]
+    fn test_run_result_and_csv() {
+        let params = SimulationParams {
+            essential_fraction: 0.3,
+            stratify: vec![Stratification::Essential, Stratification::Vaccinated],
+            ..presets::dense_small_grid()
+        };
+        let run = result::run(&params);
+        assert_eq!(run.strata.len(), 2);
+        let records = run.records();
+        for tally in &run.strata {
+            assert_eq!(tally.len(), records.len());
+            for (tick, expected) in records.iter().enumerate() {
+                assert_eq!(&tally.total(tick), expected);
+            }
+        }
+        let essential = &run.strata[0].strata["essential"];
+        assert_eq!(
+            essential.susceptible[0] + essential.infected[0],
+            (0.3 * 500.0_f64).round() as usize
+        );
+        assert!(result::run(&presets::dense_small_grid()).strata.is_empty());
+
+        let mut csv = Vec::new();
+        write_long_csv(&mut csv, &run.strata).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("stratification,stratum,tick,susceptible,infected,recovered,dead")
+        );
+        // two strata of essential workers and a single one of vaccination
+        assert_eq!(lines.clone().count(), 3 * records.len());
+        assert_eq!(
+            lines
+                .next()
+                .map(|line| line.split(',').take(3).collect::<Vec<_>>()),
+            Some(vec!["essential", "essential", "0"])
+        );
+        assert!(csv.contains(&format!("\nvaccinated,unvaccinated,{},", records.len() - 1)));
+    }
+
+    #[test]
+    fn test_invalid_age_bounds() {
+        let params = SimulationParams {
+            stratify: vec![Stratification::Age {
+                bounds: vec![18, 18],
+            }],
+            ..presets::dense_small_grid()
+        };
+        assert!(matches!(
+            params.validate(),
+            Err(crate::params::ConfigError::InvalidRange {
+                name: "stratify.age",
+                ..
+            })
+        ));
+    }
+}
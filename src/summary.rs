@@ -0,0 +1,211 @@
+//! Headline metrics of an epidemic, computed from the tallies of a run.
+//!
+//! ```
+//! # use bkamins_sir_abm::julia_reimpl::Environment;
+//! # use bkamins_sir_abm::presets;
+//! let mut environment = Environment::from_params(&presets::dense_small_grid());
+//! let record = environment.run();
+//! let summary = environment.summarize(&record).unwrap();
+//! assert!(summary.peak_infected >= record[0].infected);
+//! println!("{}", summary);
+//! ```
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::julia_reimpl::TallyStates;
+
+/// Peak, final size, deaths and duration of an epidemic, see [`EpidemicSummary::from_records`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EpidemicSummary {
+    /// Largest number of infected agents at any tick, including the quarantined ones
+    pub peak_infected: usize,
+    /// Earliest tick at which `peak_infected` were infected
+    pub peak_tick: usize,
+    /// Ticks from the first tick with an infection, being an exposed, infected or quarantined
+    /// agent, to the peak
+    pub time_to_peak: usize,
+    /// Fraction of the population that was infected by the end of the run, being neither
+    /// susceptible nor vaccinated, i.e. `1 - S_end / N` without vaccination
+    pub attack_rate: f64,
+    /// Number of dead agents at the end of the run
+    pub deaths: usize,
+    /// Number of ticks from the first tick with an infection up to the first tick after it
+    /// without any, or up to the end of the records if the epidemic has not ended, and zero
+    /// if there never was an infection
+    pub duration: usize,
+    /// Number of agents that were neither susceptible nor vaccinated at each tick, being
+    /// those infected up to that tick as long as immunity does not wane
+    pub cumulative_infections: Vec<usize>,
+}
+
+impl EpidemicSummary {
+    /// Summarise `records`, the tallies of a run of `population` agents from tick zero on, or
+    /// `None` if there are no records
+    #[must_use]
+    pub fn from_records(records: &[TallyStates], population: usize) -> Option<Self> {
+        let last = records.last()?;
+        let active = |tally: &TallyStates| tally.exposed + tally.infected + tally.quarantined > 0;
+        let (peak_tick, peak_infected) = records
+            .iter()
+            .map(|tally| tally.infected + tally.quarantined)
+            .enumerate()
+            // earliest tick in case of ties
+            .fold((0, 0), |peak, (tick, infected)| {
+                if infected > peak.1 {
+                    (tick, infected)
+                } else {
+                    peak
+                }
+            });
+        let (time_to_peak, duration) = match records.iter().position(active) {
+            Some(onset) => {
+                let end = records[onset..]
+                    .iter()
+                    .position(|tally| !active(tally))
+                    .map_or(records.len(), |ticks| onset + ticks);
+                (peak_tick.saturating_sub(onset), end - onset)
+            }
+            None => (0, 0),
+        };
+        let infected_by =
+            |tally: &TallyStates| population.saturating_sub(tally.susceptible + tally.vaccinated);
+        let attack_rate = if population == 0 {
+            0.0
+        } else {
+            infected_by(last) as f64 / population as f64
+        };
+        Some(Self {
+            peak_infected,
+            peak_tick,
+            time_to_peak,
+            attack_rate,
+            deaths: last.dead,
+            duration,
+            cumulative_infections: records.iter().map(infected_by).collect(),
+        })
+    }
+}
+
+impl fmt::Display for EpidemicSummary {
+    /// The metrics on one line, e.g. `peak of 120 infected at tick 14 (14 ticks in), attack
+    /// rate 0.873, 43 deaths, lasting 40 ticks`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "peak of {} infected at tick {} ({} ticks in), attack rate {:.3}, {} deaths, \
+            lasting {} ticks",
+            self.peak_infected,
+            self.peak_tick,
+            self.time_to_peak,
+            self.attack_rate,
+            self.deaths,
+            self.duration
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::julia_reimpl::Environment;
+    use crate::params::SimulationParams;
+
+    /// Tally of `s` susceptible, `i` infected, `r` recovered and `d` dead agents
+    fn tally(s: usize, i: usize, r: usize, d: usize) -> TallyStates {
+        TallyStates {
+            susceptible: s,
+            infected: i,
+            recovered: r,
+            dead: d,
+            ..TallyStates::default()
+        }
+    }
+
+    #[test]
+    fn test_tiny_trajectory() {
+        // seeded at tick 1, with a tie at the peak of ticks 3 and 4, over by tick 6
+        let records = [
+            tally(10, 0, 0, 0),
+            tally(9, 1, 0, 0),
+            tally(7, 3, 0, 0),
+            tally(5, 4, 1, 0),
+            tally(4, 4, 2, 0),
+            tally(4, 2, 3, 1),
+            tally(4, 0, 4, 2),
+            tally(4, 0, 4, 2),
+        ];
+        let summary = EpidemicSummary::from_records(&records, 10).unwrap();
+        assert_eq!(
+            summary,
+            EpidemicSummary {
+                peak_infected: 4,
+                peak_tick: 3,
+                time_to_peak: 2,
+                attack_rate: 0.6,
+                deaths: 2,
+                duration: 5,
+                cumulative_infections: vec![0, 1, 3, 5, 6, 6, 6, 6],
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "peak of 4 infected at tick 3 (2 ticks in), attack rate 0.600, 2 deaths, \
+            lasting 5 ticks"
+        );
+        let json = serde_json::to_string(&summary).unwrap();
+        assert_eq!(
+            serde_json::from_str::<EpidemicSummary>(&json).unwrap(),
+            summary
+        );
+    }
+
+    #[test]
+    fn test_edge_cases() {
+        assert_eq!(EpidemicSummary::from_records(&[], 10), None);
+
+        // the initial cases recover without infecting anyone
+        let fizzled = [tally(8, 2, 0, 0), tally(8, 1, 1, 0), tally(8, 0, 2, 0)];
+        let summary = EpidemicSummary::from_records(&fizzled, 10).unwrap();
+        assert_eq!((summary.peak_infected, summary.peak_tick), (2, 0));
+        assert_eq!((summary.time_to_peak, summary.duration), (0, 2));
+        assert_eq!(summary.cumulative_infections, vec![2, 2, 2]);
+
+        // without any infection, or with a run cut short while still active
+        let none = EpidemicSummary::from_records(&vec![tally(5, 0, 0, 0); 3], 5).unwrap();
+        assert_eq!(
+            (none.peak_infected, none.peak_tick, none.duration),
+            (0, 0, 0)
+        );
+        assert_eq!(none.attack_rate, 0.0);
+        let ongoing = EpidemicSummary::from_records(&fizzled[..2], 10).unwrap();
+        assert_eq!(ongoing.duration, 2);
+    }
+
+    #[test]
+    fn test_summarize_run() {
+        let mut e = Environment::from_params(&SimulationParams {
+            n: 200,
+            infected: 5,
+            xdim: 10,
+            ydim: 10,
+            p_death: 0.2,
+            seed: Some(6),
+            ..SimulationParams::default()
+        });
+        let record = e.run();
+        let summary = e.summarize(&record).unwrap();
+        let last = record.last().unwrap();
+        assert_eq!(summary.deaths, last.dead);
+        assert_eq!(summary.cumulative_infections.len(), record.len());
+        assert_eq!(
+            *summary.cumulative_infections.last().unwrap(),
+            200 - last.susceptible
+        );
+        assert_eq!(
+            summary.peak_infected,
+            record.iter().map(|tally| tally.infected).max().unwrap()
+        );
+        // the run ends with the epidemic
+        assert_eq!(summary.duration, record.len() - 1);
+    }
+}
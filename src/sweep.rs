@@ -0,0 +1,508 @@
+//! Sweeps of a single parameter, reproducing the fraction-infected experiment of the blogpost,
+//! and of a grid of several parameters.
+//!
+//! For every value of the swept parameter, replicates run in parallel and are summarised by
+//! the mean and standard deviation of their attack rate, and the fraction that went extinct.
+//! For every combination of a [`ParameterGrid`], [`run_sweep`] summarises each replicate by
+//! its [`ReplicateOutcome`] and their means.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use crate::ensemble;
+use crate::params::{ConfigError, EnvironmentConfig, SimulationParams};
+use crate::result::{self, RunResult};
+
+/// Attack rate below which a replicate counts as extinct, i.e. the epidemic fizzled out
+pub const EXTINCTION_ATTACK_RATE: f64 = 0.1;
+
+/// Parameter that can be swept, serialized by its [name](SweepParameter::name)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SweepParameter {
+    /// [`SimulationParams::duration`], swept over whole ticks
+    Duration,
+    /// [`SimulationParams::p_death`]
+    PDeath,
+    /// [`SimulationParams::p_infect`], the transmissibility, also accepted as `beta`
+    PInfect,
+    /// [`SimulationParams::n`], swept over whole agents
+    Population,
+}
+
+impl SweepParameter {
+    /// Names accepted by [`SweepParameter::from_str`]
+    pub const NAMES: [&'static str; 4] = ["duration", "p-death", "p-infect", "population"];
+
+    /// Name of the parameter
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            SweepParameter::Duration => "duration",
+            SweepParameter::PDeath => "p-death",
+            SweepParameter::PInfect => "p-infect",
+            SweepParameter::Population => "population",
+        }
+    }
+
+    /// `base` with the parameter set to `value`
+    pub(crate) fn apply(self, base: &SimulationParams, value: f64) -> SimulationParams {
+        let mut params = base.clone();
+        match self {
+            SweepParameter::Duration => params.duration = value.round() as usize,
+            SweepParameter::PDeath => params.p_death = value,
+            SweepParameter::PInfect => params.p_infect = value,
+            SweepParameter::Population => params.n = value.round() as usize,
+        }
+        params
+    }
+}
+
+/// The parameter name is not one of [`SweepParameter::NAMES`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownParameter(pub String);
+
+impl fmt::Display for UnknownParameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown parameter `{}`, expected one of: {}",
+            self.0,
+            SweepParameter::NAMES.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownParameter {}
+
+impl FromStr for SweepParameter {
+    type Err = UnknownParameter;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "duration" => Ok(SweepParameter::Duration),
+            "p-death" | "p_death" => Ok(SweepParameter::PDeath),
+            "p-infect" | "p_infect" | "beta" => Ok(SweepParameter::PInfect),
+            "population" | "n" => Ok(SweepParameter::Population),
+            _ => Err(UnknownParameter(name.to_string())),
+        }
+    }
+}
+
+/// Summary of the replicates at one value of the swept parameter
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepPoint {
+    pub value: f64,
+    pub replicates: usize,
+    /// Mean fraction of the population that was infected at some point
+    pub mean_attack_rate: f64,
+    /// Sample standard deviation of the attack rate, zero for a single replicate
+    pub sd_attack_rate: f64,
+    /// Fraction of replicates with an attack rate below [`EXTINCTION_ATTACK_RATE`]
+    pub extinction_fraction: f64,
+}
+
+/// Values from `from` to `to` inclusive, in increments of `step`
+#[must_use]
+pub fn values(from: f64, to: f64, step: f64) -> Vec<f64> {
+    if step.is_nan() || step <= 0.0 || from > to {
+        return Vec::new();
+    }
+    // tolerate rounding errors at the end of the range
+    let count = ((to - from) / step + 1e-9).floor() as usize + 1;
+    (0..count).map(|i| from + i as f64 * step).collect()
+}
+
+/// Run `replicates` replicates at each of `values` of `parameter`, starting from `base`.
+///
+/// Fails without running anything if any of the resulting parameter sets is invalid.
+pub fn sweep(
+    base: &SimulationParams,
+    parameter: SweepParameter,
+    values: &[f64],
+    replicates: usize,
+) -> Result<Vec<SweepPoint>, ConfigError> {
+    sweep_with_budget(base, parameter, values, replicates, None)
+}
+
+/// Like [`sweep`], within an overall `budget` of wall-clock time, if any.
+///
+/// The values are run in order, and once the budget is exhausted no further replicates are
+/// started, see [`ensemble::run_replicates_with_budget`]. Each point counts the replicates
+/// that completed, and values without any are left out.
+pub fn sweep_with_budget(
+    base: &SimulationParams,
+    parameter: SweepParameter,
+    values: &[f64],
+    replicates: usize,
+    budget: Option<Duration>,
+) -> Result<Vec<SweepPoint>, ConfigError> {
+    let points: Vec<_> = values
+        .iter()
+        .map(|&value| {
+            let params = parameter.apply(base, value);
+            EnvironmentConfig::from(params)
+                .build_params()
+                .map(|(params, _)| (value, params))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let cancel = AtomicBool::new(false);
+    let deadline = budget.map(|budget| Instant::now() + budget);
+    Ok(points
+        .into_iter()
+        .filter_map(|(value, params)| {
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let rates: Vec<f64> =
+                ensemble::run_replicates_with_budget(&params, replicates, &cancel, remaining)
+                    .iter()
+                    .flatten()
+                    .map(|run| run.summary().attack_rate)
+                    .collect();
+            if rates.is_empty() && replicates > 0 {
+                None
+            } else {
+                Some(summarise(value, &rates))
+            }
+        })
+        .collect())
+}
+
+fn summarise(value: f64, rates: &[f64]) -> SweepPoint {
+    let replicates = rates.len();
+    let mean = rates.iter().sum::<f64>() / replicates as f64;
+    let sd = if replicates > 1 {
+        let squares: f64 = rates.iter().map(|rate| (rate - mean).powi(2)).sum();
+        (squares / (replicates - 1) as f64).sqrt()
+    } else {
+        0.0
+    };
+    let extinct = rates
+        .iter()
+        .filter(|&&rate| rate < EXTINCTION_ATTACK_RATE)
+        .count();
+    SweepPoint {
+        value,
+        replicates,
+        mean_attack_rate: mean,
+        sd_attack_rate: sd,
+        extinction_fraction: extinct as f64 / replicates as f64,
+    }
+}
+
+/// Cartesian product of the values of several parameters, set into a base scenario
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterGrid {
+    pub base: SimulationParams,
+    /// Values of each swept parameter, where the last one varies fastest across the
+    /// combinations
+    pub axes: Vec<(SweepParameter, Vec<f64>)>,
+}
+
+impl ParameterGrid {
+    /// Grid of the single combination `base`, until axes are added
+    #[must_use]
+    pub fn new(base: SimulationParams) -> Self {
+        Self {
+            base,
+            axes: Vec::new(),
+        }
+    }
+
+    /// Sweep `parameter` over `values`, in combination with the axes added before
+    #[must_use]
+    pub fn axis(mut self, parameter: SweepParameter, values: Vec<f64>) -> Self {
+        self.axes.push((parameter, values));
+        self
+    }
+
+    /// Every combination of a value of each axis, in the order of the axes
+    #[must_use]
+    pub fn combinations(&self) -> Vec<Vec<(SweepParameter, f64)>> {
+        self.axes
+            .iter()
+            .fold(vec![Vec::new()], |combinations, (parameter, values)| {
+                combinations
+                    .iter()
+                    .flat_map(|combination| {
+                        values.iter().map(move |&value| {
+                            let mut combination = combination.clone();
+                            combination.push((*parameter, value));
+                            combination
+                        })
+                    })
+                    .collect()
+            })
+    }
+}
+
+/// Outcome of a single replicate of a [`SweepResult`], mostly taken from its
+/// [`EpidemicSummary`](crate::summary::EpidemicSummary)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplicateOutcome {
+    /// Fraction of the population that was infected at some point, see
+    /// [`EpidemicSummary::attack_rate`](crate::summary::EpidemicSummary::attack_rate)
+    pub attack_rate: f64,
+    pub deaths: usize,
+    /// Number of ticks until the run ended
+    pub length: usize,
+    /// Largest number of infected agents at any tick, including the quarantined ones
+    pub peak_infected: usize,
+}
+
+impl ReplicateOutcome {
+    fn new(run: &RunResult) -> Self {
+        let summary = run.summary();
+        Self {
+            attack_rate: summary.attack_rate,
+            deaths: summary.deaths,
+            length: run.record.len() - 1,
+            peak_infected: summary.peak_infected,
+        }
+    }
+}
+
+/// Outcomes of the replicates at one combination of a [`ParameterGrid`], and their means
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SweepResult {
+    pub combination: Vec<(SweepParameter, f64)>,
+    pub outcomes: Vec<ReplicateOutcome>,
+    pub mean_attack_rate: f64,
+    pub mean_deaths: f64,
+    pub mean_length: f64,
+    pub mean_peak_infected: f64,
+}
+
+impl SweepResult {
+    fn new(combination: Vec<(SweepParameter, f64)>, outcomes: Vec<ReplicateOutcome>) -> Self {
+        let mean = |value: fn(&ReplicateOutcome) -> f64| {
+            outcomes.iter().map(value).sum::<f64>() / outcomes.len() as f64
+        };
+        Self {
+            mean_attack_rate: mean(|outcome| outcome.attack_rate),
+            mean_deaths: mean(|outcome| outcome.deaths as f64),
+            mean_length: mean(|outcome| outcome.length as f64),
+            mean_peak_infected: mean(|outcome| outcome.peak_infected as f64),
+            combination,
+            outcomes,
+        }
+    }
+}
+
+/// Run `replicates` replicates at each combination of `grid`, in the order of
+/// [`ParameterGrid::combinations`], where all runs share the available cores.
+///
+/// The replicates of combination `c` are an ensemble with the seed
+/// [`ensemble::replicate_seed`] of `master_seed` and `c`, such that the sweep is reproducible
+/// regardless of the number of cores, and combinations share no replicate seeds in practice.
+/// Fails without running anything if any combination is invalid.
+pub fn run_sweep(
+    grid: &ParameterGrid,
+    replicates: usize,
+    master_seed: u64,
+) -> Result<Vec<SweepResult>, ConfigError> {
+    let combinations = grid.combinations();
+    let runs: Vec<SimulationParams> = combinations
+        .iter()
+        .enumerate()
+        .map(|(index, combination)| {
+            let params = combination
+                .iter()
+                .fold(grid.base.clone(), |params, &(parameter, value)| {
+                    parameter.apply(&params, value)
+                });
+            EnvironmentConfig::from(params)
+                .build_params()
+                .map(|(params, _)| SimulationParams {
+                    seed: Some(ensemble::replicate_seed(master_seed, index)),
+                    ..params
+                })
+        })
+        .collect::<Result<_, _>>()?;
+
+    let total = runs.len() * replicates;
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(total);
+    let results = ensemble::dispatch(
+        total,
+        threads,
+        &AtomicBool::new(false),
+        None,
+        |run, _| {
+            let params = ensemble::replicate_params(&runs[run / replicates], run % replicates);
+            result::run(&params)
+        },
+        |_, _| {},
+    );
+    let mut outcomes = results
+        .iter()
+        .map(|run| ReplicateOutcome::new(run.as_ref().expect("sweeps are not cancelled")));
+    Ok(combinations
+        .into_iter()
+        .map(|combination| {
+            let outcomes = outcomes.by_ref().take(replicates).collect();
+            SweepResult::new(combination, outcomes)
+        })
+        .collect())
+}
+
+/// Write `points` as CSV, with the swept parameter named `parameter` in the first column
+pub fn write_csv<W: io::Write>(
+    mut writer: W,
+    parameter: SweepParameter,
+    points: &[SweepPoint],
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{},replicates,mean_attack_rate,sd_attack_rate,extinction_fraction",
+        parameter.name()
+    )?;
+    for point in points {
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            point.value,
+            point.replicates,
+            point.mean_attack_rate,
+            point.sd_attack_rate,
+            point.extinction_fraction
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    #[test]
+    fn test_values() {
+        assert_eq!(values(5.0, 8.0, 1.0), vec![5.0, 6.0, 7.0, 8.0]);
+        assert_eq!(values(0.0, 0.3, 0.1).len(), 4);
+        assert!(values(1.0, 0.0, 1.0).is_empty());
+        assert!(values(0.0, 1.0, 0.0).is_empty());
+    }
+
+    #[test]
+    fn test_sweep_duration() {
+        let points = sweep(
+            &presets::dense_small_grid(),
+            SweepParameter::Duration,
+            &values(2.0, 6.0, 2.0),
+            3,
+        )
+        .unwrap();
+        assert_eq!(points.len(), 3);
+        for (point, value) in points.iter().zip(&[2.0, 4.0, 6.0]) {
+            assert_eq!(point.value, *value);
+            assert_eq!(point.replicates, 3);
+            assert!((0.0..=1.0).contains(&point.mean_attack_rate));
+            assert!((0.0..=1.0).contains(&point.extinction_fraction));
+        }
+
+        let mut csv = Vec::new();
+        write_csv(&mut csv, SweepParameter::Duration, &points).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("duration,replicates,"));
+        assert_eq!(csv.lines().count(), 4);
+    }
+
+    #[test]
+    fn test_sweep_budget() {
+        let sweep = |budget| {
+            sweep_with_budget(
+                &presets::dense_small_grid(),
+                SweepParameter::Duration,
+                &[2.0, 3.0],
+                2,
+                Some(budget),
+            )
+            .unwrap()
+        };
+        assert!(sweep(Duration::from_secs(0)).is_empty());
+        let points = sweep(Duration::from_secs(600));
+        assert_eq!(points.len(), 2);
+        assert!(points.iter().all(|point| point.replicates == 2));
+    }
+
+    #[test]
+    fn test_sweep_rejects_invalid_values() {
+        let result = sweep(
+            &presets::dense_small_grid(),
+            SweepParameter::PDeath,
+            &[0.5, 1.5],
+            1,
+        );
+        assert!(matches!(
+            result,
+            Err(ConfigError::InvalidProbability { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parameter_names() {
+        for name in &SweepParameter::NAMES {
+            assert_eq!(name.parse::<SweepParameter>().unwrap().name(), *name);
+        }
+        assert_eq!("beta".parse(), Ok(SweepParameter::PInfect));
+        assert!("gamma".parse::<SweepParameter>().is_err());
+    }
+
+    #[test]
+    fn test_parameter_grid() {
+        let grid = ParameterGrid::new(SimulationParams {
+            n: 100,
+            infected: 5,
+            xdim: 10,
+            ydim: 10,
+            ..presets::blog_default()
+        })
+        .axis(SweepParameter::Duration, vec![5.0, 10.0])
+        .axis(SweepParameter::PDeath, vec![0.0, 0.5]);
+        assert_eq!(
+            grid.combinations()[1],
+            vec![
+                (SweepParameter::Duration, 5.0),
+                (SweepParameter::PDeath, 0.5)
+            ]
+        );
+
+        let results = run_sweep(&grid, 2, 17).unwrap();
+        assert_eq!(results.len(), 4);
+        for (result, combination) in results.iter().zip(grid.combinations()) {
+            assert_eq!(result.combination, combination);
+            assert_eq!(result.outcomes.len(), 2);
+            let deaths = result
+                .outcomes
+                .iter()
+                .map(|outcome| outcome.deaths)
+                .sum::<usize>();
+            assert_eq!(result.mean_deaths, deaths as f64 / 2.0);
+            for outcome in &result.outcomes {
+                assert!((0.0..=1.0).contains(&outcome.attack_rate));
+                assert!(outcome.peak_infected >= 5);
+            }
+        }
+        assert!(results[0]
+            .outcomes
+            .iter()
+            .all(|outcome| outcome.deaths == 0));
+        assert_eq!(run_sweep(&grid, 2, 17).unwrap(), results);
+        assert_ne!(run_sweep(&grid, 2, 18).unwrap(), results);
+        let json = serde_json::to_string(&results).unwrap();
+        assert!(json.contains(r#""p-death""#), "{}", json);
+        assert_eq!(
+            serde_json::from_str::<Vec<SweepResult>>(&json).unwrap(),
+            results
+        );
+
+        let invalid = grid.axis(SweepParameter::PInfect, vec![2.0]);
+        assert!(run_sweep(&invalid, 2, 17).is_err());
+    }
+}
@@ -0,0 +1,366 @@
+//! Decomposition of the variance of an outcome into parameter and stochastic uncertainty.
+//!
+//! A two-level ensemble samples parameter sets from their ranges by Latin hypercube sampling,
+//! and runs replicates of each set. By the law of total variance, the variance of the outcome
+//! across all runs is the sum of the variance of the means of the parameter sets, and the mean
+//! of the variances within the parameter sets. Note that the means of the parameter sets
+//! carry stochastic noise of their own, such that the between-parameter component includes
+//! about `1 / replicates` of the within-parameter component.
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::io;
+use std::sync::atomic::AtomicBool;
+
+use crate::ensemble;
+use crate::params::{ConfigError, EnvironmentConfig, SimulationParams};
+use crate::result::RunResult;
+use crate::sweep::SweepParameter;
+
+/// Outcome of a run whose variance is decomposed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Number of agents that were ever infected
+    FinalSize,
+    /// Largest number of agents infected at the same time, including the quarantined ones
+    Peak,
+    /// Number of agents that died
+    Deaths,
+}
+
+impl Metric {
+    /// Name of the metric
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::FinalSize => "final_size",
+            Metric::Peak => "peak",
+            Metric::Deaths => "deaths",
+        }
+    }
+
+    /// Value of the metric for `run`, taken from its
+    /// [`EpidemicSummary`](crate::summary::EpidemicSummary)
+    #[must_use]
+    pub fn value(self, run: &RunResult) -> f64 {
+        let summary = run.summary();
+        match self {
+            Metric::FinalSize => summary.cumulative_infections.last().copied().unwrap_or(0) as f64,
+            Metric::Peak => summary.peak_infected as f64,
+            Metric::Deaths => summary.deaths as f64,
+        }
+    }
+}
+
+/// Range of values from which a parameter is sampled
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterRange {
+    pub parameter: SweepParameter,
+    pub low: f64,
+    pub high: f64,
+}
+
+/// Latin hypercube sample of `samples` points from `ranges`, where each point holds a value
+/// for each range, and the values of each range are spread over as many strata of equal width.
+#[must_use]
+pub fn latin_hypercube(
+    ranges: &[ParameterRange],
+    samples: usize,
+    rng: &mut impl Rng,
+) -> Vec<Vec<f64>> {
+    let columns: Vec<Vec<f64>> = ranges
+        .iter()
+        .map(|range| {
+            let mut strata: Vec<usize> = (0..samples).collect();
+            strata.shuffle(rng);
+            strata
+                .into_iter()
+                .map(|stratum| {
+                    let u = (stratum as f64 + rng.gen::<f64>()) / samples as f64;
+                    range.low + u * (range.high - range.low)
+                })
+                .collect()
+        })
+        .collect();
+    (0..samples)
+        .map(|sample| columns.iter().map(|column| column[sample]).collect())
+        .collect()
+}
+
+/// A run of a two-level ensemble
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnsembleRun {
+    /// Index of the parameter set
+    pub parameter_set: usize,
+    pub replicate: usize,
+    /// Values of the parameters, in the order of the ranges
+    pub parameters: Vec<f64>,
+    pub metric: f64,
+}
+
+/// Variance of a metric across all runs, split into its two components
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarianceDecomposition {
+    pub metric: Metric,
+    pub mean: f64,
+    pub total: f64,
+    /// Variance of the means of the parameter sets
+    pub between: f64,
+    /// Mean of the variances within the parameter sets, being the stochastic component
+    pub within: f64,
+}
+
+impl VarianceDecomposition {
+    /// Fraction of the total variance that is due to the parameters, if there is any variance
+    #[must_use]
+    pub fn between_fraction(&self) -> Option<f64> {
+        if self.total > 0.0 {
+            Some(self.between / self.total)
+        } else {
+            None
+        }
+    }
+
+    /// Fraction of the total variance that is stochastic, if there is any variance
+    #[must_use]
+    pub fn within_fraction(&self) -> Option<f64> {
+        self.between_fraction().map(|fraction| 1.0 - fraction)
+    }
+}
+
+/// Runs of a two-level ensemble with the decomposition of their variance
+#[derive(Debug, Clone, PartialEq)]
+pub struct TwoLevelEnsemble {
+    pub ranges: Vec<ParameterRange>,
+    /// Runs ordered by parameter set and replicate
+    pub runs: Vec<EnsembleRun>,
+    pub decomposition: VarianceDecomposition,
+}
+
+/// Population variance of `values` around `mean`
+fn variance(values: impl Iterator<Item = f64>, mean: f64) -> f64 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), value| {
+        (sum + (value - mean).powi(2), count + 1)
+    });
+    sum / count as f64
+}
+
+/// Decompose the variance of `values`, given for each parameter set
+fn decompose(metric: Metric, values: &[Vec<f64>]) -> VarianceDecomposition {
+    let all = || values.iter().flatten().copied();
+    let mean = all().sum::<f64>() / all().count() as f64;
+    let means: Vec<f64> = values
+        .iter()
+        .map(|set| set.iter().sum::<f64>() / set.len() as f64)
+        .collect();
+    let within = values
+        .iter()
+        .zip(&means)
+        .map(|(set, &set_mean)| variance(set.iter().copied(), set_mean))
+        .sum::<f64>()
+        / values.len() as f64;
+    VarianceDecomposition {
+        metric,
+        mean,
+        total: variance(all(), mean),
+        between: variance(means.iter().copied(), mean),
+        within,
+    }
+}
+
+/// Run `replicates` replicates of each of `parameter_sets` sets sampled from `ranges`, where
+/// all other parameters are those of `base`, and decompose the variance of `metric`.
+///
+/// Fails without running anything if any of the sampled parameter sets is invalid.
+///
+/// # Panics
+///
+/// If there are no parameter sets or no replicates.
+pub fn run_two_level(
+    base: &SimulationParams,
+    ranges: &[ParameterRange],
+    parameter_sets: usize,
+    replicates: usize,
+    metric: Metric,
+    seed: u64,
+) -> Result<TwoLevelEnsemble, ConfigError> {
+    assert!(
+        parameter_sets > 0 && replicates > 0,
+        "a two-level ensemble needs parameter sets and replicates"
+    );
+    let samples = latin_hypercube(ranges, parameter_sets, &mut StdRng::seed_from_u64(seed));
+    let sets: Vec<SimulationParams> = samples
+        .iter()
+        .map(|values| {
+            let params = ranges
+                .iter()
+                .zip(values)
+                .fold(base.clone(), |params, (range, &value)| {
+                    range.parameter.apply(&params, value)
+                });
+            EnvironmentConfig::from(params)
+                .build_params()
+                .map(|(params, _)| params)
+        })
+        .collect::<Result<_, _>>()?;
+
+    let cancel = AtomicBool::new(false);
+    let values: Vec<Vec<f64>> = sets
+        .iter()
+        .map(|params| {
+            ensemble::run_replicates(params, replicates, &cancel)
+                .iter()
+                .flatten()
+                .map(|run| metric.value(run))
+                .collect()
+        })
+        .collect();
+    let runs = values
+        .iter()
+        .zip(&samples)
+        .enumerate()
+        .flat_map(|(parameter_set, (set, parameters))| {
+            set.iter()
+                .enumerate()
+                .map(move |(replicate, &metric)| EnsembleRun {
+                    parameter_set,
+                    replicate,
+                    parameters: parameters.clone(),
+                    metric,
+                })
+        })
+        .collect();
+    Ok(TwoLevelEnsemble {
+        ranges: ranges.to_vec(),
+        runs,
+        decomposition: decompose(metric, &values),
+    })
+}
+
+/// Write the runs of `ensemble` as CSV, with a column for each parameter and the metric
+pub fn write_csv<W: io::Write>(mut writer: W, ensemble: &TwoLevelEnsemble) -> io::Result<()> {
+    let mut header = vec!["parameter_set", "replicate"];
+    header.extend(ensemble.ranges.iter().map(|range| range.parameter.name()));
+    header.push(ensemble.decomposition.metric.name());
+    writeln!(writer, "{}", header.join(","))?;
+    for run in &ensemble.runs {
+        write!(writer, "{},{}", run.parameter_set, run.replicate)?;
+        for value in &run.parameters {
+            write!(writer, ",{}", value)?;
+        }
+        writeln!(writer, ",{}", run.metric)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets;
+
+    fn duration(low: f64, high: f64) -> Vec<ParameterRange> {
+        vec![ParameterRange {
+            parameter: SweepParameter::Duration,
+            low,
+            high,
+        }]
+    }
+
+    #[test]
+    fn test_latin_hypercube() {
+        let ranges = vec![
+            ParameterRange {
+                parameter: SweepParameter::PDeath,
+                low: 0.0,
+                high: 1.0,
+            },
+            ParameterRange {
+                parameter: SweepParameter::Duration,
+                low: 10.0,
+                high: 20.0,
+            },
+        ];
+        let samples = latin_hypercube(&ranges, 5, &mut StdRng::seed_from_u64(1));
+        assert_eq!(samples.len(), 5);
+        // one value in each fifth of each range
+        let mut strata: Vec<usize> = samples.iter().map(|s| (s[0] * 5.0) as usize).collect();
+        strata.sort_unstable();
+        assert_eq!(strata, vec![0, 1, 2, 3, 4]);
+        let mut strata: Vec<usize> = samples
+            .iter()
+            .map(|s| ((s[1] - 10.0) / 2.0) as usize)
+            .collect();
+        strata.sort_unstable();
+        assert_eq!(strata, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_zero_width_ranges() {
+        let ensemble = run_two_level(
+            &presets::dense_small_grid(),
+            &duration(6.0, 6.0),
+            6,
+            10,
+            Metric::Peak,
+            2,
+        )
+        .unwrap();
+        assert_eq!(ensemble.runs.len(), 60);
+        let decomposition = &ensemble.decomposition;
+        assert!(decomposition.within > 0.0);
+        // only the noise of the means of the parameter sets
+        assert!(
+            decomposition.between_fraction().unwrap() < 0.3,
+            "{:?}",
+            decomposition
+        );
+        assert!((decomposition.between + decomposition.within - decomposition.total).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_deterministic_outcome() {
+        // without infections, no run depends on either the parameters or chance
+        let base = SimulationParams {
+            infected: 0,
+            ..presets::dense_small_grid()
+        };
+        let ensemble = run_two_level(&base, &duration(2.0, 10.0), 3, 2, Metric::Deaths, 3).unwrap();
+        assert!(ensemble.runs.iter().all(|run| run.metric == 0.0));
+        assert_eq!(ensemble.decomposition.total, 0.0);
+        assert_eq!(ensemble.decomposition.within, 0.0);
+        assert_eq!(ensemble.decomposition.within_fraction(), None);
+
+        let mut csv = Vec::new();
+        write_csv(&mut csv, &ensemble).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("parameter_set,replicate,duration,deaths\n"));
+        assert_eq!(csv.lines().count(), 7);
+    }
+
+    #[test]
+    fn test_components_sum_to_total() {
+        let values = vec![
+            vec![1.0, 2.0, 6.0],
+            vec![10.0, 14.0, 9.0],
+            vec![3.0, 3.0, 3.0],
+        ];
+        let decomposition = decompose(Metric::FinalSize, &values);
+        assert!((decomposition.between + decomposition.within - decomposition.total).abs() < 1e-9);
+        assert!(decomposition.between > decomposition.within);
+        let fractions =
+            decomposition.between_fraction().unwrap() + decomposition.within_fraction().unwrap();
+        assert!((fractions - 1.0).abs() < 1e-12);
+
+        let ensemble = run_two_level(
+            &presets::dense_small_grid(),
+            &duration(2.0, 12.0),
+            4,
+            3,
+            Metric::FinalSize,
+            4,
+        )
+        .unwrap();
+        let decomposition = &ensemble.decomposition;
+        assert!((decomposition.between + decomposition.within - decomposition.total).abs() < 1e-6);
+    }
+}
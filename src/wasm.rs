@@ -0,0 +1,204 @@
+//! Bindings of an environment for JavaScript, to run the model in a web page.
+//!
+//! Build the package with `wasm-pack build --target web -- --no-default-features --features wasm`,
+//! after which the model runs in the browser one tick at a time:
+//!
+//! ```js
+//! import init, { WasmEnvironment } from "./pkg/bkamins_sir_abm.js";
+//!
+//! await init();
+//! const environment = new WasmEnvironment('{"n": 500, "xdim": 20, "ydim": 20}');
+//! while (!environment.is_finished()) {
+//!     const tally = environment.step();
+//!     const cells = environment.grid_state();
+//!     // draw `cells` on a canvas of `environment.xdim()` by `environment.ydim()` pixels
+//! }
+//! ```
+//!
+//! Under `wasm32-unknown-unknown`, a scenario without a seed draws its seed from
+//! `crypto.getRandomValues`, after which all random numbers are drawn from that seed as in a
+//! native run.
+//!
+//! The bindings are tested in Node.js by `cargo test --target wasm32-unknown-unknown --lib
+//! --no-default-features --features wasm`, with `wasm-bindgen-test-runner` of `wasm-bindgen-cli`
+//! as the runner of the target.
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+use crate::julia_reimpl::{AgentType, Environment, TallyStates};
+use crate::params::{EnvironmentConfig, SimulationParams};
+
+/// [`Environment`] of a scenario given as JSON, for JavaScript
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub struct WasmEnvironment {
+    environment: Environment,
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+impl WasmEnvironment {
+    /// Set up the scenario of `config_json`, being [`SimulationParams`] as JSON in which
+    /// missing fields take their default values.
+    ///
+    /// Fails with a message if the JSON or the parameters are invalid.
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen(constructor))]
+    pub fn new(config_json: &str) -> Result<WasmEnvironment, String> {
+        let params: SimulationParams =
+            serde_json::from_str(config_json).map_err(|error| error.to_string())?;
+        let (params, _) = EnvironmentConfig::from(params)
+            .build_params()
+            .map_err(|error| error.to_string())?;
+        Ok(Self {
+            environment: Environment::from_params(&params),
+        })
+    }
+
+    /// Advance by one tick, returning the tally of the tick as an object with a field for
+    /// each state such as `infected`
+    #[cfg(target_arch = "wasm32")]
+    pub fn step(&mut self) -> JsValue {
+        let tally = serde_json::to_string(&self.step_tally()).expect("tally is valid JSON");
+        js_sys::JSON::parse(&tally).expect("tally is valid JSON")
+    }
+
+    /// Dominant state of each cell in row-major order, such that the cell `(x, y)` is at
+    /// index `y * xdim + x`, see [`cell_code`]
+    #[must_use]
+    pub fn grid_state(&self) -> Vec<u8> {
+        self.environment
+            .grid_snapshot()
+            .iter()
+            .flatten()
+            .map(|cell| cell_code(cell.dominant()))
+            .collect()
+    }
+
+    /// Whether the run has ended, see [`Environment::is_finished`]
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.environment.is_finished()
+    }
+
+    /// Current tick
+    #[must_use]
+    pub fn tick(&self) -> usize {
+        self.environment.tick()
+    }
+
+    /// Number of cells along the x-dimension of the grid
+    #[must_use]
+    pub fn xdim(&self) -> usize {
+        self.environment.grid_size().0
+    }
+
+    /// Number of cells along the y-dimension of the grid
+    #[must_use]
+    pub fn ydim(&self) -> usize {
+        self.environment.grid_size().1
+    }
+}
+
+impl WasmEnvironment {
+    /// Advance by one tick, returning its tally, as `step` does in JavaScript
+    pub fn step_tally(&mut self) -> TallyStates {
+        self.environment.step()
+    }
+
+    /// The environment itself
+    #[must_use]
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+}
+
+/// Byte of a cell in [`WasmEnvironment::grid_state`] given its dominant state: zero for an
+/// empty cell, then one to seven for susceptible, exposed, infected, quarantined, recovered,
+/// vaccinated and dead
+#[must_use]
+pub fn cell_code(dominant: Option<AgentType>) -> u8 {
+    match dominant {
+        None => 0,
+        Some(AgentType::AgentS) => 1,
+        Some(AgentType::AgentE) => 2,
+        Some(AgentType::AgentI) => 3,
+        Some(AgentType::AgentQ) => 4,
+        Some(AgentType::AgentR) => 5,
+        Some(AgentType::AgentV) => 6,
+        Some(AgentType::AgentD) => 7,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steps_of_small_grid() {
+        // a grid that is not square, such that the cells are in row-major order of width xdim
+        let config = r#"{"n": 30, "infected": 3, "xdim": 7, "ydim": 3, "seed": 4}"#;
+        let mut environment = WasmEnvironment::new(config).unwrap();
+        assert_eq!((environment.xdim(), environment.ydim()), (7, 3));
+        assert!(!environment.is_finished());
+
+        let mut expected = Environment::from_params(&SimulationParams {
+            n: 30,
+            infected: 3,
+            xdim: 7,
+            ydim: 3,
+            seed: Some(4),
+            ..SimulationParams::default()
+        });
+        for tick in 1..=5 {
+            let tally = environment.step_tally();
+            assert_eq!(tally, expected.step());
+            assert_eq!((tally.total(), environment.tick()), (30, tick));
+
+            let cells = environment.grid_state();
+            assert_eq!(cells.len(), 21);
+            for (x, y) in (0..7).flat_map(|x| (0..3).map(move |y| (x, y))) {
+                let states: Vec<_> = environment
+                    .environment()
+                    .agents_in_cell(x, y)
+                    .map(|agent| agent.state)
+                    .collect();
+                // no agent is exposed, quarantined or vaccinated in this scenario
+                use AgentType::*;
+                let dominant = [AgentI, AgentS, AgentR, AgentD]
+                    .iter()
+                    .find(|state| states.contains(state))
+                    .cloned();
+                assert_eq!(cells[y * 7 + x], cell_code(dominant), "cell {:?}", (x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(WasmEnvironment::new("{\"n\": ").is_err());
+        let error = WasmEnvironment::new(r#"{"xdim": 0}"#).err().unwrap();
+        assert!(error.contains("no cells"), "{}", error);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    fn test_step_in_javascript() {
+        let config = r#"{"n": 30, "infected": 3, "xdim": 7, "ydim": 3, "seed": 4}"#;
+        let mut environment = WasmEnvironment::new(config).unwrap();
+        let mut expected = WasmEnvironment::new(config).unwrap();
+        while !environment.is_finished() {
+            let tally = environment.step();
+            let expected = expected.step_tally();
+            for (state, count) in &[
+                ("susceptible", expected.susceptible),
+                ("infected", expected.infected),
+                ("recovered", expected.recovered),
+                ("dead", expected.dead),
+            ] {
+                let field = js_sys::Reflect::get(&tally, &JsValue::from_str(state)).unwrap();
+                assert_eq!(field.as_f64(), Some(*count as f64), "{}", state);
+            }
+            assert_eq!(environment.grid_state().len(), 21);
+        }
+        // without a seed, the seed is drawn from JavaScript
+        assert!(WasmEnvironment::new(r#"{"n": 30, "xdim": 7, "ydim": 3}"#).is_ok());
+    }
+}
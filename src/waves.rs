@@ -0,0 +1,260 @@
+//! Detection of the waves of an epidemic in its incidence.
+//!
+//! A wave is a local maximum of the smoothed incidence that stands out from the troughs on
+//! either side of it, as measured by its relative prominence: the height of the peak above
+//! the higher of the two troughs separating it from any higher peaks, relative to the height
+//! of the peak. Consecutive waves meet at the lowest tick between their peaks.
+use serde::{Deserialize, Serialize};
+
+use crate::phases::smooth;
+
+/// Parameters of [`detect_waves`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WaveOptions {
+    /// Width in ticks of the centred moving average that smooths the incidence
+    pub window: usize,
+    /// Relative prominence below which a local maximum is not a wave
+    pub min_prominence: f64,
+    /// Minimum number of ticks between the peaks of two waves, of which the lower is dropped
+    pub min_separation: usize,
+}
+
+impl Default for WaveOptions {
+    /// A week-long window, and waves that drop to half their height at least a week apart
+    fn default() -> Self {
+        Self {
+            window: 7,
+            min_prominence: 0.5,
+            min_separation: 7,
+        }
+    }
+}
+
+/// A wave of the incidence, spanning the ticks from `start` up to and including `end`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Wave {
+    pub start: usize,
+    pub end: usize,
+    /// Tick of the maximum of the smoothed incidence
+    pub peak: usize,
+    /// Smoothed incidence at the peak
+    pub peak_height: f64,
+    /// Number of infections during the wave
+    pub size: f64,
+}
+
+impl Wave {
+    /// Number of ticks of the wave
+    #[must_use]
+    pub fn duration(&self) -> usize {
+        self.end - self.start + 1
+    }
+}
+
+/// Find the waves of `incidence`, in order of time
+#[must_use]
+pub fn detect_waves(incidence: &[f64], options: &WaveOptions) -> Vec<Wave> {
+    let smoothed = smooth(incidence, options.window.max(1));
+    let ticks = smoothed.len();
+
+    // local maxima, taking the first tick of a plateau
+    let candidates = (0..ticks).filter(|&tick| {
+        let height = smoothed[tick];
+        let rises = tick == 0 || height > smoothed[tick - 1];
+        let falls = smoothed[tick..]
+            .iter()
+            .find(|&&later| later != height)
+            .map_or(true, |&later| later < height);
+        height > 0.0 && rises && falls
+    });
+    let mut peaks: Vec<usize> = candidates
+        .filter(|&peak| {
+            let height = smoothed[peak];
+            // lowest point on each side before reaching a higher peak, or the end, where a
+            // peak at the end of the series is only bounded by the other side
+            let base = |range: &mut dyn Iterator<Item = usize>| {
+                range
+                    .map(|tick| smoothed[tick])
+                    .take_while(|&value| value <= height)
+                    .reduce(f64::min)
+            };
+            let left = base(&mut (0..peak).rev());
+            let right = base(&mut (peak + 1..ticks));
+            let base = match (left, right) {
+                (Some(left), Some(right)) => left.max(right),
+                (Some(base), None) | (None, Some(base)) => base,
+                (None, None) => 0.0,
+            };
+            (height - base) / height >= options.min_prominence
+        })
+        .collect();
+
+    // keep the higher of peaks that are too close to each other
+    let mut by_height = peaks.clone();
+    by_height.sort_by(|&a, &b| {
+        smoothed[b]
+            .partial_cmp(&smoothed[a])
+            .expect("incidence is finite")
+            .then(a.cmp(&b))
+    });
+    let mut kept: Vec<usize> = Vec::new();
+    for peak in by_height {
+        if kept
+            .iter()
+            .all(|&other| peak.abs_diff(other) >= options.min_separation)
+        {
+            kept.push(peak);
+        }
+    }
+    peaks.retain(|peak| kept.contains(peak));
+
+    // waves meet at the lowest tick between their peaks
+    let mut boundaries = Vec::with_capacity(peaks.len() + 1);
+    boundaries.push((0..ticks).find(|&tick| incidence[tick] > 0.0).unwrap_or(0));
+    for pair in peaks.windows(2) {
+        let trough = (pair[0]..=pair[1])
+            .min_by(|&a, &b| smoothed[a].partial_cmp(&smoothed[b]).expect("finite"))
+            .expect("peaks are ordered");
+        boundaries.push(trough + 1);
+    }
+    boundaries.push(
+        (0..ticks)
+            .rev()
+            .find(|&tick| incidence[tick] > 0.0)
+            .map_or(ticks, |tick| tick + 1),
+    );
+
+    peaks
+        .iter()
+        .enumerate()
+        .map(|(i, &peak)| {
+            let (start, end) = (boundaries[i], boundaries[i + 1] - 1);
+            Wave {
+                start,
+                end,
+                peak,
+                peak_height: smoothed[peak],
+                size: incidence[start..=end].iter().sum(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::julia_reimpl::{Environment, TallyStatesVec};
+    use crate::params::SimulationParams;
+    use crate::phases::incidence;
+    use crate::presets;
+
+    /// Sum of gaussian bumps of the given (centre, height), over 100 ticks
+    fn bumps(waves: &[(f64, f64)]) -> Vec<f64> {
+        (0..100)
+            .map(|tick| {
+                waves
+                    .iter()
+                    .map(|&(centre, height)| {
+                        height * (-((tick as f64 - centre) / 6.0).powi(2) / 2.0).exp()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_two_waves() {
+        let incidence = bumps(&[(25.0, 100.0), (65.0, 60.0)]);
+        let waves = detect_waves(&incidence, &WaveOptions::default());
+        assert_eq!(waves.len(), 2);
+        assert_eq!((waves[0].peak, waves[1].peak), (25, 65));
+        // flattened a little by the smoothing
+        assert!((90.0..100.0).contains(&waves[0].peak_height));
+        assert_eq!(waves[0].start, 0);
+        assert_eq!(waves[1].start, waves[0].end + 1);
+        assert_eq!(waves[1].end, 99);
+        // the second wave is 60% the size of the first
+        let ratio = waves[1].size / waves[0].size;
+        assert!((ratio - 0.6).abs() < 0.01, "{}", ratio);
+        let total: f64 = incidence.iter().sum();
+        assert!((waves[0].size + waves[1].size - total).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_three_waves() {
+        let incidence = bumps(&[(15.0, 50.0), (45.0, 80.0), (80.0, 30.0)]);
+        let waves = detect_waves(&incidence, &WaveOptions::default());
+        let peaks: Vec<usize> = waves.iter().map(|wave| wave.peak).collect();
+        assert_eq!(peaks, vec![15, 45, 80]);
+        // troughs between the peaks, closer to the lower one
+        assert!((29..=31).contains(&waves[1].start));
+        assert!((63..=66).contains(&waves[2].start));
+        assert_eq!(waves[2].end, 99);
+    }
+
+    #[test]
+    fn test_single_wave() {
+        let waves = detect_waves(&bumps(&[(40.0, 20.0)]), &WaveOptions::default());
+        assert_eq!(waves.len(), 1);
+        assert_eq!(waves[0].peak, 40);
+        assert_eq!(waves[0].duration(), 100);
+
+        // a series that only rises peaks at its end
+        let rising: Vec<f64> = (0..30).map(|tick| tick as f64).collect();
+        let waves = detect_waves(&rising, &WaveOptions::default());
+        assert_eq!(waves.len(), 1);
+        assert_eq!(waves[0].peak, 29);
+        assert_eq!(waves[0].start, 1);
+    }
+
+    #[test]
+    fn test_flat() {
+        assert!(detect_waves(&[5.0; 50], &WaveOptions::default()).is_empty());
+        assert!(detect_waves(&[0.0; 50], &WaveOptions::default()).is_empty());
+        assert!(detect_waves(&[], &WaveOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_separation() {
+        // two peaks 4 ticks apart with a deep trough between them
+        let mut incidence = vec![0.0; 20];
+        incidence[8] = 10.0;
+        incidence[12] = 8.0;
+        let options = WaveOptions {
+            window: 1,
+            ..WaveOptions::default()
+        };
+        let waves = detect_waves(&incidence, &options);
+        assert_eq!(waves.len(), 1);
+        assert_eq!(waves[0].peak, 8);
+        assert_eq!(waves[0].size, 18.0);
+    }
+
+    #[test]
+    fn test_default_run() {
+        let mut e = Environment::from_params(&presets::dense_small_grid());
+        let record: TallyStatesVec = e.run().into_iter().collect();
+        let waves = detect_waves(&incidence(&record), &WaveOptions::default());
+        assert!(!waves.is_empty());
+        let size: f64 = waves.iter().map(|wave| wave.size).sum();
+        assert!(size <= 500.0);
+        assert!(waves.windows(2).all(|pair| pair[0].end < pair[1].start));
+    }
+
+    #[test]
+    fn test_waning_run() {
+        let mut e = Environment::from_params(&SimulationParams {
+            seed: Some(1),
+            ..presets::long_endemic()
+        });
+        e.enable_infection_events();
+        let record = e.run();
+        // the susceptible agents are replenished as immunity wanes, so count the infections
+        let mut incidence = vec![0.0; record.len()];
+        for event in e.infection_events() {
+            incidence[event.tick] += 1.0;
+        }
+        let waves = detect_waves(&incidence, &WaveOptions::default());
+        assert!(waves.len() >= 2, "{:?}", waves);
+    }
+}
@@ -0,0 +1,538 @@
+//! Tests of the command-line interface, which spawn the native binary
+#![cfg(not(target_arch = "wasm32"))]
+
+use assert_cmd::Command;
+use bkamins_sir_abm::ensemble::replicate_seed;
+use predicates::prelude::*;
+
+fn sir_abm() -> Command {
+    Command::cargo_bin("bkamins_sir_abm").unwrap()
+}
+
+#[test]
+fn test_sweep_writes_csv() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("sweep.csv");
+    let plot = dir.path().join("sweep.html");
+    sir_abm()
+        .args([
+            "sweep",
+            "--preset",
+            "dense_small_grid",
+            "--param",
+            "duration",
+        ])
+        .args(["--from", "2", "--to", "6", "--replicates", "2"])
+        .arg("--out")
+        .arg(&out)
+        .arg("--plot")
+        .arg(&plot)
+        .assert()
+        .success();
+
+    let csv = std::fs::read_to_string(&out).unwrap();
+    let mut lines = csv.lines();
+    assert!(lines.next().unwrap().starts_with("duration,"));
+    let durations: Vec<f64> = lines
+        .map(|line| line.split(',').next().unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(durations, vec![2.0, 3.0, 4.0, 5.0, 6.0]);
+    assert!(plot.exists());
+}
+
+#[test]
+fn test_sweep_seed_reproduces_csv() {
+    let dir = tempfile::tempdir().unwrap();
+    let sweep = |name: &str, seed: &str| {
+        let out = dir.path().join(name);
+        sir_abm()
+            .args([
+                "sweep",
+                "--preset",
+                "dense_small_grid",
+                "--param",
+                "duration",
+            ])
+            .args([
+                "--from",
+                "4",
+                "--to",
+                "6",
+                "--replicates",
+                "3",
+                "--seed",
+                seed,
+            ])
+            .arg("--out")
+            .arg(&out)
+            .assert()
+            .success();
+        std::fs::read(out).unwrap()
+    };
+    let first = sweep("first.csv", "42");
+    assert_eq!(first, sweep("second.csv", "42"));
+    assert_ne!(first, sweep("other.csv", "43"));
+}
+
+#[test]
+fn test_sweep_unknown_parameter() {
+    let dir = tempfile::tempdir().unwrap();
+    sir_abm()
+        .args(["sweep", "--param", "gamma", "--from", "0", "--to", "1"])
+        .arg("--out")
+        .arg(dir.path().join("sweep.csv"))
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains("unknown parameter `gamma`"));
+}
+
+#[test]
+fn test_ensemble_writes_tallies_and_summary() {
+    let dir = tempfile::tempdir().unwrap();
+    sir_abm()
+        .args([
+            "ensemble",
+            "--preset",
+            "dense_small_grid",
+            "--replicates",
+            "3",
+        ])
+        .arg("--out-dir")
+        .arg(dir.path())
+        .assert()
+        .success();
+
+    let tallies = std::fs::read_to_string(dir.path().join("tallies.csv")).unwrap();
+    assert!(tallies.starts_with("# crate_version: "));
+    assert_eq!(
+        tallies.lines().find(|line| !line.starts_with('#')),
+        Some("replicate,tick,state,count")
+    );
+    let summary: serde_json::Value =
+        serde_json::from_reader(std::fs::File::open(dir.path().join("summary.json")).unwrap())
+            .unwrap();
+    assert_eq!(summary["replicates"], 3);
+    assert_eq!(summary["completed"], serde_json::json!([0, 1, 2]));
+    assert_eq!(summary["metadata"].as_array().unwrap().len(), 3);
+    assert_eq!(summary["final_size"].as_array().unwrap().len(), 3);
+    for state in &["susceptible", "infected", "recovered", "dead"] {
+        assert!(summary["mean"][state].is_array());
+    }
+    assert_eq!(summary["quantiles"].as_array().unwrap().len(), 5);
+}
+
+#[test]
+fn test_ensemble_per_replicate_and_summary_only() {
+    let dir = tempfile::tempdir().unwrap();
+    sir_abm()
+        .args([
+            "ensemble",
+            "--preset",
+            "dense_small_grid",
+            "--replicates",
+            "2",
+        ])
+        .arg("--per-replicate")
+        .arg("--out-dir")
+        .arg(dir.path())
+        .assert()
+        .success();
+    let mut files: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        vec!["replicate_0000.csv", "replicate_0001.csv", "summary.json"]
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    sir_abm()
+        .args([
+            "ensemble",
+            "--preset",
+            "dense_small_grid",
+            "--replicates",
+            "2",
+        ])
+        .arg("--summary-only")
+        .arg("--out-dir")
+        .arg(dir.path())
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_ensemble_seed_reproduces_output() {
+    let ensemble = |seed: &str, extra: &[&str]| {
+        let dir = tempfile::tempdir().unwrap();
+        sir_abm()
+            .args([
+                "ensemble",
+                "--preset",
+                "dense_small_grid",
+                "--replicates",
+                "3",
+            ])
+            .args(["--seed", seed])
+            .args(extra)
+            .arg("--out-dir")
+            .arg(dir.path())
+            .assert()
+            .success();
+        dir
+    };
+    let read = |dir: &tempfile::TempDir, name: &str| std::fs::read(dir.path().join(name)).unwrap();
+    let summary = |dir: &tempfile::TempDir, seed: &str| {
+        let mut summary: serde_json::Value =
+            serde_json::from_slice(&read(dir, "summary.json")).unwrap();
+        // the replicates are seeded from the seed
+        let seeds: Vec<_> = summary["metadata"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|metadata| metadata["seed"].as_u64().unwrap())
+            .collect();
+        let expected: Vec<_> = (0..3)
+            .map(|replicate| replicate_seed(seed.parse().unwrap(), replicate))
+            .collect();
+        assert_eq!(seeds, expected);
+        // the metadata records the wall-clock time of each run
+        summary.as_object_mut().unwrap().remove("metadata");
+        summary
+    };
+
+    let (first, second) = (ensemble("7", &[]), ensemble("7", &[]));
+    assert_eq!(read(&first, "tallies.csv"), read(&second, "tallies.csv"));
+    assert_eq!(summary(&first, "7"), summary(&second, "7"));
+    let other = ensemble("8", &[]);
+    assert_ne!(read(&first, "tallies.csv"), read(&other, "tallies.csv"));
+
+    let per_replicate = ensemble("7", &["--per-replicate"]);
+    assert_eq!(
+        read(&per_replicate, "replicate_0002.csv"),
+        read(&ensemble("7", &["--per-replicate"]), "replicate_0002.csv")
+    );
+}
+
+#[test]
+fn test_ensemble_time_budget() {
+    let dir = tempfile::tempdir().unwrap();
+    sir_abm()
+        .args([
+            "ensemble",
+            "--preset",
+            "dense_small_grid",
+            "--replicates",
+            "3",
+            "--budget-secs",
+            "0",
+            "--summary-only",
+        ])
+        .arg("--out-dir")
+        .arg(dir.path())
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(
+            "time budget exhausted after 0 of 3 replicates",
+        ));
+    let summary: serde_json::Value =
+        serde_json::from_reader(std::fs::File::open(dir.path().join("summary.json")).unwrap())
+            .unwrap();
+    assert_eq!(summary["completed"], serde_json::json!([]));
+
+    sir_abm()
+        .args(["ensemble", "--budget-secs", "-1", "--out-dir"])
+        .arg(dir.path())
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains("--budget-secs"));
+}
+
+#[test]
+fn test_invalid_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("scenario.toml");
+    std::fs::write(&config, "n = 10\ninfected = 20\n").unwrap();
+    sir_abm()
+        .arg("--config")
+        .arg(&config)
+        .assert()
+        .code(2)
+        .stderr(predicates::str::starts_with("error: "))
+        .stderr(predicates::str::contains("panicked").not());
+}
+
+/// Default command on a tiny seeded scenario with two replicates, writing to `output`
+fn tiny_scenario(output: &std::path::Path) -> Command {
+    let mut command = sir_abm();
+    command
+        .args(["--population", "50", "--initial-infected", "2"])
+        .args(["--duration", "4", "--p-death", "0.1"])
+        .args(["--width", "5", "--height", "5"])
+        .args(["--seed", "3", "--replicates", "2", "--output"])
+        .arg(output);
+    command
+}
+
+#[test]
+fn test_scenario_writes_csv() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("tallies.csv");
+    tiny_scenario(&out).assert().success();
+
+    let csv = std::fs::read_to_string(&out).unwrap();
+    let mut rows = csv.lines().filter(|line| !line.starts_with('#'));
+    assert_eq!(rows.next(), Some("replicate,tick,state,count"));
+    let mut population = [0; 2];
+    for row in rows {
+        let fields: Vec<&str> = row.split(',').collect();
+        let replicate: usize = fields[0].parse().unwrap();
+        if fields[1] == "0" {
+            population[replicate] += fields[3].parse::<usize>().unwrap();
+        }
+    }
+    assert_eq!(population, [50, 50]);
+
+    let again = dir.path().join("again.csv");
+    tiny_scenario(&again).assert().success();
+    assert_eq!(csv, std::fs::read_to_string(again).unwrap());
+}
+
+#[test]
+fn test_scenario_writes_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("runs.json");
+    tiny_scenario(&out).assert().success();
+
+    let runs: Vec<bkamins_sir_abm::result::RunResult> =
+        serde_json::from_reader(std::fs::File::open(&out).unwrap()).unwrap();
+    assert_eq!(runs.len(), 2);
+    for run in &runs {
+        let params = &run.metadata.params;
+        assert_eq!((params.n, params.infected, params.duration), (50, 2, 4));
+        assert_eq!((params.xdim, params.ydim), (5, 5));
+        assert!(run.record.len() > 1);
+    }
+    assert_ne!(runs[0].metadata.seed, runs[1].metadata.seed);
+}
+
+#[test]
+fn test_scenario_invalid_parameters() {
+    for args in &[
+        ["--population", "10", "--initial-infected", "20"],
+        ["--width", "0", "--height", "10"],
+        ["--p-death", "1.5", "--replicates", "1"],
+    ] {
+        sir_abm()
+            .args(args)
+            .assert()
+            .code(2)
+            .stderr(predicates::str::starts_with("error: "))
+            .stderr(predicates::str::contains("panicked").not());
+    }
+    sir_abm()
+        .args(["--replicates", "1", "--output", "tallies.txt"])
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains("`.csv` or `.json`"));
+}
+
+#[cfg(feature = "frames")]
+#[test]
+fn test_animate_gif() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("scenario.toml");
+    std::fs::write(
+        &config,
+        "n = 200\ninfected = 5\nduration = 8\nxdim = 30\nydim = 30\n",
+    )
+    .unwrap();
+    let out = dir.path().join("spread.gif");
+    sir_abm()
+        .args(["animate", "--every", "2", "--max-frames", "5"])
+        .arg("--config")
+        .arg(&config)
+        .arg("--out")
+        .arg(&out)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("frames: 5"));
+    let bytes = std::fs::read(&out).unwrap();
+    assert_eq!(&bytes[..6], b"GIF89a");
+}
+
+#[cfg(feature = "frames")]
+#[test]
+fn test_animate_seed_reproduces_gif() {
+    let dir = tempfile::tempdir().unwrap();
+    let animate = |name: &str, seed: &str| {
+        let out = dir.path().join(name);
+        sir_abm()
+            .args(["animate", "--preset", "dense_small_grid", "--every", "2"])
+            .args(["--seed", seed])
+            .arg("--out")
+            .arg(&out)
+            .assert()
+            .success();
+        std::fs::read(out).unwrap()
+    };
+    let first = animate("first.gif", "3");
+    assert_eq!(first, animate("second.gif", "3"));
+    assert_ne!(first, animate("other.gif", "4"));
+}
+
+#[cfg(feature = "frames")]
+#[test]
+fn test_animate_too_large() {
+    let dir = tempfile::tempdir().unwrap();
+    sir_abm()
+        .args(["animate", "--cell-pixels", "1000"])
+        .arg("--frames-dir")
+        .arg(dir.path())
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains("--downsample"));
+}
+
+const PIPE_SCENARIO: &str = r#"{"n": 300, "infected": 3, "duration": 6, "xdim": 20, "ydim": 20}"#;
+
+#[test]
+fn test_pipe_run_result() {
+    let output = sir_abm()
+        .arg("pipe")
+        .write_stdin(PIPE_SCENARIO)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let result: bkamins_sir_abm::result::RunResult =
+        serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result.metadata.params.n, 300);
+    assert_eq!(result.metadata.params.xdim, 20);
+    let records = result.records();
+    assert!(!records.is_empty());
+    let last: serde_json::Value = serde_json::to_value(records.last().unwrap()).unwrap();
+    assert_eq!(last["infected"], 0);
+}
+
+#[test]
+fn test_pipe_stream() {
+    let output = sir_abm()
+        .args(["pipe", "--stream"])
+        .write_stdin(PIPE_SCENARIO)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(lines.len() > 1);
+    for (tick, line) in lines.iter().enumerate() {
+        assert_eq!(line["tick"], tick);
+        let total: u64 = ["susceptible", "infected", "recovered", "dead"]
+            .iter()
+            .map(|state| line[state].as_u64().unwrap())
+            .sum();
+        assert_eq!(total, 300);
+    }
+    assert_eq!(lines.last().unwrap()["infected"], 0);
+}
+
+#[test]
+fn test_pipe_matches_seeded_library_run() {
+    let scenario = r#"{"n": 300, "infected": 3, "duration": 6, "xdim": 20, "ydim": 20, "seed": 5}"#;
+    let pipe = |args: &[&str], input: &str| {
+        let output = sir_abm()
+            .arg("pipe")
+            .args(args)
+            .write_stdin(input)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+    let params: bkamins_sir_abm::params::SimulationParams = serde_json::from_str(scenario).unwrap();
+    let expected = bkamins_sir_abm::result::run(&params).records();
+    let records = |stdout: &[u8]| {
+        serde_json::from_slice::<bkamins_sir_abm::result::RunResult>(stdout)
+            .unwrap()
+            .records()
+    };
+    assert_eq!(records(&pipe(&[], scenario)), expected);
+    // the flag overrides the seed of the scenario
+    assert_eq!(records(&pipe(&["--seed", "5"], PIPE_SCENARIO)), expected);
+    let result: bkamins_sir_abm::result::RunResult =
+        serde_json::from_slice(&pipe(&["--seed", "9"], scenario)).unwrap();
+    assert_eq!(result.metadata.seed, Some(9));
+
+    let stream = pipe(&["--stream"], scenario);
+    assert_eq!(stream, pipe(&["--stream"], scenario));
+    let ticks = String::from_utf8(stream).unwrap().lines().count();
+    assert_eq!(ticks, expected.len());
+}
+
+#[test]
+fn test_pipe_malformed_input() {
+    for (input, kind) in &[
+        ("{not json", "invalid_input"),
+        (r#"{"n": 10, "infected": 20}"#, "invalid_config"),
+    ] {
+        let output = sir_abm().arg("pipe").write_stdin(*input).output().unwrap();
+        assert_eq!(output.status.code(), Some(2));
+        let error: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(error["error"]["kind"], *kind);
+        assert!(error["error"]["message"].is_string());
+    }
+}
+
+/// Allocations per tick after the warm-up that the default scenario must stay below
+#[cfg(feature = "alloc-profile")]
+const ALLOCATIONS_PER_TICK_BUDGET: f64 = 3000.0;
+
+#[cfg(feature = "alloc-profile")]
+#[test]
+fn test_allocation_budget() {
+    let params = bkamins_sir_abm::presets::blog_default();
+    let output = sir_abm()
+        .arg("pipe")
+        .write_stdin(serde_json::to_string(&params).unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let result: bkamins_sir_abm::result::RunResult =
+        serde_json::from_slice(&output.stdout).unwrap();
+    let report = result.metadata.allocations.unwrap();
+    assert!(report.bytes > 0);
+    assert!(report.peak_environment_bytes >= params.n * 8);
+    assert!(
+        report.allocations_per_tick < ALLOCATIONS_PER_TICK_BUDGET,
+        "{:?}",
+        report
+    );
+}
+
+#[cfg(feature = "alloc-profile")]
+#[test]
+fn test_profiling_changes_no_results() {
+    let params = bkamins_sir_abm::params::SimulationParams {
+        seed: Some(17),
+        ..bkamins_sir_abm::presets::dense_small_grid()
+    };
+    let output = sir_abm()
+        .arg("pipe")
+        .write_stdin(serde_json::to_string(&params).unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let profiled: bkamins_sir_abm::result::RunResult =
+        serde_json::from_slice(&output.stdout).unwrap();
+    assert!(profiled.metadata.allocations.is_some());
+    // this test does not install the counting allocator
+    let unprofiled = bkamins_sir_abm::result::run(&params);
+    assert_eq!(unprofiled.metadata.allocations, None);
+    assert_eq!(profiled.records(), unprofiled.records());
+}
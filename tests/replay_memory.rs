@@ -0,0 +1,45 @@
+//! Memory of the compressed history, counted by the allocator of the `alloc-profile` feature
+#![cfg(feature = "alloc-profile")]
+
+use bkamins_sir_abm::alloc_profile::{allocated_bytes, CountingAllocator};
+use bkamins_sir_abm::julia_reimpl::Environment;
+use bkamins_sir_abm::params::{Lockdown, SimulationParams};
+use bkamins_sir_abm::presets;
+use bkamins_sir_abm::replay::{Snapshot, StateHistory};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn test_history_allocates_less_than_snapshots() {
+    // a long run in total lockdown, where no agent moves and few change state
+    let params = SimulationParams {
+        duration: 400,
+        lockdown: Some(Lockdown {
+            start: 0,
+            end: usize::MAX,
+            p_move: 0.0,
+        }),
+        ..presets::dense_small_grid()
+    };
+    let mut environment = Environment::from_params(&params);
+    let mut history = StateHistory::new(&environment);
+    let mut snapshots = vec![Snapshot::capture(&environment)];
+    let (mut history_bytes, mut snapshot_bytes) = (0, 0);
+    environment.run_with_hook(|environment, _| {
+        let start = allocated_bytes();
+        history.record(environment);
+        let recorded = allocated_bytes();
+        snapshots.push(Snapshot::capture(environment));
+        history_bytes += recorded - start;
+        snapshot_bytes += allocated_bytes() - recorded;
+    });
+    assert!(snapshots.len() > 400);
+    assert!(
+        history_bytes * 20 < snapshot_bytes,
+        "{} bytes against {} bytes of snapshots",
+        history_bytes,
+        snapshot_bytes
+    );
+    assert_eq!(history.reconstruct(history.end()), snapshots.pop());
+}